@@ -0,0 +1,186 @@
+// Client - running SQL on a remote database from Rust
+//
+// `Client::connect(addr)` opens a session with a server started with
+// `mydb --pg-listen <addr>`, and `query` runs SQL on it, returning the same
+// `ExecutionResult` as running it locally. The session speaks the simple
+// query protocol (see `protocol.rs`): rows arrive as text and are turned back
+// into values by their column types, and statements other than queries report
+// their command tag, e.g. `INSERT 0 1`, as their message.
+
+use crate::error::DbError;
+use crate::executor::ExecutionResult;
+use crate::parser::{array, DataType, Value};
+use crate::protocol::{
+    self, BackendMessage, FieldDescription, FrontendMessage, FLOAT8_ARRAY_OID, FLOAT8_OID, INT8_ARRAY_OID,
+    INT8_OID, TEXT_ARRAY_OID,
+};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+
+/// User name sent in the startup packet; the server does not check it
+const CLIENT_USER: &str = "mini_sql_db";
+
+/// A session with a remote database
+pub struct Client {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+}
+
+impl Client {
+    /// Connect to a server and complete the startup handshake
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<Self, DbError> {
+        let stream = TcpStream::connect(addr).map_err(connection_error)?;
+        let mut client = Client {
+            reader: BufReader::new(stream.try_clone().map_err(connection_error)?),
+            writer: BufWriter::new(stream),
+        };
+        protocol::write_startup(&mut client.writer, CLIENT_USER)
+            .and_then(|_| client.writer.flush())
+            .map_err(connection_error)?;
+        loop {
+            match client.receive()? {
+                BackendMessage::ReadyForQuery => return Ok(client),
+                BackendMessage::ErrorResponse { code, message } => {
+                    return Err(protocol::error_from_sqlstate(&code, message));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Run one or more statements and return the result of the last. A
+    /// statement that fails stops the rest and its error is returned.
+    pub fn query(&mut self, sql: &str) -> Result<ExecutionResult, DbError> {
+        FrontendMessage::Query(sql.to_string()).write(&mut self.writer)
+            .and_then(|_| self.writer.flush())
+            .map_err(connection_error)?;
+
+        let mut result = Ok(ExecutionResult::Success(String::new()));
+        let mut fields = Vec::new();
+        let mut rows = Vec::new();
+        loop {
+            match self.receive()? {
+                BackendMessage::RowDescription(description) => fields = description,
+                BackendMessage::DataRow(values) => rows.push(parse_row(&fields, values)?),
+                BackendMessage::CommandComplete(tag) => {
+                    result = Ok(completed(tag, std::mem::take(&mut fields), std::mem::take(&mut rows)));
+                }
+                BackendMessage::ErrorResponse { code, message } => {
+                    result = Err(protocol::error_from_sqlstate(&code, message));
+                }
+                BackendMessage::ReadyForQuery => return result,
+                _ => {}
+            }
+        }
+    }
+
+    fn receive(&mut self) -> Result<BackendMessage, DbError> {
+        BackendMessage::read(&mut self.reader).map_err(connection_error)
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        let _ = FrontendMessage::Terminate.write(&mut self.writer);
+        let _ = self.writer.flush();
+    }
+}
+
+/// The result of a statement, from its command tag and any rows it sent
+fn completed(tag: String, fields: Vec<FieldDescription>, rows: Vec<Vec<Value>>) -> ExecutionResult {
+    // EXPLAIN sends its plan as rows of one column; locally it is one message
+    if tag == "EXPLAIN" {
+        let lines: Vec<String> = rows.into_iter()
+            .filter_map(|row| match row.into_iter().next() {
+                Some(Value::Text(line)) => Some(line),
+                _ => None,
+            })
+            .collect();
+        return ExecutionResult::Success(lines.join("\n"));
+    }
+    if !fields.is_empty() {
+        let columns = fields.into_iter().map(|field| field.name).collect();
+        return ExecutionResult::Rows { columns, rows };
+    }
+
+    let count = tag.rsplit(' ').next().and_then(|n| n.parse().ok());
+    match count {
+        Some(rows) if ["INSERT ", "UPDATE ", "DELETE "].iter().any(|kind| tag.starts_with(kind)) => {
+            ExecutionResult::Changed { message: tag, rows }
+        }
+        _ => ExecutionResult::Success(tag),
+    }
+}
+
+/// Turn a row's text values back into values of their column types
+fn parse_row(fields: &[FieldDescription], values: Vec<Option<String>>) -> Result<Vec<Value>, DbError> {
+    fields.iter()
+        .zip(values)
+        .map(|(field, value)| {
+            let Some(text) = value else {
+                return Ok(Value::Null);
+            };
+            let invalid = || DbError::Io(format!("Invalid value '{}' for column '{}'", text, field.name));
+            match field.type_oid {
+                INT8_OID => text.parse().map(Value::Int).map_err(|_| invalid()),
+                FLOAT8_OID => text.parse().map(Value::Float).map_err(|_| invalid()),
+                INT8_ARRAY_OID | FLOAT8_ARRAY_OID | TEXT_ARRAY_OID => {
+                    let element = match field.type_oid {
+                        INT8_ARRAY_OID => DataType::Int,
+                        FLOAT8_ARRAY_OID => DataType::Float,
+                        _ => DataType::Text,
+                    };
+                    array::parse_array(&text, &DataType::Array(Box::new(element)))
+                        .map(Value::Array)
+                        .map_err(|_| invalid())
+                }
+                _ => Ok(Value::Text(text)),
+            }
+        })
+        .collect()
+}
+
+fn connection_error(error: io::Error) -> DbError {
+    DbError::Io(format!("Connection to server failed: {}", error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_results_from_tags() {
+        let fields = vec![FieldDescription { name: "n".to_string(), type_oid: INT8_OID }];
+        let rows = parse_row(&fields, vec![Some("7".to_string())]).unwrap();
+        assert_eq!(completed("SELECT 1".to_string(), fields, vec![rows]), ExecutionResult::Rows {
+            columns: vec!["n".to_string()],
+            rows: vec![vec![Value::Int(7)]],
+        });
+        assert_eq!(
+            completed("INSERT 0 2".to_string(), Vec::new(), Vec::new()),
+            ExecutionResult::Changed { message: "INSERT 0 2".to_string(), rows: 2 }
+        );
+        assert_eq!(
+            completed("CREATE TABLE".to_string(), Vec::new(), Vec::new()),
+            ExecutionResult::Success("CREATE TABLE".to_string())
+        );
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn test_query_over_pgwire() {
+        use crate::storage::Database;
+        use std::net::TcpListener;
+        use std::sync::{Arc, Mutex};
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let db = Arc::new(Mutex::new(Database::new()));
+        std::thread::spawn(move || crate::pgwire::serve(listener, db));
+
+        let mut client = Client::connect(addr).unwrap();
+        let err = client.query("SELECT * FROM missing").unwrap_err();
+        assert_eq!(err, DbError::Plan("Table 'missing' does not exist".to_string()));
+        assert_eq!(client.query("").unwrap(), ExecutionResult::Success(String::new()));
+    }
+}
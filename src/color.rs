@@ -0,0 +1,14 @@
+// Color - ANSI styling for terminal output
+
+pub const RED: &str = "31";
+pub const GREEN: &str = "32";
+pub const BOLD: &str = "1";
+
+/// Wrap text in an ANSI style when color is enabled
+pub fn paint(text: &str, style: &str, enabled: bool) -> String {
+    if enabled {
+        format!("\x1b[{}m{}\x1b[0m", style, text)
+    } else {
+        text.to_string()
+    }
+}
@@ -0,0 +1,150 @@
+// Completion - tab completion of keywords, tables, and columns in the REPL
+
+use crate::parser::lexer::KEYWORDS;
+use crate::repl::META_COMMANDS;
+use crate::storage::Database;
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+
+/// Scalar and aggregate functions offered alongside keywords
+const FUNCTIONS: &[&str] = &["COUNT", "SUM", "AVG", "MIN", "MAX", "LOWER", "UPPER", "LENGTH", "RANDOM", "UUID", "NOW", "CARDINALITY"];
+
+/// Keywords after which a table name is expected
+const TABLE_CONTEXT: &[&str] = &["FROM", "INTO", "UPDATE", "TABLE", "ON", "ANALYZE"];
+
+/// Line editor helper that completes SQL from the current schema
+#[derive(Default)]
+pub struct SqlHelper {
+    tables: Vec<(String, Vec<String>)>, // Table names with their column names
+    schema_version: Option<u64>,
+}
+
+impl SqlHelper {
+    /// Pick up tables and columns created since the last refresh
+    pub fn refresh(&mut self, db: &Database) {
+        if self.schema_version == Some(db.schema_version()) {
+            return;
+        }
+        self.tables = db.list_tables().into_iter()
+            .filter_map(|name| {
+                let columns = db.get_table(&name).ok()?
+                    .columns.iter()
+                    .map(|c| c.name.clone())
+                    .collect();
+                Some((name, columns))
+            })
+            .collect();
+        self.schema_version = Some(db.schema_version());
+    }
+
+    /// Where the word ending at `pos` starts, and the candidates for it
+    pub fn candidates<'a>(&self, line: &'a str, pos: usize) -> (usize, Vec<String>) {
+        let before = &line[..pos];
+        let start = before.char_indices()
+            .rev()
+            .find(|&(_, c)| !(c.is_alphanumeric() || c == '_' || c == '.'))
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        let word = &before[start..];
+        let words = |text: &'a str| {
+            text.split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.'))
+                .filter(|w| !w.is_empty())
+        };
+        let previous: Vec<&str> = words(&before[..start]).collect();
+
+        let mut candidates: Vec<String> = if before.trim_start().starts_with('.') {
+            match previous.as_slice() {
+                [] => META_COMMANDS.iter().map(|c| c.to_string()).collect(),
+                [".indexes"] => self.table_names(),
+                _ => Vec::new(),
+            }
+        } else if previous.last().is_some_and(|w| TABLE_CONTEXT.contains(&w.to_uppercase().as_str())) {
+            self.table_names()
+        } else {
+            // Columns of the tables the statement mentions (of every table
+            // if it names none yet), then keywords
+            let named: Vec<&str> = words(line).filter(|w| self.tables.iter().any(|(t, _)| t == w)).collect();
+            let mut names: Vec<String> = self.tables.iter()
+                .filter(|(table, _)| named.is_empty() || named.contains(&table.as_str()))
+                .flat_map(|(_, columns)| columns.iter().cloned())
+                .collect();
+            let lowercase = !word.is_empty() && !word.chars().any(|c| c.is_uppercase());
+            names.extend(KEYWORDS.iter().chain(FUNCTIONS).map(|k| {
+                if lowercase { k.to_lowercase() } else { k.to_string() }
+            }));
+            names
+        };
+
+        let prefix = word.to_lowercase();
+        candidates.retain(|c| c.to_lowercase().starts_with(&prefix));
+        candidates.sort_by_key(|c| c.to_lowercase());
+        candidates.dedup();
+        (start, candidates)
+    }
+
+    fn table_names(&self) -> Vec<String> {
+        self.tables.iter().map(|(name, _)| name.clone()).collect()
+    }
+}
+
+impl Completer for SqlHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(self.candidates(line, pos))
+    }
+}
+
+impl Hinter for SqlHelper {
+    type Hint = String;
+}
+
+impl Highlighter for SqlHelper {}
+
+impl Validator for SqlHelper {}
+
+impl Helper for SqlHelper {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::lexer::{Lexer, Token};
+
+    #[test]
+    fn test_candidates_follow_context() {
+        let helper = SqlHelper {
+            tables: vec![
+                ("users".to_string(), vec!["id".to_string(), "name".to_string()]),
+                ("orders".to_string(), vec!["total".to_string()]),
+            ],
+            schema_version: Some(0),
+        };
+
+        let complete = |line: &str| helper.candidates(line, line.len());
+        assert_eq!(complete("SELECT * FROM u"), (14, vec!["users".to_string()]));
+        assert_eq!(complete("sel"), (0, vec!["select".to_string()]));
+        assert_eq!(complete("SELECT n"), (7, vec!["name".to_string(), "not".to_string(), "now".to_string(), "null".to_string()]));
+        assert_eq!(helper.candidates("SELECT to FROM orders", 9), (7, vec!["total".to_string()]));
+        assert_eq!(complete("SELECT name FROM users WHERE I").1, vec![
+            "id".to_string(),
+            "INDEX".to_string(),
+            "INSERT".to_string(),
+            "INT".to_string(),
+            "INTERSECT".to_string(),
+            "INTO".to_string(),
+            "IS".to_string(),
+        ]);
+        assert_eq!(complete(".tim"), (0, vec![".timeout".to_string(), ".timer".to_string()]));
+        assert_eq!(complete(".indexes o"), (9, vec!["orders".to_string()]));
+    }
+
+    #[test]
+    fn test_keywords_match_lexer() {
+        for keyword in KEYWORDS {
+            let token = Lexer::new(keyword).tokenize().unwrap().remove(0);
+            assert!(!matches!(token, Token::Identifier(_)), "{} is not a keyword", keyword);
+        }
+    }
+}
@@ -0,0 +1,79 @@
+// Error - the error type shared by every layer of the database
+
+/// Why a statement failed. Every variant displays as a message ready to show
+/// the user, so callers that only report errors can use `to_string()`.
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum DbError {
+    /// SQL that could not be tokenized or parsed
+    #[error("{0}")]
+    Parse(String),
+    /// A statement that parsed but cannot be planned, e.g. a predicate
+    /// comparing incompatible types
+    #[error("{0}")]
+    Plan(String),
+    #[error("Table '{0}' does not exist")]
+    TableNotFound(String),
+    #[error("Table '{0}' already exists")]
+    TableExists(String),
+    #[error("Column '{0}' does not exist")]
+    ColumnNotFound(String),
+    /// A value of the wrong type for its column, operator, or function
+    #[error("{0}")]
+    Type(String),
+    /// A row rejected by a PRIMARY KEY or UNIQUE constraint
+    #[error("{0}")]
+    ConstraintViolation(String),
+    /// Reading or writing table, spill, or import files failed
+    #[error("{0}")]
+    Io(String),
+    /// A failure while running a statement, such as division by zero
+    #[error("{0}")]
+    Execution(String),
+    #[error("Query cancelled")]
+    Cancelled,
+    #[error("Statement timeout of {0} ms exceeded")]
+    Timeout(u128),
+    #[error("Query exceeded its memory limit of {0} bytes")]
+    MemoryLimit(usize),
+}
+
+impl DbError {
+    /// Prefix the message with where the error happened, e.g. "Row 3", keeping
+    /// its kind. Errors naming a table or column, and cancellations, are
+    /// complete on their own and are returned unchanged.
+    pub fn context(self, prefix: impl std::fmt::Display) -> Self {
+        let wrap = |message: String| format!("{}: {}", prefix, message);
+        match self {
+            DbError::Parse(m) => DbError::Parse(wrap(m)),
+            DbError::Plan(m) => DbError::Plan(wrap(m)),
+            DbError::Type(m) => DbError::Type(wrap(m)),
+            DbError::ConstraintViolation(m) => DbError::ConstraintViolation(wrap(m)),
+            DbError::Io(m) => DbError::Io(wrap(m)),
+            DbError::Execution(m) => DbError::Execution(wrap(m)),
+            other => other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+    use crate::planner;
+    use crate::storage::Database;
+
+    #[test]
+    fn test_errors_keep_their_kind() {
+        let db = Database::new();
+        assert!(matches!(parser::parse("SELECT FROM"), Err(DbError::Parse(_))));
+
+        let statement = parser::parse("SELECT * FROM missing").unwrap();
+        let err = planner::plan(statement, &db).unwrap_err();
+        assert_eq!(err, DbError::TableNotFound("missing".to_string()));
+        assert_eq!(err.to_string(), "Table 'missing' does not exist");
+
+        let err = DbError::Type("expected INT".to_string()).context("Row 2");
+        assert_eq!(err, DbError::Type("Row 2: expected INT".to_string()));
+        assert_eq!(DbError::Cancelled.context("Row 2"), DbError::Cancelled);
+    }
+}
@@ -0,0 +1,1038 @@
+// Expression evaluation - computes scalar expressions against rows
+
+use crate::error::DbError;
+use crate::parser::array;
+use crate::parser::{
+    Aggregate, AggregateFunction, BinaryOp, Column, DataType, Expr, Operator, Value, WhereClause,
+};
+use crate::storage::audit;
+use crate::storage::btree::OrderedFloat;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, RwLock};
+
+/// Scalar functions implemented by the evaluator itself
+const BUILTIN_FUNCTIONS: &[&str] = &["LOWER", "UPPER", "LENGTH", "RANDOM", "UUID", "NOW", "CARDINALITY"];
+
+/// Built-in functions that return a new value on every call
+const VOLATILE_FUNCTIONS: &[&str] = &["RANDOM", "UUID", "NOW"];
+
+/// A scalar function supplied by the embedding program
+pub type ScalarFunction = Arc<dyn Fn(&[Value]) -> Result<Value, DbError> + Send + Sync>;
+
+/// Functions registered with `register_global_function`, by uppercase name,
+/// with their argument count
+static USER_FUNCTIONS: LazyLock<RwLock<HashMap<String, (usize, ScalarFunction)>>> =
+    LazyLock::new(Default::default);
+
+/// Make a Rust function callable from SQL in every database of the process,
+/// e.g. `eval::register_global_function("slugify", 1, |args| ...)`. It
+/// receives its arguments already evaluated and is called once per row,
+/// including for NULL arguments. Registering a name again replaces the
+/// function.
+pub fn register_global_function<F>(name: &str, arity: usize, function: F) -> Result<(), DbError>
+where
+    F: Fn(&[Value]) -> Result<Value, DbError> + Send + Sync + 'static,
+{
+    let name = name.to_uppercase();
+    if is_builtin(&name) {
+        return Err(DbError::Plan(format!("Cannot replace built-in function {}", name)));
+    }
+    USER_FUNCTIONS.write()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(name, (arity, Arc::new(function)));
+    Ok(())
+}
+
+/// Whether a function is built in, rather than registered by the embedder
+pub fn is_builtin(name: &str) -> bool {
+    BUILTIN_FUNCTIONS.contains(&name)
+}
+
+/// Whether an expression calls a function that may return a new value on
+/// every call, so it may not be folded to a constant, indexed, or cached.
+/// Registered functions may not be pure, so they count as volatile, as do
+/// session variables, which may be set to something else.
+pub fn is_volatile(expr: &Expr) -> bool {
+    match expr {
+        Expr::Function { name, args } => {
+            VOLATILE_FUNCTIONS.contains(&name.as_str()) || !is_builtin(name) || args.iter().any(is_volatile)
+        }
+        Expr::Binary { left, right, .. } => is_volatile(left) || is_volatile(right),
+        Expr::Array(elements) | Expr::Row(elements) => elements.iter().any(is_volatile),
+        Expr::Subscript { array, index } => is_volatile(array) || is_volatile(index),
+        Expr::Column(_) | Expr::Literal(_) => false,
+        Expr::Variable { .. } => true,
+    }
+}
+
+/// Give the session variables an expression refers to their current values.
+/// Variables that were never set are left without one, and evaluating them
+/// is an error.
+pub fn bind_variables(expr: &mut Expr, variables: &HashMap<String, Value>) {
+    match expr {
+        Expr::Variable { name, value } => *value = variables.get(name).cloned(),
+        Expr::Function { args: elements, .. } | Expr::Array(elements) | Expr::Row(elements) => {
+            elements.iter_mut().for_each(|element| bind_variables(element, variables));
+        }
+        Expr::Binary { left, right, .. } | Expr::Subscript { array: left, index: right } => {
+            bind_variables(left, variables);
+            bind_variables(right, variables);
+        }
+        Expr::Column(_) | Expr::Literal(_) => {}
+    }
+}
+
+/// The value of a literal or of a bound variable
+pub fn known_value(expr: &Expr) -> Option<&Value> {
+    match expr {
+        Expr::Literal(value) | Expr::Variable { value: Some(value), .. } => Some(value),
+        _ => None,
+    }
+}
+
+/// The value of a session variable, an error if it was never set
+fn variable_value(name: &str, value: &Option<Value>) -> Result<Value, DbError> {
+    value.clone().ok_or_else(|| {
+        DbError::Plan(format!("Unknown variable @{}; set it with SET @{} = value", name, name))
+    })
+}
+
+/// Look up a registered function by its uppercase name
+fn user_function(name: &str) -> Option<(usize, ScalarFunction)> {
+    USER_FUNCTIONS.read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(name)
+        .cloned()
+}
+
+/// Evaluate an expression, resolving column references through `column_value`
+pub fn evaluate<F>(expr: &Expr, column_value: &F) -> Result<Value, DbError>
+where
+    F: Fn(&str) -> Result<Value, DbError>,
+{
+    match expr {
+        Expr::Column(name) => column_value(name),
+        Expr::Literal(value) => Ok(value.clone()),
+        Expr::Variable { name, value } => variable_value(name, value),
+        Expr::Function { name, args } => {
+            let args = args.iter()
+                .map(|arg| evaluate(arg, column_value))
+                .collect::<Result<Vec<_>, _>>()?;
+            call_function(name, args)
+        }
+        Expr::Binary { left, op, right } => {
+            let left = evaluate(left, column_value)?;
+            let right = evaluate(right, column_value)?;
+            apply_binary(&left, *op, &right)
+        }
+        Expr::Array(elements) => {
+            let elements = elements.iter()
+                .map(|element| evaluate(element, column_value))
+                .collect::<Result<Vec<_>, _>>()?;
+            build_array(elements)
+        }
+        Expr::Subscript { array, index } => {
+            let array = evaluate(array, column_value)?;
+            let index = evaluate(index, column_value)?;
+            subscript(&array, &index)
+        }
+        // Elements may differ in type, unlike an array's
+        Expr::Row(elements) => elements.iter()
+            .map(|element| evaluate(element, column_value))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Value::Array),
+    }
+}
+
+/// An array of evaluated elements. INT elements become FLOAT when there is
+/// a FLOAT among them; other mixes of types are errors.
+fn build_array(mut elements: Vec<Value>) -> Result<Value, DbError> {
+    let mut element_type = None;
+    for element in &elements {
+        element_type = match (element_type, value_type(element)) {
+            (_, Some(DataType::Array(_))) => {
+                return Err(DbError::Type("Arrays of arrays are not supported".to_string()));
+            }
+            (known, None) => known,
+            (None, found) => found,
+            (Some(known), Some(found)) => Some(common_type(&known, &found).ok_or_else(|| {
+                DbError::Type(format!("Array elements must have one type, got {} and {}", known, found))
+            })?),
+        };
+    }
+    if element_type == Some(DataType::Float) {
+        elements = elements.into_iter().map(|e| coerce_value(e, &DataType::Float)).collect();
+    }
+    Ok(Value::Array(elements))
+}
+
+/// The type two element types share: their own, or FLOAT for INT and FLOAT
+fn common_type(a: &DataType, b: &DataType) -> Option<DataType> {
+    match (a, b) {
+        _ if a == b => Some(a.clone()),
+        (DataType::Int | DataType::Float, DataType::Int | DataType::Float) => Some(DataType::Float),
+        _ => None,
+    }
+}
+
+/// An array's element at a 1-based index, or NULL when there is none
+fn subscript(array: &Value, index: &Value) -> Result<Value, DbError> {
+    match (array, index) {
+        (Value::Null, _) | (_, Value::Null) => Ok(Value::Null),
+        (Value::Array(elements), Value::Int(i)) => Ok(usize::try_from(*i - 1)
+            .ok()
+            .and_then(|i| elements.get(i))
+            .cloned()
+            .unwrap_or(Value::Null)),
+        _ => Err(DbError::Type(format!(
+            "Cannot subscript {} with {}",
+            describe_value(array), describe_value(index)
+        ))),
+    }
+}
+
+/// Evaluate an expression that references no columns
+pub fn evaluate_constant(expr: &Expr) -> Result<Value, DbError> {
+    evaluate(expr, &|name| Err(DbError::Plan(format!("Column '{}' is not allowed here", name))))
+}
+
+/// Apply an arithmetic operator. INT with INT stays INT; mixing in a FLOAT
+/// gives FLOAT; any NULL operand gives NULL.
+fn apply_binary(left: &Value, op: BinaryOp, right: &Value) -> Result<Value, DbError> {
+    match (left, right) {
+        (Value::Null, _) | (_, Value::Null) => Ok(Value::Null),
+        (Value::Int(a), Value::Int(b)) => {
+            let result = match op {
+                BinaryOp::Add => a.checked_add(*b),
+                BinaryOp::Subtract => a.checked_sub(*b),
+                BinaryOp::Multiply => a.checked_mul(*b),
+                BinaryOp::Divide => {
+                    if *b == 0 {
+                        return Err(DbError::Execution("Division by zero".to_string()));
+                    }
+                    a.checked_div(*b)
+                }
+            };
+            result
+                .map(Value::Int)
+                .ok_or_else(|| DbError::Execution(format!("Integer overflow in {} {} {}", a, op, b)))
+        }
+        (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => {
+            let a = as_float(left);
+            let b = as_float(right);
+            let result = match op {
+                BinaryOp::Add => a + b,
+                BinaryOp::Subtract => a - b,
+                BinaryOp::Multiply => a * b,
+                BinaryOp::Divide => {
+                    if b == 0.0 {
+                        return Err(DbError::Execution("Division by zero".to_string()));
+                    }
+                    a / b
+                }
+            };
+            if !result.is_finite() {
+                return Err(DbError::Execution(format!("FLOAT overflow in {} {} {}", a, op, b)));
+            }
+            Ok(Value::Float(result))
+        }
+        _ => Err(DbError::Type(format!("Cannot apply '{}' to {:?} and {:?}", op, left, right))),
+    }
+}
+
+/// Widen a numeric value to f64
+fn as_float(value: &Value) -> f64 {
+    match value {
+        Value::Int(n) => *n as f64,
+        Value::Float(f) => *f,
+        _ => f64::NAN,
+    }
+}
+
+/// Evaluate an expression against a table row
+pub fn evaluate_row(expr: &Expr, columns: &[Column], row: &[Value]) -> Result<Value, DbError> {
+    evaluate(expr, &|name| {
+        columns.iter()
+            .position(|c| c.name == name)
+            .and_then(|i| row.get(i).cloned())
+            .ok_or_else(|| DbError::ColumnNotFound(name.to_string()))
+    })
+}
+
+/// Evaluate an expression over a batch of `len` rows stored column by column,
+/// producing one value per row. Column references borrow the batch's vector.
+pub fn evaluate_batch<'b>(
+    expr: &Expr,
+    columns: &[Column],
+    batch: &'b [Vec<Value>],
+    len: usize,
+) -> Result<Cow<'b, [Value]>, DbError> {
+    match expr {
+        Expr::Column(name) => columns.iter()
+            .position(|c| &c.name == name)
+            .and_then(|i| batch.get(i))
+            .map(|column| Cow::Borrowed(column.as_slice()))
+            .ok_or_else(|| DbError::ColumnNotFound(name.to_string())),
+        Expr::Literal(value) => Ok(Cow::Owned(vec![value.clone(); len])),
+        Expr::Variable { name, value } => Ok(Cow::Owned(vec![variable_value(name, value)?; len])),
+        Expr::Function { name, args } => {
+            let args = args.iter()
+                .map(|arg| evaluate_batch(arg, columns, batch, len))
+                .collect::<Result<Vec<_>, _>>()?;
+            (0..len)
+                .map(|row| call_function(name, args.iter().map(|arg| arg[row].clone()).collect()))
+                .collect::<Result<Vec<_>, _>>()
+                .map(Cow::Owned)
+        }
+        Expr::Binary { left, op, right } => {
+            let left = evaluate_batch(left, columns, batch, len)?;
+            let right = evaluate_batch(right, columns, batch, len)?;
+            left.iter()
+                .zip(right.iter())
+                .map(|(a, b)| apply_binary(a, *op, b))
+                .collect::<Result<Vec<_>, _>>()
+                .map(Cow::Owned)
+        }
+        Expr::Array(elements) => {
+            let elements = elements.iter()
+                .map(|element| evaluate_batch(element, columns, batch, len))
+                .collect::<Result<Vec<_>, _>>()?;
+            (0..len)
+                .map(|row| build_array(elements.iter().map(|element| element[row].clone()).collect()))
+                .collect::<Result<Vec<_>, _>>()
+                .map(Cow::Owned)
+        }
+        Expr::Subscript { array, index } => {
+            let arrays = evaluate_batch(array, columns, batch, len)?;
+            let indexes = evaluate_batch(index, columns, batch, len)?;
+            arrays.iter()
+                .zip(indexes.iter())
+                .map(|(array, index)| subscript(array, index))
+                .collect::<Result<Vec<_>, _>>()
+                .map(Cow::Owned)
+        }
+        Expr::Row(elements) => {
+            let elements = elements.iter()
+                .map(|element| evaluate_batch(element, columns, batch, len))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Cow::Owned((0..len)
+                .map(|row| Value::Array(elements.iter().map(|element| element[row].clone()).collect()))
+                .collect()))
+        }
+    }
+}
+
+/// Determine the result type of an expression, checking argument types
+pub fn infer_type(expr: &Expr, columns: &[Column]) -> Result<Option<DataType>, DbError> {
+    match expr {
+        Expr::Column(name) => columns.iter()
+            .find(|c| &c.name == name)
+            .map(|c| Some(c.data_type.clone()))
+            .ok_or_else(|| DbError::ColumnNotFound(name.to_string())),
+        Expr::Literal(value) => Ok(value_type(value)),
+        // Known only once the statement runs
+        Expr::Variable { value, .. } => Ok(value.as_ref().and_then(value_type)),
+        Expr::Function { name, args } => {
+            let arg_types = args.iter()
+                .map(|arg| infer_type(arg, columns))
+                .collect::<Result<Vec<_>, _>>()?;
+            function_type(name, &arg_types)
+        }
+        Expr::Binary { left, op, right } => {
+            let left = infer_type(left, columns)?;
+            let right = infer_type(right, columns)?;
+            match (left, right) {
+                (Some(DataType::Text), _) | (_, Some(DataType::Text)) => {
+                    Err(DbError::Type(format!("Cannot apply '{}' to TEXT", op)))
+                }
+                (Some(DataType::Array(_)), _) | (_, Some(DataType::Array(_))) => {
+                    Err(DbError::Type(format!("Cannot apply '{}' to an array", op)))
+                }
+                (Some(DataType::Float), _) | (_, Some(DataType::Float)) => Ok(Some(DataType::Float)),
+                (Some(DataType::Int), _) | (_, Some(DataType::Int)) => Ok(Some(DataType::Int)),
+                (None, None) => Ok(None),
+            }
+        }
+        Expr::Array(elements) => {
+            let mut element_type: Option<DataType> = None;
+            for element in elements {
+                element_type = match (element_type, infer_type(element, columns)?) {
+                    (_, Some(DataType::Array(_))) => {
+                        return Err(DbError::Type("Arrays of arrays are not supported".to_string()));
+                    }
+                    (known, None) => known,
+                    (None, found) => found,
+                    (Some(known), Some(found)) => Some(common_type(&known, &found).ok_or_else(|| {
+                        DbError::Type(format!("Array elements must have one type, got {} and {}", known, found))
+                    })?),
+                };
+            }
+            Ok(element_type.map(|t| DataType::Array(Box::new(t))))
+        }
+        Expr::Subscript { array, index } => {
+            if let Some(index_type) = infer_type(index, columns)? && index_type != DataType::Int {
+                return Err(DbError::Type(format!("Array subscripts must be INT, got {}", index_type)));
+            }
+            match infer_type(array, columns)? {
+                Some(DataType::Array(element)) => Ok(Some(*element)),
+                None => Ok(None),
+                Some(other) => Err(DbError::Type(format!("Cannot subscript {} '{}'", other, array))),
+            }
+        }
+        Expr::Row(_) => Err(DbError::Type(format!("Row value '{}' can only be compared in a WHERE clause", expr))),
+    }
+}
+
+/// Whether a computed value of one type can be stored as another, under the
+/// casts of `check_value_type`: INT widens to FLOAT, and INT[] to FLOAT[]
+pub fn widens_to(from: &DataType, to: &DataType) -> bool {
+    match (from, to) {
+        (DataType::Int, DataType::Float) => true,
+        (DataType::Array(from), DataType::Array(to)) => widens_to(from, to),
+        _ => from == to,
+    }
+}
+
+/// Check that a comparison's operands have types it can compare. TEXT only
+/// compares with TEXT, `= ANY` needs an array on the right, and `@>` arrays
+/// on both sides. Unknown (None) types are accepted.
+pub fn check_comparison(left: Option<&DataType>, operator: &Operator, right: Option<&DataType>) -> Result<(), DbError> {
+    let (left, right) = match (operator, left, right) {
+        (Operator::Any, _, Some(DataType::Array(element))) => (left, Some(element.as_ref())),
+        (Operator::Contains, Some(DataType::Array(a)), Some(DataType::Array(b))) => (Some(a.as_ref()), Some(b.as_ref())),
+        (Operator::Any | Operator::Contains, _, _) => {
+            let expected = if *operator == Operator::Any { "= ANY expects an array on the right" } else { "@> expects arrays" };
+            let describe = |t: Option<&DataType>| t.map_or("NULL".to_string(), |t| t.to_string());
+            return Err(DbError::Type(format!("{}, got {} and {}", expected, describe(left), describe(right))));
+        }
+        _ => (left, right),
+    };
+    match (left, right) {
+        (Some(DataType::Text), Some(other)) | (Some(other), Some(DataType::Text)) if *other != DataType::Text => {
+            Err(DbError::Type(format!("Cannot compare TEXT with {:?}", other)))
+        }
+        (Some(a), Some(b)) if common_type(a, b).is_none() => {
+            Err(DbError::Type(format!("Cannot compare {} with {}", a, b)))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Check a comparison of row values, e.g. `(a, b) = (1, 'x')` or
+/// `(a, b) IN ((1, 'x'), (2, 'y'))`: every row must have as many elements
+/// as the left one, each comparable with the element in its place
+pub fn check_row_comparison(predicate: &WhereClause, columns: &[Column]) -> Result<(), DbError> {
+    let Expr::Row(left) = &predicate.left else {
+        return Err(DbError::Type(format!("Cannot compare '{}' with a row value", predicate.left)));
+    };
+    let rows = match (&predicate.operator, &predicate.right) {
+        (Operator::In, Expr::Row(items)) => items.iter().collect(),
+        (
+            Operator::Equals | Operator::NotEquals | Operator::GreaterThan | Operator::LessThan
+            | Operator::GreaterOrEqual | Operator::LessOrEqual,
+            right,
+        ) => vec![right],
+        (operator, _) => return Err(DbError::Type(format!("Cannot use '{}' with row values", operator))),
+    };
+    let left_types = left.iter()
+        .map(|element| infer_type(element, columns))
+        .collect::<Result<Vec<_>, _>>()?;
+    for row in rows {
+        let Expr::Row(elements) = row else {
+            return Err(DbError::Type(format!("Cannot compare a row value with '{}'", row)));
+        };
+        if elements.len() != left.len() {
+            return Err(DbError::Type(format!(
+                "Cannot compare a row of {} values with a row of {}",
+                left.len(), elements.len()
+            )));
+        }
+        for (left_type, element) in left_types.iter().zip(elements) {
+            check_comparison(left_type.as_ref(), &Operator::Equals, infer_type(element, columns)?.as_ref())?;
+        }
+    }
+    Ok(())
+}
+
+/// Determine the result type of an aggregate, checking its argument type
+pub fn aggregate_type(aggregate: &Aggregate, columns: &[Column]) -> Result<DataType, DbError> {
+    let Some(name) = &aggregate.column else {
+        return Ok(DataType::Int); // COUNT(*)
+    };
+    let column_type = columns.iter()
+        .find(|c| &c.name == name)
+        .map(|c| c.data_type.clone())
+        .ok_or_else(|| DbError::ColumnNotFound(name.to_string()))?;
+
+    match (aggregate.function, column_type) {
+        (AggregateFunction::Count, _) => Ok(DataType::Int),
+        (AggregateFunction::Sum | AggregateFunction::Avg, column_type @ (DataType::Text | DataType::Array(_))) => {
+            Err(DbError::Type(format!(
+                "{} expects a numeric column, got {} column '{}'",
+                aggregate.function, column_type, name
+            )))
+        }
+        (AggregateFunction::Avg, _) => Ok(DataType::Float),
+        (_, column_type) => Ok(column_type),
+    }
+}
+
+/// Check that a value can be stored in a column. The implicit casts are the
+/// same for INSERT, UPDATE, and imports: NULL fits any column, INT widens to
+/// FLOAT (also as an array element), and TEXT in array form such as '{1,2}'
+/// fills an array column. Nothing else converts, so TEXT never becomes a
+/// number.
+pub fn check_value_type(value: &Value, column: &Column) -> Result<(), DbError> {
+    match (value, &column.data_type) {
+        (Value::Int(_), DataType::Int) => Ok(()),
+        (Value::Text(_), DataType::Text) => Ok(()),
+        (Value::Float(_) | Value::Int(_), DataType::Float) => Ok(()),
+        (Value::Null, _) => Ok(()),
+        (Value::Text(s), DataType::Array(_)) => array::parse_array(s, &column.data_type)
+            .map(|_| ())
+            .map_err(|e| DbError::Type(format!("Column '{}': {}", column.name, e))),
+        (Value::Array(values), DataType::Array(element)) => {
+            let element = Column::new(format!("{}[]", column.name), element.as_ref().clone());
+            values.iter().try_for_each(|value| check_value_type(value, &element)).map_err(|_| {
+                DbError::Type(format!(
+                    "Type mismatch for column '{}': expected {}, got {}",
+                    column.name, column.data_type, describe_value(value)
+                ))
+            })
+        }
+        _ => Err(DbError::Type(format!(
+            "Type mismatch for column '{}': expected {}, got {}",
+            column.name, column.data_type, describe_value(value)
+        ))),
+    }
+}
+
+/// Convert a value for storing in a column of a type: INT values become
+/// FLOAT in FLOAT columns, and array text becomes an array
+pub fn coerce_value(value: Value, data_type: &DataType) -> Value {
+    match (value, data_type) {
+        (Value::Int(n), DataType::Float) => Value::Float(n as f64),
+        (Value::Text(s), DataType::Array(_)) => match array::parse_array(&s, data_type) {
+            Ok(values) => Value::Array(values),
+            Err(_) => Value::Text(s),
+        },
+        (Value::Array(values), DataType::Array(element)) => {
+            Value::Array(values.into_iter().map(|v| coerce_value(v, element)).collect())
+        }
+        (value, _) => value,
+    }
+}
+
+/// Convert an imported text field for storing in a column. Numeric columns
+/// read the field as a number literal and then apply the same casts as
+/// INSERT, so '1' fills a FLOAT column and '1.5' or 'abc' fails an INT one.
+pub fn value_from_text(text: &str, column: &Column) -> Result<Value, DbError> {
+    if column.data_type == DataType::Text {
+        return Ok(Value::Text(text.to_string()));
+    }
+    if let DataType::Array(_) = column.data_type {
+        return array::parse_array(text, &column.data_type)
+            .map(Value::Array)
+            .map_err(|e| DbError::Type(format!("Column '{}': {}", column.name, e)));
+    }
+    let trimmed = text.trim();
+    let value = match (trimmed.parse::<i64>(), trimmed.parse::<f64>()) {
+        (Ok(n), _) => Value::Int(n),
+        (_, Ok(f)) => Value::Float(f),
+        _ => Value::Text(text.to_string()),
+    };
+    check_value_type(&value, column)?;
+    Ok(coerce_value(value, &column.data_type))
+}
+
+/// A value with its type, for error messages
+fn describe_value(value: &Value) -> String {
+    match value {
+        Value::Int(n) => format!("INT {}", n),
+        Value::Float(f) => format!("FLOAT {}", f),
+        Value::Text(s) => format!("TEXT '{}'", s),
+        Value::Array(values) => format!("array '{}'", array::format_array(values)),
+        Value::Null => "NULL".to_string(),
+    }
+}
+
+/// Make a literal compared with a FLOAT expression a FLOAT too, so index
+/// lookups and statistics find the column's values. The same goes for the
+/// elements of an array compared with a FLOAT or FLOAT[] expression.
+pub fn coerce_filter(mut predicate: WhereClause, columns: &[Column]) -> WhereClause {
+    let float_array = DataType::Array(Box::new(DataType::Float));
+    let float_type = |expr: &Expr| match infer_type(expr, columns) {
+        Ok(Some(DataType::Float)) if predicate.operator == Operator::Any => Some(float_array.clone()),
+        Ok(Some(data_type @ DataType::Float)) => Some(data_type),
+        Ok(Some(data_type)) if data_type == float_array => Some(data_type),
+        _ => None,
+    };
+    if let Some(data_type) = float_type(&predicate.left) {
+        coerce_literal(&mut predicate.right, &data_type);
+    }
+    if let Some(data_type) = float_type(&predicate.right) {
+        coerce_literal(&mut predicate.left, &data_type);
+    }
+    predicate
+}
+
+/// Coerce a literal, or the literal elements of an array constructor
+fn coerce_literal(expr: &mut Expr, data_type: &DataType) {
+    match (expr, data_type) {
+        (Expr::Literal(value), _) => *value = coerce_value(std::mem::replace(value, Value::Null), data_type),
+        (Expr::Array(elements), DataType::Array(element)) => {
+            elements.iter_mut().for_each(|e| coerce_literal(e, element));
+        }
+        _ => {}
+    }
+}
+
+/// Get the data type of a value (None for NULL)
+fn value_type(value: &Value) -> Option<DataType> {
+    match value {
+        Value::Int(_) => Some(DataType::Int),
+        Value::Text(_) => Some(DataType::Text),
+        Value::Float(_) => Some(DataType::Float),
+        // The type of the first element that is not NULL, if any
+        Value::Array(values) => values.iter()
+            .find_map(value_type)
+            .map(|element| DataType::Array(Box::new(element))),
+        Value::Null => None,
+    }
+}
+
+/// Check argument types of a function and return its result type, which is
+/// unknown (None) for registered functions
+fn function_type(name: &str, arg_types: &[Option<DataType>]) -> Result<Option<DataType>, DbError> {
+    let (params, result) = match name {
+        "LOWER" | "UPPER" => (1, DataType::Text),
+        "LENGTH" => (1, DataType::Int),
+        "UUID" | "NOW" => (0, DataType::Text),
+        "CARDINALITY" => {
+            check_arity(name, 1, arg_types.len())?;
+            if let Some(Some(arg_type)) = arg_types.first() && !matches!(arg_type, DataType::Array(_)) {
+                return Err(DbError::Type(format!("Function {} expects an array, got {}", name, arg_type)));
+            }
+            return Ok(Some(DataType::Int));
+        }
+        "RANDOM" => {
+            // RANDOM() or RANDOM(low, high)
+            if !matches!(arg_types.len(), 0 | 2) {
+                return Err(DbError::Type(format!(
+                    "Function {} expects 0 or 2 argument(s), got {}",
+                    name, arg_types.len()
+                )));
+            }
+            if let Some(arg_type) = arg_types.iter().flatten().find(|t| **t != DataType::Int) {
+                return Err(DbError::Type(format!("Function {} expects INT, got {:?}", name, arg_type)));
+            }
+            return Ok(Some(DataType::Int));
+        }
+        _ => match user_function(name) {
+            Some((params, _)) => {
+                check_arity(name, params, arg_types.len())?;
+                return Ok(None);
+            }
+            None => return Err(DbError::Plan(format!("Unknown function: {}", name))),
+        },
+    };
+
+    check_arity(name, params, arg_types.len())?;
+
+    for arg_type in arg_types.iter().flatten() {
+        if arg_type != &DataType::Text {
+            return Err(DbError::Type(format!("Function {} expects TEXT, got {:?}", name, arg_type)));
+        }
+    }
+
+    Ok(Some(result))
+}
+
+fn check_arity(name: &str, params: usize, args: usize) -> Result<(), DbError> {
+    if args != params {
+        return Err(DbError::Type(format!(
+            "Function {} expects {} argument(s), got {}",
+            name, params, args
+        )));
+    }
+    Ok(())
+}
+
+/// Call a built-in or registered scalar function
+fn call_function(name: &str, args: Vec<Value>) -> Result<Value, DbError> {
+    match (name, args.as_slice()) {
+        ("LOWER" | "UPPER" | "LENGTH" | "CARDINALITY", [Value::Null]) => Ok(Value::Null),
+        ("CARDINALITY", [Value::Array(values)]) => Ok(Value::Int(values.len() as i64)),
+        ("LOWER", [Value::Text(s)]) => Ok(Value::Text(s.to_lowercase())),
+        ("UPPER", [Value::Text(s)]) => Ok(Value::Text(s.to_uppercase())),
+        ("LENGTH", [Value::Text(s)]) => Ok(Value::Int(s.chars().count() as i64)),
+        ("RANDOM", []) => Ok(Value::Int(random_u64() as i64)),
+        ("RANDOM", [Value::Null, _] | [_, Value::Null]) => Ok(Value::Null),
+        ("RANDOM", [Value::Int(low), Value::Int(high)]) => random_between(*low, *high),
+        ("UUID", []) => Ok(Value::Text(uuid_v4())),
+        ("NOW", []) => Ok(Value::Text(audit::format_utc(std::time::SystemTime::now()))),
+        ("LOWER" | "UPPER" | "LENGTH" | "RANDOM" | "UUID" | "NOW" | "CARDINALITY", _) => {
+            Err(DbError::Type(format!("Invalid arguments to {}: {:?}", name, args)))
+        }
+        _ => match user_function(name) {
+            Some((params, function)) => {
+                check_arity(name, params, args.len())?;
+                function(&args).map_err(|e| e.context(format!("In function {}", name)))
+            }
+            None => Err(DbError::Plan(format!("Unknown function: {}", name))),
+        },
+    }
+}
+
+/// A uniformly distributed INT from `low` to `high` inclusive
+fn random_between(low: i64, high: i64) -> Result<Value, DbError> {
+    if low > high {
+        return Err(DbError::Execution(format!("RANDOM({}, {}): low bound is above high bound", low, high)));
+    }
+    let range = (high as i128 - low as i128 + 1) as u128;
+    Ok(Value::Int((low as i128 + (random_u64() as u128 % range) as i128) as i64))
+}
+
+/// A random (version 4) UUID in the usual hyphenated lowercase form
+fn uuid_v4() -> String {
+    let mut bytes = [0u8; 16];
+    bytes[..8].copy_from_slice(&random_u64().to_le_bytes());
+    bytes[8..].copy_from_slice(&random_u64().to_le_bytes());
+    bytes[6] = (bytes[6] & 0x0f) | 0x40; // Version 4
+    bytes[8] = (bytes[8] & 0x3f) | 0x80; // RFC 4122 variant
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+thread_local! {
+    /// State of this thread's random number generator
+    static RANDOM_STATE: std::cell::Cell<u64> = std::cell::Cell::new({
+        use std::hash::BuildHasher;
+        std::collections::hash_map::RandomState::new().hash_one(std::time::SystemTime::now())
+    });
+}
+
+/// The next number from a SplitMix64 generator, randomly seeded per thread.
+/// It is fast and well distributed, but not suitable for secrets.
+fn random_u64() -> u64 {
+    RANDOM_STATE.with(|state| {
+        let mut z = state.get().wrapping_add(0x9E37_79B9_7F4A_7C15);
+        state.set(z);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    })
+}
+
+/// `count` distinct positions below `len` chosen at random (all of them when
+/// `count` is at least `len`), in increasing order. Robert Floyd's
+/// algorithm draws only `count` numbers, however large `len` is.
+pub fn random_positions(len: usize, count: usize) -> Vec<usize> {
+    if count >= len {
+        return (0..len).collect();
+    }
+    let mut chosen = std::collections::BTreeSet::new();
+    for upper in len - count..len {
+        let position = (random_u64() % (upper as u64 + 1)) as usize;
+        if !chosen.insert(position) {
+            chosen.insert(upper);
+        }
+    }
+    chosen.into_iter().collect()
+}
+
+/// Collect the names of all columns referenced by an expression
+pub fn referenced_columns(expr: &Expr) -> Vec<&str> {
+    match expr {
+        Expr::Column(name) => vec![name.as_str()],
+        Expr::Literal(_) | Expr::Variable { .. } => Vec::new(),
+        Expr::Function { args, .. } => args.iter().flat_map(referenced_columns).collect(),
+        Expr::Binary { left, right, .. } => {
+            let mut columns = referenced_columns(left);
+            columns.extend(referenced_columns(right));
+            columns
+        }
+        Expr::Array(elements) => elements.iter().flat_map(referenced_columns).collect(),
+        Expr::Subscript { array, index } => {
+            let mut columns = referenced_columns(array);
+            columns.extend(referenced_columns(index));
+            columns
+        }
+        Expr::Row(elements) => elements.iter().flat_map(referenced_columns).collect(),
+    }
+}
+
+/// Check both sides of a WHERE clause against a table's columns
+pub fn check_where(where_clause: &WhereClause, columns: &[Column]) -> Result<(), DbError> {
+    if where_clause.compares_rows() {
+        return check_row_comparison(where_clause, columns);
+    }
+    infer_type(&where_clause.left, columns)?;
+    infer_type(&where_clause.right, columns)?;
+    Ok(())
+}
+
+/// Evaluate a WHERE clause against a row
+pub fn matches(where_clause: &WhereClause, columns: &[Column], row: &[Value]) -> Result<bool, DbError> {
+    let left = evaluate_row(&where_clause.left, columns, row)?;
+    let right = evaluate_row(&where_clause.right, columns, row)?;
+    Ok(compare_operands(&left, &where_clause.operator, &right, where_clause.compares_rows()))
+}
+
+/// Evaluate a WHERE clause over a batch, returning which rows match
+pub fn matches_batch(
+    where_clause: &WhereClause,
+    columns: &[Column],
+    batch: &[Vec<Value>],
+    len: usize,
+) -> Result<Vec<bool>, DbError> {
+    let left = evaluate_batch(&where_clause.left, columns, batch, len)?;
+    let operator = &where_clause.operator;
+    let rows = where_clause.compares_rows();
+
+    // The common `expr <op> constant` case compares without expanding the constant
+    if let Some(constant) = known_value(&where_clause.right) {
+        return Ok(left.iter().map(|value| compare_operands(value, operator, constant, rows)).collect());
+    }
+
+    let right = evaluate_batch(&where_clause.right, columns, batch, len)?;
+    Ok(left.iter()
+        .zip(right.iter())
+        .map(|(l, r)| compare_operands(l, operator, r, rows))
+        .collect())
+}
+
+/// Compare the values of a WHERE clause's operands, which are evaluated
+/// to arrays when the clause compares `rows`
+fn compare_operands(left: &Value, operator: &Operator, right: &Value, rows: bool) -> bool {
+    match (left, right) {
+        (Value::Array(row), Value::Array(items)) if rows && *operator == Operator::In => items.iter()
+            .any(|item| matches!(item, Value::Array(item) if compare_rows(row, &Operator::Equals, item))),
+        (Value::Array(left), Value::Array(right)) if rows => compare_rows(left, operator, right),
+        _ => compare_values(left, operator, right),
+    }
+}
+
+/// Compare row values as SQL does: equal when every pair of elements is
+/// equal, and otherwise ordered by the first pair that is not. A pair with
+/// a NULL there makes the comparison unknown, so it does not match.
+pub fn compare_rows(left: &[Value], operator: &Operator, right: &[Value]) -> bool {
+    if left.len() != right.len() {
+        return false;
+    }
+    let mut pairs = left.iter().zip(right);
+    if *operator == Operator::NotEquals {
+        return pairs.any(|(a, b)| compare_values(a, operator, b));
+    }
+    match pairs.find(|(a, b)| !compare_values(a, &Operator::Equals, b)) {
+        Some((a, b)) => compare_values(a, operator, b),
+        None => matches!(operator, Operator::Equals | Operator::GreaterOrEqual | Operator::LessOrEqual),
+    }
+}
+
+/// Compare two values using an operator. INT and FLOAT values compare
+/// numerically; other mixed types are unequal and unordered. As in SQL, a
+/// comparison with NULL is never true; only IS and IS NOT test for NULL.
+pub fn compare_values(left: &Value, operator: &Operator, right: &Value) -> bool {
+    let is_null = |value: &Value| *value == Value::Null;
+    if is_null(left) || is_null(right) {
+        return match operator {
+            Operator::Is => is_null(left) && is_null(right),
+            Operator::IsNot => is_null(left) != is_null(right),
+            _ => false,
+        };
+    }
+
+    match (operator, left, right) {
+        // An element equal to a NULL element is unknown, so never matches
+        (Operator::Any | Operator::In, _, Value::Array(elements)) => {
+            return elements.iter().any(|element| compare_values(left, &Operator::Equals, element));
+        }
+        (Operator::Contains, Value::Array(elements), Value::Array(wanted)) => {
+            return wanted.iter().all(|w| elements.iter().any(|e| compare_values(e, &Operator::Equals, w)));
+        }
+        _ => {}
+    }
+
+    let ordering = order_values(left, right);
+    match operator {
+        Operator::Equals | Operator::Is => ordering.map_or(left == right, |o| o == Ordering::Equal),
+        Operator::NotEquals | Operator::IsNot => ordering.map_or(left != right, |o| o != Ordering::Equal),
+        Operator::GreaterThan => ordering == Some(Ordering::Greater),
+        Operator::LessThan => ordering == Some(Ordering::Less),
+        Operator::GreaterOrEqual => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+        Operator::LessOrEqual => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+        // Without arrays where they belong
+        Operator::Any | Operator::Contains | Operator::In => false,
+    }
+}
+
+/// Order two values for ORDER BY: NULLs after every other value, and values
+/// of types that cannot be compared as equal
+pub fn sort_order(left: &Value, right: &Value) -> Ordering {
+    match (left, right) {
+        (Value::Null, Value::Null) => Ordering::Equal,
+        (Value::Null, _) => Ordering::Greater,
+        (_, Value::Null) => Ordering::Less,
+        _ => order_values(left, right).unwrap_or(Ordering::Equal),
+    }
+}
+
+/// Order two values of the same type, or an INT and a FLOAT. NaN sorts
+/// after every number, as it does in indexes.
+fn order_values(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+        (Value::Float(a), Value::Float(b)) => Some(OrderedFloat(*a).cmp(&OrderedFloat(*b))),
+        (Value::Int(a), Value::Float(b)) => Some(OrderedFloat(*a as f64).cmp(&OrderedFloat(*b))),
+        (Value::Float(a), Value::Int(b)) => Some(OrderedFloat(*a).cmp(&OrderedFloat(*b as f64))),
+        (Value::Text(a), Value::Text(b)) => Some(a.cmp(b)),
+        // Element by element, then a shorter array before a longer one
+        (Value::Array(a), Value::Array(b)) => Some(a.iter()
+            .zip(b)
+            .map(|(x, y)| sort_order(x, y))
+            .find(|o| o.is_ne())
+            .unwrap_or(a.len().cmp(&b.len()))),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registered_function() {
+        register_global_function("double_it", 1, |args| match args {
+            [Value::Int(n)] => Ok(Value::Int(n * 2)),
+            _ => Ok(Value::Null),
+        }).unwrap();
+
+        let call = |args: Vec<Expr>| Expr::Function { name: "DOUBLE_IT".to_string(), args };
+        assert_eq!(evaluate_constant(&call(vec![Expr::Literal(Value::Int(21))])), Ok(Value::Int(42)));
+        assert!(matches!(infer_type(&call(Vec::new()), &[]), Err(DbError::Type(_))));
+        assert!(register_global_function("lower", 1, |_| Ok(Value::Null)).is_err());
+    }
+    #[test]
+    fn test_mixed_numeric_comparison() {
+        assert!(compare_values(&Value::Float(10.5), &Operator::GreaterThan, &Value::Int(10)));
+        assert!(compare_values(&Value::Int(10), &Operator::Equals, &Value::Float(10.0)));
+        assert!(compare_values(&Value::Int(1), &Operator::NotEquals, &Value::Text("1".to_string())));
+        assert!(!compare_values(&Value::Null, &Operator::LessThan, &Value::Int(1)));
+
+        let price = Column::new("price".to_string(), DataType::Float);
+        assert!(check_value_type(&Value::Int(3), &price).is_ok());
+        assert_eq!(coerce_value(Value::Int(3), &price.data_type), Value::Float(3.0));
+        assert_eq!(value_from_text(" 3 ", &price), Ok(Value::Float(3.0)));
+
+        let id = Column::new("id".to_string(), DataType::Int);
+        assert_eq!(
+            check_value_type(&Value::Text("7".to_string()), &id),
+            Err(DbError::Type("Type mismatch for column 'id': expected INT, got TEXT '7'".to_string()))
+        );
+        assert!(matches!(value_from_text("1.5", &id), Err(DbError::Type(_))));
+        assert!(matches!(value_from_text("abc", &id), Err(DbError::Type(_))));
+    }
+    #[test]
+    fn test_null_comparisons() {
+        let null = Value::Null;
+        assert!(!compare_values(&null, &Operator::Equals, &null));
+        assert!(!compare_values(&null, &Operator::NotEquals, &Value::Int(1)));
+        assert!(compare_values(&null, &Operator::Is, &null));
+        assert!(!compare_values(&Value::Int(1), &Operator::Is, &null));
+        assert!(compare_values(&Value::Int(1), &Operator::IsNot, &null));
+    }
+
+    #[test]
+    fn test_numeric_edge_cases() {
+        let max = Value::Float(f64::MAX);
+        assert!(matches!(apply_binary(&max, BinaryOp::Multiply, &Value::Int(2)), Err(DbError::Execution(_))));
+        assert!(matches!(apply_binary(&Value::Int(i64::MIN), BinaryOp::Divide, &Value::Int(-1)), Err(DbError::Execution(_))));
+
+        let nan = Value::Float(f64::NAN);
+        assert!(compare_values(&nan, &Operator::Equals, &nan));
+        assert!(compare_values(&nan, &Operator::GreaterThan, &Value::Int(i64::MAX)));
+    }
+
+    #[test]
+    fn test_row_values() {
+        let row = |values: &[Value]| values.to_vec();
+        let (one, two, null) = (Value::Int(1), Value::Int(2), Value::Null);
+        assert!(compare_rows(&row(&[one.clone(), two.clone()]), &Operator::Equals, &row(&[Value::Float(1.0), two.clone()])));
+        assert!(compare_rows(&row(&[one.clone(), two.clone()]), &Operator::LessThan, &row(&[two.clone(), one.clone()])));
+        assert!(compare_rows(&row(&[one.clone(), null.clone()]), &Operator::LessThan, &row(&[two.clone(), one.clone()])));
+        assert!(!compare_rows(&row(&[one.clone(), null.clone()]), &Operator::LessOrEqual, &row(&[one.clone(), one.clone()])));
+        assert!(compare_rows(&row(&[null.clone(), one.clone()]), &Operator::NotEquals, &row(&[null.clone(), two.clone()])));
+        assert!(!compare_rows(&row(&[null.clone(), one.clone()]), &Operator::Equals, &row(&[null, one])));
+
+        let columns = [Column::new("a".to_string(), DataType::Int), Column::new("b".to_string(), DataType::Text)];
+        let check = |sql: &str| match crate::parser::parse(&format!("DELETE FROM t WHERE {}", sql)).unwrap() {
+            crate::parser::Statement::Delete { where_clause: Some(predicate), .. } => check_where(&predicate, &columns),
+            other => panic!("unexpected statement {:?}", other),
+        };
+        assert!(check("(a, b) IN ((1, 'x'), (2, 'y'))").is_ok());
+        assert!(check("(a, b) = (1, 2)").is_err());
+        assert!(check("(a, b) > (1, 'x', 3)").is_err());
+        assert!(check("a = (1, 2)").is_err());
+    }
+
+    #[test]
+    fn test_arrays() {
+        let ints = |ns: &[i64]| Value::Array(ns.iter().map(|&n| Value::Int(n)).collect());
+        let constructor = Expr::Array(vec![Expr::Literal(Value::Int(1)), Expr::Literal(Value::Float(2.5))]);
+        assert_eq!(evaluate_constant(&constructor), Ok(Value::Array(vec![Value::Float(1.0), Value::Float(2.5)])));
+
+        let element = |index: i64| evaluate_constant(&Expr::Subscript {
+            array: Box::new(Expr::Literal(ints(&[10, 20]))),
+            index: Box::new(Expr::Literal(Value::Int(index))),
+        });
+        assert_eq!(element(2), Ok(Value::Int(20)));
+        assert_eq!(element(0), Ok(Value::Null));
+        assert_eq!(element(3), Ok(Value::Null));
+
+        let with_null = Value::Array(vec![Value::Int(1), Value::Null]);
+        assert!(compare_values(&Value::Float(1.0), &Operator::Any, &with_null));
+        assert!(!compare_values(&Value::Null, &Operator::Any, &with_null));
+        assert!(compare_values(&ints(&[1, 2, 3]), &Operator::Contains, &ints(&[3, 1])));
+        assert!(!compare_values(&ints(&[1, 2]), &Operator::Contains, &with_null));
+        assert_eq!(sort_order(&ints(&[1, 2]), &ints(&[1, 2, 0])), Ordering::Less);
+
+        let tags = Column::new("tags".to_string(), DataType::Array(Box::new(DataType::Float)));
+        assert!(check_value_type(&ints(&[1]), &tags).is_ok());
+        assert!(check_value_type(&Value::Array(vec![Value::Text("a".to_string())]), &tags).is_err());
+        assert_eq!(value_from_text("{1, NULL}", &tags), Ok(Value::Array(vec![Value::Float(1.0), Value::Null])));
+    }
+
+    #[test]
+    fn test_random_and_uuid() {
+        let call = |name: &str, args: Vec<i64>| Expr::Function {
+            name: name.to_string(),
+            args: args.into_iter().map(|n| Expr::Literal(Value::Int(n))).collect(),
+        };
+        for _ in 0..100 {
+            let Ok(Value::Int(n)) = evaluate_constant(&call("RANDOM", vec![-2, 2])) else {
+                panic!("expected an INT");
+            };
+            assert!((-2..=2).contains(&n));
+        }
+        assert!(evaluate_constant(&call("RANDOM", vec![i64::MIN, i64::MAX])).is_ok());
+        assert!(matches!(evaluate_constant(&call("RANDOM", vec![2, 1])), Err(DbError::Execution(_))));
+        assert!(matches!(infer_type(&call("RANDOM", vec![1]), &[]), Err(DbError::Type(_))));
+
+        let Ok(Value::Text(uuid)) = evaluate_constant(&call("UUID", Vec::new())) else {
+            panic!("expected TEXT");
+        };
+        assert_eq!(uuid.len(), 36);
+        assert_eq!(&uuid[14..15], "4");
+        assert_ne!(evaluate_constant(&call("UUID", Vec::new())), Ok(Value::Text(uuid)));
+        assert!(is_volatile(&call("UUID", Vec::new())));
+
+        let positions = random_positions(1_000_000, 50);
+        assert_eq!(positions.len(), 50);
+        assert!(positions.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(positions.iter().all(|&p| p < 1_000_000));
+        assert_eq!(random_positions(3, 10), [0, 1, 2]);
+    }
+}
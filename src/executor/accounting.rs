@@ -0,0 +1,93 @@
+// Accounting - the cost of each statement
+//
+// A statement's stats are the rows it read from tables, the rows it
+// returned, the lookups an index answered, and the bytes it wrote to table
+// files. They are measured from the database's running counters before and
+// after it runs, so they cover scans by queries and by UPDATE and DELETE
+// alike. A result answered from the result cache reads nothing.
+
+use super::{execute_with_interrupt, ExecutionResult, Interrupt};
+use crate::error::DbError;
+use crate::planner::Plan;
+use crate::storage::counters::AccessCounts;
+use crate::storage::Database;
+use std::fmt;
+
+/// Work done by one statement
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ExecutionStats {
+    pub rows_scanned: u64,
+    pub rows_returned: u64,
+    pub index_hits: u64,
+    pub bytes_written: u64,
+}
+
+impl ExecutionStats {
+    /// Stats of a statement that began when the database's counts were
+    /// `before` and produced `result`
+    pub fn measure(db: &Database, before: &AccessCounts, result: Option<&ExecutionResult>) -> Self {
+        let counts = db.access_counts().since(before);
+        let rows_returned = match result {
+            Some(ExecutionResult::Rows { rows, .. }) => rows.len() as u64,
+            _ => 0,
+        };
+        Self {
+            rows_scanned: counts.rows_scanned,
+            rows_returned,
+            index_hits: counts.index_hits,
+            bytes_written: counts.bytes_written,
+        }
+    }
+}
+
+impl fmt::Display for ExecutionStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Rows scanned: {}, returned: {}, index hits: {}, bytes written: {}",
+            self.rows_scanned, self.rows_returned, self.index_hits, self.bytes_written
+        )
+    }
+}
+
+/// Execute a query plan, returning what it cost alongside its result
+pub fn execute_with_stats(
+    plan: Plan,
+    db: &mut Database,
+    interrupt: &Interrupt,
+) -> Result<(ExecutionResult, ExecutionStats), DbError> {
+    let before = db.access_counts();
+    let result = execute_with_interrupt(plan, db, interrupt)?;
+    let stats = ExecutionStats::measure(db, &before, Some(&result));
+    Ok((result, stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{self, Column, DataType, Expr, Value};
+    use crate::planner;
+
+    #[test]
+    fn test_execution_stats() {
+        let mut db = Database::new();
+        db.set_persistent(false);
+        let run = |sql: &str, db: &mut Database| {
+            let plan = planner::plan(parser::parse(sql).unwrap(), db).unwrap();
+            execute_with_stats(plan, db, &Interrupt::default()).unwrap().1
+        };
+
+        db.create_table("t".to_string(), vec![Column::new("n".to_string(), DataType::Int)]).unwrap();
+        for n in 0..10 {
+            db.insert_row("t", vec![Value::Int(n)]).unwrap();
+        }
+        let stats = run("SELECT * FROM t WHERE n > 6", &mut db);
+        assert_eq!((stats.rows_scanned, stats.rows_returned, stats.index_hits), (10, 3, 0));
+
+        db.create_index("t", &Expr::Column("n".to_string()), None, false).unwrap();
+        let stats = run("SELECT * FROM t WHERE n = 4", &mut db);
+        assert_eq!((stats.rows_scanned, stats.rows_returned, stats.index_hits), (1, 1, 1));
+        let stats = run("DELETE FROM t WHERE n = 4", &mut db);
+        assert_eq!((stats.rows_scanned, stats.rows_returned, stats.index_hits), (1, 0, 1));
+    }
+}
@@ -0,0 +1,460 @@
+// Aggregation - GROUP BY and aggregate functions via a hash table of groups
+//
+// Each input row is routed to its group's accumulators by hashing the
+// grouping values. When the number of groups exceeds the in-memory limit,
+// rows for groups not already in memory are spilled to temporary partition
+// files by key hash and aggregated one partition at a time afterwards.
+// Groups held in memory are charged to the statement's memory budget.
+
+use super::batch::{Batch, BATCH_SIZE};
+use super::cancel::Interrupt;
+use super::operator::Operator;
+use crate::error::DbError;
+use crate::eval;
+use crate::parser::{Aggregate, AggregateFunction, Column, Value};
+use crate::storage::btree::IndexKey;
+use crate::storage::{self, disk};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Most groups held in memory before rows start spilling to disk
+pub const MAX_GROUPS_IN_MEMORY: usize = 100_000;
+
+/// Number of partition files rows are spilled into
+const SPILL_PARTITIONS: usize = 16;
+
+/// Spilled partitions are themselves split at most this many times
+const MAX_SPILL_DEPTH: usize = 3;
+
+/// Distinguishes spill files of concurrent aggregations in one process
+static SPILL_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Running state of one aggregate function for one group
+#[derive(Debug, Clone)]
+enum Accumulator {
+    Count(i64),
+    Sum(Option<Value>),
+    Avg { sum: f64, count: i64 },
+    Min(Option<Value>),
+    Max(Option<Value>),
+}
+
+impl Accumulator {
+    fn new(function: AggregateFunction) -> Self {
+        match function {
+            AggregateFunction::Count => Accumulator::Count(0),
+            AggregateFunction::Sum => Accumulator::Sum(None),
+            AggregateFunction::Avg => Accumulator::Avg { sum: 0.0, count: 0 },
+            AggregateFunction::Min => Accumulator::Min(None),
+            AggregateFunction::Max => Accumulator::Max(None),
+        }
+    }
+
+    /// Add one row's value; None stands for a COUNT(*) row. NULLs are ignored
+    /// by every function except COUNT(*).
+    fn update(&mut self, value: Option<&Value>) -> Result<(), DbError> {
+        let value = match value {
+            None => {
+                if let Accumulator::Count(n) = self {
+                    *n += 1;
+                }
+                return Ok(());
+            }
+            Some(Value::Null) => return Ok(()),
+            Some(value) => value,
+        };
+
+        match self {
+            Accumulator::Count(n) => *n += 1,
+            Accumulator::Sum(sum) => {
+                *sum = Some(match (sum.take(), value) {
+                    (None, value) => value.clone(),
+                    (Some(Value::Int(a)), Value::Int(b)) => Value::Int(
+                        a.checked_add(*b).ok_or_else(|| DbError::Execution("Integer overflow in SUM".to_string()))?,
+                    ),
+                    (Some(Value::Float(a)), Value::Float(b)) => {
+                        let total = a + b;
+                        if !total.is_finite() {
+                            return Err(DbError::Execution("FLOAT overflow in SUM".to_string()));
+                        }
+                        Value::Float(total)
+                    }
+                    (Some(a), b) => return Err(DbError::Type(format!("Cannot SUM {:?} and {:?}", a, b))),
+                });
+            }
+            Accumulator::Avg { sum, count } => {
+                *sum += match value {
+                    Value::Int(n) => *n as f64,
+                    Value::Float(f) => *f,
+                    other => return Err(DbError::Type(format!("Cannot AVG {:?}", other))),
+                };
+                if !sum.is_finite() {
+                    return Err(DbError::Execution("FLOAT overflow in AVG".to_string()));
+                }
+                *count += 1;
+            }
+            Accumulator::Min(min) => {
+                if min.as_ref().is_none_or(|m| IndexKey::from(value) < IndexKey::from(m)) {
+                    *min = Some(value.clone());
+                }
+            }
+            Accumulator::Max(max) => {
+                if max.as_ref().is_none_or(|m| IndexKey::from(value) > IndexKey::from(m)) {
+                    *max = Some(value.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Final value of the aggregate
+    fn finish(self) -> Value {
+        match self {
+            Accumulator::Count(n) => Value::Int(n),
+            Accumulator::Avg { count: 0, .. } => Value::Null,
+            Accumulator::Avg { sum, count } => Value::Float(sum / count as f64),
+            Accumulator::Sum(value) | Accumulator::Min(value) | Accumulator::Max(value) => {
+                value.unwrap_or(Value::Null)
+            }
+        }
+    }
+}
+
+/// Groups an input and computes aggregates for each group
+pub struct HashAggregate<'a> {
+    input: Box<dyn Operator + 'a>,
+    columns: Vec<Column>,
+    key_indices: Vec<usize>,        // Input positions of the grouping columns
+    arg_indices: Vec<Option<usize>>, // Input position of each aggregate's column
+    functions: Vec<AggregateFunction>,
+    max_groups: usize,
+    interrupt: &'a Interrupt,
+    output: Option<std::vec::IntoIter<Batch>>,
+}
+
+impl<'a> HashAggregate<'a> {
+    pub fn new(
+        input: Box<dyn Operator + 'a>,
+        group_by: &[String],
+        aggregates: &[Aggregate],
+        interrupt: &'a Interrupt,
+    ) -> Result<Self, DbError> {
+        let position = |name: &str| {
+            input.columns().iter()
+                .position(|c| c.name == name)
+                .ok_or_else(|| DbError::ColumnNotFound(name.to_string()))
+        };
+        let key_indices = group_by.iter()
+            .map(|name| position(name))
+            .collect::<Result<Vec<_>, _>>()?;
+        let arg_indices = aggregates.iter()
+            .map(|a| a.column.as_deref().map(position).transpose())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut columns: Vec<Column> = key_indices.iter()
+            .map(|&i| input.columns()[i].clone())
+            .collect();
+        for aggregate in aggregates {
+            let data_type = eval::aggregate_type(aggregate, input.columns())?;
+            columns.push(Column::new(aggregate.to_string(), data_type));
+        }
+
+        Ok(Self {
+            input,
+            columns,
+            key_indices,
+            arg_indices,
+            functions: aggregates.iter().map(|a| a.function).collect(),
+            max_groups: MAX_GROUPS_IN_MEMORY,
+            interrupt,
+            output: None,
+        })
+    }
+
+    /// Consume the whole input and compute every group's result rows
+    fn aggregate(&mut self) -> Result<Vec<Vec<Value>>, DbError> {
+        // Rows are reduced to their grouping values followed by the value of
+        // each aggregate's column, which is also the layout spilled to disk
+        let key_len = self.key_indices.len();
+        let narrow_indices: Vec<usize> = self.key_indices.iter()
+            .chain(self.arg_indices.iter().flatten())
+            .copied()
+            .collect();
+        let narrow_columns: Vec<Column> = narrow_indices.iter()
+            .map(|&i| self.input.columns()[i].clone())
+            .collect();
+        let mut next_slot = key_len;
+        let arg_slots: Vec<Option<usize>> = self.arg_indices.iter()
+            .map(|arg| {
+                arg.map(|_| {
+                    next_slot += 1;
+                    next_slot - 1
+                })
+            })
+            .collect();
+
+        let mut table = GroupTable::new(self, &arg_slots, 0);
+        while let Some(batch) = self.input.next_batch()? {
+            for row in batch.project(&narrow_indices).into_rows() {
+                table.add(row)?;
+            }
+        }
+
+        // With no GROUP BY there is always exactly one group, even for no rows
+        if key_len == 0 && table.groups.is_empty() {
+            table.groups.push((Vec::new(), self.new_accumulators()));
+        }
+        table.finish(&narrow_columns)
+    }
+
+    fn new_accumulators(&self) -> Vec<Accumulator> {
+        self.functions.iter().map(|&f| Accumulator::new(f)).collect()
+    }
+}
+
+impl Operator for HashAggregate<'_> {
+    fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    fn next_batch(&mut self) -> Result<Option<Batch>, DbError> {
+        if self.output.is_none() {
+            let rows = self.aggregate()?;
+            let all: Vec<usize> = (0..self.columns.len()).collect();
+            let batches: Vec<Batch> = rows.chunks(BATCH_SIZE)
+                .map(|chunk| Batch::from_rows(chunk, &all))
+                .collect();
+            self.output = Some(batches.into_iter());
+        }
+        // Handing a batch on passes its memory to the consumer
+        let batch = self.output.as_mut().and_then(|batches| batches.next());
+        if let Some(batch) = &batch {
+            self.interrupt.release_memory(batch.memory_size());
+        }
+        Ok(batch)
+    }
+}
+
+/// In-memory groups, in first-seen order, plus any rows spilled to disk
+struct GroupTable<'s> {
+    interrupt: &'s Interrupt,
+    index: HashMap<Vec<IndexKey>, usize>,
+    groups: Vec<(Vec<Value>, Vec<Accumulator>)>,
+    functions: Vec<AggregateFunction>,
+    arg_slots: &'s [Option<usize>],
+    key_len: usize,
+    max_groups: usize,
+    depth: usize,
+    spill: Option<SpillFiles>,
+}
+
+impl<'s> GroupTable<'s> {
+    fn new(aggregate: &HashAggregate<'s>, arg_slots: &'s [Option<usize>], depth: usize) -> Self {
+        Self {
+            interrupt: aggregate.interrupt,
+            index: HashMap::new(),
+            groups: Vec::new(),
+            functions: aggregate.functions.clone(),
+            arg_slots,
+            key_len: aggregate.key_indices.len(),
+            max_groups: aggregate.max_groups,
+            depth,
+            spill: None,
+        }
+    }
+
+    /// A table for re-aggregating one spilled partition
+    fn for_partition(&self) -> Self {
+        Self {
+            interrupt: self.interrupt,
+            index: HashMap::new(),
+            groups: Vec::new(),
+            functions: self.functions.clone(),
+            arg_slots: self.arg_slots,
+            key_len: self.key_len,
+            max_groups: self.max_groups,
+            depth: self.depth + 1,
+            spill: None,
+        }
+    }
+
+    /// Add a row laid out as grouping values then aggregate arguments
+    fn add(&mut self, row: Vec<Value>) -> Result<(), DbError> {
+        let key: Vec<IndexKey> = row[..self.key_len].iter().map(IndexKey::from).collect();
+
+        let group = match self.index.get(&key) {
+            Some(&group) => group,
+            None if self.groups.len() >= self.max_groups && self.depth < MAX_SPILL_DEPTH => {
+                let partition = partition_of(&key, self.depth);
+                let spill = match &mut self.spill {
+                    Some(spill) => spill,
+                    None => self.spill.insert(SpillFiles::create()?),
+                };
+                return spill.write(partition, &row);
+            }
+            None => {
+                let size = storage::row_size(&row[..self.key_len])
+                    + self.functions.len() * std::mem::size_of::<Accumulator>();
+                self.interrupt.reserve_memory(size)?;
+                let accumulators = self.functions.iter().map(|&f| Accumulator::new(f)).collect();
+                self.groups.push((row[..self.key_len].to_vec(), accumulators));
+                self.index.insert(key, self.groups.len() - 1);
+                self.groups.len() - 1
+            }
+        };
+
+        let accumulators = &mut self.groups[group].1;
+        for (accumulator, slot) in accumulators.iter_mut().zip(self.arg_slots) {
+            accumulator.update(slot.map(|i| &row[i]))?;
+        }
+        Ok(())
+    }
+
+    /// Produce result rows for in-memory groups, then for each spilled partition
+    fn finish(mut self, columns: &[Column]) -> Result<Vec<Vec<Value>>, DbError> {
+        let spill = self.spill.take();
+        let mut rows: Vec<Vec<Value>> = std::mem::take(&mut self.groups)
+            .into_iter()
+            .map(|(mut key, accumulators)| {
+                key.extend(accumulators.into_iter().map(Accumulator::finish));
+                key
+            })
+            .collect();
+
+        if let Some(mut spill) = spill {
+            spill.flush()?;
+            for path in &spill.paths {
+                let mut partition = self.for_partition();
+                let file = File::open(path).map_err(|e| DbError::Io(format!("Failed to read spill file: {}", e)))?;
+                for line in BufReader::new(file).lines() {
+                    let line = line.map_err(|e| DbError::Io(format!("Failed to read spill file: {}", e)))?;
+                    let row = disk::parse_row(&line, columns)
+                        .map_err(|e| DbError::Io(format!("Corrupt spill file: {}", e)))?;
+                    partition.add(row)?;
+                }
+                rows.extend(partition.finish(columns)?);
+            }
+        }
+        Ok(rows)
+    }
+}
+
+/// Which spill partition a group key belongs to; the depth is mixed in so a
+/// partition that spills again is split differently
+fn partition_of(key: &[IndexKey], depth: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    depth.hash(&mut hasher);
+    key.hash(&mut hasher);
+    (hasher.finish() % SPILL_PARTITIONS as u64) as usize
+}
+
+/// Temporary partition files holding spilled rows; removed when dropped
+struct SpillFiles {
+    paths: Vec<PathBuf>,
+    writers: Vec<BufWriter<File>>,
+}
+
+impl SpillFiles {
+    fn create() -> Result<Self, DbError> {
+        let id = SPILL_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let mut paths = Vec::new();
+        let mut writers = Vec::new();
+        for partition in 0..SPILL_PARTITIONS {
+            let path = std::env::temp_dir()
+                .join(format!("mydb-spill-{}-{}-{}", std::process::id(), id, partition));
+            let file = File::create(&path).map_err(|e| DbError::Io(format!("Failed to create spill file: {}", e)))?;
+            paths.push(path);
+            writers.push(BufWriter::new(file));
+        }
+        Ok(Self { paths, writers })
+    }
+
+    fn write(&mut self, partition: usize, row: &[Value]) -> Result<(), DbError> {
+        writeln!(self.writers[partition], "{}", disk::format_row(row))
+            .map_err(|e| DbError::Io(format!("Failed to write spill file: {}", e)))
+    }
+
+    /// Flush all partitions so they can be read back
+    fn flush(&mut self) -> Result<(), DbError> {
+        for writer in &mut self.writers {
+            writer.flush().map_err(|e| DbError::Io(format!("Failed to write spill file: {}", e)))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SpillFiles {
+    fn drop(&mut self) {
+        for path in &self.paths {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::operator;
+    use crate::parser::DataType;
+
+    /// Operator over a fixed set of rows
+    struct Rows {
+        columns: Vec<Column>,
+        rows: Option<Vec<Vec<Value>>>,
+    }
+
+    impl Operator for Rows {
+        fn columns(&self) -> &[Column] {
+            &self.columns
+        }
+
+        fn next_batch(&mut self) -> Result<Option<Batch>, DbError> {
+            let all: Vec<usize> = (0..self.columns.len()).collect();
+            Ok(self.rows.take().map(|rows| Batch::from_rows(&rows, &all)))
+        }
+    }
+
+    fn grouped_sums(max_groups: usize) -> Vec<Vec<Value>> {
+        let input = Rows {
+            columns: vec![
+                Column::new("k".to_string(), DataType::Int),
+                Column::new("v".to_string(), DataType::Int),
+            ],
+            rows: Some((0..1000).map(|i| vec![Value::Int(i % 50), Value::Int(i)]).collect()),
+        };
+        let aggregates = [
+            Aggregate { function: AggregateFunction::Count, column: None },
+            Aggregate { function: AggregateFunction::Sum, column: Some("v".to_string()) },
+        ];
+        let interrupt = Interrupt::default();
+        let mut aggregate = HashAggregate::new(Box::new(input), &["k".to_string()], &aggregates, &interrupt).unwrap();
+        aggregate.max_groups = max_groups;
+
+        let mut rows = operator::collect(&mut aggregate, &interrupt).unwrap();
+        rows.sort_by_key(|row| IndexKey::from(&row[0]));
+        rows
+    }
+
+    #[test]
+    fn test_group_by_with_spilling() {
+        let in_memory = grouped_sums(MAX_GROUPS_IN_MEMORY);
+        assert_eq!(in_memory.len(), 50);
+        // Group 7 holds 7, 57, ..., 957: 20 rows summing to 20 * 7 + 50 * 190
+        assert_eq!(in_memory[7], vec![Value::Int(7), Value::Int(20), Value::Int(9640)]);
+
+        assert_eq!(grouped_sums(4), in_memory);
+    }
+
+    #[test]
+    fn test_float_overflow_is_an_error() {
+        for function in [AggregateFunction::Sum, AggregateFunction::Avg] {
+            let mut accumulator = Accumulator::new(function);
+            accumulator.update(Some(&Value::Float(f64::MAX))).unwrap();
+            assert!(matches!(accumulator.update(Some(&Value::Float(f64::MAX))), Err(DbError::Execution(_))));
+        }
+    }
+}
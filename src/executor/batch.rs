@@ -0,0 +1,130 @@
+// Batch - columnar chunks of rows passed between operators
+
+use crate::parser::Value;
+use crate::storage::value_size;
+
+/// Number of rows operators aim to put in each batch
+pub const BATCH_SIZE: usize = 1024;
+
+/// A set of rows stored column by column
+#[derive(Debug, Clone, PartialEq)]
+pub struct Batch {
+    columns: Vec<Vec<Value>>,
+    len: usize,
+}
+
+impl Batch {
+    /// Transpose rows into a batch, keeping only the columns at `indices`
+    pub fn from_rows<'r>(rows: impl IntoIterator<Item = &'r Vec<Value>>, indices: &[usize]) -> Self {
+        let mut columns: Vec<Vec<Value>> = vec![Vec::new(); indices.len()];
+        let mut len = 0;
+        for row in rows {
+            for (column, &i) in columns.iter_mut().zip(indices) {
+                column.push(row.get(i).cloned().unwrap_or(Value::Null));
+            }
+            len += 1;
+        }
+        Self { columns, len }
+    }
+
+    /// A batch of `len` rows from column vectors of that length
+    pub fn from_columns(columns: Vec<Vec<Value>>, len: usize) -> Self {
+        Self { columns, len }
+    }
+
+    /// Number of rows in the batch
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Approximate memory held by the batch
+    pub fn memory_size(&self) -> usize {
+        self.columns.iter()
+            .map(|column| {
+                std::mem::size_of::<Vec<Value>>() + column.iter().map(value_size).sum::<usize>()
+            })
+            .sum()
+    }
+
+    /// The batch's column vectors
+    pub fn columns(&self) -> &[Vec<Value>] {
+        &self.columns
+    }
+
+    /// Keep only the rows whose entry in `mask` is true
+    pub fn select(self, mask: &[bool]) -> Self {
+        let len = mask.iter().filter(|&&keep| keep).count();
+        if len == self.len {
+            return self;
+        }
+        let columns = self.columns.into_iter()
+            .map(|column| {
+                column.into_iter()
+                    .zip(mask)
+                    .filter_map(|(value, &keep)| keep.then_some(value))
+                    .collect()
+            })
+            .collect();
+        Self { columns, len }
+    }
+
+    /// Keep only the first `len` rows
+    pub fn truncate(mut self, len: usize) -> Self {
+        for column in &mut self.columns {
+            column.truncate(len);
+        }
+        self.len = self.len.min(len);
+        self
+    }
+
+    /// Keep only the columns at `indices`, in that order
+    pub fn project(mut self, indices: &[usize]) -> Self {
+        let columns = indices.iter()
+            .enumerate()
+            .map(|(pos, &i)| {
+                // Move a column out on its last use, clone it otherwise
+                if indices[pos + 1..].contains(&i) {
+                    self.columns[i].clone()
+                } else {
+                    std::mem::take(&mut self.columns[i])
+                }
+            })
+            .collect();
+        Self { columns, len: self.len }
+    }
+
+    /// Transpose the batch back into rows
+    pub fn into_rows(self) -> Vec<Vec<Value>> {
+        let mut rows = vec![Vec::with_capacity(self.columns.len()); self.len];
+        for column in self.columns {
+            for (row, value) in rows.iter_mut().zip(column) {
+                row.push(value);
+            }
+        }
+        rows
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_select_and_project() {
+        let rows: Vec<Vec<Value>> = (0..5)
+            .map(|i| vec![Value::Int(i), Value::Text(format!("r{}", i))])
+            .collect();
+        let batch = Batch::from_rows(&rows, &[0, 1]);
+        assert_eq!(batch.len(), 5);
+
+        let batch = batch.select(&[true, false, true, false, false]).project(&[1, 0, 1]);
+        assert_eq!(batch.into_rows(), vec![
+            vec![Value::Text("r0".to_string()), Value::Int(0), Value::Text("r0".to_string())],
+            vec![Value::Text("r2".to_string()), Value::Int(2), Value::Text("r2".to_string())],
+        ]);
+    }
+}
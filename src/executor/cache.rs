@@ -0,0 +1,230 @@
+// Result cache - reuses the rows of repeated queries
+//
+// A query's rows are kept under its SQL text, along with the version of each
+// table it read. Running the same SQL again returns the kept rows without
+// executing anything, as long as none of those tables has changed since; a
+// change to any of them makes the entry stale. Queries whose result can differ
+// between runs on the same rows are not cached: those calling RANDOM(), NOW()
+// and the like, those using SAMPLE, and those reading an external table (whose
+// file may change at any time) or the audit log.
+
+use super::ExecutionResult;
+use crate::eval;
+use crate::parser::{Series, Value};
+use crate::planner::cache::cache_key;
+use crate::planner::PhysicalPlan;
+use crate::storage::audit::AUDIT_TABLE;
+use crate::storage::Database;
+use std::collections::HashMap;
+
+const DEFAULT_CAPACITY: usize = 64;
+
+/// Results with more rows are not cached, to bound the memory entries use
+const MAX_CACHED_ROWS: usize = 10_000;
+
+/// Cache of query results keyed by SQL text, evicting the least recently
+/// used entry when full
+pub struct ResultCache {
+    entries: HashMap<String, CacheEntry>,
+    capacity: usize,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+struct CacheEntry {
+    columns: Vec<String>,
+    rows: Vec<Vec<Value>>,
+    versions: Vec<(String, u64)>, // Each table read, with its version then
+    last_used: u64,
+}
+
+impl ResultCache {
+    /// Create a cache holding the results of at most `capacity` queries
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            capacity,
+            clock: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Look up the result of SQL text, if none of the tables it read has
+    /// changed since
+    pub fn get(&mut self, sql: &str, db: &Database) -> Option<ExecutionResult> {
+        let key = cache_key(sql);
+        self.clock += 1;
+
+        match self.entries.get_mut(key) {
+            Some(entry) if entry.versions.iter().all(|(table, version)| db.table_version(table) == *version) => {
+                entry.last_used = self.clock;
+                self.hits += 1;
+                Some(ExecutionResult::Rows { columns: entry.columns.clone(), rows: entry.rows.clone() })
+            }
+            Some(_) => {
+                self.entries.remove(key);
+                self.misses += 1;
+                None
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Remember the result of a query, unless running it again could give
+    /// different rows
+    pub fn insert(&mut self, sql: &str, plan: &PhysicalPlan, result: &ExecutionResult, db: &Database) {
+        let ExecutionResult::Rows { columns, rows } = result else {
+            return;
+        };
+        if self.capacity == 0 || rows.len() > MAX_CACHED_ROWS || !is_deterministic(plan) {
+            return;
+        }
+        let mut tables = Vec::new();
+        read_tables(plan, &mut tables);
+        if tables.iter().any(|table| table == AUDIT_TABLE || db.external_location(table).is_some()) {
+            return;
+        }
+
+        let key = cache_key(sql);
+        if !self.entries.contains_key(key) && self.entries.len() >= self.capacity {
+            let oldest = self.entries.iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        self.clock += 1;
+        let versions = tables.into_iter()
+            .map(|table| {
+                let version = db.table_version(&table);
+                (table, version)
+            })
+            .collect();
+        self.entries.insert(key.to_string(), CacheEntry {
+            columns: columns.clone(),
+            rows: rows.clone(),
+            versions,
+            last_used: self.clock,
+        });
+    }
+
+    /// Drop all cached results
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Number of cached results
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check whether the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Number of lookups that found a current result, and that did not
+    pub fn stats(&self) -> (u64, u64) {
+        (self.hits, self.misses)
+    }
+}
+
+impl Default for ResultCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+/// Whether a plan gives the same rows every time it runs over the same tables
+fn is_deterministic(plan: &PhysicalPlan) -> bool {
+    let stable = match plan {
+        PhysicalPlan::SampleScan { .. } => false,
+        PhysicalPlan::Compute { exprs, .. } => !exprs.iter().any(eval::is_volatile),
+        PhysicalPlan::Sort { keys, .. } => !keys.iter().any(|key| eval::is_volatile(&key.expr)),
+        PhysicalPlan::Filter { predicate, .. }
+        | PhysicalPlan::IndexScan { predicate, .. }
+        | PhysicalPlan::ParallelScan { predicate, .. } => {
+            !eval::is_volatile(&predicate.left) && !eval::is_volatile(&predicate.right)
+        }
+        _ => true,
+    };
+    stable && plan.inputs().into_iter().all(is_deterministic)
+}
+
+/// Add the names of the tables a plan reads to `tables`
+fn read_tables(plan: &PhysicalPlan, tables: &mut Vec<String>) {
+    match plan.inputs().as_slice() {
+        [] => {
+            let name = plan.table_name();
+            if !name.is_empty() && name != Series::NAME && !tables.iter().any(|t| t == name) {
+                tables.push(name.to_string());
+            }
+        }
+        inputs => {
+            for input in inputs {
+                read_tables(input, tables);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::execute;
+    use crate::parser::{self, Column, DataType};
+    use crate::planner::{self, Plan};
+
+    #[test]
+    fn test_result_cache() {
+        let mut db = Database::new();
+        db.set_persistent(false);
+        let mut cache = ResultCache::default();
+        let mut run = |sql: &str, db: &mut Database| {
+            if let Some(result) = cache.get(sql, db) {
+                return (result, true);
+            }
+            let Plan::Query(plan) = planner::plan(parser::parse(sql).unwrap(), db).unwrap() else {
+                panic!("not a query: {}", sql);
+            };
+            let result = execute(Plan::Query(plan.clone()), db).unwrap();
+            cache.insert(sql, &plan, &result, db);
+            (result, false)
+        };
+
+        db.create_table("t".to_string(), vec![Column::new("n".to_string(), DataType::Int)]).unwrap();
+        db.insert_row("t", vec![Value::Int(1)]).unwrap();
+        assert!(!run("SELECT * FROM t", &mut db).1);
+        assert!(run("SELECT * FROM t;", &mut db).1);
+
+        // A change to the table makes the cached rows stale
+        db.insert_row("t", vec![Value::Int(2)]).unwrap();
+        let (result, cached) = run("SELECT * FROM t", &mut db);
+        assert!(!cached);
+        assert_eq!(result.row_count(), Some(2));
+
+        run("SELECT n, RANDOM() FROM t", &mut db);
+        assert!(!run("SELECT n, RANDOM() FROM t", &mut db).1);
+
+        // A registered function may return something new on every call
+        let calls = std::sync::Arc::new(std::sync::atomic::AtomicI64::new(0));
+        let counter = std::sync::Arc::clone(&calls);
+        eval::register_global_function("cache_test_tick", 0, move |_| {
+            Ok(Value::Int(counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst)))
+        }).unwrap();
+        run("SELECT n, cache_test_tick() FROM t", &mut db);
+        let (result, cached) = run("SELECT n, cache_test_tick() FROM t", &mut db);
+        assert!(!cached);
+        assert_eq!(result, ExecutionResult::Rows {
+            columns: vec!["n".to_string(), "CACHE_TEST_TICK()".to_string()],
+            rows: vec![vec![Value::Int(1), Value::Int(2)], vec![Value::Int(2), Value::Int(3)]],
+        });
+    }
+}
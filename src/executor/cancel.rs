@@ -0,0 +1,100 @@
+// Cancellation - stopping a running statement early on request, on timeout,
+// or when its materialized results outgrow a memory budget
+
+use crate::error::DbError;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Shared flag that another thread (e.g. a Ctrl+C handler) sets to cancel
+/// the running statement
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request cancellation
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Clear a previous cancellation before running the next statement
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Conditions under which a running statement stops: cancellation through
+/// its token, running past its timeout, or holding more memory than its
+/// limit. Operators check it between batches and report memory they retain.
+#[derive(Debug, Default)]
+pub struct Interrupt {
+    token: CancelToken,
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    memory_limit: Option<usize>, // Bytes
+    memory_used: AtomicUsize,
+}
+
+impl Interrupt {
+    /// Start the clock for a statement that may run for at most `timeout`
+    pub fn new(token: CancelToken, timeout: Option<Duration>) -> Self {
+        Self {
+            token,
+            timeout,
+            deadline: timeout.map(|timeout| Instant::now() + timeout),
+            memory_limit: None,
+            memory_used: AtomicUsize::new(0),
+        }
+    }
+
+    /// Cap the memory the statement's materialized results may use
+    pub fn with_memory_limit(mut self, bytes: Option<usize>) -> Self {
+        self.memory_limit = bytes;
+        self
+    }
+
+    /// Account for `bytes` more retained memory, failing if that exceeds the limit
+    pub fn reserve_memory(&self, bytes: usize) -> Result<(), DbError> {
+        let used = self.memory_used.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        match self.memory_limit {
+            Some(limit) if used > limit => {
+                self.memory_used.fetch_sub(bytes, Ordering::Relaxed);
+                Err(DbError::MemoryLimit(limit))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Return memory reserved earlier, e.g. when a retained batch is handed on
+    pub fn release_memory(&self, bytes: usize) {
+        let _ = self.memory_used.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+            Some(used.saturating_sub(bytes))
+        });
+    }
+
+    /// Approximate bytes reserved so far
+    pub fn memory_used(&self) -> usize {
+        self.memory_used.load(Ordering::Relaxed)
+    }
+
+    /// Fail if the statement has been cancelled or has timed out
+    pub fn check(&self) -> Result<(), DbError> {
+        if self.token.is_cancelled() {
+            return Err(DbError::Cancelled);
+        }
+        if let (Some(deadline), Some(timeout)) = (self.deadline, self.timeout)
+            && Instant::now() >= deadline
+        {
+            return Err(DbError::Timeout(timeout.as_millis()));
+        }
+        Ok(())
+    }
+}
@@ -1,145 +1,256 @@
-use crate::planner::Plan;
+mod accounting;
+mod aggregate;
+mod batch;
+mod cache;
+mod cancel;
+mod operator;
+mod output;
+mod parquet;
+mod rows;
+mod set_operation;
+mod settings;
+
+pub use accounting::{execute_with_stats, ExecutionStats};
+pub use cache::ResultCache;
+pub use cancel::{CancelToken, Interrupt};
+pub use output::{format_results, json_string, json_value, OutputMode, OutputOptions};
+pub use parquet::{encode_parquet, infer_columns, write_parquet};
+pub use rows::{FromValue, Row, Rows};
+pub use settings::{execute_in_session, DryRun, Settings, SETTING_NAMES};
+
+use crate::error::DbError;
+use crate::eval;
+use crate::planner::{estimate, PhysicalPlan, Plan};
 use crate::storage::Database;
-use crate::parser::Value;
+use crate::parser::{Column, Value};
+use std::time::{Duration, Instant};
 
 /// Result of a query execution
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ExecutionResult {
     Success(String),
+    /// INSERT, UPDATE, or DELETE, with the number of rows it changed
+    Changed { message: String, rows: usize },
     Rows { columns: Vec<String>, rows: Vec<Vec<Value>> },
 }
 
+impl ExecutionResult {
+    /// Rows returned by a query or changed by a statement, if either
+    pub fn row_count(&self) -> Option<usize> {
+        match self {
+            ExecutionResult::Success(_) => None,
+            ExecutionResult::Changed { rows, .. } => Some(*rows),
+            ExecutionResult::Rows { rows, .. } => Some(rows.len()),
+        }
+    }
+}
+
 /// Execute a query plan
-pub fn execute(plan: Plan, db: &mut Database) -> Result<ExecutionResult, String> {
+pub fn execute(plan: Plan, db: &mut Database) -> Result<ExecutionResult, DbError> {
+    execute_with_interrupt(plan, db, &Interrupt::default())
+}
+
+/// Execute a query plan, stopping early if `interrupt` fires
+pub fn execute_with_interrupt(
+    plan: Plan,
+    db: &mut Database,
+    interrupt: &Interrupt,
+) -> Result<ExecutionResult, DbError> {
+    let _span = tracing::debug_span!("execute").entered();
     match plan {
-        Plan::CreateTable { table_name, columns } => {
+        Plan::CreateTable { table_name, columns, append_only: false } => {
             db.create_table(table_name.clone(), columns)?;
             Ok(ExecutionResult::Success(format!(
                 "Table '{}' created successfully",
                 table_name
             )))
         }
-        Plan::CreateIndex { table_name, column_name } => {
-            db.create_index(&table_name, &column_name)?;
+        Plan::CreateTable { table_name, columns, append_only: true } => {
+            db.create_append_only_table(table_name.clone(), columns)?;
+            Ok(ExecutionResult::Success(format!(
+                "Table '{}' created successfully",
+                table_name
+            )))
+        }
+        Plan::SetConstraints { deferred } => {
+            db.set_constraints_deferred(deferred)?;
+            Ok(ExecutionResult::Success(
+                if deferred { "Constraints deferred until COMMIT" } else { "Constraints checked immediately" }.to_string(),
+            ))
+        }
+        Plan::CreateTableLike { table_name, source, with_data } => {
+            let count = db.create_table_like(&table_name, &source, with_data)?;
+            let rows = if with_data { format!(" with {} row(s)", count) } else { String::new() };
+            Ok(ExecutionResult::Success(format!("Table '{}' created like '{}'{}", table_name, source, rows)))
+        }
+        Plan::CreateExternalTable { table_name, columns, location } => {
+            let count = db.create_external_table(&table_name, columns, &location)?;
+            Ok(ExecutionResult::Success(format!(
+                "External table '{}' created over '{}' with {} row(s)",
+                table_name, location, count
+            )))
+        }
+        Plan::CreateIndex { index_name, table_name, expr, unique } => {
+            let index_name = db.create_index(&table_name, &expr, index_name, unique)?;
+            let target = match expr {
+                crate::parser::Expr::Column(name) => format!("column '{}'", name),
+                expr => format!("expression '{}'", expr),
+            };
             Ok(ExecutionResult::Success(format!(
-                "Index created on column '{}' of table '{}'",
-                column_name, table_name
+                "Index '{}' created on {} of table '{}'",
+                index_name, target, table_name
             )))
         }
         Plan::Insert { table_name, values } => {
+            let values = values.iter().map(eval::evaluate_constant).collect::<Result<Vec<_>, _>>()?;
             db.insert_row(&table_name, values)?;
-            Ok(ExecutionResult::Success("1 row inserted".to_string()))
+            Ok(ExecutionResult::Changed { message: "1 row inserted".to_string(), rows: 1 })
         }
-        Plan::Scan { table_name, columns, filter } => {
-            let (col_names, rows) = if columns.is_empty() {
-                db.select_all(&table_name)?
-            } else {
-                db.select_with_filter(&table_name, columns, filter.as_ref())?
-            };
+        Plan::InsertSelect { table_name, query } => {
+            let (_, rows) = execute_query(&query, db, interrupt)?;
+            let count = db.insert_rows(&table_name, rows)?;
+            Ok(ExecutionResult::Changed { message: format!("{} row(s) inserted", count), rows: count })
+        }
+        Plan::Query(physical) => {
+            let (columns, rows) = execute_query(&physical, db, interrupt)?;
 
             Ok(ExecutionResult::Rows {
-                columns: col_names,
+                columns: columns.into_iter().map(|c| c.name).collect(),
                 rows,
             })
         }
+        Plan::Explain { plan: physical, analyze: false } => {
+            let text = physical.render(&|node| {
+                format!(" (rows={:.0})", displayed_estimate(node, db))
+            });
+            Ok(ExecutionResult::Success(text.trim_end().to_string()))
+        }
+        Plan::Explain { plan: physical, analyze: true } => {
+            refresh_external_tables(&physical, db)?;
+            let profile = operator::Profile::default();
+            let start = Instant::now();
+            let mut root = operator::build(&physical, db, interrupt, Some(&profile))?;
+            operator::collect(root.as_mut(), interrupt)?;
+            let total = start.elapsed();
+            drop(root);
+            let profile = profile.borrow();
+
+            let text = physical.render(&|node| {
+                let estimated = displayed_estimate(node, db);
+                match profile.iter().find(|m| std::ptr::eq(m.node, node)) {
+                    Some(m) => format!(
+                        " (rows={:.0}) (actual rows={} time={:.3} ms)",
+                        estimated, m.rows, millis(m.elapsed)
+                    ),
+                    None => format!(" (rows={:.0}) (never executed)", estimated),
+                }
+            });
+            Ok(ExecutionResult::Success(format!(
+                "{}Execution time: {:.3} ms",
+                text, millis(total)
+            )))
+        }
+        Plan::Analyze { table_name } => {
+            let count = db.analyze(table_name.as_deref())?;
+            Ok(ExecutionResult::Success(format!("Analyzed {} table(s)", count)))
+        }
+        Plan::Purge { table_name } => {
+            let count = db.purge_expired(table_name.as_deref())?;
+            Ok(ExecutionResult::Changed { message: format!("{} expired row(s) deleted", count), rows: count })
+        }
+        Plan::Set { .. } | Plan::SetVariable { .. } => Err(DbError::Plan(
+            "Settings and variables belong to a session; SET and PRAGMA work in the REPL and PostgreSQL connections".to_string(),
+        )),
+        Plan::CreateMaterializedView { view_name, query, sql } => {
+            let (columns, rows) = execute_query(&query, db, interrupt)?;
+            let count = rows.len();
+            db.create_materialized_view(&view_name, columns, rows, sql)?;
+            Ok(ExecutionResult::Success(format!(
+                "Materialized view '{}' created with {} row(s)",
+                view_name, count
+            )))
+        }
+        Plan::RefreshMaterializedView { view_name, query } => {
+            let (columns, rows) = execute_query(&query, db, interrupt)?;
+            let count = db.refresh_materialized_view(&view_name, columns, rows)?;
+            Ok(ExecutionResult::Success(format!(
+                "Materialized view '{}' refreshed with {} row(s)",
+                view_name, count
+            )))
+        }
+        Plan::Copy { table_name, path, format } => {
+            db.refresh_external_table(&table_name)?;
+            let table = db.get_table(&table_name)?;
+            if format == "parquet" {
+                write_parquet(&path, &table.columns, &table.rows)?;
+            } else {
+                let mode = OutputMode::from_name(&format)
+                    .ok_or_else(|| DbError::Plan(format!("Unknown COPY format '{}'", format)))?;
+                let options = OutputOptions { mode, float_precision: None, max_width: None, ..OutputOptions::default() };
+                let result = ExecutionResult::Rows {
+                    columns: table.columns.iter().map(|c| c.name.clone()).collect(),
+                    rows: table.rows.clone(),
+                };
+                let mut text = format_results(result, &options);
+                if !text.ends_with('\n') {
+                    text.push('\n');
+                }
+                std::fs::write(&path, text)
+                    .map_err(|e| DbError::Io(format!("Failed to write '{}': {}", path, e)))?;
+            }
+            Ok(ExecutionResult::Success(format!(
+                "Copied {} row(s) from '{}' to '{}'",
+                table.rows.len(), table_name, path
+            )))
+        }
         Plan::Delete { table_name, filter } => {
             let count = db.delete_rows(&table_name, filter.as_ref())?;
-            Ok(ExecutionResult::Success(format!("{} row(s) deleted", count)))
+            Ok(ExecutionResult::Changed { message: format!("{} row(s) deleted", count), rows: count })
         }
         Plan::Update { table_name, column, value, filter } => {
-            let count = db.update_rows(&table_name, &column, value, filter.as_ref())?;
-            Ok(ExecutionResult::Success(format!("{} row(s) updated", count)))
+            let count = db.update_rows(&table_name, &column, &value, filter.as_ref())?;
+            Ok(ExecutionResult::Changed { message: format!("{} row(s) updated", count), rows: count })
         }
-    }
-}
-
-/// Format execution results
-pub fn format_results(result: ExecutionResult) -> String {
-    match result {
-        ExecutionResult::Success(msg) => msg,
-        ExecutionResult::Rows { columns, rows } => {
-            if rows.is_empty() {
-                return "No rows returned".to_string();
-            }
-            format_table(&columns, &rows)
+        Plan::AlterColumnType { table_name, column, data_type } => {
+            let count = db.alter_column_type(&table_name, &column, data_type.clone())?;
+            Ok(ExecutionResult::Success(format!(
+                "Column '{}' of table '{}' changed to {}, converting {} value(s)",
+                column, table_name, data_type, count
+            )))
         }
     }
 }
 
-/// Format rows as ASCII table
-fn format_table(columns: &[String], rows: &[Vec<Value>]) -> String {
-    // Calculate column widths
-    let mut widths: Vec<usize> = columns.iter().map(|c| c.len()).collect();
-    
-    for row in rows {
-        for (i, val) in row.iter().enumerate() {
-            if i < widths.len() {
-                let val_str = value_to_string(val);
-                if val_str.len() > widths[i] {
-                    widths[i] = val_str.len();
-                }
-            }
-        }
-    }
-
-    // Build table
-    let mut output = String::new();
-    
-    // Top border
-    output.push('+');
-    for width in &widths {
-        output.push_str(&"-".repeat(width + 2));
-        output.push('+');
-    }
-    output.push('\n');
-
-    // Header
-    output.push('|');
-    for (i, col) in columns.iter().enumerate() {
-        output.push_str(&format!(" {:width$} ", col, width = widths[i]));
-        output.push('|');
-    }
-    output.push('\n');
-
-    // Middle border
-    output.push('+');
-    for width in &widths {
-        output.push_str(&"-".repeat(width + 2));
-        output.push('+');
-    }
-    output.push('\n');
+/// Execute a physical query plan, returning the output columns and rows
+fn execute_query(
+    plan: &PhysicalPlan,
+    db: &mut Database,
+    interrupt: &Interrupt,
+) -> Result<(Vec<Column>, Vec<Vec<Value>>), DbError> {
+    refresh_external_tables(plan, db)?;
+    let mut root = operator::build(plan, db, interrupt, None)?;
+    let rows = operator::collect(root.as_mut(), interrupt)?;
+    Ok((root.columns().to_vec(), rows))
+}
 
-    // Rows
-    for row in rows {
-        output.push('|');
-        for (i, val) in row.iter().enumerate() {
-            let val_str = value_to_string(val);
-            output.push_str(&format!(" {:width$} ", val_str, width = widths[i]));
-            output.push('|');
-        }
-        output.push('\n');
+/// Read again the files of the external tables a query reads that have
+/// changed since their rows were read
+fn refresh_external_tables(plan: &PhysicalPlan, db: &mut Database) -> Result<(), DbError> {
+    match plan.inputs().as_slice() {
+        [] => db.refresh_external_table(plan.table_name()),
+        inputs => inputs.iter().try_for_each(|input| refresh_external_tables(input, db)),
     }
+}
 
-    // Bottom border
-    output.push('+');
-    for width in &widths {
-        output.push_str(&"-".repeat(width + 2));
-        output.push('+');
+/// Row estimate EXPLAIN shows for a node. Only a plan that is known to be
+/// empty shows zero rows; a selective lookup shows at least one.
+fn displayed_estimate(node: &PhysicalPlan, db: &Database) -> f64 {
+    match node {
+        PhysicalPlan::Empty { .. } => 0.0,
+        _ => estimate::estimate_rows(node, db).max(1.0),
     }
-    output.push('\n');
-
-    // Add row count
-    output.push_str(&format!("{} row(s) returned\n", rows.len()));
-
-    output
 }
 
-/// Convert Value to display string
-fn value_to_string(value: &Value) -> String {
-    match value {
-        Value::Int(n) => n.to_string(),
-        Value::Text(s) => s.clone(),
-        Value::Float(f) => format!("{:.2}", f),
-        Value::Null => "NULL".to_string(),
-    }
-}
\ No newline at end of file
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
@@ -0,0 +1,850 @@
+// Operators - pull-based (volcano) execution of physical plans
+//
+// Each operator produces a batch of up to BATCH_SIZE rows from
+// `next_batch()`, pulling from its input only as needed, so rows stream
+// through filters and projections without intermediate copies of the whole
+// result. Batches are columnar, so predicates and projections work over
+// column slices rather than dispatching per row. A filter over a scan asks it
+// for matching rows only, so rows the predicate rejects are never copied.
+
+use super::aggregate::HashAggregate;
+use super::batch::{Batch, BATCH_SIZE};
+use super::cancel::Interrupt;
+use super::set_operation::SetOperation;
+use crate::error::DbError;
+use crate::eval;
+use crate::parser::{Column, DataType, Expr, Series, Value, WhereClause};
+use crate::planner::logical::SortKey;
+use crate::planner::PhysicalPlan;
+use crate::storage::counters::Counters;
+use crate::storage::Database;
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
+
+/// A row-producing operator
+pub trait Operator {
+    /// Columns of the rows this operator produces
+    fn columns(&self) -> &[Column];
+
+    /// Produce the next non-empty batch of rows, or None when exhausted
+    fn next_batch(&mut self) -> Result<Option<Batch>, DbError>;
+
+    /// Produce the next batch with only the rows that satisfy a predicate
+    /// (possibly none), and the number of rows read to make it. Scans
+    /// override this to copy only the rows that match.
+    fn next_batch_matching(&mut self, predicate: &WhereClause) -> Result<Option<(Batch, usize)>, DbError> {
+        let Some(batch) = self.next_batch()? else {
+            return Ok(None);
+        };
+        let read = batch.len();
+        let mask = eval::matches_batch(predicate, self.columns(), batch.columns(), batch.len())?;
+        Ok(Some((batch.select(&mask), read)))
+    }
+}
+
+/// Rows produced and time taken by one operator, for EXPLAIN ANALYZE
+pub struct OperatorMetrics<'a> {
+    pub node: &'a PhysicalPlan,
+    pub rows: usize,
+    // Includes the time spent in the operator's input
+    pub elapsed: Duration,
+}
+
+/// Collects metrics for every operator built with it
+pub type Profile<'a> = RefCell<Vec<OperatorMetrics<'a>>>;
+
+/// Build the operator tree for a physical plan. Scans check `interrupt`
+/// before each batch, and operators that hold rows charge them to its
+/// memory budget. With a profile, every operator is wrapped to record
+/// its row count and elapsed time.
+pub fn build<'a>(
+    plan: &'a PhysicalPlan,
+    db: &'a Database,
+    interrupt: &'a Interrupt,
+    profile: Option<&'a Profile<'a>>,
+) -> Result<Box<dyn Operator + 'a>, DbError> {
+    let operator: Box<dyn Operator + 'a> = match plan {
+        PhysicalPlan::SeqScan { table_name, projection, limit } => {
+            let table = db.get_table(table_name)?;
+            let indices = column_indices(&table.columns, projection.as_deref())?;
+            let end = limit.map_or(table.rows.len(), |limit| limit.min(table.rows.len()));
+            Box::new(SeqScan {
+                columns: project_columns(&table.columns, &indices),
+                rows: &table.rows[..end],
+                position: 0,
+                indices,
+                interrupt,
+                counters: db.counters(),
+            })
+        }
+        PhysicalPlan::Empty { table_name } => {
+            // Only the schema is needed; no rows are read
+            let table = db.get_table(table_name)?;
+            Box::new(Empty { columns: table.columns.clone() })
+        }
+        PhysicalPlan::SampleScan { table_name, count } => {
+            let table = db.get_table(table_name)?;
+            let row_ids = eval::random_positions(table.rows.len(), *count);
+            interrupt.reserve_memory(row_ids.len() * std::mem::size_of::<usize>())?;
+            Box::new(IndexScan {
+                columns: table.columns.clone(),
+                table_rows: &table.rows,
+                row_ids: row_ids.into_iter(),
+                indices: (0..table.columns.len()).collect(),
+                interrupt,
+                counters: db.counters(),
+            })
+        }
+        PhysicalPlan::Series(series) => Box::new(SeriesScan {
+            columns: series.columns(),
+            series,
+            position: 0,
+            interrupt,
+        }),
+        PhysicalPlan::SingleRow => Box::new(SingleRow { done: false }),
+        PhysicalPlan::TableCount { table_name, aggregates } => {
+            let table = db.get_table(table_name)?;
+            let columns = aggregates.iter()
+                .map(|a| Ok(Column::new(a.to_string(), eval::aggregate_type(a, &table.columns)?)))
+                .collect::<Result<Vec<_>, DbError>>()?;
+            Box::new(TableCount { columns, rows: Some(table.rows.len()) })
+        }
+        PhysicalPlan::IndexScan { table_name, predicate, projection, .. } => {
+            let table = db.get_table(table_name)?;
+            let value = eval::evaluate_constant(&predicate.right)?;
+            let indices = column_indices(&table.columns, projection.as_deref())?;
+            let row_ids = match db.index_for(table_name, predicate) {
+                Some(index) => {
+                    db.counters().count_index_hit();
+                    index.matching_rows(&predicate.operator, &value)
+                }
+                // A variable's value may not fit the index, e.g. a FLOAT
+                // compared with an INT column; then every row is checked
+                None => {
+                    db.counters().count_scanned(table.rows.len());
+                    let mut row_ids = Vec::new();
+                    for (idx, row) in table.rows.iter().enumerate() {
+                        if eval::matches(predicate, &table.columns, row)? {
+                            row_ids.push(idx);
+                        }
+                    }
+                    row_ids
+                }
+            };
+            interrupt.reserve_memory(row_ids.len() * std::mem::size_of::<usize>())?;
+            Box::new(IndexScan {
+                columns: project_columns(&table.columns, &indices),
+                table_rows: &table.rows,
+                row_ids: row_ids.into_iter(),
+                indices,
+                interrupt,
+                counters: db.counters(),
+            })
+        }
+        PhysicalPlan::ParallelScan { table_name, predicate, projection, workers } => {
+            let table = db.get_table(table_name)?;
+            eval::check_where(predicate, &table.columns)?;
+            let indices = column_indices(&table.columns, projection.as_deref())?;
+            Box::new(ParallelScan {
+                columns: project_columns(&table.columns, &indices),
+                table_columns: &table.columns,
+                table_rows: &table.rows,
+                predicate,
+                indices,
+                workers: *workers,
+                interrupt,
+                counters: db.counters(),
+                batches: None,
+            })
+        }
+        PhysicalPlan::Filter { input, predicate } => {
+            let input = build(input, db, interrupt, profile)?;
+            eval::check_where(predicate, input.columns())?;
+            Box::new(Filter { input, predicate })
+        }
+        PhysicalPlan::HashAggregate { input, group_by, aggregates } => {
+            let input = build(input, db, interrupt, profile)?;
+            Box::new(HashAggregate::new(input, group_by, aggregates, interrupt)?)
+        }
+        PhysicalPlan::Sort { input, keys } => {
+            let input = build(input, db, interrupt, profile)?;
+            for key in keys {
+                eval::infer_type(&key.expr, input.columns())?;
+            }
+            Box::new(Sort { input, keys, interrupt, output: None })
+        }
+        PhysicalPlan::SetOperation { operator, all, left, right } => {
+            let left = build(left, db, interrupt, profile)?;
+            let right = build(right, db, interrupt, profile)?;
+            Box::new(SetOperation::new(*operator, *all, left, right, interrupt)?)
+        }
+        PhysicalPlan::Limit { input, count } => Box::new(Limit {
+            input: build(input, db, interrupt, profile)?,
+            remaining: *count,
+        }),
+        PhysicalPlan::Compute { input, exprs } => {
+            let input = build(input, db, interrupt, profile)?;
+            // Functions registered by the embedder have no declared type
+            let columns = exprs.iter()
+                .map(|expr| {
+                    let data_type = eval::infer_type(expr, input.columns())?.unwrap_or(DataType::Text);
+                    Ok(Column::new(expr.to_string(), data_type))
+                })
+                .collect::<Result<Vec<_>, DbError>>()?;
+            Box::new(Compute { columns, input, exprs })
+        }
+        PhysicalPlan::Project { input, columns: names } => {
+            let input = build(input, db, interrupt, profile)?;
+            let indices = column_indices(input.columns(), Some(names))?;
+            Box::new(Project {
+                columns: project_columns(input.columns(), &indices),
+                input,
+                indices,
+            })
+        }
+    };
+
+    Ok(match profile {
+        Some(profile) => {
+            let slot = profile.borrow().len();
+            profile.borrow_mut().push(OperatorMetrics {
+                node: plan,
+                rows: 0,
+                elapsed: Duration::ZERO,
+            });
+            Box::new(Profiled { input: operator, profile, slot })
+        }
+        None => operator,
+    })
+}
+
+/// Pull every row from an operator, charging them to the memory budget
+pub fn collect(operator: &mut dyn Operator, interrupt: &Interrupt) -> Result<Vec<Vec<Value>>, DbError> {
+    let mut rows = Vec::new();
+    while let Some(batch) = operator.next_batch()? {
+        interrupt.reserve_memory(batch.memory_size())?;
+        rows.extend(batch.into_rows());
+    }
+    Ok(rows)
+}
+
+/// Sequential scan over a table's rows
+struct SeqScan<'a> {
+    columns: Vec<Column>,
+    rows: &'a [Vec<Value>],
+    position: usize,
+    indices: Vec<usize>,
+    interrupt: &'a Interrupt,
+    counters: &'a Counters,
+}
+
+impl Operator for SeqScan<'_> {
+    fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    fn next_batch(&mut self) -> Result<Option<Batch>, DbError> {
+        if self.position >= self.rows.len() {
+            return Ok(None);
+        }
+        self.interrupt.check()?;
+        let end = (self.position + BATCH_SIZE).min(self.rows.len());
+        let batch = Batch::from_rows(&self.rows[self.position..end], &self.indices);
+        self.counters.count_scanned(end - self.position);
+        self.position = end;
+        Ok(Some(batch))
+    }
+
+    /// Evaluate the predicate over copies of just the columns it reads, then
+    /// copy whole only the rows that match
+    fn next_batch_matching(&mut self, predicate: &WhereClause) -> Result<Option<(Batch, usize)>, DbError> {
+        if self.position >= self.rows.len() {
+            return Ok(None);
+        }
+        self.interrupt.check()?;
+        let end = (self.position + BATCH_SIZE).min(self.rows.len());
+        let rows = &self.rows[self.position..end];
+        self.counters.count_scanned(rows.len());
+        self.position = end;
+
+        let mut names = eval::referenced_columns(&predicate.left);
+        names.extend(eval::referenced_columns(&predicate.right));
+        let positions: Vec<usize> = (0..self.columns.len())
+            .filter(|&i| names.contains(&self.columns[i].name.as_str()))
+            .collect();
+        let key_indices: Vec<usize> = positions.iter().map(|&i| self.indices[i]).collect();
+        let keys = Batch::from_rows(rows, &key_indices);
+        let mask = eval::matches_batch(predicate, &project_columns(&self.columns, &positions), keys.columns(), rows.len())?;
+
+        let matching = rows.iter().zip(&mask).filter_map(|(row, &keep)| keep.then_some(row));
+        Ok(Some((Batch::from_rows(matching, &self.indices), rows.len())))
+    }
+}
+
+/// Produces no rows
+struct Empty {
+    columns: Vec<Column>,
+}
+
+impl Operator for Empty {
+    fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    fn next_batch(&mut self) -> Result<Option<Batch>, DbError> {
+        Ok(None)
+    }
+}
+
+/// Produces one row with no columns, once
+struct SingleRow {
+    done: bool,
+}
+
+impl Operator for SingleRow {
+    fn columns(&self) -> &[Column] {
+        &[]
+    }
+
+    fn next_batch(&mut self) -> Result<Option<Batch>, DbError> {
+        if self.done {
+            return Ok(None);
+        }
+        self.done = true;
+        Ok(Some(Batch::from_columns(Vec::new(), 1)))
+    }
+}
+
+/// Generates the values of a series, a batch at a time
+struct SeriesScan<'a> {
+    columns: Vec<Column>,
+    series: &'a Series,
+    position: u64, // Index of the next value
+    interrupt: &'a Interrupt,
+}
+
+impl Operator for SeriesScan<'_> {
+    fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    fn next_batch(&mut self) -> Result<Option<Batch>, DbError> {
+        let len = self.series.len();
+        if self.position >= len {
+            return Ok(None);
+        }
+        self.interrupt.check()?;
+        let end = (self.position + BATCH_SIZE as u64).min(len);
+        let values = (self.position..end).map(|i| Value::Int(self.series.value(i))).collect();
+        let len = (end - self.position) as usize;
+        self.position = end;
+        Ok(Some(Batch::from_columns(vec![values], len)))
+    }
+}
+
+/// Produces one row holding a table's row count in every column
+struct TableCount {
+    columns: Vec<Column>,
+    rows: Option<usize>, // Taken once produced
+}
+
+impl Operator for TableCount {
+    fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    fn next_batch(&mut self) -> Result<Option<Batch>, DbError> {
+        Ok(self.rows.take().map(|rows| {
+            let row = vec![Value::Int(rows as i64); self.columns.len()];
+            Batch::from_rows([&row], &(0..row.len()).collect::<Vec<_>>())
+        }))
+    }
+}
+
+/// Fetches the rows an index lookup matched (or a sample chose), in table order
+struct IndexScan<'a> {
+    columns: Vec<Column>,
+    table_rows: &'a [Vec<Value>],
+    row_ids: std::vec::IntoIter<usize>,
+    indices: Vec<usize>,
+    interrupt: &'a Interrupt,
+    counters: &'a Counters,
+}
+
+impl Operator for IndexScan<'_> {
+    fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    fn next_batch(&mut self) -> Result<Option<Batch>, DbError> {
+        self.interrupt.check()?;
+        let table_rows = self.table_rows;
+        let rows = self.row_ids.by_ref()
+            .take(BATCH_SIZE)
+            .filter_map(|idx| table_rows.get(idx));
+        let batch = Batch::from_rows(rows, &self.indices);
+        self.counters.count_scanned(batch.len());
+        Ok((!batch.is_empty()).then_some(batch))
+    }
+}
+
+/// Filtered scan split into contiguous chunks, one per thread. All chunks
+/// are scanned on the first `next_batch()`; results are merged in table order
+/// and count against the memory budget until handed on.
+struct ParallelScan<'a> {
+    columns: Vec<Column>,
+    table_columns: &'a [Column],
+    table_rows: &'a [Vec<Value>],
+    predicate: &'a WhereClause,
+    indices: Vec<usize>,
+    workers: usize,
+    interrupt: &'a Interrupt,
+    counters: &'a Counters,
+    batches: Option<std::vec::IntoIter<Batch>>,
+}
+
+impl ParallelScan<'_> {
+    fn scan(&self) -> Result<Vec<Batch>, DbError> {
+        let chunk_size = self.table_rows.len().div_ceil(self.workers.max(1)).max(1);
+
+        let chunks = std::thread::scope(|scope| {
+            let handles: Vec<_> = self.table_rows.chunks(chunk_size)
+                .map(|chunk| scope.spawn(move || self.scan_chunk(chunk)))
+                .collect();
+            handles.into_iter()
+                .map(|handle| handle.join().map_err(|_| DbError::Execution("Scan worker panicked".to_string()))?)
+                .collect::<Result<Vec<_>, DbError>>()
+        })?;
+
+        Ok(chunks.into_iter().flatten().collect())
+    }
+
+    /// Evaluate the predicate over copies of just the columns it reads, as
+    /// `SeqScan` does, then copy only the matching rows
+    fn scan_chunk(&self, chunk: &[Vec<Value>]) -> Result<Vec<Batch>, DbError> {
+        let mut names = eval::referenced_columns(&self.predicate.left);
+        names.extend(eval::referenced_columns(&self.predicate.right));
+        let key_indices: Vec<usize> = (0..self.table_columns.len())
+            .filter(|&i| names.contains(&self.table_columns[i].name.as_str()))
+            .collect();
+        let key_columns = project_columns(self.table_columns, &key_indices);
+
+        let mut batches = Vec::new();
+        for rows in chunk.chunks(BATCH_SIZE) {
+            self.interrupt.check()?;
+            self.counters.count_scanned(rows.len());
+            let keys = Batch::from_rows(rows, &key_indices);
+            let mask = eval::matches_batch(self.predicate, &key_columns, keys.columns(), rows.len())?;
+            let matching = rows.iter().zip(&mask).filter_map(|(row, &keep)| keep.then_some(row));
+            let batch = Batch::from_rows(matching, &self.indices);
+            if !batch.is_empty() {
+                self.interrupt.reserve_memory(batch.memory_size())?;
+                batches.push(batch);
+            }
+        }
+        Ok(batches)
+    }
+}
+
+impl Operator for ParallelScan<'_> {
+    fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    fn next_batch(&mut self) -> Result<Option<Batch>, DbError> {
+        if self.batches.is_none() {
+            self.batches = Some(self.scan()?.into_iter());
+        }
+        let batch = self.batches.as_mut().and_then(|batches| batches.next());
+        if let Some(batch) = &batch {
+            self.interrupt.release_memory(batch.memory_size());
+        }
+        Ok(batch)
+    }
+}
+
+/// Passes through rows that satisfy a predicate
+struct Filter<'a> {
+    input: Box<dyn Operator + 'a>,
+    predicate: &'a WhereClause,
+}
+
+impl Operator for Filter<'_> {
+    fn columns(&self) -> &[Column] {
+        self.input.columns()
+    }
+
+    fn next_batch(&mut self) -> Result<Option<Batch>, DbError> {
+        while let Some((batch, _)) = self.input.next_batch_matching(self.predicate)? {
+            if !batch.is_empty() {
+                return Ok(Some(batch));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Keeps only the selected columns of each row
+struct Project<'a> {
+    columns: Vec<Column>,
+    input: Box<dyn Operator + 'a>,
+    indices: Vec<usize>,
+}
+
+impl Operator for Project<'_> {
+    fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    fn next_batch(&mut self) -> Result<Option<Batch>, DbError> {
+        Ok(self.input.next_batch()?.map(|batch| batch.project(&self.indices)))
+    }
+}
+
+/// Computes expressions over each batch of its input
+struct Compute<'a> {
+    columns: Vec<Column>,
+    input: Box<dyn Operator + 'a>,
+    exprs: &'a [Expr],
+}
+
+impl Operator for Compute<'_> {
+    fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    fn next_batch(&mut self) -> Result<Option<Batch>, DbError> {
+        let Some(batch) = self.input.next_batch()? else {
+            return Ok(None);
+        };
+        let columns = self.exprs.iter()
+            .map(|expr| {
+                eval::evaluate_batch(expr, self.input.columns(), batch.columns(), batch.len()).map(|v| v.into_owned())
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Some(Batch::from_columns(columns, batch.len())))
+    }
+}
+
+/// Reads its whole input, then produces it ordered by the sort keys. Rows
+/// with equal keys keep their input order.
+struct Sort<'a> {
+    input: Box<dyn Operator + 'a>,
+    keys: &'a [SortKey],
+    interrupt: &'a Interrupt,
+    output: Option<std::vec::IntoIter<Batch>>,
+}
+
+impl Sort<'_> {
+    fn sort(&mut self) -> Result<Vec<Vec<Value>>, DbError> {
+        let mut rows = Vec::new();
+        let mut sort_keys = Vec::new();
+        let mut key_size = 0;
+        while let Some(batch) = self.input.next_batch()? {
+            let values = self.keys.iter()
+                .map(|key| {
+                    eval::evaluate_batch(&key.expr, self.input.columns(), batch.columns(), batch.len())
+                        .map(|v| v.into_owned())
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let keys = Batch::from_columns(values, batch.len());
+            key_size += keys.memory_size();
+            self.interrupt.reserve_memory(batch.memory_size() + keys.memory_size())?;
+            sort_keys.extend(keys.into_rows());
+            rows.extend(batch.into_rows());
+        }
+
+        let mut order: Vec<usize> = (0..rows.len()).collect();
+        order.sort_by(|&a, &b| {
+            self.keys.iter()
+                .zip(sort_keys[a].iter().zip(&sort_keys[b]))
+                .map(|(key, (x, y))| {
+                    let ordering = eval::sort_order(x, y);
+                    if key.descending { ordering.reverse() } else { ordering }
+                })
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        // The keys are no longer needed; the rows are charged until handed on
+        self.interrupt.release_memory(key_size);
+        Ok(order.into_iter().map(|i| std::mem::take(&mut rows[i])).collect())
+    }
+}
+
+impl Operator for Sort<'_> {
+    fn columns(&self) -> &[Column] {
+        self.input.columns()
+    }
+
+    fn next_batch(&mut self) -> Result<Option<Batch>, DbError> {
+        if self.output.is_none() {
+            let rows = self.sort()?;
+            let all: Vec<usize> = (0..self.columns().len()).collect();
+            let batches: Vec<Batch> = rows.chunks(BATCH_SIZE)
+                .map(|chunk| Batch::from_rows(chunk, &all))
+                .collect();
+            self.output = Some(batches.into_iter());
+        }
+        // Handing a batch on passes its memory to the consumer
+        let batch = self.output.as_mut().and_then(|batches| batches.next());
+        if let Some(batch) = &batch {
+            self.interrupt.release_memory(batch.memory_size());
+        }
+        Ok(batch)
+    }
+}
+
+/// Passes through the first rows of its input, then stops pulling from it
+struct Limit<'a> {
+    input: Box<dyn Operator + 'a>,
+    remaining: usize,
+}
+
+impl Operator for Limit<'_> {
+    fn columns(&self) -> &[Column] {
+        self.input.columns()
+    }
+
+    fn next_batch(&mut self) -> Result<Option<Batch>, DbError> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        let Some(batch) = self.input.next_batch()? else {
+            return Ok(None);
+        };
+        let batch = if batch.len() > self.remaining {
+            batch.truncate(self.remaining)
+        } else {
+            batch
+        };
+        self.remaining -= batch.len();
+        Ok(Some(batch))
+    }
+}
+
+/// Records rows produced and time spent by the wrapped operator
+struct Profiled<'a> {
+    input: Box<dyn Operator + 'a>,
+    profile: &'a Profile<'a>,
+    slot: usize,
+}
+
+impl Operator for Profiled<'_> {
+    fn columns(&self) -> &[Column] {
+        self.input.columns()
+    }
+
+    fn next_batch(&mut self) -> Result<Option<Batch>, DbError> {
+        let start = Instant::now();
+        let batch = self.input.next_batch()?;
+        let elapsed = start.elapsed();
+
+        let mut profile = self.profile.borrow_mut();
+        let metrics = &mut profile[self.slot];
+        metrics.elapsed += elapsed;
+        metrics.rows += batch.as_ref().map_or(0, Batch::len);
+        Ok(batch)
+    }
+
+    fn next_batch_matching(&mut self, predicate: &WhereClause) -> Result<Option<(Batch, usize)>, DbError> {
+        let start = Instant::now();
+        let batch = self.input.next_batch_matching(predicate)?;
+        let elapsed = start.elapsed();
+
+        // The rows read, not the rows kept, are this operator's output
+        let mut profile = self.profile.borrow_mut();
+        let metrics = &mut profile[self.slot];
+        metrics.elapsed += elapsed;
+        metrics.rows += batch.as_ref().map_or(0, |(_, read)| *read);
+        Ok(batch)
+    }
+}
+
+/// Resolve projected column names to positions (all columns when None)
+fn column_indices(columns: &[Column], names: Option<&[String]>) -> Result<Vec<usize>, DbError> {
+    match names {
+        None => Ok((0..columns.len()).collect()),
+        Some(names) => names.iter()
+            .map(|name| {
+                columns.iter()
+                    .position(|c| &c.name == name)
+                    .ok_or_else(|| DbError::ColumnNotFound(name.to_string()))
+            })
+            .collect(),
+    }
+}
+
+/// Select the column definitions at the given positions
+fn project_columns(columns: &[Column], indices: &[usize]) -> Vec<Column> {
+    indices.iter().map(|&i| columns[i].clone()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::ExecutionResult;
+    use crate::parser::{DataType, Operator as CompareOp};
+
+    #[test]
+    fn test_parallel_scan_matches_sequential_order() {
+        let columns = vec![Column::new("id".to_string(), DataType::Int)];
+        let rows: Vec<Vec<Value>> = (0..3000).map(|i| vec![Value::Int(i % 7)]).collect();
+        let predicate = WhereClause {
+            left: Expr::Column("id".to_string()),
+            operator: CompareOp::Equals,
+            right: Expr::Literal(Value::Int(3)),
+        };
+
+        let mut scan = ParallelScan {
+            columns: columns.clone(),
+            table_columns: &columns,
+            table_rows: &rows,
+            predicate: &predicate,
+            indices: vec![0],
+            workers: 4,
+            interrupt: &Interrupt::default(),
+            counters: &Counters::default(),
+            batches: None,
+        };
+        let parallel = collect(&mut scan, &Interrupt::default()).unwrap();
+
+        let expected: Vec<Vec<Value>> = rows.iter()
+            .filter(|row| row[0] == Value::Int(3))
+            .cloned()
+            .collect();
+        assert_eq!(parallel, expected);
+    }
+
+    #[test]
+    fn test_memory_limit_stops_collect() {
+        let columns = vec![Column::new("name".to_string(), DataType::Text)];
+        let rows: Vec<Vec<Value>> = (0..2000).map(|i| vec![Value::Text(format!("row {}", i))]).collect();
+        let counters = Counters::default();
+        let scan = |interrupt| SeqScan {
+            columns: columns.clone(),
+            rows: &rows,
+            position: 0,
+            indices: vec![0],
+            interrupt,
+            counters: &counters,
+        };
+
+        let unlimited = Interrupt::default();
+        assert_eq!(collect(&mut scan(&unlimited), &unlimited).unwrap().len(), 2000);
+        assert!(unlimited.memory_used() > 2000 * std::mem::size_of::<Value>());
+
+        let limited = Interrupt::default().with_memory_limit(Some(16 * 1024));
+        let err = collect(&mut scan(&limited), &limited).unwrap_err();
+        assert_eq!(err, DbError::MemoryLimit(16 * 1024));
+        assert_eq!(err.to_string(), "Query exceeded its memory limit of 16384 bytes");
+    }
+
+    #[test]
+    fn test_filter_reads_scan_rows_in_place() {
+        let id = Column::new("id".to_string(), DataType::Int);
+        let name = Column::new("name".to_string(), DataType::Text);
+        let rows: Vec<Vec<Value>> = (0..3000)
+            .map(|i| vec![Value::Int(i), Value::Text(format!("row {}", i))])
+            .collect();
+        let predicate = WhereClause {
+            left: Expr::Column("id".to_string()),
+            operator: CompareOp::GreaterOrEqual,
+            right: Expr::Literal(Value::Int(2998)),
+        };
+        let interrupt = Interrupt::default();
+        let scan = SeqScan {
+            columns: vec![name, id],
+            rows: &rows,
+            position: 0,
+            indices: vec![1, 0],
+            interrupt: &interrupt,
+            counters: &Counters::default(),
+        };
+
+        let mut filter = Filter { input: Box::new(scan), predicate: &predicate };
+        assert_eq!(collect(&mut filter, &interrupt).unwrap(), vec![
+            vec![Value::Text("row 2998".to_string()), Value::Int(2998)],
+            vec![Value::Text("row 2999".to_string()), Value::Int(2999)],
+        ]);
+    }
+
+    #[test]
+    fn test_count_star_reads_row_count() {
+        let mut db = Database::new();
+        db.set_persistent(false);
+        db.create_table("counted".to_string(), vec![Column::new("id".to_string(), DataType::Int)]).unwrap();
+        db.insert_rows("counted", (0..5).map(|i| vec![Value::Int(i)]).collect()).unwrap();
+
+        let statement = crate::parser::parse("EXPLAIN SELECT COUNT(*) FROM counted").unwrap();
+        let plan = crate::planner::plan(statement, &db).unwrap();
+        assert!(matches!(
+            crate::executor::execute(plan, &mut db),
+            Ok(ExecutionResult::Success(text)) if text.starts_with("TableCount counted COUNT(*)")
+        ));
+
+        let statement = crate::parser::parse("SELECT COUNT(*) FROM counted").unwrap();
+        let plan = crate::planner::plan(statement, &db).unwrap();
+        assert_eq!(crate::executor::execute(plan, &mut db), Ok(ExecutionResult::Rows {
+            columns: vec!["COUNT(*)".to_string()],
+            rows: vec![vec![Value::Int(5)]],
+        }));
+    }
+
+    #[test]
+    fn test_generate_series_feeds_insert() {
+        let mut db = Database::new();
+        db.set_persistent(false);
+        db.create_table("numbers".to_string(), vec![Column::new("n".to_string(), DataType::Float)]).unwrap();
+        let mut run = |sql: &str| {
+            let plan = crate::planner::plan(crate::parser::parse(sql).unwrap(), &db)?;
+            crate::executor::execute(plan, &mut db)
+        };
+
+        assert_eq!(run("SELECT * FROM generate_series(10, 1, -4)"), Ok(ExecutionResult::Rows {
+            columns: vec!["generate_series".to_string()],
+            rows: vec![vec![Value::Int(10)], vec![Value::Int(6)], vec![Value::Int(2)]],
+        }));
+        assert!(run("SELECT * FROM generate_series(1, 5, 0)").is_err());
+
+        let inserted = run("INSERT INTO numbers SELECT * FROM generate_series(1, 3000) WHERE generate_series > 1000");
+        assert_eq!(inserted.unwrap().row_count(), Some(2000));
+        assert_eq!(db.get_table("numbers").unwrap().rows[0], vec![Value::Float(1001.0)]);
+    }
+
+    #[test]
+    fn test_order_by_expressions_and_ordinals() {
+        let mut db = Database::new();
+        db.set_persistent(false);
+        let mut run = |sql: &str| {
+            let plan = crate::planner::plan(crate::parser::parse(sql).unwrap(), &db)?;
+            crate::executor::execute(plan, &mut db)
+        };
+        run("CREATE TABLE items (name TEXT, price INT, quantity INT)").unwrap();
+        for values in ["'a', 2, 5", "'b', 10, 2", "'c', 3, NULL", "'d', 1, 20"] {
+            run(&format!("INSERT INTO items VALUES ({})", values)).unwrap();
+        }
+        let names = |result: Result<ExecutionResult, DbError>| match result {
+            Ok(ExecutionResult::Rows { rows, .. }) => rows.into_iter().map(|row| row[0].clone()).collect::<Vec<_>>(),
+            other => panic!("expected rows, got {:?}", other),
+        };
+
+        let text = |names: &[&str]| names.iter().map(|n| Value::Text(n.to_string())).collect::<Vec<_>>();
+
+        // NULL keys sort last ascending, so first descending; ties keep table order
+        assert_eq!(names(run("SELECT name FROM items ORDER BY price * quantity DESC")), text(&["c", "b", "d", "a"]));
+        assert_eq!(names(run("SELECT name, price FROM items ORDER BY 2 LIMIT 2")), text(&["d", "a"]));
+        assert_eq!(names(run("SELECT * FROM items WHERE price > 1 ORDER BY 3")), text(&["b", "a", "c"]));
+        assert!(run("SELECT name FROM items ORDER BY 2").is_err());
+    }
+
+    #[test]
+    fn test_select_without_from() {
+        let mut db = Database::new();
+        let plan = crate::planner::plan(crate::parser::parse("SELECT 1 + 1, UPPER('abc')").unwrap(), &db).unwrap();
+        assert_eq!(crate::executor::execute(plan, &mut db), Ok(ExecutionResult::Rows {
+            columns: vec!["1 + 1".to_string(), "UPPER('abc')".to_string()],
+            rows: vec![vec![Value::Int(2), Value::Text("ABC".to_string())]],
+        }));
+        assert!(crate::planner::plan(crate::parser::parse("SELECT name").unwrap(), &db).is_err());
+    }
+}
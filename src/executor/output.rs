@@ -0,0 +1,362 @@
+// Output - rendering execution results for display or export
+
+use super::ExecutionResult;
+use crate::color::{self, BOLD};
+use crate::parser::{array, Value};
+
+/// How result sets are rendered
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OutputMode {
+    #[default]
+    Table,
+    Csv,
+    Json,
+    Vertical,
+    Markdown,
+    Html,
+}
+
+impl OutputMode {
+    /// Look up a mode by its `.mode` name
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "table" => Some(OutputMode::Table),
+            "csv" => Some(OutputMode::Csv),
+            "json" => Some(OutputMode::Json),
+            "vertical" => Some(OutputMode::Vertical),
+            "markdown" => Some(OutputMode::Markdown),
+            "html" => Some(OutputMode::Html),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputMode::Table => "table",
+            OutputMode::Csv => "csv",
+            OutputMode::Json => "json",
+            OutputMode::Vertical => "vertical",
+            OutputMode::Markdown => "markdown",
+            OutputMode::Html => "html",
+        }
+    }
+}
+
+/// Settings controlling how results are rendered
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputOptions {
+    pub mode: OutputMode,
+    pub null_value: String,             // Shown for NULL in table and vertical output
+    pub float_precision: Option<usize>, // Decimal places; None prints full precision
+    pub max_width: Option<usize>,       // Longest table cell before truncation; None never truncates
+    pub color: bool,                    // Highlight column names with ANSI styles
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        Self {
+            mode: OutputMode::Table,
+            null_value: "NULL".to_string(),
+            float_precision: Some(2),
+            max_width: Some(40),
+            color: false,
+        }
+    }
+}
+
+/// Format execution results with the given output options
+pub fn format_results(result: ExecutionResult, options: &OutputOptions) -> String {
+    match result {
+        ExecutionResult::Success(msg) | ExecutionResult::Changed { message: msg, .. } => msg,
+        ExecutionResult::Rows { columns, rows } => match options.mode {
+            OutputMode::Table | OutputMode::Vertical if rows.is_empty() => {
+                "No rows returned".to_string()
+            }
+            OutputMode::Table => format_table(&columns, &rows, options),
+            OutputMode::Csv => format_csv(&columns, &rows),
+            OutputMode::Json => format_json(&columns, &rows),
+            OutputMode::Vertical => format_vertical(&columns, &rows, options),
+            OutputMode::Markdown => format_markdown(&columns, &rows, options),
+            OutputMode::Html => format_html(&columns, &rows, options),
+        },
+    }
+}
+
+/// Format rows as ASCII table
+fn format_table(columns: &[String], rows: &[Vec<Value>], options: &OutputOptions) -> String {
+    // Calculate column widths
+    let mut widths: Vec<usize> = columns.iter().map(|c| c.chars().count()).collect();
+    
+    for row in rows {
+        for (i, val) in row.iter().enumerate() {
+            if i < widths.len() {
+                let len = table_cell(val, options).chars().count();
+                if len > widths[i] {
+                    widths[i] = len;
+                }
+            }
+        }
+    }
+
+    // Build table
+    let mut output = String::new();
+    
+    // Top border
+    output.push('+');
+    for width in &widths {
+        output.push_str(&"-".repeat(width + 2));
+        output.push('+');
+    }
+    output.push('\n');
+
+    // Header
+    output.push('|');
+    for (i, col) in columns.iter().enumerate() {
+        let header = format!("{:width$}", col, width = widths[i]);
+        output.push_str(&format!(" {} ", color::paint(&header, BOLD, options.color)));
+        output.push('|');
+    }
+    output.push('\n');
+
+    // Middle border
+    output.push('+');
+    for width in &widths {
+        output.push_str(&"-".repeat(width + 2));
+        output.push('+');
+    }
+    output.push('\n');
+
+    // Rows
+    for row in rows {
+        output.push('|');
+        for (i, val) in row.iter().enumerate() {
+            let val_str = table_cell(val, options);
+            output.push_str(&format!(" {:width$} ", val_str, width = widths[i]));
+            output.push('|');
+        }
+        output.push('\n');
+    }
+
+    // Bottom border
+    output.push('+');
+    for width in &widths {
+        output.push_str(&"-".repeat(width + 2));
+        output.push('+');
+    }
+    output.push('\n');
+
+    // Add row count
+    output.push_str(&format!("{} row(s) returned\n", rows.len()));
+
+    output
+}
+
+/// Display string for a table cell, cut to the maximum width with an ellipsis
+fn table_cell(value: &Value, options: &OutputOptions) -> String {
+    let text = value_to_string(value, options);
+    match options.max_width {
+        Some(max) if max > 0 && text.chars().count() > max => {
+            let mut cut: String = text.chars().take(max - 1).collect();
+            cut.push('…');
+            cut
+        }
+        _ => text,
+    }
+}
+
+/// Convert Value to display string
+fn value_to_string(value: &Value, options: &OutputOptions) -> String {
+    match value {
+        Value::Int(n) => n.to_string(),
+        Value::Text(s) => s.clone(),
+        Value::Float(f) => match options.float_precision {
+            Some(precision) => format!("{:.*}", precision, f),
+            None => f.to_string(),
+        },
+        Value::Array(values) => array::format_array(values),
+        Value::Null => options.null_value.clone(),
+    }
+}
+
+/// Format each row as a block of "column: value" lines, with the column
+/// names right-aligned, for tables too wide to read as a grid
+fn format_vertical(columns: &[String], rows: &[Vec<Value>], options: &OutputOptions) -> String {
+    let width = columns.iter().map(|c| c.len()).max().unwrap_or(0);
+    let mut output = String::new();
+
+    for (n, row) in rows.iter().enumerate() {
+        output.push_str(&format!("{:*^59}\n", format!(" {}. row ", n + 1)));
+        for (column, value) in columns.iter().zip(row) {
+            let name = format!("{:>width$}", column, width = width);
+            output.push_str(&format!(
+                "{}: {}\n",
+                color::paint(&name, BOLD, options.color),
+                value_to_string(value, options)
+            ));
+        }
+    }
+
+    output.push_str(&format!("{} row(s) returned\n", rows.len()));
+    output
+}
+
+/// Format rows as a GitHub-flavored Markdown table
+fn format_markdown(columns: &[String], rows: &[Vec<Value>], options: &OutputOptions) -> String {
+    let cell = |text: &str| text.replace('|', "\\|").replace('\n', "<br>");
+    let line = |cells: Vec<String>| format!("| {} |\n", cells.join(" | "));
+
+    let mut output = line(columns.iter().map(|c| cell(c)).collect());
+    output.push_str(&line(columns.iter().map(|_| "---".to_string()).collect()));
+    for row in rows {
+        output.push_str(&line(row.iter().map(|v| cell(&value_to_string(v, options))).collect()));
+    }
+    output
+}
+
+/// Format rows as an HTML table with a header row
+fn format_html(columns: &[String], rows: &[Vec<Value>], options: &OutputOptions) -> String {
+    let line = |tag: &str, cells: Vec<String>| {
+        let cells: Vec<String> = cells.iter()
+            .map(|text| format!("<{}>{}</{}>", tag, html_escape(text), tag))
+            .collect();
+        format!("  <tr>{}</tr>\n", cells.concat())
+    };
+
+    let mut output = String::from("<table>\n");
+    output.push_str(&line("th", columns.to_vec()));
+    for row in rows {
+        output.push_str(&line("td", row.iter().map(|v| value_to_string(v, options)).collect()));
+    }
+    output.push_str("</table>");
+    output
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Format rows as RFC 4180 CSV with a header line. NULL is an empty field,
+/// while empty text is written as `""` to tell them apart.
+fn format_csv(columns: &[String], rows: &[Vec<Value>]) -> String {
+    let mut output = String::new();
+    let header: Vec<String> = columns.iter().map(|c| csv_field(c)).collect();
+    output.push_str(&header.join(","));
+    output.push_str("\r\n");
+
+    for row in rows {
+        let fields: Vec<String> = row.iter()
+            .map(|value| match value {
+                Value::Null => String::new(),
+                Value::Text(s) if s.is_empty() => "\"\"".to_string(),
+                Value::Text(s) => csv_field(s),
+                Value::Int(n) => n.to_string(),
+                Value::Float(f) => f.to_string(),
+                Value::Array(values) => csv_field(&array::format_array(values)),
+            })
+            .collect();
+        output.push_str(&fields.join(","));
+        output.push_str("\r\n");
+    }
+
+    output
+}
+
+/// Quote a CSV field if it contains a delimiter, quote, or line break
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// Format rows as a JSON array with one object per row, keyed by column name
+fn format_json(columns: &[String], rows: &[Vec<Value>]) -> String {
+    if rows.is_empty() {
+        return "[]".to_string();
+    }
+
+    let objects: Vec<String> = rows.iter()
+        .map(|row| {
+            let fields: Vec<String> = columns.iter()
+                .zip(row)
+                .map(|(column, value)| format!("{}: {}", json_string(column), json_value(value)))
+                .collect();
+            format!("  {{{}}}", fields.join(", "))
+        })
+        .collect();
+    format!("[\n{}\n]", objects.join(",\n"))
+}
+
+/// A value as a JSON literal; NULL and non-finite floats become null
+pub fn json_value(value: &Value) -> String {
+    match value {
+        Value::Int(n) => n.to_string(),
+        // JSON has no NaN or infinity
+        Value::Float(f) if f.is_finite() => f.to_string(),
+        Value::Float(_) | Value::Null => "null".to_string(),
+        Value::Text(s) => json_string(s),
+        Value::Array(values) => {
+            let elements: Vec<String> = values.iter().map(json_value).collect();
+            format!("[{}]", elements.join(","))
+        }
+    }
+}
+
+/// Quote and escape a string as a JSON string literal
+pub fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_quotes_special_fields() {
+        let result = ExecutionResult::Rows {
+            columns: vec!["id".to_string(), "note".to_string()],
+            rows: vec![
+                vec![Value::Int(1), Value::Text("plain".to_string())],
+                vec![Value::Float(2.5), Value::Text("a, \"b\"\nc".to_string())],
+                vec![Value::Null, Value::Text(String::new())],
+            ],
+        };
+        assert_eq!(
+            format_results(result, &OutputOptions { mode: OutputMode::Csv, ..Default::default() }),
+            "id,note\r\n1,plain\r\n2.5,\"a, \"\"b\"\"\nc\"\r\n,\"\"\r\n"
+        );
+    }
+
+    #[test]
+    fn test_json_objects_keyed_by_column() {
+        let result = ExecutionResult::Rows {
+            columns: vec!["id".to_string(), "note".to_string()],
+            rows: vec![
+                vec![Value::Int(1), Value::Text("say \"hi\"\n".to_string())],
+                vec![Value::Float(0.5), Value::Null],
+            ],
+        };
+        assert_eq!(
+            format_results(result, &OutputOptions { mode: OutputMode::Json, ..Default::default() }),
+            "[\n  {\"id\": 1, \"note\": \"say \\\"hi\\\"\\n\"},\n  {\"id\": 0.5, \"note\": null}\n]"
+        );
+    }
+}
@@ -0,0 +1,300 @@
+// Parquet - writing result sets as Apache Parquet files
+//
+// A minimal writer for tools like DuckDB, Polars, and Spark: one row group
+// with one uncompressed, PLAIN-encoded data page per column. INT columns are
+// stored as INT64, FLOAT as DOUBLE, and TEXT as UTF-8 BYTE_ARRAY, all
+// OPTIONAL so NULLs survive. Arrays are written as text in their {a,b} form. The file metadata is encoded with Thrift's
+// compact protocol, as the format requires.
+
+use crate::error::DbError;
+use crate::parser::{array, Column, DataType, Value};
+
+const MAGIC: &[u8] = b"PAR1";
+
+// Parquet enum values, from parquet.thrift
+const TYPE_INT64: i32 = 2;
+const TYPE_DOUBLE: i32 = 5;
+const TYPE_BYTE_ARRAY: i32 = 6;
+const REPETITION_OPTIONAL: i32 = 1;
+const CONVERTED_TYPE_UTF8: i32 = 0;
+const ENCODING_PLAIN: i32 = 0;
+const ENCODING_RLE: i32 = 3;
+const CODEC_UNCOMPRESSED: i32 = 0;
+const PAGE_DATA: i32 = 0;
+
+// Thrift compact protocol type codes
+const COMPACT_I32: u8 = 5;
+const COMPACT_I64: u8 = 6;
+const COMPACT_BINARY: u8 = 8;
+const COMPACT_LIST: u8 = 9;
+const COMPACT_STRUCT: u8 = 12;
+
+/// Write rows to a Parquet file at `path`, with a column per entry in `columns`
+pub fn write_parquet(path: &str, columns: &[Column], rows: &[Vec<Value>]) -> Result<(), DbError> {
+    let bytes = encode_parquet(columns, rows)?;
+    std::fs::write(path, bytes).map_err(|e| DbError::Io(format!("Failed to write '{}': {}", path, e)))
+}
+
+/// Columns for a query's result, typed by each column's first non-NULL value
+pub fn infer_columns(names: &[String], rows: &[Vec<Value>]) -> Vec<Column> {
+    names.iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let data_type = match rows.iter().map(|row| &row[i]).find(|v| **v != Value::Null) {
+                Some(Value::Int(_)) => DataType::Int,
+                Some(Value::Float(_)) => DataType::Float,
+                _ => DataType::Text,
+            };
+            Column::new(name.clone(), data_type)
+        })
+        .collect()
+}
+
+/// The bytes of a Parquet file holding the rows
+pub fn encode_parquet(columns: &[Column], rows: &[Vec<Value>]) -> Result<Vec<u8>, DbError> {
+    let mut file = MAGIC.to_vec();
+    let mut chunks = Vec::new();
+    for (i, column) in columns.iter().enumerate() {
+        let page = data_page(column, rows.iter().map(|row| &row[i]))?;
+
+        let mut header = Compact::default();
+        header.i32(1, PAGE_DATA);
+        header.i32(2, page.len() as i32); // Uncompressed size
+        header.i32(3, page.len() as i32); // Compressed size
+        header.begin_struct(5); // DataPageHeader
+        header.i32(1, rows.len() as i32);
+        header.i32(2, ENCODING_PLAIN);
+        header.i32(3, ENCODING_RLE); // Definition levels
+        header.i32(4, ENCODING_RLE); // Repetition levels
+        header.end_struct();
+        header.end_struct();
+
+        let offset = file.len() as i64;
+        let size = (header.bytes.len() + page.len()) as i64;
+        file.extend(header.bytes);
+        file.extend(page);
+        chunks.push((offset, size));
+    }
+
+    let mut meta = Compact::default();
+    meta.i32(1, 1); // Format version
+    meta.list(2, COMPACT_STRUCT, columns.len() + 1);
+    meta.begin_element();
+    meta.binary(4, b"schema");
+    meta.i32(5, columns.len() as i32);
+    meta.end_struct();
+    for column in columns {
+        meta.begin_element();
+        meta.i32(1, physical_type(&column.data_type));
+        meta.i32(3, REPETITION_OPTIONAL);
+        meta.binary(4, column.name.as_bytes());
+        if matches!(column.data_type, DataType::Text | DataType::Array(_)) {
+            meta.i32(6, CONVERTED_TYPE_UTF8);
+        }
+        meta.end_struct();
+    }
+    meta.i64(3, rows.len() as i64);
+
+    meta.list(4, COMPACT_STRUCT, 1);
+    meta.begin_element(); // RowGroup
+    meta.list(1, COMPACT_STRUCT, columns.len());
+    for (column, &(offset, size)) in columns.iter().zip(&chunks) {
+        meta.begin_element(); // ColumnChunk
+        meta.i64(2, offset);
+        meta.begin_struct(3); // ColumnMetaData
+        meta.i32(1, physical_type(&column.data_type));
+        meta.list(2, COMPACT_I32, 2);
+        meta.element_i32(ENCODING_PLAIN);
+        meta.element_i32(ENCODING_RLE);
+        meta.list(3, COMPACT_BINARY, 1);
+        meta.element_binary(column.name.as_bytes());
+        meta.i32(4, CODEC_UNCOMPRESSED);
+        meta.i64(5, rows.len() as i64);
+        meta.i64(6, size);
+        meta.i64(7, size);
+        meta.i64(9, offset);
+        meta.end_struct();
+        meta.end_struct();
+    }
+    meta.i64(2, chunks.iter().map(|&(_, size)| size).sum());
+    meta.i64(3, rows.len() as i64);
+    meta.end_struct();
+    meta.binary(6, b"mini_sql_db");
+    meta.end_struct();
+
+    file.extend(&meta.bytes);
+    file.extend((meta.bytes.len() as u32).to_le_bytes());
+    file.extend(MAGIC);
+    Ok(file)
+}
+
+fn physical_type(data_type: &DataType) -> i32 {
+    match data_type {
+        DataType::Int => TYPE_INT64,
+        DataType::Float => TYPE_DOUBLE,
+        DataType::Text | DataType::Array(_) => TYPE_BYTE_ARRAY,
+    }
+}
+
+/// A data page: definition levels (1 for a value, 0 for NULL) as runs of the
+/// RLE hybrid encoding, then the non-NULL values PLAIN-encoded
+fn data_page<'a>(column: &Column, values: impl Iterator<Item = &'a Value>) -> Result<Vec<u8>, DbError> {
+    let mut levels = Vec::new();
+    let mut plain = Vec::new();
+    let mut run: Option<(u8, u32)> = None;
+    for value in values {
+        let level = u8::from(*value != Value::Null);
+        match (&column.data_type, value) {
+            (_, Value::Null) => {}
+            (DataType::Int, Value::Int(n)) => plain.extend(n.to_le_bytes()),
+            (DataType::Float, Value::Float(f)) => plain.extend(f.to_le_bytes()),
+            (DataType::Float, Value::Int(n)) => plain.extend((*n as f64).to_le_bytes()),
+            (DataType::Text, Value::Text(s)) => {
+                plain.extend((s.len() as u32).to_le_bytes());
+                plain.extend(s.as_bytes());
+            }
+            (DataType::Text | DataType::Array(_), Value::Array(values)) => {
+                let text = array::format_array(values);
+                plain.extend((text.len() as u32).to_le_bytes());
+                plain.extend(text.as_bytes());
+            }
+            (data_type, value) => {
+                return Err(DbError::Type(format!(
+                    "{:?} in column '{}' cannot be written as {}",
+                    value, column.name, data_type
+                )));
+            }
+        }
+        run = match run {
+            Some((current, count)) if current == level => Some((current, count + 1)),
+            Some((current, count)) => {
+                put_run(&mut levels, current, count);
+                Some((level, 1))
+            }
+            None => Some((level, 1)),
+        };
+    }
+    if let Some((level, count)) = run {
+        put_run(&mut levels, level, count);
+    }
+
+    let mut page = (levels.len() as u32).to_le_bytes().to_vec();
+    page.extend(levels);
+    page.extend(plain);
+    Ok(page)
+}
+
+/// An RLE run of a 1-bit value: its length shifted left once, then the value
+fn put_run(out: &mut Vec<u8>, value: u8, count: u32) {
+    put_varint(out, u64::from(count) << 1);
+    out.push(value);
+}
+
+fn put_varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+/// Encodes Thrift structs with the compact protocol. Field ids are written
+/// as deltas from the previous field of the same struct.
+#[derive(Default)]
+struct Compact {
+    bytes: Vec<u8>,
+    last_field: i16,
+    outer_fields: Vec<i16>, // Last field ids of the enclosing structs
+}
+
+impl Compact {
+    fn field(&mut self, id: i16, kind: u8) {
+        let delta = id - self.last_field;
+        if (1..=15).contains(&delta) {
+            self.bytes.push((delta as u8) << 4 | kind);
+        } else {
+            self.bytes.push(kind);
+            put_varint(&mut self.bytes, zigzag(id as i64));
+        }
+        self.last_field = id;
+    }
+
+    fn i32(&mut self, id: i16, value: i32) {
+        self.field(id, COMPACT_I32);
+        put_varint(&mut self.bytes, zigzag(value as i64));
+    }
+
+    fn i64(&mut self, id: i16, value: i64) {
+        self.field(id, COMPACT_I64);
+        put_varint(&mut self.bytes, zigzag(value));
+    }
+
+    fn binary(&mut self, id: i16, value: &[u8]) {
+        self.field(id, COMPACT_BINARY);
+        self.element_binary(value);
+    }
+
+    fn list(&mut self, id: i16, element_kind: u8, len: usize) {
+        self.field(id, COMPACT_LIST);
+        if len < 15 {
+            self.bytes.push((len as u8) << 4 | element_kind);
+        } else {
+            self.bytes.push(0xF0 | element_kind);
+            put_varint(&mut self.bytes, len as u64);
+        }
+    }
+
+    fn element_i32(&mut self, value: i32) {
+        put_varint(&mut self.bytes, zigzag(value as i64));
+    }
+
+    fn element_binary(&mut self, value: &[u8]) {
+        put_varint(&mut self.bytes, value.len() as u64);
+        self.bytes.extend_from_slice(value);
+    }
+
+    /// Start a struct-valued field
+    fn begin_struct(&mut self, id: i16) {
+        self.field(id, COMPACT_STRUCT);
+        self.begin_element();
+    }
+
+    /// Start a struct that is an element of a list
+    fn begin_element(&mut self) {
+        self.outer_fields.push(self.last_field);
+        self.last_field = 0;
+    }
+
+    fn end_struct(&mut self) {
+        self.bytes.push(0);
+        self.last_field = self.outer_fields.pop().unwrap_or(0);
+    }
+}
+
+fn zigzag(n: i64) -> u64 {
+    ((n << 1) ^ (n >> 63)) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_and_footer() {
+        let columns = vec![Column::new("n".to_string(), DataType::Int)];
+        let rows = vec![vec![Value::Int(5)], vec![Value::Null], vec![Value::Null]];
+
+        // Levels [1, 0, 0] as two runs, then the one value
+        let page = data_page(&columns[0], rows.iter().map(|r| &r[0])).unwrap();
+        assert_eq!(page[..8], [4, 0, 0, 0, 2, 1, 4, 0]);
+        assert_eq!(page[8..], 5i64.to_le_bytes());
+
+        let file = encode_parquet(&columns, &rows).unwrap();
+        assert!(file.starts_with(MAGIC) && file.ends_with(MAGIC));
+        let footer_len = u32::from_le_bytes(file[file.len() - 8..file.len() - 4].try_into().unwrap()) as usize;
+        assert_eq!(file[file.len() - 8 - footer_len], 0x15); // Field 1, i32: the format version
+
+        let text = vec![Column::new("s".to_string(), DataType::Text)];
+        assert!(encode_parquet(&text, &[vec![Value::Int(1)]]).is_err());
+    }
+}
@@ -0,0 +1,202 @@
+// Rows - streaming query results for library callers
+//
+// Rows are pulled from the operator tree one batch at a time as the caller
+// iterates, so a large result set never has to be held in memory at once.
+
+use super::cancel::Interrupt;
+use super::operator::{self, Operator};
+use crate::error::DbError;
+use crate::parser::{Column, Value};
+use crate::planner::PhysicalPlan;
+use crate::storage::Database;
+use std::sync::Arc;
+
+/// One result row, with a value per output column
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    columns: Arc<[String]>, // Shared by every row of a result
+    values: Vec<Value>,
+}
+
+impl Row {
+    pub fn new(columns: Arc<[String]>, values: Vec<Value>) -> Self {
+        Self { columns, values }
+    }
+
+    /// The value of a column converted to a Rust type, e.g.
+    /// `row.get::<i64>("id")` or `row.get::<Option<String>>("name")`
+    pub fn get<T: FromValue>(&self, column: &str) -> Result<T, DbError> {
+        let index = self.columns.iter()
+            .position(|c| c == column)
+            .ok_or_else(|| DbError::ColumnNotFound(column.to_string()))?;
+        T::from_value(&self.values[index]).map_err(|e| e.context(format!("Column '{}'", column)))
+    }
+
+    /// Names of the row's columns
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    pub fn values(&self) -> &[Value] {
+        &self.values
+    }
+
+    pub fn into_values(self) -> Vec<Value> {
+        self.values
+    }
+}
+
+/// Conversion from a column value, used by `Row::get`
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> Result<Self, DbError>;
+}
+
+/// SQL type name of a value, for conversion errors
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Int(_) => "INT",
+        Value::Float(_) => "FLOAT",
+        Value::Text(_) => "TEXT",
+        Value::Array(_) => "array",
+        Value::Null => "NULL",
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: &Value) -> Result<Self, DbError> {
+        match value {
+            Value::Int(n) => Ok(*n),
+            other => Err(DbError::Type(format!("expected INT, got {}", type_name(other)))),
+        }
+    }
+}
+
+impl FromValue for f64 {
+    /// Integers widen to floats
+    fn from_value(value: &Value) -> Result<Self, DbError> {
+        match value {
+            Value::Float(f) => Ok(*f),
+            Value::Int(n) => Ok(*n as f64),
+            other => Err(DbError::Type(format!("expected FLOAT, got {}", type_name(other)))),
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Result<Self, DbError> {
+        match value {
+            Value::Text(s) => Ok(s.clone()),
+            other => Err(DbError::Type(format!("expected TEXT, got {}", type_name(other)))),
+        }
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(value: &Value) -> Result<Self, DbError> {
+        Ok(value.clone())
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    /// Each element is converted, so `Vec<Option<i64>>` reads an INT[]
+    /// that may hold NULLs
+    fn from_value(value: &Value) -> Result<Self, DbError> {
+        match value {
+            Value::Array(values) => values.iter().map(T::from_value).collect(),
+            other => Err(DbError::Type(format!("expected an array, got {}", type_name(other)))),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    /// NULL becomes None; only non-NULL values need converting
+    fn from_value(value: &Value) -> Result<Self, DbError> {
+        match value {
+            Value::Null => Ok(None),
+            other => T::from_value(other).map(Some),
+        }
+    }
+}
+
+/// Cursor over the rows of a running query
+pub struct Rows<'a> {
+    root: Box<dyn Operator + 'a>,
+    names: Arc<[String]>,
+    batch: std::vec::IntoIter<Vec<Value>>,
+    done: bool,
+}
+
+impl<'a> Rows<'a> {
+    /// Start running a query plan; rows are produced as the cursor advances
+    pub fn new(plan: &'a PhysicalPlan, db: &'a Database, interrupt: &'a Interrupt) -> Result<Self, DbError> {
+        let root = operator::build(plan, db, interrupt, None)?;
+        let names = root.columns().iter().map(|c| c.name.clone()).collect();
+        Ok(Self {
+            root,
+            names,
+            batch: Vec::new().into_iter(),
+            done: false,
+        })
+    }
+
+    /// Columns of the rows the query produces
+    pub fn columns(&self) -> &[Column] {
+        self.root.columns()
+    }
+
+    /// Pull the next batch from the operator tree, stopping after an error
+    fn fill(&mut self) -> Result<bool, DbError> {
+        match self.root.next_batch() {
+            Ok(Some(batch)) => {
+                self.batch = batch.into_rows().into_iter();
+                Ok(true)
+            }
+            Ok(None) => {
+                self.done = true;
+                Ok(false)
+            }
+            Err(e) => {
+                self.done = true;
+                Err(e)
+            }
+        }
+    }
+}
+
+impl Iterator for Rows<'_> {
+    type Item = Result<Row, DbError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(values) = self.batch.next() {
+                return Some(Ok(Row::new(Arc::clone(&self.names), values)));
+            }
+            if self.done {
+                return None;
+            }
+            match self.fill() {
+                Ok(true) => continue,
+                Ok(false) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_typed_column_access() {
+        let columns: Arc<[String]> = ["id", "name", "score"].iter().map(|c| c.to_string()).collect();
+        let row = Row::new(columns, vec![Value::Int(7), Value::Null, Value::Int(3)]);
+
+        assert_eq!(row.get::<i64>("id"), Ok(7));
+        assert_eq!(row.get::<Option<String>>("name"), Ok(None));
+        assert_eq!(row.get::<f64>("score"), Ok(3.0));
+        assert_eq!(row.get::<String>("id"), Err(DbError::Type("Column 'id': expected TEXT, got INT".to_string())));
+        assert_eq!(row.get::<String>("name"), Err(DbError::Type("Column 'name': expected TEXT, got NULL".to_string())));
+        assert_eq!(row.get::<i64>("age"), Err(DbError::ColumnNotFound("age".to_string())));
+    }
+}
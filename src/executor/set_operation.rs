@@ -0,0 +1,194 @@
+// Set operations - UNION, INTERSECT, and EXCEPT over two inputs
+//
+// UNION ALL passes through the left input's batches and then the right's.
+// UNION removes duplicates as rows stream through, remembering each row
+// seen in a hash set. INTERSECT and EXCEPT first count the rows of the right
+// input in a hash table, then stream the left input against it: with ALL a
+// row is kept (INTERSECT) or dropped (EXCEPT) as many times as it occurs on
+// the right, and without ALL each distinct row appears at most once. Rows
+// are compared whole, with NULLs equal to each other, as in standard SQL.
+// The hash table is charged to the statement's memory budget.
+
+use super::batch::Batch;
+use super::cancel::Interrupt;
+use super::operator::Operator;
+use crate::error::DbError;
+use crate::parser::{Column, SetOperator, Value};
+use crate::storage::{self, btree::IndexKey};
+use std::collections::HashMap;
+
+/// Combines the rows of two inputs with the same number of columns
+pub struct SetOperation<'a> {
+    operator: SetOperator,
+    all: bool,
+    left: Box<dyn Operator + 'a>,
+    right: Box<dyn Operator + 'a>,
+    interrupt: &'a Interrupt,
+    /// UNION: rows already produced. INTERSECT and EXCEPT: how many times
+    /// each row of the right input is still to be matched.
+    rows: HashMap<Vec<IndexKey>, usize>,
+    right_counted: bool,
+    left_done: bool,
+}
+
+impl<'a> SetOperation<'a> {
+    pub fn new(
+        operator: SetOperator,
+        all: bool,
+        left: Box<dyn Operator + 'a>,
+        right: Box<dyn Operator + 'a>,
+        interrupt: &'a Interrupt,
+    ) -> Result<Self, DbError> {
+        if left.columns().len() != right.columns().len() {
+            return Err(DbError::Execution(format!(
+                "Each query in a {} must have the same number of columns, got {} and {}",
+                operator,
+                left.columns().len(),
+                right.columns().len()
+            )));
+        }
+        Ok(Self {
+            operator,
+            all,
+            left,
+            right,
+            interrupt,
+            rows: HashMap::new(),
+            right_counted: false,
+            left_done: false,
+        })
+    }
+
+    /// Count the rows of the right input, for INTERSECT and EXCEPT
+    fn count_right(&mut self) -> Result<(), DbError> {
+        while let Some(batch) = self.right.next_batch()? {
+            for row in batch.into_rows() {
+                *self.entry(&row)? += 1;
+            }
+        }
+        self.right_counted = true;
+        Ok(())
+    }
+
+    /// A row's count, starting at zero and charged to the memory budget
+    /// the first time the row is seen
+    fn entry(&mut self, row: &[Value]) -> Result<&mut usize, DbError> {
+        let key: Vec<IndexKey> = row.iter().map(IndexKey::from).collect();
+        if !self.rows.contains_key(&key) {
+            self.interrupt.reserve_memory(storage::row_size(row))?;
+        }
+        Ok(self.rows.entry(key).or_insert(0))
+    }
+
+    /// Whether to produce a row of the left input, or of the right for UNION
+    fn keep(&mut self, row: &[Value]) -> Result<bool, DbError> {
+        if self.operator == SetOperator::Union || (self.operator == SetOperator::Except && !self.all) {
+            // A produced row is remembered with a count of one, so later
+            // copies are dropped like rows of the right input
+            return Ok(std::mem::replace(self.entry(row)?, 1) == 0);
+        }
+        let key: Vec<IndexKey> = row.iter().map(IndexKey::from).collect();
+        let count = self.rows.get_mut(&key);
+        Ok(match (self.operator, count) {
+            (SetOperator::Intersect, Some(count)) if *count > 0 => {
+                // Without ALL a matched row's count drops to zero, so it is
+                // produced once
+                *count = if self.all { *count - 1 } else { 0 };
+                true
+            }
+            (SetOperator::Intersect, _) => false,
+            (_, Some(count)) if *count > 0 => {
+                *count -= 1;
+                false
+            }
+            _ => true,
+        })
+    }
+}
+
+impl Operator for SetOperation<'_> {
+    fn columns(&self) -> &[Column] {
+        self.left.columns()
+    }
+
+    fn next_batch(&mut self) -> Result<Option<Batch>, DbError> {
+        if self.operator != SetOperator::Union && !self.right_counted {
+            self.count_right()?;
+        }
+        loop {
+            let batch = if !self.left_done {
+                match self.left.next_batch()? {
+                    Some(batch) => batch,
+                    None => {
+                        self.left_done = true;
+                        continue;
+                    }
+                }
+            } else if self.operator == SetOperator::Union {
+                match self.right.next_batch()? {
+                    Some(batch) => batch,
+                    None => return Ok(None),
+                }
+            } else {
+                return Ok(None);
+            };
+
+            if self.operator == SetOperator::Union && self.all {
+                return Ok(Some(batch));
+            }
+            let mut mask = Vec::with_capacity(batch.len());
+            let mut row = Vec::with_capacity(batch.columns().len());
+            for i in 0..batch.len() {
+                row.clear();
+                row.extend(batch.columns().iter().map(|column| column[i].clone()));
+                mask.push(self.keep(&row)?);
+            }
+            let batch = batch.select(&mask);
+            if !batch.is_empty() {
+                return Ok(Some(batch));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::DbError;
+    use crate::executor::ExecutionResult;
+    use crate::parser::Value;
+    use crate::storage::Database;
+
+    #[test]
+    fn test_duplicates_in_set_operations() {
+        let mut db = Database::new();
+        db.set_persistent(false);
+        let mut run = |sql: &str| {
+            let plan = crate::planner::plan(crate::parser::parse(sql).unwrap(), &db)?;
+            crate::executor::execute(plan, &mut db)
+        };
+        run("CREATE TABLE a (n INT)").unwrap();
+        run("CREATE TABLE b (n INT)").unwrap();
+        for n in ["1", "1", "1", "2", "NULL", "NULL"] {
+            run(&format!("INSERT INTO a VALUES ({})", n)).unwrap();
+        }
+        for n in ["1", "3", "NULL"] {
+            run(&format!("INSERT INTO b VALUES ({})", n)).unwrap();
+        }
+        let values = |result: Result<ExecutionResult, DbError>| match result {
+            Ok(ExecutionResult::Rows { rows, .. }) => rows.into_iter().map(|row| row[0].clone()).collect::<Vec<_>>(),
+            other => panic!("expected rows, got {:?}", other),
+        };
+        let ints = |ns: &[i64]| ns.iter().map(|&n| Value::Int(n)).collect::<Vec<_>>();
+
+        assert_eq!(values(run("SELECT n FROM a UNION SELECT n FROM b ORDER BY n")), [ints(&[1, 2, 3]), vec![Value::Null]].concat());
+        assert_eq!(values(run("SELECT n FROM a UNION ALL SELECT n FROM b")).len(), 9);
+        // NULLs match each other
+        assert_eq!(values(run("SELECT n FROM a INTERSECT SELECT n FROM b")), [ints(&[1]), vec![Value::Null]].concat());
+        assert_eq!(values(run("SELECT n FROM a INTERSECT ALL SELECT n FROM a WHERE n = 1")), ints(&[1, 1, 1]));
+        assert_eq!(values(run("SELECT n FROM a EXCEPT SELECT n FROM b")), ints(&[2]));
+        assert_eq!(values(run("SELECT n FROM a EXCEPT ALL SELECT n FROM b")), [ints(&[1, 1, 2]), vec![Value::Null]].concat());
+        // INTERSECT binds tighter than EXCEPT
+        assert_eq!(values(run("SELECT n FROM a EXCEPT SELECT n FROM a INTERSECT SELECT n FROM b")), ints(&[2]));
+        assert!(run("SELECT n FROM a UNION SELECT n, n FROM b").is_err());
+    }
+}
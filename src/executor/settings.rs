@@ -0,0 +1,245 @@
+// Settings - runtime configuration of a session
+//
+// `SET name = value` (or `PRAGMA name = value`) changes a setting for the
+// rest of the session, and `PRAGMA name` shows it. The statement timeout and
+// memory limit bound each statement the session runs. With autocommit off,
+// changes stay in memory until autocommit is turned back on (or the REPL
+// exits), so a batch of statements is written once instead of after each.
+// In dry-run mode, UPDATE and DELETE report how many rows they would change
+// (or, with `show`, list them) without changing anything. The output mode
+// and safe mode are applied by the REPL. Variables set by
+// `SET @name = value` give `@name` its value in the session's later
+// statements, bound into each plan just before it runs.
+
+use super::{execute_with_interrupt, CancelToken, ExecutionResult, Interrupt, OutputMode, OutputOptions};
+use crate::error::DbError;
+use crate::eval;
+use crate::parser::Value;
+use crate::planner::Plan;
+use crate::storage::Database;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Names of the settings, as given to SET and PRAGMA
+pub const SETTING_NAMES: &[&str] = &[
+    "autocommit", "dry_run", "memory_limit", "output_mode", "safe_mode", "statement_timeout",
+];
+
+const MB: usize = 1024 * 1024;
+
+/// What UPDATE and DELETE do in dry-run mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DryRun {
+    #[default]
+    Off,
+    /// Report how many rows would change
+    On,
+    /// List the rows that would change, as they would be after an UPDATE
+    Show,
+}
+
+/// Settings of one session: a REPL or a client connection
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub autocommit: bool, // Write changes to disk after each statement
+    pub safe: bool,       // Confirm statements that change every row of a table
+    pub dry_run: DryRun,
+    pub statement_timeout: Option<Duration>,
+    pub memory_limit: Option<usize>, // Bytes
+    pub output: OutputOptions,
+    pub variables: HashMap<String, Value>, // Set by SET @name = value
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            autocommit: true,
+            safe: false,
+            dry_run: DryRun::Off,
+            statement_timeout: None,
+            memory_limit: None,
+            output: OutputOptions::default(),
+            variables: HashMap::new(),
+        }
+    }
+}
+
+impl Settings {
+    /// The current value of a setting: on/off, a mode name, megabytes for the
+    /// memory limit, or milliseconds for the timeout
+    pub fn get(&self, name: &str) -> Result<Value, DbError> {
+        let on_off = |on: bool| Value::Text(if on { "on" } else { "off" }.to_string());
+        Ok(match name {
+            "autocommit" => on_off(self.autocommit),
+            "safe_mode" => on_off(self.safe),
+            "dry_run" => Value::Text(match self.dry_run {
+                DryRun::Off => "off",
+                DryRun::On => "on",
+                DryRun::Show => "show",
+            }.to_string()),
+            "output_mode" => Value::Text(self.output.mode.name().to_string()),
+            "memory_limit" => self.memory_limit.map_or(on_off(false), |bytes| Value::Int((bytes / MB) as i64)),
+            "statement_timeout" => self.statement_timeout.map_or(on_off(false), |t| Value::Int(t.as_millis() as i64)),
+            _ => return Err(unknown(name)),
+        })
+    }
+
+    /// Change a setting. The memory limit is in megabytes and the timeout in
+    /// milliseconds; 0 or 'off' removes either.
+    pub fn set(&mut self, name: &str, value: &Value) -> Result<(), DbError> {
+        let invalid = |expected: &str| {
+            DbError::Type(format!("Invalid value '{}' for {}; expected {}", display(value), name, expected))
+        };
+        match name {
+            "autocommit" => self.autocommit = parse_bool(value).ok_or_else(|| invalid("on or off"))?,
+            "safe_mode" => self.safe = parse_bool(value).ok_or_else(|| invalid("on or off"))?,
+            "dry_run" => {
+                self.dry_run = match (parse_bool(value), value) {
+                    (Some(true), _) => DryRun::On,
+                    (Some(false), _) => DryRun::Off,
+                    (None, Value::Text(text)) if text.eq_ignore_ascii_case("show") => DryRun::Show,
+                    _ => return Err(invalid("on, off, or show")),
+                };
+            }
+            "output_mode" => {
+                self.output.mode = match value {
+                    Value::Text(mode) => OutputMode::from_name(mode),
+                    _ => None,
+                }
+                .ok_or_else(|| invalid("table, csv, json, vertical, markdown, or html"))?;
+            }
+            "memory_limit" => {
+                let mb = parse_limit(value).ok_or_else(|| invalid("a number of megabytes or off"))?;
+                self.memory_limit = mb.map(|mb| (mb as usize).saturating_mul(MB));
+            }
+            "statement_timeout" => {
+                let ms = parse_limit(value).ok_or_else(|| invalid("a number of milliseconds or off"))?;
+                self.statement_timeout = ms.map(Duration::from_millis);
+            }
+            _ => return Err(unknown(name)),
+        }
+        Ok(())
+    }
+
+    /// Conditions stopping a statement run under these settings
+    pub fn interrupt(&self, token: CancelToken) -> Interrupt {
+        Interrupt::new(token, self.statement_timeout).with_memory_limit(self.memory_limit)
+    }
+}
+
+/// Execute a plan in a session: SET and PRAGMA read or change `settings`,
+/// and other statements run under them
+pub fn execute_in_session(
+    mut plan: Plan,
+    db: &mut Database,
+    settings: &mut Settings,
+    token: CancelToken,
+) -> Result<ExecutionResult, DbError> {
+    plan.bind_variables(&settings.variables);
+    match plan {
+        Plan::Set { name, value: None } => Ok(ExecutionResult::Rows {
+            columns: vec![name.clone()],
+            rows: vec![vec![settings.get(&name)?]],
+        }),
+        Plan::Set { name, value: Some(value) } => {
+            settings.set(&name, &value)?;
+            // Turning autocommit back on writes what it held back
+            if name == "autocommit" && settings.autocommit {
+                db.flush()?;
+            }
+            Ok(ExecutionResult::Success(format!("{} set to {}", name, display(&settings.get(&name)?))))
+        }
+        Plan::SetVariable { name, value } => {
+            let value = eval::evaluate_constant(&value)?;
+            settings.variables.insert(name.clone(), value);
+            Ok(ExecutionResult::Success(format!("@{} set", name)))
+        }
+        Plan::Delete { table_name, filter } if settings.dry_run != DryRun::Off => {
+            let rows = db.preview_delete(&table_name, filter.as_ref())?;
+            Ok(dry_run_result(settings.dry_run, db, &table_name, rows, "deleted"))
+        }
+        Plan::Update { table_name, column, value, filter } if settings.dry_run != DryRun::Off => {
+            let rows = db.preview_update(&table_name, &column, &value, filter.as_ref())?;
+            Ok(dry_run_result(settings.dry_run, db, &table_name, rows, "updated"))
+        }
+        plan if settings.autocommit => execute_with_interrupt(plan, db, &settings.interrupt(token)),
+        plan => {
+            let persistent = db.is_persistent();
+            db.set_persistent(false);
+            let result = execute_with_interrupt(plan, db, &settings.interrupt(token));
+            db.set_persistent(persistent);
+            result
+        }
+    }
+}
+
+/// The rows a dry run found, or just how many there are
+fn dry_run_result(dry_run: DryRun, db: &Database, table_name: &str, rows: Vec<Vec<Value>>, verb: &str) -> ExecutionResult {
+    match (dry_run, db.get_table(table_name)) {
+        (DryRun::Show, Ok(table)) => ExecutionResult::Rows {
+            columns: table.columns.iter().map(|c| c.name.clone()).collect(),
+            rows,
+        },
+        _ => ExecutionResult::Success(format!("Dry run: {} row(s) would be {}", rows.len(), verb)),
+    }
+}
+
+fn unknown(name: &str) -> DbError {
+    DbError::Plan(format!("Unknown setting '{}'; settings are {}", name, SETTING_NAMES.join(", ")))
+}
+
+fn display(value: &Value) -> String {
+    match value {
+        Value::Int(n) => n.to_string(),
+        Value::Text(text) => text.clone(),
+        other => format!("{:?}", other),
+    }
+}
+
+fn parse_bool(value: &Value) -> Option<bool> {
+    match value {
+        Value::Int(1) => Some(true),
+        Value::Int(0) => Some(false),
+        Value::Text(text) => match text.to_lowercase().as_str() {
+            "on" | "true" | "yes" => Some(true),
+            "off" | "false" | "no" => Some(false),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// A limit in whole units, or None to remove it (for 0 or 'off')
+fn parse_limit(value: &Value) -> Option<Option<u64>> {
+    match value {
+        Value::Int(0) => Some(None),
+        Value::Int(n) => u64::try_from(*n).ok().map(Some),
+        Value::Text(text) if text.eq_ignore_ascii_case("off") => Some(None),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings() {
+        let mut settings = Settings::default();
+        settings.set("output_mode", &Value::Text("JSON".to_string())).unwrap();
+        assert_eq!(settings.output.mode, OutputMode::Json);
+        settings.set("memory_limit", &Value::Int(64)).unwrap();
+        assert_eq!(settings.memory_limit, Some(64 * MB));
+        assert_eq!(settings.get("memory_limit"), Ok(Value::Int(64)));
+        settings.set("memory_limit", &Value::Text("off".to_string())).unwrap();
+        assert_eq!(settings.get("memory_limit"), Ok(Value::Text("off".to_string())));
+        settings.set("autocommit", &Value::Int(0)).unwrap();
+        assert!(!settings.autocommit);
+        settings.set("dry_run", &Value::Text("show".to_string())).unwrap();
+        assert_eq!(settings.dry_run, DryRun::Show);
+
+        assert!(settings.set("safe_mode", &Value::Text("maybe".to_string())).is_err());
+        assert!(settings.set("statement_timeout", &Value::Int(-1)).is_err());
+        assert!(settings.get("colour").is_err());
+    }
+}
@@ -0,0 +1,277 @@
+// HTTP - a small JSON API over HTTP/1.1
+//
+// `POST /query` runs the SQL statement in the request body and responds with
+// its result as JSON; `GET /tables` describes every table. Each connection
+// carries one request and is closed after the response, except `GET /ws`,
+// which upgrades to a WebSocket session (see `websocket.rs`). Statements from
+// concurrent clients take turns on the shared database.
+
+use crate::error::DbError;
+use crate::executor::{self, json_string, json_value, ExecutionResult, ResultCache};
+use crate::parser;
+use crate::planner::{self, Plan};
+use crate::storage::{ttl, Database, TableInfo};
+use crate::websocket::{self, Listeners};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+/// Largest request body accepted
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// Accept clients until the listener fails, serving each on its own thread.
+/// Expired rows are purged in the background meanwhile.
+pub fn serve(listener: TcpListener, db: Arc<Mutex<Database>>) -> io::Result<()> {
+    ttl::purge_periodically(&db);
+    let listeners = Arc::new(Listeners::default());
+    let cache = Arc::new(Mutex::new(ResultCache::default()));
+    let hub = Arc::clone(&listeners);
+    db.lock().unwrap_or_else(|e| e.into_inner()).on_change(move |event| hub.notify(event));
+
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let db = Arc::clone(&db);
+        let listeners = Arc::clone(&listeners);
+        let cache = Arc::clone(&cache);
+        thread::spawn(move || {
+            if let Err(e) = handle_client(stream, &db, &listeners, &cache) {
+                tracing::debug!(error = %e, "HTTP connection closed");
+            }
+        });
+    }
+    Ok(())
+}
+
+/// A request's line, headers, and body
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Request {
+    /// Value of a header, matched case-insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Read one request, route it, and write the response. Queries are answered
+/// from `cache`, shared by the server's clients, when their tables have not
+/// changed.
+pub fn handle_client(stream: TcpStream, db: &Mutex<Database>, listeners: &Listeners, cache: &Mutex<ResultCache>) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let request = match read_request(&mut reader) {
+        Ok(request) => request,
+        Err(e) => return respond(stream, 400, &error_json(&e.to_string())),
+    };
+
+    let path = request.path.split('?').next().unwrap_or(&request.path);
+    if path == "/ws" && request.header("upgrade").is_some_and(|v| v.eq_ignore_ascii_case("websocket")) {
+        return match request.header("sec-websocket-key") {
+            Some(key) => websocket::session(reader, stream, key, db, listeners),
+            None => respond(stream, 400, &error_json("Missing Sec-WebSocket-Key header")),
+        };
+    }
+    let (status, body) = route(&request.method, &request.path, &request.body, db, cache);
+    respond(stream, status, &body)
+}
+
+fn respond(mut stream: TcpStream, status: u16, body: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, reason(status), body.len(), body
+    )?;
+    stream.flush()
+}
+
+/// Status code and JSON body for a request
+fn route(method: &str, path: &str, body: &str, db: &Mutex<Database>, cache: &Mutex<ResultCache>) -> (u16, String) {
+    let path = path.split('?').next().unwrap_or(path);
+    match (method, path) {
+        ("POST", "/query") => {
+            let mut db = db.lock().unwrap_or_else(|e| e.into_inner());
+            let mut cache = cache.lock().unwrap_or_else(|e| e.into_inner());
+            match run_query(body, &mut db, &mut cache) {
+                Ok(result) => (200, result_json(result)),
+                Err(e) => (error_status(&e), error_json(&e.to_string())),
+            }
+        }
+        ("GET", "/tables") => {
+            let db = db.lock().unwrap_or_else(|e| e.into_inner());
+            let mut names = db.list_tables();
+            names.sort();
+            let tables: Vec<String> = names.iter()
+                .filter_map(|name| db.table_schema(name).ok())
+                .map(|info| table_json(&info))
+                .collect();
+            (200, format!("[{}]", tables.join(",")))
+        }
+        ("GET", "/ws") => (400, error_json("Expected a WebSocket upgrade request")),
+        (_, "/query" | "/tables" | "/ws") => (405, error_json(&format!("{} is not allowed on {}", method, path))),
+        _ => (404, error_json(&format!("No such endpoint: {}", path))),
+    }
+}
+
+/// Run the single statement in a request body, recording it in the audit
+/// log. A query whose tables have not changed since it last ran is answered
+/// from `cache`.
+pub fn run_query(body: &str, db: &mut Database, cache: &mut ResultCache) -> Result<ExecutionResult, DbError> {
+    let (mut statements, rest) = parser::split_statements(body);
+    if !rest.is_empty() {
+        statements.push(rest);
+    }
+    let [sql] = statements.as_slice() else {
+        return Err(DbError::Parse("Send exactly one SQL statement per request".to_string()));
+    };
+
+    let start = Instant::now();
+    let result = match cache.get(sql, db) {
+        Some(result) => Ok(result),
+        None => {
+            let plan = planner::plan(parser::parse(sql)?, db)?;
+            let query = match &plan {
+                Plan::Query(physical) => Some(physical.clone()),
+                _ => None,
+            };
+            let result = executor::execute(plan, db);
+            if let (Some(query), Ok(result)) = (&query, &result) {
+                cache.insert(sql, query, result, db);
+            }
+            result
+        }
+    };
+    let rows = result.as_ref().ok().and_then(ExecutionResult::row_count);
+    db.record_statement(sql, rows, start.elapsed(), result.as_ref().err())?;
+    result
+}
+
+/// Read the request line, headers, and body
+fn read_request(reader: &mut impl BufRead) -> io::Result<Request> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(invalid("Malformed request line"));
+    };
+    let (method, path) = (method.to_string(), path.to_string());
+
+    let mut headers = Vec::new();
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    let mut request = Request { method, path, headers, body: String::new() };
+    let content_length = match request.header("content-length") {
+        Some(value) => value.parse().map_err(|_| invalid("Invalid Content-Length"))?,
+        None => 0,
+    };
+    if content_length > MAX_BODY_SIZE {
+        return Err(invalid("Request body too large"));
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    request.body = String::from_utf8(body).map_err(|_| invalid("Request body is not UTF-8"))?;
+    Ok(request)
+}
+
+/// `{"columns": [...], "rows": [{column: value}, ...]}` for queries, and
+/// the message and changed row count for other statements
+pub fn result_json(result: ExecutionResult) -> String {
+    match result {
+        ExecutionResult::Rows { columns, rows } => {
+            let names: Vec<String> = columns.iter().map(|c| json_string(c)).collect();
+            let objects: Vec<String> = rows.iter()
+                .map(|row| {
+                    let fields: Vec<String> = names.iter()
+                        .zip(row)
+                        .map(|(name, value)| format!("{}:{}", name, json_value(value)))
+                        .collect();
+                    format!("{{{}}}", fields.join(","))
+                })
+                .collect();
+            format!("{{\"columns\":[{}],\"rows\":[{}]}}", names.join(","), objects.join(","))
+        }
+        ExecutionResult::Changed { message, rows } => {
+            format!("{{\"message\":{},\"rows_affected\":{}}}", json_string(&message), rows)
+        }
+        ExecutionResult::Success(message) => format!("{{\"message\":{}}}", json_string(&message)),
+    }
+}
+
+fn table_json(info: &TableInfo) -> String {
+    let columns: Vec<String> = info.columns.iter()
+        .map(|c| format!(
+            "{{\"name\":{},\"type\":\"{}\",\"primary_key\":{},\"unique\":{},\"ttl\":{}}}",
+            json_string(&c.name), c.data_type, c.primary_key, c.unique,
+            c.ttl.map_or("null".to_string(), |ttl| ttl.to_string())
+        ))
+        .collect();
+    let indexes: Vec<String> = info.indexes.iter().map(|i| json_string(&i.name)).collect();
+    format!(
+        "{{\"name\":{},\"rows\":{},\"columns\":[{}],\"indexes\":[{}],\"query\":{},\"append_only\":{}}}",
+        json_string(&info.name), info.rows, columns.join(","), indexes.join(","),
+        info.query.as_deref().map_or("null".to_string(), json_string), info.append_only
+    )
+}
+
+pub fn error_json(message: &str) -> String {
+    format!("{{\"error\":{}}}", json_string(message))
+}
+
+/// Statements the client got wrong are 400s; failures on our side are 500s
+fn error_status(error: &DbError) -> u16 {
+    match error {
+        DbError::Io(_) | DbError::MemoryLimit(_) | DbError::Timeout(_) | DbError::Cancelled => 500,
+        _ => 400,
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Value;
+
+    #[test]
+    fn test_routes() {
+        let db = Mutex::new(Database::new());
+        let cache = Mutex::new(ResultCache::default());
+        assert_eq!(route("GET", "/tables", "", &db, &cache), (200, "[]".to_string()));
+        let (status, body) = route("POST", "/query", "SELECT * FROM missing", &db, &cache);
+        assert_eq!(status, 400);
+        assert_eq!(body, r#"{"error":"Table 'missing' does not exist"}"#);
+        assert_eq!(route("POST", "/query", "SELECT 1; SELECT 2", &db, &cache).0, 400);
+        assert_eq!(route("GET", "/query", "", &db, &cache).0, 405);
+        assert_eq!(route("GET", "/", "", &db, &cache).0, 404);
+
+        let result = ExecutionResult::Rows {
+            columns: vec!["id".to_string(), "name".to_string()],
+            rows: vec![vec![Value::Int(1), Value::Null]],
+        };
+        assert_eq!(result_json(result), r#"{"columns":["id","name"],"rows":[{"id":1,"name":null}]}"#);
+    }
+}
@@ -0,0 +1,200 @@
+// Import - bulk loading CSV, TSV, and SQLite files into tables
+
+use crate::error::DbError;
+use crate::eval;
+use crate::parser::{normalize_identifier, Column, DataType, Value};
+use crate::sqlite;
+use crate::storage::Database;
+use std::path::Path;
+
+/// Whether a path names a SQLite database rather than a delimited text file
+pub fn is_sqlite_file(path: &str) -> bool {
+    matches!(Path::new(path).extension().and_then(|e| e.to_str()), Some("sqlite" | "sqlite3" | "db"))
+}
+
+/// Load a CSV file (TSV for `.tsv` and `.tab` files) into a table, creating
+/// the table from the header line if it does not exist. Returns the number of
+/// rows imported.
+pub fn import_file(db: &mut Database, path: &str, table_name: &str) -> Result<usize, DbError> {
+    let table_name = &normalize_identifier(table_name);
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| DbError::Io(format!("Failed to read '{}': {}", path, e)))?;
+    let delimiter = match Path::new(path).extension().and_then(|e| e.to_str()) {
+        Some("tsv") | Some("tab") => '\t',
+        _ => ',',
+    };
+    let mut records = parse_delimited(text.trim_start_matches('\u{feff}'), delimiter)?;
+    if records.is_empty() {
+        return Err(DbError::Parse(format!("'{}' is empty", path)));
+    }
+
+    let columns = match db.get_table(table_name) {
+        Ok(table) => {
+            // An existing table's header line is skipped if present
+            let header = records[0].iter().map(|f| f.as_deref().unwrap_or("").trim().to_lowercase());
+            if header.eq(table.columns.iter().map(|c| c.name.to_lowercase())) {
+                records.remove(0);
+            }
+            table.columns.clone()
+        }
+        Err(_) => {
+            let header = records.remove(0);
+            let columns = infer_columns(&header, &records)?;
+            db.create_table(table_name.to_string(), columns.clone())?;
+            columns
+        }
+    };
+
+    let rows = records.iter()
+        .enumerate()
+        .map(|(n, record)| {
+            convert_record(record, &columns).map_err(|e| e.context(format!("Record {}", n + 1)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    db.insert_rows(table_name, rows)
+}
+
+/// Load the tables of a SQLite database file, or only `only` if given,
+/// creating each table that does not exist. Returns each table's name and the
+/// number of rows imported into it.
+pub fn import_sqlite(db: &mut Database, path: &str, only: Option<&str>) -> Result<Vec<(String, usize)>, DbError> {
+    let mut tables = sqlite::read_tables(path)?;
+    for table in &mut tables {
+        table.name = normalize_identifier(&table.name);
+    }
+    if let Some(name) = only {
+        tables.retain(|t| t.name == normalize_identifier(name));
+        if tables.is_empty() {
+            return Err(DbError::TableNotFound(name.to_string()));
+        }
+    }
+
+    let mut imported = Vec::new();
+    for table in tables {
+        match db.get_table(&table.name) {
+            Ok(existing) if existing.columns.len() != table.columns.len() => {
+                return Err(DbError::Type(format!(
+                    "Table '{}' has {} columns, but the imported table has {}",
+                    table.name, existing.columns.len(), table.columns.len()
+                )));
+            }
+            Ok(_) => {}
+            Err(_) => db.create_table(table.name.clone(), table.columns)?,
+        }
+        let count = db.insert_rows(&table.name, table.rows)?;
+        imported.push((table.name, count));
+    }
+    Ok(imported)
+}
+
+/// Split delimited text into records. Fields follow RFC 4180 quoting; an
+/// unquoted empty field is None (NULL), a quoted one is empty text.
+pub fn parse_delimited(text: &str, delimiter: char) -> Result<Vec<Vec<Option<String>>>, DbError> {
+    let mut records = Vec::new();
+    let mut record: Vec<Option<String>> = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+
+    let end_field = |record: &mut Vec<Option<String>>, field: &mut String, quoted: &mut bool| {
+        let value = std::mem::take(field);
+        record.push((*quoted || !value.is_empty()).then_some(value));
+        *quoted = false;
+    };
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if field.is_empty() && !quoted => {
+                quoted = true;
+                // Read up to the closing quote; "" inside stands for one quote
+                loop {
+                    match chars.next() {
+                        Some('"') if chars.peek() == Some(&'"') => {
+                            chars.next();
+                            field.push('"');
+                        }
+                        Some('"') => break,
+                        Some(c) => field.push(c),
+                        None => return Err(DbError::Parse(format!("Unterminated quoted field in record {}", records.len() + 1))),
+                    }
+                }
+            }
+            c if c == delimiter => end_field(&mut record, &mut field, &mut quoted),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                end_field(&mut record, &mut field, &mut quoted);
+                records.push(std::mem::take(&mut record));
+            }
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || quoted || !record.is_empty() {
+        end_field(&mut record, &mut field, &mut quoted);
+        records.push(record);
+    }
+
+    // Blank lines carry no data
+    records.retain(|r| !(r.len() == 1 && r[0].is_none()));
+    Ok(records)
+}
+
+/// Column definitions from a header line, typed by the values beneath it:
+/// INT if every value is an integer, FLOAT if every value is a number,
+/// TEXT otherwise
+pub fn infer_columns(header: &[Option<String>], records: &[Vec<Option<String>>]) -> Result<Vec<Column>, DbError> {
+    header.iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let name = name.as_deref().map(str::trim).unwrap_or("");
+            if name.is_empty() {
+                return Err(DbError::Parse(format!("Header column {} has no name", i + 1)));
+            }
+            let values: Vec<&str> = records.iter()
+                .filter_map(|r| r.get(i).and_then(|v| v.as_deref()))
+                .map(str::trim)
+                .collect();
+            let data_type = if values.iter().all(|v| v.parse::<i64>().is_ok()) {
+                DataType::Int
+            } else if values.iter().all(|v| v.parse::<f64>().is_ok()) {
+                DataType::Float
+            } else {
+                DataType::Text
+            };
+            Ok(Column::new(name.to_string(), data_type))
+        })
+        .collect()
+}
+
+/// Convert one record's fields to values of the table's column types
+pub fn convert_record(record: &[Option<String>], columns: &[Column]) -> Result<Vec<Value>, DbError> {
+    if record.len() != columns.len() {
+        return Err(DbError::Parse(format!("Expected {} fields, got {}", columns.len(), record.len())));
+    }
+    record.iter()
+        .zip(columns)
+        .map(|(field, column)| {
+            let Some(text) = field else {
+                return Ok(Value::Null);
+            };
+            eval::value_from_text(text, column)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_quoted_fields() {
+        let text = "id,note\r\n1,\"a, \"\"b\"\"\nc\"\r\n2,\r\n\n3,\"\"\n";
+        let records = parse_delimited(text, ',').unwrap();
+        let field = |s: &str| Some(s.to_string());
+        assert_eq!(records, vec![
+            vec![field("id"), field("note")],
+            vec![field("1"), field("a, \"b\"\nc")],
+            vec![field("2"), None],
+            vec![field("3"), field("")],
+        ]);
+    }
+}
@@ -1,9 +1,30 @@
-pub mod repl;
 pub mod parser;
 pub mod storage;
 pub mod planner;
 pub mod executor;
+pub mod eval;
+pub mod error;
+pub mod color;
+pub mod import;
+pub mod sqlite;
+pub mod builder;
+#[cfg(feature = "serde")]
+pub mod typed;
+#[cfg(any(feature = "server", feature = "client"))]
+pub mod protocol;
+#[cfg(feature = "client")]
+pub mod client;
+#[cfg(feature = "server")]
+pub mod pgwire;
+#[cfg(feature = "server")]
+pub mod http;
+#[cfg(feature = "server")]
+pub mod websocket;
 
-
-
-
+// Interactive shell, behind the default `cli` feature
+#[cfg(feature = "cli")]
+pub mod repl;
+#[cfg(feature = "cli")]
+pub mod completion;
+#[cfg(feature = "cli")]
+pub mod pager;
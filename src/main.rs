@@ -1,14 +1,145 @@
+use mini_sql_db::executor::DryRun;
 use mini_sql_db::repl::Repl;
+use mini_sql_db::storage::disk;
+use std::io::{self, IsTerminal};
+use std::path::Path;
 use std::process;
 
+const USAGE: &str = "Usage: mydb [--no-color] [--quiet] [--safe] [--force] [--dry-run] [--in-memory] [--init <file>] [-c|--command <sql>] [--pg-listen <addr>] [--http-listen <addr>]";
+
 fn main() {
-    println!("Mini SQL Database v0.1.0");
-    println!("Type '.help' for available commands, '.exit' to quit\n");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let flag = |name: &str| args.iter().any(|arg| arg == name);
+    let quiet = flag("--quiet");
+
+    // --init runs the given file at startup instead of ~/.minisqlrc
+    let init = match args.iter().position(|arg| arg == "--init") {
+        Some(pos) => match args.get(pos + 1) {
+            Some(path) => Some(Path::new(path)),
+            None => {
+                eprintln!("{}", USAGE);
+                process::exit(2);
+            }
+        },
+        None => None,
+    };
+
+    // --in-memory, or an unwritable data directory the user agrees to go on
+    // without, keeps every change in memory only
+    let in_memory = flag("--in-memory") || !check_data_dir();
 
-    let mut repl = Repl::new();
+    let configure = |repl: &mut Repl| {
+        if flag("--no-color") {
+            repl.set_color(false);
+        }
+        repl.set_quiet(quiet);
+        repl.set_safe(flag("--safe"));
+        repl.set_force(flag("--force"));
+        if flag("--dry-run") {
+            repl.set_dry_run(DryRun::On);
+        }
+        repl.set_persistent(!in_memory);
+    };
+
+    // --pg-listen and --http-listen serve the database over the network
+    // instead of starting the REPL
+    for listen in ["--pg-listen", "--http-listen"] {
+        if let Some(pos) = args.iter().position(|arg| arg == listen) {
+            let Some(addr) = args.get(pos + 1) else {
+                eprintln!("{}", USAGE);
+                process::exit(2);
+            };
+            serve(listen, addr, in_memory);
+        }
+    }
+
+    // -c/--command runs one command and exits, for scripts and cron jobs
+    if let Some(pos) = args.iter().position(|arg| arg == "-c" || arg == "--command") {
+        let Some(command) = args.get(pos + 1) else {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        };
+        let mut repl = Repl::new_quiet();
+        configure(&mut repl);
+        let ok = repl.run_startup_file(init) && repl.run_command(command);
+        let ok = repl.finish() && ok;
+        process::exit(if ok { 0 } else { 1 });
+    }
+
+    // --quiet leaves out the banner and success messages
+    let mut repl = if quiet {
+        Repl::new_quiet()
+    } else {
+        println!("Mini SQL Database v0.1.0");
+        println!("Type '.help' for available commands, '.exit' to quit\n");
+        Repl::new()
+    };
+    configure(&mut repl);
+    repl.run_startup_file(init);
     
     if let Err(e) = repl.run() {
         eprintln!("Error: {}", e);
         process::exit(1);
     }
-}
\ No newline at end of file
+}
+
+/// Check that the data directory can be written. If it cannot, ask whether
+/// to continue without saving, or exit when there is no terminal to ask on.
+fn check_data_dir() -> bool {
+    let Err(e) = disk::check_data_dir() else {
+        return true;
+    };
+    eprintln!("The data directory '{}' cannot be written: {}", disk::data_path("").display(), e);
+    if !io::stdin().is_terminal() {
+        eprintln!("Run with --in-memory to continue without saving changes");
+        process::exit(1);
+    }
+    eprint!("Continue in memory-only mode? Changes will not be saved. [y/N] ");
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() || !answer.trim().eq_ignore_ascii_case("y") {
+        process::exit(1);
+    }
+    false
+}
+
+/// Serve the database with the protocol named by a --*-listen option
+#[cfg(feature = "server")]
+fn serve(listen: &str, addr: &str, in_memory: bool) -> ! {
+    use mini_sql_db::storage::Database;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    let mut db = match Database::load_from_disk() {
+        Ok(db) => db,
+        Err(e) => {
+            eprintln!("Could not load database from disk: {}", e);
+            process::exit(1);
+        }
+    };
+    db.set_persistent(!in_memory);
+    let listener = match TcpListener::bind(addr) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Could not listen on {}: {}", addr, e);
+            process::exit(1);
+        }
+    };
+    let db = Arc::new(Mutex::new(db));
+    let result = if listen == "--http-listen" {
+        println!("Serving HTTP on http://{}", addr);
+        mini_sql_db::http::serve(listener, db)
+    } else {
+        println!("Listening for PostgreSQL clients on {}", addr);
+        mini_sql_db::pgwire::serve(listener, db)
+    };
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+    }
+    process::exit(1);
+}
+
+#[cfg(not(feature = "server"))]
+fn serve(_listen: &str, _addr: &str, _in_memory: bool) -> ! {
+    eprintln!("mydb was built without the server feature");
+    process::exit(2);
+}
@@ -0,0 +1,70 @@
+// Pager - paging long output in an interactive terminal
+
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::process::{Command, Stdio};
+
+/// Terminal height assumed when $LINES is not set
+const DEFAULT_PAGE_LINES: usize = 24;
+
+/// Pager run when $PAGER is not set; quits at once if the text fits
+const DEFAULT_PAGER: &str = "less -FRX";
+
+/// Print text, paging it when it is taller than the terminal and the session
+/// is interactive. $PAGER is used when it can be started; otherwise the text
+/// is shown a page at a time with a "more" prompt.
+pub fn print_paged(text: &str) {
+    let page = page_lines();
+    let interactive = io::stdin().is_terminal() && io::stdout().is_terminal();
+    if !interactive || text.lines().count() < page {
+        println!("{}", text);
+        return;
+    }
+
+    if run_pager(text).is_err() {
+        print_pages(text, page);
+    }
+}
+
+/// Lines that fit on one screen
+fn page_lines() -> usize {
+    std::env::var("LINES").ok()
+        .and_then(|lines| lines.parse::<usize>().ok())
+        .filter(|&lines| lines > 2)
+        .unwrap_or(DEFAULT_PAGE_LINES)
+}
+
+/// Pipe the text through the external pager and wait for it to exit
+fn run_pager(text: &str) -> io::Result<()> {
+    let command = std::env::var("PAGER").unwrap_or_else(|_| DEFAULT_PAGER.to_string());
+    let mut parts = command.split_whitespace();
+    let program = parts.next().ok_or_else(|| io::Error::other("PAGER is empty"))?;
+
+    let mut child = Command::new(program)
+        .args(parts)
+        .stdin(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // Quitting the pager early closes the pipe, which is not an error
+        let _ = writeln!(stdin, "{}", text);
+    }
+    child.wait()?;
+    Ok(())
+}
+
+/// Print a screenful at a time, waiting for Enter between pages
+fn print_pages(text: &str, page: usize) {
+    let lines: Vec<&str> = text.lines().collect();
+    for (n, chunk) in lines.chunks(page - 1).enumerate() {
+        if n > 0 {
+            print!("-- More -- (Enter to continue, q to stop) ");
+            let _ = io::stdout().flush();
+            let mut answer = String::new();
+            if io::stdin().lock().read_line(&mut answer).is_err() || answer.trim() == "q" {
+                break;
+            }
+        }
+        for line in chunk {
+            println!("{}", line);
+        }
+    }
+}
@@ -0,0 +1,131 @@
+// Array - the text form of array values
+//
+// Arrays are written as in PostgreSQL: elements in braces, separated by
+// commas, e.g. {1,2,NULL}. A TEXT element is double-quoted when it is empty,
+// spells NULL, or holds whitespace or one of {},"\ with " and \ escaped by
+// a backslash, so any string survives a round trip. The same form is used
+// for display, CSV, the wire protocols, and the table files.
+
+use super::{DataType, Value};
+use crate::error::DbError;
+
+/// An array in its text form, e.g. {1,2,NULL}
+pub fn format_array(values: &[Value]) -> String {
+    let elements: Vec<String> = values.iter().map(format_element).collect();
+    format!("{{{}}}", elements.join(","))
+}
+
+fn format_element(value: &Value) -> String {
+    match value {
+        Value::Null => "NULL".to_string(),
+        Value::Int(n) => n.to_string(),
+        Value::Float(f) => f.to_string(),
+        Value::Text(s) if needs_quotes(s) => {
+            format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+        }
+        Value::Text(s) => s.clone(),
+        Value::Array(values) => format_array(values),
+    }
+}
+
+fn needs_quotes(s: &str) -> bool {
+    s.is_empty()
+        || s.eq_ignore_ascii_case("NULL")
+        || s.chars().any(|c| c.is_whitespace() || "{},\"\\".contains(c))
+}
+
+/// Parse an array's text form into elements of the array type's element type
+pub fn parse_array(text: &str, data_type: &DataType) -> Result<Vec<Value>, DbError> {
+    let element_type = match data_type {
+        DataType::Array(element) => element.as_ref(),
+        other => return Err(DbError::Type(format!("{} is not an array type", other))),
+    };
+    let invalid = || DbError::Type(format!("Invalid {} value '{}'", data_type, text));
+    let inner = text.trim()
+        .strip_prefix('{')
+        .and_then(|rest| rest.strip_suffix('}'))
+        .ok_or_else(invalid)?;
+    if inner.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut values = Vec::new();
+    let mut chars = inner.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let value = if chars.next_if_eq(&'"').is_some() {
+            let mut element = String::new();
+            loop {
+                match chars.next().ok_or_else(invalid)? {
+                    '"' => break,
+                    '\\' => element.push(chars.next().ok_or_else(invalid)?),
+                    c => element.push(c),
+                }
+            }
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            match element_type {
+                DataType::Text => Value::Text(element),
+                _ => parse_element(&element, element_type).ok_or_else(invalid)?,
+            }
+        } else {
+            let mut element = String::new();
+            while let Some(c) = chars.next_if(|&c| c != ',') {
+                if "{}\"\\".contains(c) {
+                    return Err(invalid());
+                }
+                element.push(c);
+            }
+            let element = element.trim();
+            if element.is_empty() {
+                return Err(invalid());
+            }
+            if element.eq_ignore_ascii_case("NULL") {
+                Value::Null
+            } else {
+                parse_element(element, element_type).ok_or_else(invalid)?
+            }
+        };
+        values.push(value);
+        match chars.next() {
+            Some(',') => {}
+            None => return Ok(values),
+            Some(_) => return Err(invalid()),
+        }
+    }
+}
+
+fn parse_element(text: &str, data_type: &DataType) -> Option<Value> {
+    match data_type {
+        DataType::Int => text.parse().ok().map(Value::Int),
+        DataType::Float => text.parse().ok().map(Value::Float),
+        DataType::Text => Some(Value::Text(text.to_string())),
+        DataType::Array(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_array_text_round_trip() {
+        let text_array = DataType::Array(Box::new(DataType::Text));
+        let values = vec![
+            Value::Text("plain".to_string()),
+            Value::Text("".to_string()),
+            Value::Text("NULL".to_string()),
+            Value::Null,
+            Value::Text("a, \"b\" {c} \\d".to_string()),
+        ];
+        let text = format_array(&values);
+        assert_eq!(text, r#"{plain,"","NULL",NULL,"a, \"b\" {c} \\d"}"#);
+        assert_eq!(parse_array(&text, &text_array), Ok(values));
+
+        let int_array = DataType::Array(Box::new(DataType::Int));
+        assert_eq!(parse_array("{ 1, 2 ,NULL}", &int_array), Ok(vec![Value::Int(1), Value::Int(2), Value::Null]));
+        assert_eq!(parse_array("{}", &int_array), Ok(vec![]));
+        assert!(parse_array("{1,x}", &int_array).is_err());
+        assert!(parse_array("{1,}", &int_array).is_err());
+        assert!(parse_array("1,2", &int_array).is_err());
+    }
+}
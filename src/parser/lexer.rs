@@ -1,5 +1,15 @@
 // Lexer - tokenizes SQL input
 
+use crate::error::DbError;
+
+/// Reserved words, as recognized by `read_identifier`
+pub const KEYWORDS: &[&str] = &[
+    "CREATE", "TABLE", "INSERT", "INTO", "SELECT", "FROM", "WHERE", "VALUES", "INDEX", "ON",
+    "DELETE", "UPDATE", "SET", "PRIMARY", "KEY", "UNIQUE", "EXPLAIN", "ANALYZE", "GROUP", "BY",
+    "LIMIT", "COPY", "IS", "NOT", "NULL", "INT", "TEXT", "FLOAT", "ORDER", "ASC", "DESC",
+    "UNION", "INTERSECT", "EXCEPT", "ALL", "ALTER", "PURGE",
+];
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     // Keywords
@@ -16,6 +26,27 @@ pub enum Token {
     Delete,
     Update,
     Set,
+    Primary,
+    Key,
+    Unique,
+    Explain,
+    Analyze,
+    Purge,
+    Group,
+    By,
+    Limit,
+    Copy,
+    Is,
+    Not,
+    Null,
+    Order,
+    Asc,
+    Desc,
+    Union,
+    Intersect,
+    Except,
+    All,
+    Alter,
     
     // Data types
     Int,
@@ -29,16 +60,24 @@ pub enum Token {
     LessThan,
     GreaterOrEqual,
     LessOrEqual,
+    Contains, // @>
     
     // Symbols
     LeftParen,
     RightParen,
+    LeftBracket,
+    RightBracket,
     Comma,
     Semicolon,
     Star,
+    Dot,
+    Plus,
+    Minus,
+    Slash,
     
     // Literals
     Identifier(String),
+    Variable(String), // @name, without the @
     IntLiteral(i64),
     FloatLiteral(f64),
     StringLiteral(String),
@@ -47,9 +86,26 @@ pub enum Token {
     Eof,
 }
 
+/// Where a token starts in the input: a byte offset, and a 1-based line and
+/// column counted in characters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl std::fmt::Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
 pub struct Lexer {
     input: Vec<char>,
     position: usize,
+    location: Position,
+    after_operand: bool, // Whether the last token ends an operand, making a following '-' binary
 }
 
 impl Lexer {
@@ -57,34 +113,38 @@ impl Lexer {
         Self {
             input: input.chars().collect(),
             position: 0,
+            location: Position { offset: 0, line: 1, column: 1 },
+            after_operand: false,
         }
     }
 
-    pub fn tokenize(&mut self) -> Result<Vec<Token>, String> {
+    pub fn tokenize(&mut self) -> Result<Vec<Token>, DbError> {
+        let tokens = self.tokenize_with_positions().map_err(|(e, _)| e)?;
+        Ok(tokens.into_iter().map(|(token, _)| token).collect())
+    }
+
+    /// Tokenize, pairing each token with where it starts. An error comes with
+    /// the position of the token it is about.
+    pub fn tokenize_with_positions(&mut self) -> Result<Vec<(Token, Position)>, (DbError, Position)> {
         let mut tokens = Vec::new();
 
         loop {
             self.skip_whitespace();
+            let start = self.location;
 
-            if self.position >= self.input.len() {
-                tokens.push(Token::Eof);
-                break;
-            }
-
-            let token = self.next_token()?;
-            
-            if token == Token::Eof {
-                tokens.push(token);
+            let token = self.next_token().map_err(|e| (e, start))?;
+            let done = token == Token::Eof;
+            self.after_operand = ends_operand(&token);
+            tokens.push((token, start));
+            if done {
                 break;
             }
-            
-            tokens.push(token);
         }
 
         Ok(tokens)
     }
 
-    fn next_token(&mut self) -> Result<Token, String> {
+    fn next_token(&mut self) -> Result<Token, DbError> {
         if self.position >= self.input.len() {
             return Ok(Token::Eof);
         }
@@ -101,6 +161,14 @@ impl Lexer {
                 self.advance();
                 return Ok(Token::RightParen);
             }
+            '[' => {
+                self.advance();
+                return Ok(Token::LeftBracket);
+            }
+            ']' => {
+                self.advance();
+                return Ok(Token::RightBracket);
+            }
             ',' => {
                 self.advance();
                 return Ok(Token::Comma);
@@ -113,6 +181,27 @@ impl Lexer {
                 self.advance();
                 return Ok(Token::Star);
             }
+            '.' => {
+                self.advance();
+                return Ok(Token::Dot);
+            }
+            '+' => {
+                self.advance();
+                return Ok(Token::Plus);
+            }
+            '-' => {
+                self.advance();
+                // A sign is part of the number it is written against, so it
+                // counts in the range check and -9223372036854775808 is an INT
+                if !self.after_operand && self.position < self.input.len() && self.current_char().is_ascii_digit() {
+                    return self.read_number(true);
+                }
+                return Ok(Token::Minus);
+            }
+            '/' => {
+                self.advance();
+                return Ok(Token::Slash);
+            }
             '=' => {
                 self.advance();
                 return Ok(Token::Equals);
@@ -148,7 +237,26 @@ impl Lexer {
                     self.advance();
                     return Ok(Token::NotEquals);
                 }
-                return Err("Unexpected character '!'".to_string());
+                return Err(DbError::Parse("Unexpected character '!'".to_string()));
+            }
+            '@' => {
+                self.advance();
+                if self.position < self.input.len() && self.current_char() == '>' {
+                    self.advance();
+                    return Ok(Token::Contains);
+                }
+                if self.position < self.input.len() && (self.current_char().is_alphabetic() || self.current_char() == '_') {
+                    // The name is taken as written, so keywords are fine as names
+                    let start = self.position;
+                    while self.position < self.input.len()
+                        && (self.current_char().is_alphanumeric() || self.current_char() == '_')
+                    {
+                        self.advance();
+                    }
+                    let name: String = self.input[start..self.position].iter().collect();
+                    return Ok(Token::Variable(name.to_lowercase()));
+                }
+                return Err(DbError::Parse("Unexpected character '@'".to_string()));
             }
             _ => {}
         }
@@ -160,7 +268,7 @@ impl Lexer {
 
         // Numbers
         if ch.is_ascii_digit() {
-            return self.read_number();
+            return self.read_number(false);
         }
 
         // Identifiers and keywords
@@ -168,10 +276,10 @@ impl Lexer {
             return self.read_identifier();
         }
 
-        Err(format!("Unexpected character: '{}'", ch))
+        Err(DbError::Parse(format!("Unexpected character: '{}'", ch)))
     }
 
-    fn read_string(&mut self, quote: char) -> Result<Token, String> {
+    fn read_string(&mut self, quote: char) -> Result<Token, DbError> {
         self.advance(); // Skip opening quote
         let mut value = String::new();
 
@@ -201,11 +309,11 @@ impl Lexer {
             }
         }
 
-        Err("Unterminated string literal".to_string())
+        Err(DbError::Parse("Unterminated string literal".to_string()))
     }
 
-    fn read_number(&mut self) -> Result<Token, String> {
-        let mut value = String::new();
+    fn read_number(&mut self, negative: bool) -> Result<Token, DbError> {
+        let mut value = if negative { "-".to_string() } else { String::new() };
         let mut is_float = false;
 
         while self.position < self.input.len() {
@@ -225,16 +333,18 @@ impl Lexer {
 
         if is_float {
             value.parse::<f64>()
+                .ok()
+                .filter(|f| f.is_finite())
                 .map(Token::FloatLiteral)
-                .map_err(|_| format!("Invalid float: {}", value))
+                .ok_or_else(|| DbError::Parse(format!("Invalid float: {}", value)))
         } else {
             value.parse::<i64>()
                 .map(Token::IntLiteral)
-                .map_err(|_| format!("Invalid integer: {}", value))
+                .map_err(|_| DbError::Parse(format!("Integer {} is out of range for INT", value)))
         }
     }
 
-    fn read_identifier(&mut self) -> Result<Token, String> {
+    fn read_identifier(&mut self) -> Result<Token, DbError> {
         let mut value = String::new();
 
         while self.position < self.input.len() {
@@ -263,10 +373,31 @@ impl Lexer {
             "DELETE" => Token::Delete,
             "UPDATE" => Token::Update,
             "SET" => Token::Set,
+            "PRIMARY" => Token::Primary,
+            "KEY" => Token::Key,
+            "UNIQUE" => Token::Unique,
+            "EXPLAIN" => Token::Explain,
+            "ANALYZE" => Token::Analyze,
+            "PURGE" => Token::Purge,
+            "GROUP" => Token::Group,
+            "BY" => Token::By,
+            "LIMIT" => Token::Limit,
+            "COPY" => Token::Copy,
+            "IS" => Token::Is,
+            "NOT" => Token::Not,
+            "NULL" => Token::Null,
             "INT" => Token::Int,
             "TEXT" => Token::Text,
             "FLOAT" => Token::Float,
-            _ => Token::Identifier(value),
+            "ORDER" => Token::Order,
+            "ASC" => Token::Asc,
+            "DESC" => Token::Desc,
+            "UNION" => Token::Union,
+            "INTERSECT" => Token::Intersect,
+            "EXCEPT" => Token::Except,
+            "ALL" => Token::All,
+            "ALTER" => Token::Alter,
+            _ => Token::Identifier(super::normalize_identifier(&value)),
         };
 
         Ok(token)
@@ -277,6 +408,14 @@ impl Lexer {
     }
 
     fn advance(&mut self) {
+        let ch = self.current_char();
+        self.location.offset += ch.len_utf8();
+        if ch == '\n' {
+            self.location.line += 1;
+            self.location.column = 1;
+        } else {
+            self.location.column += 1;
+        }
         self.position += 1;
     }
 
@@ -285,4 +424,19 @@ impl Lexer {
             self.advance();
         }
     }
-}
\ No newline at end of file
+}
+/// Whether a token can be the last one of an operand, so that a '-' after
+/// it subtracts rather than negates
+fn ends_operand(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Identifier(_)
+            | Token::Variable(_)
+            | Token::IntLiteral(_)
+            | Token::FloatLiteral(_)
+            | Token::StringLiteral(_)
+            | Token::Null
+            | Token::RightParen
+            | Token::RightBracket
+    )
+}
@@ -1,18 +1,52 @@
 // Parser module - converts SQL strings into AST
 
+use crate::error::DbError;
+
 /// SQL data types
 #[derive(Debug, Clone, PartialEq)]
 pub enum DataType {
     Int,
     Text,
     Float,
+    /// A one-dimensional array of INT, TEXT, or FLOAT, e.g. `TEXT[]`
+    Array(Box<DataType>),
+}
+
+impl std::fmt::Display for DataType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            DataType::Int => "INT",
+            DataType::Text => "TEXT",
+            DataType::Float => "FLOAT",
+            DataType::Array(element) => return write!(f, "{}[]", element),
+        };
+        write!(f, "{}", name)
+    }
 }
 
 /// Column definition in a table
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Column {
     pub name: String,
     pub data_type: DataType,
+    pub primary_key: bool,
+    pub unique: bool,
+    /// Seconds after the time in this column that its row expires
+    /// (`WITH TTL n`)
+    pub ttl: Option<u64>,
+}
+
+impl Column {
+    /// Create a column without constraints
+    pub fn new(name: String, data_type: DataType) -> Self {
+        Self {
+            name,
+            data_type,
+            primary_key: false,
+            unique: false,
+            ttl: None,
+        }
+    }
 }
 
 /// SQL Statement AST
@@ -21,30 +55,281 @@ pub enum Statement {
     CreateTable {
         table_name: String,
         columns: Vec<Column>,
+        append_only: bool, // APPEND ONLY: rows are never updated or deleted
+    },
+    /// SET CONSTRAINTS ALL DEFERRED | IMMEDIATE, inside a transaction
+    SetConstraints {
+        deferred: bool,
+    },
+    /// CREATE EXTERNAL TABLE table [(columns)] USING CSV LOCATION 'path'
+    CreateExternalTable {
+        table_name: String,
+        columns: Option<Vec<Column>>, // Inferred from the file when not declared
+        location: String,
+    },
+    /// CREATE TABLE table LIKE source [WITH DATA]
+    CreateTableLike {
+        table_name: String,
+        source: String,
+        with_data: bool,
     },
     CreateIndex {
+        index_name: Option<String>,
         table_name: String,
-        column_name: String,
+        expr: Expr,
+        unique: bool,
     },
     Insert {
         table_name: String,
-        values: Vec<Value>,
+        values: Vec<Expr>, // Constant expressions, evaluated when the row is inserted
     },
-    Select {
+    /// INSERT INTO table SELECT ...
+    InsertSelect {
         table_name: String,
-        columns: Vec<String>, // Empty vec means SELECT *
+        query: Box<Statement>,
+    },
+    Select {
+        table_name: String, // Empty for a SELECT without FROM
+        series: Option<Series>, // Set when reading FROM generate_series(...)
+        sample: Option<usize>, // Read this many random rows of the table
+        columns: Vec<SelectItem>, // Empty vec means SELECT *
         where_clause: Option<WhereClause>,
+        group_by: Vec<String>,
+        order_by: Vec<OrderBy>,
+        limit: Option<usize>,
+    },
+    /// Two queries combined by UNION, INTERSECT, or EXCEPT. ORDER BY and
+    /// LIMIT apply to the combined rows.
+    SetOperation {
+        operator: SetOperator,
+        all: bool, // Keep duplicate rows
+        left: Box<Statement>,
+        right: Box<Statement>,
+        order_by: Vec<OrderBy>,
+        limit: Option<usize>,
     },
     Delete {
         table_name: String,
         where_clause: Option<WhereClause>,
     },
+    /// ALTER TABLE table ALTER COLUMN column TYPE type
+    AlterColumnType {
+        table_name: String,
+        column: String,
+        data_type: DataType,
+    },
     Update {
         table_name: String,
         column: String,
-        value: Value,
+        value: Expr, // May reference the row's current values
         where_clause: Option<WhereClause>,
     },
+    Explain {
+        statement: Box<Statement>,
+        analyze: bool,
+    },
+    Analyze {
+        table_name: Option<String>, // None analyzes every table
+    },
+    /// CREATE MATERIALIZED VIEW view AS SELECT ...
+    CreateMaterializedView {
+        view_name: String,
+        query: Box<Statement>,
+        sql: String, // The query's text, stored to run it again on refresh
+    },
+    /// REFRESH MATERIALIZED VIEW view
+    RefreshMaterializedView {
+        view_name: String,
+    },
+    /// SET name = value, PRAGMA name = value, or PRAGMA name to show it
+    Set {
+        name: String,
+        value: Option<Expr>,
+    },
+    /// SET @name = value
+    SetVariable {
+        name: String,
+        value: Expr,
+    },
+    /// Delete rows past their TTL
+    Purge {
+        table_name: Option<String>, // None purges every table with a TTL
+    },
+    /// COPY table TO 'file' [FORMAT name]
+    Copy {
+        table_name: String,
+        path: String,
+        format: String, // "parquet" or an output mode name such as "csv"
+    },
+}
+
+/// The integers from `start` to `stop` (inclusive) in steps of `step`,
+/// read as a table with `FROM generate_series(start, stop[, step])`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Series {
+    pub start: i64,
+    pub stop: i64,
+    pub step: i64,
+}
+
+impl Series {
+    /// Name of the series and of its one INT column, as in PostgreSQL
+    pub const NAME: &'static str = "generate_series";
+
+    /// The columns of the rows the series produces
+    pub fn columns(&self) -> Vec<Column> {
+        vec![Column::new(Self::NAME.to_string(), DataType::Int)]
+    }
+
+    /// Number of values in the series; none if the step is zero or points
+    /// away from `stop`
+    pub fn len(&self) -> u64 {
+        let span = self.stop as i128 - self.start as i128;
+        if self.step == 0 || (span != 0 && (span < 0) != (self.step < 0)) {
+            return 0;
+        }
+        (span / self.step as i128) as u64 + 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The value at a position in the series
+    pub fn value(&self, position: u64) -> i64 {
+        (self.start as i128 + position as i128 * self.step as i128) as i64
+    }
+}
+
+impl std::fmt::Display for Series {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}({}, {}", Self::NAME, self.start, self.stop)?;
+        if self.step != 1 {
+            write!(f, ", {}", self.step)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// An entry in a SELECT list
+#[derive(Debug, Clone, PartialEq)]
+pub enum SelectItem {
+    Column(String),
+    Aggregate(Aggregate),
+    /// Any other expression, e.g. `price * 2` or `UUID()`, computed per row
+    Expr(Expr),
+    /// `t.*`: every column of the table `t`, replaced by them when planning
+    AllColumns(String),
+}
+
+/// How a set operation combines the rows of its two queries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetOperator {
+    Union,
+    Intersect,
+    Except,
+}
+
+impl std::fmt::Display for SetOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SetOperator::Union => write!(f, "UNION"),
+            SetOperator::Intersect => write!(f, "INTERSECT"),
+            SetOperator::Except => write!(f, "EXCEPT"),
+        }
+    }
+}
+
+/// An ORDER BY key: a SELECT list entry or any expression, or with an
+/// integer literal, e.g. `ORDER BY 2`, a 1-based position in the SELECT list
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderBy {
+    pub item: SelectItem,
+    pub descending: bool,
+}
+
+impl OrderBy {
+    /// The SELECT list position this key refers to, if it is an ordinal
+    pub fn ordinal(&self) -> Option<i64> {
+        match self.item {
+            SelectItem::Expr(Expr::Literal(Value::Int(n))) => Some(n),
+            _ => None,
+        }
+    }
+}
+
+/// Aggregate function applied to a column, e.g. SUM(price)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Aggregate {
+    pub function: AggregateFunction,
+    pub column: Option<String>, // None means COUNT(*)
+}
+
+/// Supported aggregate functions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AggregateFunction {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+impl AggregateFunction {
+    /// Look up an aggregate function by (case-insensitive) name
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_uppercase().as_str() {
+            "COUNT" => Some(AggregateFunction::Count),
+            "SUM" => Some(AggregateFunction::Sum),
+            "AVG" => Some(AggregateFunction::Avg),
+            "MIN" => Some(AggregateFunction::Min),
+            "MAX" => Some(AggregateFunction::Max),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for AggregateFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            AggregateFunction::Count => "COUNT",
+            AggregateFunction::Sum => "SUM",
+            AggregateFunction::Avg => "AVG",
+            AggregateFunction::Min => "MIN",
+            AggregateFunction::Max => "MAX",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl std::fmt::Display for Aggregate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.column {
+            Some(column) => write!(f, "{}({})", self.function, column),
+            None => write!(f, "{}(*)", self.function),
+        }
+    }
+}
+
+impl std::fmt::Display for SelectItem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SelectItem::Column(name) => write!(f, "{}", name),
+            SelectItem::Aggregate(aggregate) => write!(f, "{}", aggregate),
+            SelectItem::Expr(expr) => write!(f, "{}", expr),
+            SelectItem::AllColumns(table_name) => write!(f, "{}.*", table_name),
+        }
+    }
+}
+
+impl std::fmt::Display for OrderBy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.item)?;
+        if self.descending {
+            write!(f, " DESC")?;
+        }
+        Ok(())
+    }
 }
 
 /// Represents a value in SQL
@@ -53,19 +338,127 @@ pub enum Value {
     Int(i64),
     Text(String),
     Float(f64),
+    /// Elements are INT, TEXT, or FLOAT values of one type, or NULL
+    Array(Vec<Value>),
     Null,
 }
 
-/// WHERE clause representation
-#[derive(Debug)]
+/// A value written as a SQL literal, e.g. `1`, `'ann'`, or `NULL`
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Float(x) => write!(f, "{:?}", x),
+            Value::Text(s) => write!(f, "'{}'", s.replace('\'', "\\'")),
+            Value::Null => write!(f, "NULL"),
+            Value::Array(values) => {
+                let elements: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+                write!(f, "ARRAY[{}]", elements.join(", "))
+            }
+        }
+    }
+}
+
+/// Scalar expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Column(String),
+    Literal(Value),
+    Function { name: String, args: Vec<Expr> },
+    Binary { left: Box<Expr>, op: BinaryOp, right: Box<Expr> },
+    /// `ARRAY[a, b, ...]`
+    Array(Vec<Expr>),
+    /// `array[index]`, counting from 1
+    Subscript { array: Box<Expr>, index: Box<Expr> },
+    /// `(a, b, ...)`: a row value compared as a whole, or the list of an IN
+    Row(Vec<Expr>),
+    /// `@name`, a session variable. Its value is filled in from the session
+    /// when the statement runs, so a planned statement can run again after
+    /// the variable changes.
+    Variable { name: String, value: Option<Value> },
+}
+
+/// Arithmetic operators
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+impl std::fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let symbol = match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+impl std::fmt::Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expr::Column(name) => write!(f, "{}", name),
+            Expr::Literal(value) => write!(f, "{}", value),
+            Expr::Array(elements) => {
+                let elements: Vec<String> = elements.iter().map(|e| e.to_string()).collect();
+                write!(f, "ARRAY[{}]", elements.join(", "))
+            }
+            Expr::Row(elements) => {
+                let elements: Vec<String> = elements.iter().map(|e| e.to_string()).collect();
+                write!(f, "({})", elements.join(", "))
+            }
+            Expr::Subscript { array, index } => match **array {
+                Expr::Binary { .. } => write!(f, "({})[{}]", array, index),
+                _ => write!(f, "{}[{}]", array, index),
+            },
+            Expr::Function { name, args } => {
+                let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+                write!(f, "{}({})", name, args.join(", "))
+            }
+            Expr::Binary { left, op, right } => {
+                // Parenthesize nested operations so the text is unambiguous
+                let operand = |e: &Expr| match e {
+                    Expr::Binary { .. } => format!("({})", e),
+                    _ => e.to_string(),
+                };
+                write!(f, "{} {} {}", operand(left), op, operand(right))
+            }
+            Expr::Variable { name, .. } => write!(f, "@{}", name),
+        }
+    }
+}
+
+/// WHERE clause representation: a comparison between two expressions
+#[derive(Debug, Clone)]
 pub struct WhereClause {
-    pub column: String,
+    pub left: Expr,
     pub operator: Operator,
-    pub value: Value,
+    pub right: Expr,
+}
+
+impl WhereClause {
+    /// Whether the clause compares row values, e.g. `(a, b) = (1, 'x')`
+    pub fn compares_rows(&self) -> bool {
+        matches!(self.left, Expr::Row(_)) || matches!(self.right, Expr::Row(_))
+    }
+}
+
+impl std::fmt::Display for WhereClause {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.operator {
+            Operator::Any => write!(f, "{} = ANY({})", self.left, self.right),
+            _ => write!(f, "{} {} {}", self.left, self.operator, self.right),
+        }
+    }
 }
 
 /// Comparison operators
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Operator {
     Equals,
     NotEquals,
@@ -73,31 +466,190 @@ pub enum Operator {
     LessThan,
     GreaterOrEqual,
     LessOrEqual,
+    /// `IS NULL`, with NULL as the right operand
+    Is,
+    /// `IS NOT NULL`, with NULL as the right operand
+    IsNot,
+    /// `value = ANY(array)`: the value equals an element of the array
+    Any,
+    /// `array @> array`: the left array has every element of the right one
+    Contains,
+    /// `(a, b) IN ((1, 'x'), ...)`: the row equals a row of the list. A
+    /// single value's IN list is parsed as `= ANY(ARRAY[...])` instead.
+    In,
 }
 
+impl Operator {
+    /// The operator with its operands swapped, e.g. `a < b` becomes `b > a`.
+    /// The array tests have no such counterpart.
+    pub fn flip(&self) -> Option<Operator> {
+        let flipped = match self {
+            Operator::Equals => Operator::Equals,
+            Operator::NotEquals => Operator::NotEquals,
+            Operator::GreaterThan => Operator::LessThan,
+            Operator::LessThan => Operator::GreaterThan,
+            Operator::GreaterOrEqual => Operator::LessOrEqual,
+            Operator::LessOrEqual => Operator::GreaterOrEqual,
+            Operator::Is => Operator::Is,
+            Operator::IsNot => Operator::IsNot,
+            Operator::Any | Operator::Contains | Operator::In => return None,
+        };
+        Some(flipped)
+    }
+}
+
+impl std::fmt::Display for Operator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let symbol = match self {
+            Operator::Equals => "=",
+            Operator::NotEquals => "!=",
+            Operator::GreaterThan => ">",
+            Operator::LessThan => "<",
+            Operator::GreaterOrEqual => ">=",
+            Operator::LessOrEqual => "<=",
+            Operator::Is => "IS",
+            Operator::IsNot => "IS NOT",
+            Operator::Any => "= ANY",
+            Operator::Contains => "@>",
+            Operator::In => "IN",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+pub mod array;
 pub mod lexer;
-use lexer::{Lexer, Token};
+use lexer::{Lexer, Position, Token, KEYWORDS};
 
 /// Parse SQL string into Statement
-pub fn parse(sql: &str) -> Result<Statement, String> {
+pub fn parse(sql: &str) -> Result<Statement, DbError> {
+    parse_with_position(sql).map_err(|(e, _)| e)
+}
+
+/// Parse SQL, returning an error with the position of the token it is about.
+/// The position is also given in the error message.
+pub fn parse_with_position(sql: &str) -> Result<Statement, (DbError, Position)> {
+    let _span = tracing::debug_span!("parse").entered();
     let mut lexer = Lexer::new(sql);
-    let tokens = lexer.tokenize()?;
-    
-    let mut parser = Parser::new(tokens);
-    parser.parse_statement()
+    let (tokens, positions): (Vec<Token>, Vec<Position>) = lexer.tokenize_with_positions()
+        .map_err(locate)?
+        .into_iter()
+        .unzip();
+
+    let offsets = positions.iter().map(|position| position.offset).collect();
+    let mut parser = Parser::new(tokens, offsets, sql);
+    let mut statement = parser.parse_statement()
+        .and_then(|statement| parser.expect_end().map(|_| statement))
+        .map_err(|e| {
+            // The parser stops on the token an error is about
+            let position = positions[parser.position.min(positions.len() - 1)];
+            locate((e, position))
+        })?;
+    if let Statement::CreateMaterializedView { sql: text, .. } = &mut statement
+        && let Some(start) = parser.view_query_start
+    {
+        *text = sql[positions[start].offset..].trim().trim_end_matches(';').trim_end().to_string();
+    }
+    Ok(statement)
+}
+
+/// Add a position to an error's message
+fn locate((error, position): (DbError, Position)) -> (DbError, Position) {
+    let error = match error {
+        DbError::Parse(message) => DbError::Parse(format!("{} at {}", message, position)),
+        other => other,
+    };
+    (error, position)
+}
+
+/// The line of `sql` holding a position, with a caret under that column
+pub fn show_position(sql: &str, position: Position) -> String {
+    let line_start = sql[..position.offset].rfind('\n').map_or(0, |i| i + 1);
+    let line = sql[line_start..].lines().next().unwrap_or("");
+    // Tabs are kept so the caret lines up however they are displayed
+    let indent: String = line.chars()
+        .take(position.column - 1)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    format!("{}\n{}^", line, indent)
+}
+
+/// Remove the ORDER BY and LIMIT of the last SELECT in a query
+fn take_order_and_limit(query: &mut Statement) -> (Vec<OrderBy>, Option<usize>) {
+    match query {
+        Statement::Select { order_by, limit, .. } => (std::mem::take(order_by), limit.take()),
+        Statement::SetOperation { right, .. } => take_order_and_limit(right),
+        _ => (Vec::new(), None),
+    }
 }
 
-struct Parser {
+/// Whether a token is a keyword, e.g. `Token::Select`
+fn is_keyword(token: &Token) -> bool {
+    let name = format!("{:?}", token);
+    KEYWORDS.iter().any(|k| k.eq_ignore_ascii_case(&name))
+}
+
+/// The form table and column names are stored and looked up in: SQL
+/// identifiers are case-insensitive, so they are folded to lowercase
+pub fn normalize_identifier(name: &str) -> String {
+    name.to_lowercase()
+}
+
+/// Split input into complete statements, each ended by `;` or `\G` outside a
+/// string literal, and the unfinished remainder. `\G` is kept on its
+/// statement so it can be printed vertically.
+pub fn split_statements(input: &str) -> (Vec<String>, String) {
+    let mut statements = Vec::new();
+    let mut start = 0;
+    let mut in_string = false;
+    let mut chars = input.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        let end = match c {
+            '\'' => {
+                in_string = !in_string;
+                continue;
+            }
+            ';' if !in_string => i + 1,
+            '\\' if !in_string && chars.peek().is_some_and(|&(_, next)| next == 'G') => {
+                chars.next();
+                i + 2
+            }
+            _ => continue,
+        };
+        let statement = input[start..end].trim().trim_end_matches(';').trim_end();
+        if !statement.is_empty() {
+            statements.push(statement.to_string());
+        }
+        start = end;
+    }
+
+    let rest = input[start..].trim_start();
+    (statements, rest.to_string())
+}
+
+struct Parser<'a> {
     tokens: Vec<Token>,
+    offsets: Vec<usize>, // Where each token starts in `source`
+    source: &'a str,
     position: usize,
+    view_query_start: Option<usize>, // Token where a view's query begins
 }
 
-impl Parser {
-    fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, position: 0 }
+impl<'a> Parser<'a> {
+    fn new(tokens: Vec<Token>, offsets: Vec<usize>, source: &'a str) -> Self {
+        Self { tokens, offsets, source, position: 0, view_query_start: None }
+    }
+
+    /// The current token's word as written, e.g. a keyword used as a value
+    fn current_word(&self) -> &'a str {
+        let start = self.offsets.get(self.position).copied().unwrap_or(self.source.len());
+        let rest = &self.source[start..];
+        let end = rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len());
+        &rest[..end]
     }
 
-    fn parse_statement(&mut self) -> Result<Statement, String> {
+    fn parse_statement(&mut self) -> Result<Statement, DbError> {
         let token = self.current_token();
         
         match token {
@@ -106,35 +658,240 @@ impl Parser {
                 let next = self.current_token();
                 match next {
                     Token::Table => self.parse_create_table(),
-                    Token::Index => self.parse_create_index(),
-                    _ => Err(format!("Expected TABLE or INDEX after CREATE, got {:?}", next)),
+                    Token::Index => self.parse_create_index(false),
+                    Token::Unique => {
+                        self.advance();
+                        self.parse_create_index(true)
+                    }
+                    Token::Identifier(word) if word.eq_ignore_ascii_case("MATERIALIZED") => {
+                        self.parse_create_materialized_view()
+                    }
+                    Token::Identifier(word) if word.eq_ignore_ascii_case("EXTERNAL") => {
+                        self.parse_create_external_table()
+                    }
+                    _ => Err(DbError::Parse(format!(
+                        "Expected TABLE, INDEX, EXTERNAL TABLE, or MATERIALIZED VIEW after CREATE, got {:?}",
+                        next
+                    ))),
                 }
             }
             Token::Insert => self.parse_insert(),
-            Token::Select => self.parse_select(),
+            Token::Select => self.parse_query(),
             Token::Delete => self.parse_delete(),
             Token::Update => self.parse_update(),
-            _ => Err(format!("Unexpected token: {:?}", token)),
+            Token::Explain => {
+                self.advance();
+                let analyze = self.current_token() == &Token::Analyze;
+                if analyze {
+                    self.advance();
+                }
+                Ok(Statement::Explain {
+                    statement: Box::new(self.parse_statement()?),
+                    analyze,
+                })
+            }
+            Token::Analyze => {
+                self.advance();
+                let table_name = match self.current_token() {
+                    Token::Identifier(_) => Some(self.expect_identifier()?),
+                    _ => None,
+                };
+                Ok(Statement::Analyze { table_name })
+            }
+            Token::Purge => {
+                self.advance();
+                let table_name = match self.current_token() {
+                    Token::Identifier(_) => Some(self.expect_identifier()?),
+                    _ => None,
+                };
+                Ok(Statement::Purge { table_name })
+            }
+            Token::Copy => self.parse_copy(),
+            Token::Alter => self.parse_alter_table(),
+            Token::Set => {
+                self.advance();
+                // SET CONSTRAINTS [ALL] DEFERRED | IMMEDIATE
+                let constraints = matches!(self.current_token(), Token::Identifier(word) if word == "constraints")
+                    && (self.peek_token() == &Token::All
+                        || matches!(self.peek_token(), Token::Identifier(word) if word == "deferred" || word == "immediate"));
+                if constraints {
+                    self.advance();
+                    if self.current_token() == &Token::All {
+                        self.advance();
+                    }
+                    let deferred = match self.current_token() {
+                        Token::Identifier(word) if word == "deferred" => true,
+                        Token::Identifier(word) if word == "immediate" => false,
+                        token => return Err(DbError::Parse(format!("Expected DEFERRED or IMMEDIATE, got {:?}", token))),
+                    };
+                    self.advance();
+                    return Ok(Statement::SetConstraints { deferred });
+                }
+                let variable = match self.current_token().clone() {
+                    Token::Variable(name) => {
+                        self.advance();
+                        Some(name)
+                    }
+                    _ => None,
+                };
+                let name = match &variable {
+                    Some(name) => name.clone(),
+                    None => self.expect_identifier()?,
+                };
+                if self.current_token() == &Token::Equals {
+                    self.advance();
+                } else {
+                    self.expect_word("TO")?;
+                }
+                match variable {
+                    Some(_) => Ok(Statement::SetVariable { name, value: self.parse_expr()? }),
+                    None => Ok(Statement::Set { name, value: Some(self.parse_setting_value()?) }),
+                }
+            }
+            Token::Identifier(word) if word.eq_ignore_ascii_case("PRAGMA") => {
+                self.advance();
+                let name = self.expect_identifier()?;
+                let value = match self.current_token() {
+                    Token::Equals => {
+                        self.advance();
+                        Some(self.parse_setting_value()?)
+                    }
+                    _ => None,
+                };
+                Ok(Statement::Set { name, value })
+            }
+            Token::Identifier(word) if word.eq_ignore_ascii_case("REFRESH") => {
+                self.advance();
+                self.expect_word("MATERIALIZED")?;
+                self.expect_word("VIEW")?;
+                let view_name = self.expect_identifier()?;
+                Ok(Statement::RefreshMaterializedView { view_name })
+            }
+            _ => Err(DbError::Parse(format!("Unexpected token: {:?}", token))),
+        }
+    }
+
+    /// A setting's value: an expression, or a bare word such as `on` or `json`
+    fn parse_setting_value(&mut self) -> Result<Expr, DbError> {
+        let word = match self.current_token() {
+            Token::Identifier(word) => word.clone(),
+            Token::Null => return self.parse_expr(),
+            token if is_keyword(token) => self.current_word().to_lowercase(),
+            _ => return self.parse_expr(),
+        };
+        self.advance();
+        Ok(Expr::Literal(Value::Text(word)))
+    }
+
+    fn parse_create_materialized_view(&mut self) -> Result<Statement, DbError> {
+        self.expect_word("MATERIALIZED")?;
+        self.expect_word("VIEW")?;
+        let view_name = self.expect_identifier()?;
+        self.expect_word("AS")?;
+        self.view_query_start = Some(self.position);
+        if self.current_token() != &Token::Select {
+            return Err(DbError::Parse(format!("Expected SELECT, got {:?}", self.current_token())));
         }
+        let query = self.parse_query()?;
+        Ok(Statement::CreateMaterializedView {
+            view_name,
+            query: Box::new(query),
+            sql: String::new(),
+        })
     }
 
-    fn parse_create_table(&mut self) -> Result<Statement, String> {
+    fn parse_create_table(&mut self) -> Result<Statement, DbError> {
         self.expect_token(Token::Table)?;
         
         let table_name = self.expect_identifier()?;
-        
+
+        // LIKE and WITH DATA are not reserved words
+        if matches!(self.current_token(), Token::Identifier(word) if word == "like") {
+            self.advance();
+            let source = self.expect_identifier()?;
+            let with_data = matches!(self.current_token(), Token::Identifier(word) if word == "with");
+            if with_data {
+                self.advance();
+                self.expect_word("DATA")?;
+            }
+            return Ok(Statement::CreateTableLike { table_name, source, with_data });
+        }
+
+        let columns = self.parse_column_definitions()?;
+
+        // APPEND ONLY is only special here, so neither word is reserved
+        let append_only = matches!(self.current_token(), Token::Identifier(word) if word == "append");
+        if append_only {
+            self.advance();
+            self.expect_word("ONLY")?;
+        }
+
+        Ok(Statement::CreateTable { table_name, columns, append_only })
+    }
+
+    /// Parse CREATE EXTERNAL TABLE name [(columns)] USING CSV LOCATION 'path'
+    fn parse_create_external_table(&mut self) -> Result<Statement, DbError> {
+        self.expect_word("EXTERNAL")?;
+        self.expect_token(Token::Table)?;
+        let table_name = self.expect_identifier()?;
+        let columns = match self.current_token() {
+            Token::LeftParen => Some(self.parse_column_definitions()?),
+            _ => None,
+        };
+        self.expect_word("USING")?;
+        self.expect_word("CSV")?;
+        self.expect_word("LOCATION")?;
+        match self.current_token().clone() {
+            Token::StringLiteral(location) => {
+                self.advance();
+                Ok(Statement::CreateExternalTable { table_name, columns, location })
+            }
+            token => Err(DbError::Parse(format!("Expected a quoted file name after LOCATION, got {:?}", token))),
+        }
+    }
+
+    /// Parse a parenthesized list of column definitions with their constraints
+    fn parse_column_definitions(&mut self) -> Result<Vec<Column>, DbError> {
         self.expect_token(Token::LeftParen)?;
-        
+
         let mut columns = Vec::new();
-        
+
         loop {
             let col_name = self.expect_identifier()?;
             let col_type = self.parse_data_type()?;
-            
-            columns.push(Column {
-                name: col_name,
-                data_type: col_type,
-            });
+            let mut column = Column::new(col_name, col_type);
+
+            // Column constraints
+            loop {
+                match self.current_token() {
+                    Token::Primary => {
+                        self.advance();
+                        self.expect_token(Token::Key)?;
+                        column.primary_key = true;
+                    }
+                    Token::Unique => {
+                        self.advance();
+                        column.unique = true;
+                    }
+                    // WITH is only special here, so it is not a reserved word
+                    Token::Identifier(word) if word.eq_ignore_ascii_case("with") => {
+                        self.advance();
+                        self.expect_word("TTL")?;
+                        match self.current_token() {
+                            Token::IntLiteral(seconds) if *seconds > 0 => {
+                                column.ttl = Some(*seconds as u64);
+                                self.advance();
+                            }
+                            token => {
+                                return Err(DbError::Parse(format!("Expected a positive number of seconds after TTL, got {:?}", token)));
+                            }
+                        }
+                    }
+                    _ => break,
+                }
+            }
+
+            columns.push(column);
             
             if self.current_token() == &Token::Comma {
                 self.advance();
@@ -144,28 +901,89 @@ impl Parser {
         }
         
         self.expect_token(Token::RightParen)?;
-        
-        Ok(Statement::CreateTable { table_name, columns })
+        Ok(columns)
+    }
+
+    fn parse_copy(&mut self) -> Result<Statement, DbError> {
+        self.expect_token(Token::Copy)?;
+        let table_name = self.expect_identifier()?;
+        // TO and FORMAT are only special here, so they are not reserved words
+        self.expect_word("TO")?;
+        let path = match self.current_token().clone() {
+            Token::StringLiteral(path) => {
+                self.advance();
+                path
+            }
+            token => return Err(DbError::Parse(format!("Expected a quoted file name after TO, got {:?}", token))),
+        };
+
+        // FORMAT name, optionally in parentheses as in PostgreSQL
+        let parenthesized = self.current_token() == &Token::LeftParen;
+        if parenthesized {
+            self.advance();
+        }
+        let format = match self.current_token() {
+            Token::Identifier(word) if word.eq_ignore_ascii_case("FORMAT") => {
+                self.advance();
+                self.expect_identifier()?.to_lowercase()
+            }
+            _ if parenthesized => return Err(DbError::Parse("Expected FORMAT in COPY options".to_string())),
+            _ => "csv".to_string(),
+        };
+        if parenthesized {
+            self.expect_token(Token::RightParen)?;
+        }
+        Ok(Statement::Copy { table_name, path, format })
     }
 
-    fn parse_create_index(&mut self) -> Result<Statement, String> {
+    fn parse_alter_table(&mut self) -> Result<Statement, DbError> {
+        self.expect_token(Token::Alter)?;
+        self.expect_token(Token::Table)?;
+        let table_name = self.expect_identifier()?;
+        self.expect_token(Token::Alter)?;
+        // COLUMN is optional, and COLUMN and TYPE are not reserved words
+        if let Token::Identifier(word) = self.current_token()
+            && word.eq_ignore_ascii_case("COLUMN")
+            && matches!(self.peek_token(), Token::Identifier(_))
+        {
+            self.advance();
+        }
+        let column = self.expect_identifier()?;
+        self.expect_word("TYPE")?;
+        let data_type = self.parse_data_type()?;
+        Ok(Statement::AlterColumnType { table_name, column, data_type })
+    }
+
+    fn parse_create_index(&mut self, unique: bool) -> Result<Statement, DbError> {
         self.expect_token(Token::Index)?;
+
+        // Optional index name: CREATE INDEX name ON ...
+        let index_name = match self.current_token() {
+            Token::Identifier(_) => Some(self.expect_identifier()?),
+            _ => None,
+        };
+
         self.expect_token(Token::On)?;
         
         let table_name = self.expect_identifier()?;
         
         self.expect_token(Token::LeftParen)?;
-        let column_name = self.expect_identifier()?;
+        let expr = self.parse_expr()?;
         self.expect_token(Token::RightParen)?;
         
-        Ok(Statement::CreateIndex { table_name, column_name })
+        Ok(Statement::CreateIndex { index_name, table_name, expr, unique })
     }
 
-    fn parse_insert(&mut self) -> Result<Statement, String> {
+    fn parse_insert(&mut self) -> Result<Statement, DbError> {
         self.expect_token(Token::Insert)?;
         self.expect_token(Token::Into)?;
         
         let table_name = self.expect_identifier()?;
+
+        if self.current_token() == &Token::Select {
+            let query = Box::new(self.parse_query()?);
+            return Ok(Statement::InsertSelect { table_name, query });
+        }
         
         self.expect_token(Token::Values)?;
         self.expect_token(Token::LeftParen)?;
@@ -173,8 +991,7 @@ impl Parser {
         let mut values = Vec::new();
         
         loop {
-            let value = self.parse_value()?;
-            values.push(value);
+            values.push(self.parse_expr()?);
             
             if self.current_token() == &Token::Comma {
                 self.advance();
@@ -188,7 +1005,64 @@ impl Parser {
         Ok(Statement::Insert { table_name, values })
     }
 
-    fn parse_select(&mut self) -> Result<Statement, String> {
+    /// Parse a SELECT, or several combined by set operations. INTERSECT
+    /// binds more tightly than UNION and EXCEPT, as in standard SQL.
+    fn parse_query(&mut self) -> Result<Statement, DbError> {
+        let mut query = self.parse_intersection()?;
+        loop {
+            let operator = match self.current_token() {
+                Token::Union => SetOperator::Union,
+                Token::Except => SetOperator::Except,
+                _ => break,
+            };
+            query = self.parse_set_operation(query, operator, Self::parse_intersection)?;
+        }
+
+        // The last query's ORDER BY and LIMIT apply to the combined rows
+        if let Statement::SetOperation { right, order_by, limit, .. } = &mut query {
+            (*order_by, *limit) = take_order_and_limit(right);
+        }
+        Ok(query)
+    }
+
+    fn parse_intersection(&mut self) -> Result<Statement, DbError> {
+        let mut query = self.parse_select()?;
+        while self.current_token() == &Token::Intersect {
+            query = self.parse_set_operation(query, SetOperator::Intersect, Self::parse_select)?;
+        }
+        Ok(query)
+    }
+
+    /// Parse the rest of `left OPERATOR [ALL] right`, starting at the operator
+    fn parse_set_operation(
+        &mut self,
+        mut left: Statement,
+        operator: SetOperator,
+        parse_right: fn(&mut Self) -> Result<Statement, DbError>,
+    ) -> Result<Statement, DbError> {
+        if take_order_and_limit(&mut left) != (Vec::new(), None) {
+            return Err(DbError::Parse(format!(
+                "ORDER BY and LIMIT must follow the last query of a {}",
+                operator
+            )));
+        }
+        self.advance();
+        let all = self.current_token() == &Token::All;
+        if all {
+            self.advance();
+        }
+        let right = parse_right(self)?;
+        Ok(Statement::SetOperation {
+            operator,
+            all,
+            left: Box::new(left),
+            right: Box::new(right),
+            order_by: Vec::new(),
+            limit: None,
+        })
+    }
+
+    fn parse_select(&mut self) -> Result<Statement, DbError> {
         self.expect_token(Token::Select)?;
         
         let columns = if self.current_token() == &Token::Star {
@@ -197,7 +1071,7 @@ impl Parser {
         } else {
             let mut cols = Vec::new();
             loop {
-                cols.push(self.expect_identifier()?);
+                cols.push(self.parse_select_item()?);
                 
                 if self.current_token() == &Token::Comma {
                     self.advance();
@@ -208,8 +1082,32 @@ impl Parser {
             cols
         };
         
-        self.expect_token(Token::From)?;
-        let table_name = self.expect_identifier()?;
+        // Without FROM, the SELECT list is computed once, e.g. SELECT 1 + 1
+        let table_name = if self.current_token() == &Token::From {
+            self.advance();
+            self.expect_identifier()?
+        } else {
+            String::new()
+        };
+        let series = if table_name == Series::NAME && self.current_token() == &Token::LeftParen {
+            Some(self.parse_series()?)
+        } else {
+            None
+        };
+        // SAMPLE n (or TABLESAMPLE n), neither a reserved word
+        let sample = match self.current_token() {
+            Token::Identifier(word) if !table_name.is_empty() && (word == "sample" || word == "tablesample") => {
+                self.advance();
+                match self.current_token().clone() {
+                    Token::IntLiteral(n) if n >= 0 => {
+                        self.advance();
+                        Some(n as usize)
+                    }
+                    other => return Err(DbError::Parse(format!("Expected a non-negative integer after SAMPLE, got {:?}", other))),
+                }
+            }
+            _ => None,
+        };
         
         let where_clause = if self.current_token() == &Token::Where {
             self.advance();
@@ -217,15 +1115,132 @@ impl Parser {
         } else {
             None
         };
+
+        let mut group_by = Vec::new();
+        if self.current_token() == &Token::Group {
+            self.advance();
+            self.expect_token(Token::By)?;
+            loop {
+                group_by.push(self.expect_identifier()?);
+                if self.current_token() == &Token::Comma {
+                    self.advance();
+                } else {
+                    break;
+                }
+            }
+        }
+
+        let mut order_by = Vec::new();
+        if self.current_token() == &Token::Order {
+            self.advance();
+            self.expect_token(Token::By)?;
+            loop {
+                let item = self.parse_select_item()?;
+                let descending = self.current_token() == &Token::Desc;
+                if matches!(self.current_token(), Token::Asc | Token::Desc) {
+                    self.advance();
+                }
+                order_by.push(OrderBy { item, descending });
+                if self.current_token() == &Token::Comma {
+                    self.advance();
+                } else {
+                    break;
+                }
+            }
+        }
+
+        let limit = if self.current_token() == &Token::Limit {
+            self.advance();
+            match self.current_token().clone() {
+                Token::IntLiteral(n) if n >= 0 => {
+                    self.advance();
+                    Some(n as usize)
+                }
+                other => return Err(DbError::Parse(format!("Expected a non-negative integer after LIMIT, got {:?}", other))),
+            }
+        } else {
+            None
+        };
         
         Ok(Statement::Select {
             table_name,
+            series,
+            sample,
             columns,
             where_clause,
+            group_by,
+            order_by,
+            limit,
         })
     }
 
-    fn parse_delete(&mut self) -> Result<Statement, String> {
+    /// Parse the `(start, stop[, step])` arguments of generate_series
+    fn parse_series(&mut self) -> Result<Series, DbError> {
+        self.expect_token(Token::LeftParen)?;
+        let mut args = Vec::new();
+        loop {
+            let negative = self.current_token() == &Token::Minus;
+            if negative {
+                self.advance();
+            }
+            match self.current_token() {
+                Token::IntLiteral(n) => args.push(if negative { -n } else { *n }),
+                token => return Err(DbError::Parse(format!("generate_series takes INT arguments, got {:?}", token))),
+            }
+            self.advance();
+            if self.current_token() == &Token::Comma {
+                self.advance();
+            } else {
+                break;
+            }
+        }
+        self.expect_token(Token::RightParen)?;
+        match args[..] {
+            [start, stop] => Ok(Series { start, stop, step: 1 }),
+            [start, stop, step] => Ok(Series { start, stop, step }),
+            _ => Err(DbError::Parse(format!("generate_series takes 2 or 3 arguments, got {}", args.len()))),
+        }
+    }
+
+    /// Parse a column name, an aggregate call such as COUNT(*) or SUM(price),
+    /// or another expression
+    fn parse_select_item(&mut self) -> Result<SelectItem, DbError> {
+        if let Token::Identifier(table_name) = self.current_token().clone()
+            && self.peek_token() == &Token::Dot
+        {
+            self.advance();
+            self.advance();
+            self.expect_token(Token::Star)?;
+            return Ok(SelectItem::AllColumns(table_name));
+        }
+        let function = match self.current_token() {
+            Token::Identifier(name) if self.peek_token() == &Token::LeftParen => AggregateFunction::from_name(name),
+            _ => None,
+        };
+        let Some(function) = function else {
+            return match self.parse_expr()? {
+                Expr::Column(name) => Ok(SelectItem::Column(name)),
+                expr => Ok(SelectItem::Expr(expr)),
+            };
+        };
+        self.advance();
+        self.advance();
+
+        let column = if self.current_token() == &Token::Star {
+            if function != AggregateFunction::Count {
+                return Err(DbError::Parse(format!("{}(*) is not supported; only COUNT(*) is", function)));
+            }
+            self.advance();
+            None
+        } else {
+            Some(self.expect_identifier()?)
+        };
+        self.expect_token(Token::RightParen)?;
+
+        Ok(SelectItem::Aggregate(Aggregate { function, column }))
+    }
+
+    fn parse_delete(&mut self) -> Result<Statement, DbError> {
         self.expect_token(Token::Delete)?;
         self.expect_token(Token::From)?;
         
@@ -244,7 +1259,7 @@ impl Parser {
         })
     }
 
-    fn parse_update(&mut self) -> Result<Statement, String> {
+    fn parse_update(&mut self) -> Result<Statement, DbError> {
         self.expect_token(Token::Update)?;
         
         let table_name = self.expect_identifier()?;
@@ -255,7 +1270,7 @@ impl Parser {
         
         self.expect_token(Token::Equals)?;
         
-        let value = self.parse_value()?;
+        let value = self.parse_expr()?;
         
         let where_clause = if self.current_token() == &Token::Where {
             self.advance();
@@ -272,80 +1287,326 @@ impl Parser {
         })
     }
 
-    fn parse_where_clause(&mut self) -> Result<WhereClause, String> {
-        let column = self.expect_identifier()?;
-        let operator = self.parse_operator()?;
-        let value = self.parse_value()?;
+    /// Parse a comparison, or equalities joined by AND, which compare as one
+    /// row value: `id = 1 AND _version = 2` is `(id, _version) = (1, 2)`
+    fn parse_where_clause(&mut self) -> Result<WhereClause, DbError> {
+        let mut comparisons = vec![self.parse_comparison()?];
+        while matches!(self.current_token(), Token::Identifier(name) if name == "and") {
+            self.advance();
+            comparisons.push(self.parse_comparison()?);
+        }
+        if comparisons.len() == 1 {
+            return Ok(comparisons.remove(0));
+        }
+
+        let (mut left, mut right) = (Vec::new(), Vec::new());
+        for comparison in comparisons {
+            if comparison.operator != Operator::Equals
+                || matches!(comparison.left, Expr::Row(_))
+                || matches!(comparison.right, Expr::Row(_))
+            {
+                return Err(DbError::Parse(format!(
+                    "AND can only join comparisons of single values with =, not '{}'",
+                    comparison
+                )));
+            }
+            left.push(comparison.left);
+            right.push(comparison.right);
+        }
+        Ok(WhereClause { left: Expr::Row(left), operator: Operator::Equals, right: Expr::Row(right) })
+    }
+
+    fn parse_comparison(&mut self) -> Result<WhereClause, DbError> {
+        let left = self.parse_expr()?;
+        let mut operator = self.parse_operator()?;
+        let right = match operator {
+            // IS only tests for NULL
+            Operator::Is | Operator::IsNot => {
+                self.expect_token(Token::Null)?;
+                Expr::Literal(Value::Null)
+            }
+            // ANY is not a reserved word, so = ANY(...) is told apart from a
+            // call to a function of that name here
+            Operator::Equals if matches!(self.current_token(), Token::Identifier(name) if name == "any")
+                && self.peek_token() == &Token::LeftParen =>
+            {
+                self.advance();
+                self.advance();
+                let array = self.parse_expr()?;
+                self.expect_token(Token::RightParen)?;
+                operator = Operator::Any;
+                array
+            }
+            // A single value IN a list equals an element of the list's array
+            Operator::In => {
+                let items = self.parse_list()?;
+                if matches!(left, Expr::Row(_)) {
+                    Expr::Row(items)
+                } else {
+                    operator = Operator::Any;
+                    Expr::Array(items)
+                }
+            }
+            _ => self.parse_expr()?,
+        };
         
         Ok(WhereClause {
-            column,
+            left,
             operator,
-            value,
+            right,
         })
     }
 
-    fn parse_operator(&mut self) -> Result<Operator, String> {
-        let token = self.current_token().clone();
-        self.advance();
-        
-        match token {
-            Token::Equals => Ok(Operator::Equals),
-            Token::NotEquals => Ok(Operator::NotEquals),
-            Token::GreaterThan => Ok(Operator::GreaterThan),
-            Token::LessThan => Ok(Operator::LessThan),
-            Token::GreaterOrEqual => Ok(Operator::GreaterOrEqual),
-            Token::LessOrEqual => Ok(Operator::LessOrEqual),
-            _ => Err(format!("Expected operator, got {:?}", token)),
+    /// Parse a parenthesized, comma-separated list of expressions
+    fn parse_list(&mut self) -> Result<Vec<Expr>, DbError> {
+        self.expect_token(Token::LeftParen)?;
+        let mut items = vec![self.parse_expr()?];
+        while self.current_token() == &Token::Comma {
+            self.advance();
+            items.push(self.parse_expr()?);
+        }
+        self.expect_token(Token::RightParen)?;
+        Ok(items)
+    }
+
+    /// Parse an arithmetic expression: term (('+' | '-') term)*
+    fn parse_expr(&mut self) -> Result<Expr, DbError> {
+        let mut expr = self.parse_term()?;
+
+        loop {
+            let op = match self.current_token() {
+                Token::Plus => BinaryOp::Add,
+                Token::Minus => BinaryOp::Subtract,
+                _ => break,
+            };
+            self.advance();
+            let right = self.parse_term()?;
+            expr = Expr::Binary { left: Box::new(expr), op, right: Box::new(right) };
         }
+
+        Ok(expr)
     }
 
-    fn parse_data_type(&mut self) -> Result<DataType, String> {
-        let token = self.current_token().clone();
+    /// Parse a term: primary (('*' | '/') primary)*
+    fn parse_term(&mut self) -> Result<Expr, DbError> {
+        let mut expr = self.parse_primary()?;
+
+        loop {
+            let op = match self.current_token() {
+                Token::Star => BinaryOp::Multiply,
+                Token::Slash => BinaryOp::Divide,
+                _ => break,
+            };
+            self.advance();
+            let right = self.parse_primary()?;
+            expr = Expr::Binary { left: Box::new(expr), op, right: Box::new(right) };
+        }
+
+        Ok(expr)
+    }
+
+    /// Parse an operand followed by any subscripts, e.g. `tags[1]`
+    fn parse_primary(&mut self) -> Result<Expr, DbError> {
+        let mut expr = self.parse_operand()?;
+        while self.current_token() == &Token::LeftBracket {
+            self.advance();
+            let index = self.parse_expr()?;
+            self.expect_token(Token::RightBracket)?;
+            expr = Expr::Subscript { array: Box::new(expr), index: Box::new(index) };
+        }
+        Ok(expr)
+    }
+
+    /// Parse a column reference, literal, parenthesized expression, row
+    /// value, array constructor, or function call such as LOWER(name)
+    fn parse_operand(&mut self) -> Result<Expr, DbError> {
+        match self.current_token().clone() {
+            Token::LeftParen => {
+                let mut items = self.parse_list()?;
+                if items.len() == 1 {
+                    return Ok(items.remove(0));
+                }
+                Ok(Expr::Row(items))
+            }
+            Token::Minus if !matches!(self.peek_token(), Token::IntLiteral(_) | Token::FloatLiteral(_)) => {
+                // Unary minus on a non-literal: 0 - expr
+                self.advance();
+                let operand = self.parse_primary()?;
+                Ok(Expr::Binary {
+                    left: Box::new(Expr::Literal(Value::Int(0))),
+                    op: BinaryOp::Subtract,
+                    right: Box::new(operand),
+                })
+            }
+            // ARRAY is not a reserved word; ARRAY[...] builds an array
+            Token::Identifier(name) if name == "array" && self.peek_token() == &Token::LeftBracket => {
+                self.advance();
+                self.advance();
+                let mut elements = Vec::new();
+                if self.current_token() != &Token::RightBracket {
+                    loop {
+                        elements.push(self.parse_expr()?);
+                        if self.current_token() != &Token::Comma {
+                            break;
+                        }
+                        self.advance();
+                    }
+                }
+                self.expect_token(Token::RightBracket)?;
+                Ok(Expr::Array(elements))
+            }
+            Token::Identifier(name) => {
+                self.advance();
+                if self.current_token() != &Token::LeftParen {
+                    return Ok(Expr::Column(name));
+                }
+                self.advance();
+
+                let mut args = Vec::new();
+                if self.current_token() != &Token::RightParen {
+                    loop {
+                        args.push(self.parse_expr()?);
+
+                        if self.current_token() == &Token::Comma {
+                            self.advance();
+                        } else {
+                            break;
+                        }
+                    }
+                }
+                self.expect_token(Token::RightParen)?;
+
+                Ok(Expr::Function {
+                    name: name.to_uppercase(),
+                    args,
+                })
+            }
+            // A materialized view's query runs again on refresh, when the
+            // variable may hold something else or be gone
+            Token::Variable(name) if self.view_query_start.is_some() => Err(DbError::Parse(format!(
+                "A materialized view's query cannot use variables such as @{}",
+                name
+            ))),
+            Token::Variable(name) => {
+                self.advance();
+                Ok(Expr::Variable { name, value: None })
+            }
+            _ => Ok(Expr::Literal(self.parse_value()?)),
+        }
+    }
+
+    fn parse_operator(&mut self) -> Result<Operator, DbError> {
+        let operator = match self.current_token() {
+            Token::Equals => Operator::Equals,
+            Token::NotEquals => Operator::NotEquals,
+            Token::GreaterThan => Operator::GreaterThan,
+            Token::LessThan => Operator::LessThan,
+            Token::GreaterOrEqual => Operator::GreaterOrEqual,
+            Token::LessOrEqual => Operator::LessOrEqual,
+            Token::Contains => Operator::Contains,
+            Token::Is => Operator::Is,
+            // IN is not a keyword token, but no column may be named in
+            Token::Identifier(name) if name == "in" => Operator::In,
+            token => return Err(DbError::Parse(format!("Expected operator, got {:?}", token))),
+        };
         self.advance();
-        
-        match token {
-            Token::Int => Ok(DataType::Int),
-            Token::Text => Ok(DataType::Text),
-            Token::Float => Ok(DataType::Float),
-            _ => Err(format!("Expected data type, got {:?}", token)),
+
+        if operator == Operator::Is && self.current_token() == &Token::Not {
+            self.advance();
+            return Ok(Operator::IsNot);
         }
+        Ok(operator)
     }
 
-    fn parse_value(&mut self) -> Result<Value, String> {
-        let token = self.current_token().clone();
+    fn parse_data_type(&mut self) -> Result<DataType, DbError> {
+        let data_type = match self.current_token() {
+            Token::Int => DataType::Int,
+            Token::Text => DataType::Text,
+            Token::Float => DataType::Float,
+            token => return Err(DbError::Parse(format!("Expected data type, got {:?}", token))),
+        };
         self.advance();
-        
-        match token {
-            Token::IntLiteral(n) => Ok(Value::Int(n)),
-            Token::FloatLiteral(f) => Ok(Value::Float(f)),
-            Token::StringLiteral(s) => Ok(Value::Text(s)),
-            _ => Err(format!("Expected value, got {:?}", token)),
+        if self.current_token() == &Token::LeftBracket {
+            self.advance();
+            self.expect_token(Token::RightBracket)?;
+            return Ok(DataType::Array(Box::new(data_type)));
         }
+        Ok(data_type)
     }
 
-    fn expect_token(&mut self, expected: Token) -> Result<(), String> {
+    fn parse_value(&mut self) -> Result<Value, DbError> {
+        let value = match self.current_token().clone() {
+            Token::IntLiteral(n) => Value::Int(n),
+            Token::FloatLiteral(f) => Value::Float(f),
+            Token::StringLiteral(s) => Value::Text(s),
+            Token::Null => Value::Null,
+            Token::Minus => {
+                self.advance();
+                return match self.parse_value()? {
+                    Value::Int(n) => Ok(Value::Int(-n)),
+                    Value::Float(f) => Ok(Value::Float(-f)),
+                    other => Err(DbError::Parse(format!("Cannot negate {:?}", other))),
+                };
+            }
+            token => return Err(DbError::Parse(format!("Expected value, got {:?}", token))),
+        };
+        self.advance();
+        Ok(value)
+    }
+
+    fn expect_token(&mut self, expected: Token) -> Result<(), DbError> {
         if self.current_token() == &expected {
             self.advance();
             Ok(())
         } else {
-            Err(format!(
+            Err(DbError::Parse(format!(
                 "Expected {:?}, got {:?}",
                 expected,
                 self.current_token()
-            ))
+            )))
+        }
+    }
+
+    /// Expect the end of the statement: an optional `;`, then nothing
+    fn expect_end(&mut self) -> Result<(), DbError> {
+        if self.current_token() == &Token::Semicolon {
+            self.advance();
+        }
+        match self.current_token() {
+            Token::Eof => Ok(()),
+            token => Err(DbError::Parse(format!("Unexpected {:?} after end of statement", token))),
+        }
+    }
+
+    /// Expect an unreserved word, in any case
+    fn expect_word(&mut self, word: &str) -> Result<(), DbError> {
+        match self.current_token() {
+            Token::Identifier(name) if name.eq_ignore_ascii_case(word) => {
+                self.advance();
+                Ok(())
+            }
+            token => Err(DbError::Parse(format!("Expected {}, got {:?}", word, token))),
         }
     }
 
-    fn expect_identifier(&mut self) -> Result<String, String> {
+    fn expect_identifier(&mut self) -> Result<String, DbError> {
         match self.current_token().clone() {
             Token::Identifier(name) => {
                 self.advance();
                 Ok(name)
             }
-            token => Err(format!("Expected identifier, got {:?}", token)),
+            token if is_keyword(&token) => Err(DbError::Parse(format!(
+                "Expected identifier, got reserved word '{}'",
+                format!("{:?}", token).to_uppercase()
+            ))),
+            token => Err(DbError::Parse(format!("Expected identifier, got {:?}", token))),
         }
     }
 
+    fn peek_token(&self) -> &Token {
+        self.tokens.get(self.position + 1).unwrap_or(&Token::Eof)
+    }
+
     fn current_token(&self) -> &Token {
         if self.position < self.tokens.len() {
             &self.tokens[self.position]
@@ -359,4 +1620,106 @@ impl Parser {
             self.position += 1;
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_statements_across_lines() {
+        let (statements, rest) = split_statements("CREATE TABLE t (\n  id INT\n);\nSELECT ';' FROM t\\G SELECT");
+        assert_eq!(statements, vec![
+            "CREATE TABLE t (\n  id INT\n)".to_string(),
+            "SELECT ';' FROM t\\G".to_string(),
+        ]);
+        assert_eq!(rest, "SELECT");
+
+        let (statements, rest) = split_statements(";;\n");
+        assert!(statements.is_empty());
+        assert_eq!(rest, "");
+    }
+    #[test]
+    fn test_identifiers_fold_to_lowercase() {
+        let Ok(Statement::Select { table_name, columns, .. }) = parse("SELECT Name FROM Users") else {
+            panic!("expected a SELECT");
+        };
+        assert_eq!(table_name, "users");
+        assert_eq!(columns, vec![SelectItem::Column("name".to_string())]);
+    }
+    #[test]
+    fn test_qualified_star() {
+        let Ok(Statement::Select { columns, .. }) = parse("SELECT Users.*, LENGTH(name) FROM users") else {
+            panic!("expected a SELECT");
+        };
+        assert_eq!(columns[0], SelectItem::AllColumns("users".to_string()));
+        assert_eq!(columns[0].to_string(), "users.*");
+        assert!(parse("SELECT users.name FROM users").is_err());
+    }
+    #[test]
+    fn test_trailing_input_is_rejected() {
+        assert!(parse("SELECT * FROM users;").is_ok());
+        assert_eq!(
+            parse("SELECT * FROM users garbage garbage").unwrap_err(),
+            DbError::Parse("Unexpected Identifier(\"garbage\") after end of statement at line 1, column 21".to_string())
+        );
+        assert!(parse("SELECT * FROM users; SELECT").is_err());
+    }
+    #[test]
+    fn test_error_positions() {
+        let sql = "SELECT *\nFROM users\nWHERE age >= 'é' OR";
+        let (err, position) = parse_with_position(sql).unwrap_err();
+        assert_eq!(position, Position { offset: 38, line: 3, column: 18 });
+        assert_eq!(err.to_string(), "Unexpected Identifier(\"or\") after end of statement at line 3, column 18");
+        assert_eq!(show_position(sql, position), "WHERE age >= 'é' OR\n                 ^");
+
+        let (err, position) = parse_with_position("SELECT 'open").unwrap_err();
+        assert_eq!(err, DbError::Parse("Unterminated string literal at line 1, column 8".to_string()));
+        assert_eq!(position.offset, 7);
+    }
+    #[test]
+    fn test_variables() {
+        let Ok(Statement::Delete { where_clause: Some(filter), .. }) = parse("DELETE FROM users WHERE id = @User_Id") else {
+            panic!("expected a DELETE");
+        };
+        assert_eq!(filter.right, Expr::Variable { name: "user_id".to_string(), value: None });
+        assert_eq!(filter.right.to_string(), "@user_id");
+
+        // Keywords are names like any other, taken as written
+        let Ok(Statement::SetVariable { name, .. }) = parse("SET @Table = 1") else {
+            panic!("expected SET @table");
+        };
+        assert_eq!(name, "table");
+        let Ok(Statement::Set { value: Some(value), .. }) = parse("SET output_mode = TABLE") else {
+            panic!("expected SET");
+        };
+        assert_eq!(value, Expr::Literal(Value::Text("table".to_string())));
+    }
+    #[test]
+    fn test_equalities_joined_by_and() {
+        let Ok(Statement::Update { where_clause: Some(filter), .. }) =
+            parse("UPDATE accounts SET balance = 90 WHERE id = 1 AND _version = 2")
+        else {
+            panic!("expected an UPDATE");
+        };
+        assert_eq!(filter.to_string(), "(id, _version) = (1, 2)");
+        assert!(parse("SELECT * FROM accounts WHERE id = 1 AND balance > 10").is_err());
+    }
+    #[test]
+    fn test_smallest_int_literal() {
+        let sql = format!("SELECT {} FROM t", i64::MIN);
+        let Ok(Statement::Select { columns, .. }) = parse(&sql) else {
+            panic!("expected a SELECT");
+        };
+        assert_eq!(columns, vec![SelectItem::Expr(Expr::Literal(Value::Int(i64::MIN)))]);
+        assert_eq!(format!("SELECT {} FROM t", columns[0]), sql);
+
+        assert!(parse("SELECT -9223372036854775809 FROM t").is_err());
+        assert!(parse("SELECT 9223372036854775808 FROM t").is_err());
+        // A minus after an operand still subtracts
+        let Ok(Statement::Select { columns, .. }) = parse("SELECT n-1 FROM t") else {
+            panic!("expected a SELECT");
+        };
+        assert_eq!(columns[0].to_string(), "n - 1");
+    }
+}
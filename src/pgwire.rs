@@ -0,0 +1,260 @@
+// PgWire - a subset of the PostgreSQL frontend/backend protocol
+//
+// Enough of protocol version 3 for `psql` and drivers using the simple query
+// protocol: startup without authentication (SSL is declined), then `Query`
+// messages answered with RowDescription, DataRow, CommandComplete, or
+// ErrorResponse, and ReadyForQuery. All values are sent in text format.
+// The extended query protocol (Parse/Bind/Execute) is answered with an error.
+// Each client gets a thread; statements take turns on the shared database.
+
+use crate::error::DbError;
+use crate::executor::{self, CancelToken, ExecutionResult, Settings};
+use crate::parser::{self, array, Value};
+use crate::planner::{self, Plan};
+use crate::protocol::{
+    self, BackendMessage, FieldDescription, FrontendMessage, CANCEL_REQUEST, FLOAT8_ARRAY_OID, FLOAT8_OID,
+    GSSENC_REQUEST, INT8_ARRAY_OID, INT8_OID, PROTOCOL_VERSION_3, SSL_REQUEST, TEXT_ARRAY_OID, TEXT_OID,
+};
+use crate::storage::{ttl, Database};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+/// Accept clients until the listener fails, serving each on its own thread.
+/// Expired rows are purged in the background meanwhile.
+pub fn serve(listener: TcpListener, db: Arc<Mutex<Database>>) -> io::Result<()> {
+    ttl::purge_periodically(&db);
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let db = Arc::clone(&db);
+        thread::spawn(move || {
+            if let Err(e) = handle_client(stream, &db) {
+                tracing::debug!(error = %e, "client connection closed");
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Run one client session from startup until it terminates or disconnects
+pub fn handle_client(stream: TcpStream, db: &Mutex<Database>) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    if !startup(&mut reader, &mut writer)? {
+        return Ok(());
+    }
+
+    // After an extended-protocol message fails, the protocol skips messages
+    // until the next Sync
+    let mut skip_until_sync = false;
+    let mut settings = Settings::default();
+    while let Some(message) = FrontendMessage::read(&mut reader)? {
+        match message {
+            FrontendMessage::Query(sql) => {
+                simple_query(&sql, db, &mut settings, &mut writer)?;
+                BackendMessage::ReadyForQuery.write(&mut writer)?;
+            }
+            FrontendMessage::Terminate => return Ok(()),
+            FrontendMessage::Sync => {
+                skip_until_sync = false;
+                BackendMessage::ReadyForQuery.write(&mut writer)?;
+            }
+            FrontendMessage::Flush => {}
+            FrontendMessage::Other(_) if skip_until_sync => {}
+            FrontendMessage::Other(_) => {
+                skip_until_sync = true;
+                error_response(&mut writer, "0A000", "Only the simple query protocol is supported")?;
+            }
+        }
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// Handle the startup packet, declining SSL. Returns false for a cancel
+/// request, which has no session.
+fn startup(reader: &mut impl Read, writer: &mut impl Write) -> io::Result<bool> {
+    loop {
+        let code = protocol::read_startup(reader)?;
+        match code {
+            SSL_REQUEST | GSSENC_REQUEST => {
+                writer.write_all(b"N")?;
+                writer.flush()?;
+            }
+            CANCEL_REQUEST => return Ok(false),
+            PROTOCOL_VERSION_3 => break,
+            _ => {
+                error_response(writer, "08P01", &format!("Unsupported protocol version {}", code))?;
+                writer.flush()?;
+                return Ok(false);
+            }
+        }
+    }
+
+    // AuthenticationOk, then the settings clients expect to be told
+    BackendMessage::AuthenticationOk.write(writer)?;
+    for (name, value) in [
+        ("server_version", "14.0"),
+        ("server_encoding", "UTF8"),
+        ("client_encoding", "UTF8"),
+        ("DateStyle", "ISO, MDY"),
+        ("integer_datetimes", "on"),
+        ("standard_conforming_strings", "on"),
+    ] {
+        let (name, value) = (name.to_string(), value.to_string());
+        BackendMessage::ParameterStatus { name, value }.write(writer)?;
+    }
+    BackendMessage::BackendKeyData { process_id: std::process::id() as i32, secret_key: 0 }.write(writer)?;
+    BackendMessage::ReadyForQuery.write(writer)?;
+    writer.flush()?;
+    Ok(true)
+}
+
+/// Run each statement of a Query message, stopping at the first error
+fn simple_query(sql: &str, db: &Mutex<Database>, settings: &mut Settings, writer: &mut impl Write) -> io::Result<()> {
+    let (mut statements, rest) = parser::split_statements(sql);
+    if !rest.is_empty() {
+        statements.push(rest);
+    }
+    if statements.is_empty() {
+        return BackendMessage::EmptyQueryResponse.write(writer);
+    }
+
+    for statement in statements {
+        let mut db = db.lock().unwrap_or_else(|e| e.into_inner());
+        match run_statement(&statement, &mut db, settings) {
+            Ok((tag, result)) => send_result(writer, &tag, result)?,
+            Err(e) => return error_response(writer, protocol::sqlstate(&e), &e.to_string()),
+        }
+    }
+    Ok(())
+}
+
+/// Plan and execute one statement, recording it in the audit log, and
+/// return its command tag and result
+fn run_statement(sql: &str, db: &mut Database, settings: &mut Settings) -> Result<(String, ExecutionResult), DbError> {
+    let plan = planner::plan(parser::parse(sql)?, db)?;
+    let tag = match &plan {
+        Plan::CreateTable { .. } | Plan::CreateTableLike { .. } | Plan::CreateExternalTable { .. } => "CREATE TABLE",
+        Plan::CreateIndex { .. } => "CREATE INDEX",
+        Plan::Insert { .. } | Plan::InsertSelect { .. } => "INSERT",
+        Plan::Query(_) => "SELECT",
+        Plan::Explain { .. } => "EXPLAIN",
+        Plan::Analyze { .. } => "ANALYZE",
+        Plan::Copy { .. } => "COPY",
+        Plan::Delete { .. } | Plan::Purge { .. } => "DELETE",
+        Plan::Update { .. } => "UPDATE",
+        Plan::AlterColumnType { .. } => "ALTER TABLE",
+        Plan::CreateMaterializedView { .. } => "CREATE MATERIALIZED VIEW",
+        Plan::RefreshMaterializedView { .. } => "REFRESH MATERIALIZED VIEW",
+        Plan::Set { value: Some(_), .. } | Plan::SetVariable { .. } => "SET",
+        Plan::SetConstraints { .. } => "SET CONSTRAINTS",
+        Plan::Set { value: None, .. } => "SHOW",
+    };
+    let start = Instant::now();
+    let result = executor::execute_in_session(plan, db, settings, CancelToken::new());
+    let rows = result.as_ref().ok().and_then(ExecutionResult::row_count);
+    db.record_statement(sql, rows, start.elapsed(), result.as_ref().err())?;
+    Ok((tag.to_string(), result?))
+}
+
+/// Send a statement's rows, if any, and its CommandComplete
+fn send_result(writer: &mut impl Write, tag: &str, result: ExecutionResult) -> io::Result<()> {
+    let complete = match result {
+        ExecutionResult::Rows { columns, rows } => {
+            row_description(writer, &columns, &rows)?;
+            for row in &rows {
+                data_row(writer, row)?;
+            }
+            format!("{} {}", tag, rows.len())
+        }
+        ExecutionResult::Changed { rows, .. } if tag == "INSERT" => format!("INSERT 0 {}", rows),
+        ExecutionResult::Changed { rows, .. } => format!("{} {}", tag, rows),
+        // EXPLAIN output is returned as rows of a "QUERY PLAN" column, as
+        // PostgreSQL does
+        ExecutionResult::Success(text) if tag == "EXPLAIN" => {
+            let rows: Vec<Vec<Value>> = text.lines().map(|line| vec![Value::Text(line.to_string())]).collect();
+            row_description(writer, &["QUERY PLAN".to_string()], &rows)?;
+            for row in &rows {
+                data_row(writer, row)?;
+            }
+            tag.to_string()
+        }
+        ExecutionResult::Success(_) => tag.to_string(),
+    };
+    BackendMessage::CommandComplete(complete).write(writer)
+}
+
+/// Describe result columns, typed by their first non-NULL value
+fn row_description(writer: &mut impl Write, columns: &[String], rows: &[Vec<Value>]) -> io::Result<()> {
+    let fields = columns.iter().enumerate()
+        .map(|(i, name)| {
+            let type_oid = match rows.iter().map(|row| &row[i]).find(|v| **v != Value::Null) {
+                Some(Value::Int(_)) => INT8_OID,
+                Some(Value::Float(_)) => FLOAT8_OID,
+                Some(Value::Array(values)) => match values.iter().find(|v| **v != Value::Null) {
+                    Some(Value::Int(_)) => INT8_ARRAY_OID,
+                    Some(Value::Float(_)) => FLOAT8_ARRAY_OID,
+                    _ => TEXT_ARRAY_OID,
+                },
+                _ => TEXT_OID,
+            };
+            FieldDescription { name: name.clone(), type_oid }
+        })
+        .collect();
+    BackendMessage::RowDescription(fields).write(writer)
+}
+
+fn data_row(writer: &mut impl Write, row: &[Value]) -> io::Result<()> {
+    let values = row.iter()
+        .map(|value| match value {
+            Value::Null => None,
+            Value::Int(n) => Some(n.to_string()),
+            Value::Float(f) => Some(f.to_string()),
+            Value::Text(s) => Some(s.clone()),
+            Value::Array(values) => Some(array::format_array(values)),
+        })
+        .collect();
+    BackendMessage::DataRow(values).write(writer)
+}
+
+fn error_response(writer: &mut impl Write, code: &str, text: &str) -> io::Result<()> {
+    BackendMessage::ErrorResponse { code: code.to_string(), message: text.to_string() }.write(writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simple_query_messages() {
+        let db = Mutex::new(Database::new());
+        let mut out = Vec::new();
+        simple_query("SELECT * FROM missing", &db, &mut Settings::default(), &mut out).unwrap();
+        assert_eq!(out[0], b'E');
+        assert!(String::from_utf8_lossy(&out).contains("42P01"));
+
+        let mut out = Vec::new();
+        send_result(&mut out, "SELECT", ExecutionResult::Rows {
+            columns: vec!["n".to_string()],
+            rows: vec![vec![Value::Int(7)], vec![Value::Null]],
+        }).unwrap();
+        let tags: Vec<u8> = message_tags(&out);
+        assert_eq!(tags, b"TDDC");
+        assert!(out.ends_with(b"SELECT 2\0"));
+    }
+
+    /// Type bytes of the messages in a buffer
+    fn message_tags(mut out: &[u8]) -> Vec<u8> {
+        let mut tags = Vec::new();
+        while let [tag, rest @ ..] = out {
+            tags.push(*tag);
+            let len = i32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            out = &rest[len..];
+        }
+        tags
+    }
+}
@@ -0,0 +1,576 @@
+// Semantic analysis - checks statements against the catalog before planning
+//
+// Resolves table and column names and checks the types of predicates and
+// assigned values, so mistakes are reported with context at plan time
+// instead of partway through execution. New table and column names are
+// checked against SQL's reserved words and the database's size limits.
+
+use crate::error::DbError;
+use crate::eval;
+use crate::executor::{OutputMode, Settings};
+use crate::parser::{self, Column, DataType, Expr, OrderBy, SelectItem, Series, SetOperator, Statement, WhereClause};
+use crate::storage::audit::AUDIT_TABLE;
+use crate::storage::{external, ttl, version, view};
+use crate::storage::Database;
+use std::fmt::Display;
+
+/// SQL keywords this database does not use yet. They may not name tables or
+/// columns, so such names keep working when the keywords are added.
+const FUTURE_KEYWORDS: &[&str] = &[
+    "AND", "AS", "BETWEEN", "CASE", "DISTINCT", "DROP", "ELSE", "END", "HAVING", "IN",
+    "JOIN", "LIKE", "OFFSET", "OR", "THEN", "WHEN",
+];
+
+/// Check that a statement is valid against the current schema. Every problem
+/// found is reported, not just the first.
+pub fn analyze(statement: &Statement, db: &Database) -> Result<(), DbError> {
+    let mut problems = Vec::new();
+    check_statement(statement, db, &mut problems)?;
+    if problems.len() > 1 {
+        let messages: Vec<String> = problems.iter().map(|e| e.to_string()).collect();
+        return Err(DbError::Plan(messages.join("\n")));
+    }
+    problems.pop().map_or(Ok(()), Err)
+}
+
+/// Check a statement, adding its problems to `problems`. Problems that stop
+/// the rest of the checks, like a missing table, are returned instead.
+fn check_statement(statement: &Statement, db: &Database, problems: &mut Vec<DbError>) -> Result<(), DbError> {
+    match statement {
+        Statement::CreateTable { table_name, columns, append_only } => {
+            if db.get_table(table_name).is_ok() {
+                return Err(DbError::TableExists(table_name.clone()));
+            }
+            let limits = db.limits();
+            problems.extend(limits.check_identifier(table_name, "Table").err());
+            problems.extend(limits.check_columns(table_name, columns).err());
+            check_name(table_name, "table", problems);
+            for (i, column) in columns.iter().enumerate() {
+                check_name(&column.name, "column", problems);
+                if columns[..i].iter().any(|c| c.name == column.name) {
+                    problems.push(DbError::Plan(format!(
+                        "Duplicate column '{}' in CREATE TABLE '{}'",
+                        column.name, table_name
+                    )));
+                }
+            }
+            problems.extend(ttl::check_columns(table_name, columns).err());
+            problems.extend(version::check_columns(table_name, columns).err());
+            if *append_only && columns.iter().any(|c| c.ttl.is_some()) {
+                problems.push(DbError::Plan(format!(
+                    "Append-only table '{}' cannot have a TTL column: its rows are never deleted",
+                    table_name
+                )));
+            }
+        }
+        Statement::CreateTableLike { table_name, source, .. } => {
+            table_columns(db, source)?;
+            if db.get_table(table_name).is_ok() {
+                return Err(DbError::TableExists(table_name.clone()));
+            }
+            problems.extend(db.limits().check_identifier(table_name, "Table").err());
+            check_name(table_name, "table", problems);
+        }
+        Statement::CreateExternalTable { table_name, columns, .. } => {
+            if db.get_table(table_name).is_ok() {
+                return Err(DbError::TableExists(table_name.clone()));
+            }
+            problems.extend(db.limits().check_identifier(table_name, "Table").err());
+            check_name(table_name, "table", problems);
+            // Columns inferred from the file are checked when it is read
+            if let Some(columns) = columns {
+                problems.extend(db.limits().check_columns(table_name, columns).err());
+                for column in columns {
+                    check_name(&column.name, "column", problems);
+                }
+                problems.extend(external::check_columns(table_name, columns).err());
+            }
+        }
+        Statement::CreateIndex { index_name, table_name, expr, .. } => {
+            let columns = writable_columns(db, table_name)?;
+            if let Some(name) = index_name {
+                problems.extend(db.limits().check_identifier(name, "Index").err());
+            }
+            if let Err(e) = eval::infer_type(expr, columns) {
+                problems.push(DbError::Plan(format!("In index expression on '{}': {}", table_name, e)));
+            }
+            if eval::is_volatile(expr) {
+                problems.push(DbError::Plan(format!(
+                    "Cannot index '{}' on '{}': its value may change on every call",
+                    expr, table_name
+                )));
+            }
+        }
+        Statement::Insert { table_name, values } => {
+            let columns = modifiable_columns(db, table_name)?;
+            let inserted = version::inserted_columns(columns, values.len());
+            if values.len() != inserted.len() {
+                problems.push(DbError::Plan(format!(
+                    "INSERT into '{}' expects {} values, got {}",
+                    table_name, columns.len(), values.len()
+                )));
+            }
+            for (expr, column) in values.iter().zip(inserted) {
+                match eval::referenced_columns(expr).first() {
+                    Some(name) => problems.push(DbError::Plan(format!("INSERT values cannot reference column '{}'", name))),
+                    None => problems.extend(check_assignment(expr, column, columns, db).err()),
+                }
+            }
+        }
+        Statement::InsertSelect { table_name, query } => {
+            let columns = modifiable_columns(db, table_name)?;
+            check_statement(query, db, problems)?;
+            if let Some(output) = output_columns(query, db) {
+                let inserted = version::inserted_columns(columns, output.len());
+                if output.len() != inserted.len() {
+                    problems.push(DbError::Plan(format!(
+                        "INSERT into '{}' expects {} values, the query gives {}",
+                        table_name, columns.len(), output.len()
+                    )));
+                }
+                for ((_, data_type), column) in output.into_iter().zip(inserted) {
+                    problems.extend(check_assigned_type(data_type, column, &"the query"));
+                }
+            }
+        }
+        Statement::Select { table_name, series: Some(series), sample, columns: selected, where_clause, group_by, order_by, .. } => {
+            if series.step == 0 {
+                problems.push(DbError::Plan(format!("{} step cannot be zero", Series::NAME)));
+            }
+            if sample.is_some() {
+                problems.push(DbError::Plan(format!("SAMPLE reads a table, not {}", Series::NAME)));
+            }
+            let columns = &series.columns();
+            check_select(selected, group_by, order_by, where_clause.as_ref(), columns, table_name, problems);
+        }
+        Statement::Select { table_name, series: None, columns: selected, where_clause, group_by, order_by, .. } => {
+            // A SELECT without FROM reads one row with no columns
+            let columns = match table_name.as_str() {
+                "" if selected.is_empty() => return Err(DbError::Plan("SELECT * needs a FROM clause".to_string())),
+                "" => &[],
+                _ => table_columns(db, table_name)?,
+            };
+            check_select(selected, group_by, order_by, where_clause.as_ref(), columns, table_name, problems);
+        }
+        Statement::SetOperation { operator, left, right, order_by, .. } => {
+            check_statement(left, db, problems)?;
+            check_statement(right, db, problems)?;
+            check_set_operation(*operator, left, right, order_by, db, problems);
+        }
+        Statement::Delete { table_name, where_clause } => {
+            let columns = modifiable_columns(db, table_name)?;
+            problems.extend(db.check_changeable(table_name).err());
+            check_filter(where_clause.as_ref(), columns, table_name, problems);
+        }
+        Statement::Update { table_name, column, value, where_clause } => {
+            let columns = modifiable_columns(db, table_name)?;
+            problems.extend(db.check_changeable(table_name).err());
+            match resolve_column(columns, column) {
+                Ok(target) if target.name == version::VERSION_COLUMN => problems.push(DbError::Plan(format!(
+                    "In SET of '{}': column '{}' is maintained automatically",
+                    table_name, column
+                ))),
+                Ok(target) => problems.extend(check_assignment(value, target, columns, db).err()),
+                Err(e) => problems.push(DbError::Plan(format!("In SET of '{}': {}", table_name, e))),
+            }
+            check_filter(where_clause.as_ref(), columns, table_name, problems);
+        }
+        Statement::AlterColumnType { table_name, column, data_type } => {
+            let columns = modifiable_columns(db, table_name)?;
+            match resolve_column(columns, column) {
+                Err(e) => problems.push(DbError::Plan(format!("In ALTER TABLE '{}': {}", table_name, e))),
+                Ok(target) if target.name == version::VERSION_COLUMN && *data_type != DataType::Int => {
+                    problems.push(DbError::Plan(format!(
+                        "In ALTER TABLE '{}': version column '{}' must stay INT",
+                        table_name, column
+                    )));
+                }
+                Ok(target) if target.ttl.is_some() && !ttl::is_timestamp_type(data_type) => {
+                    problems.push(DbError::Plan(format!(
+                        "In ALTER TABLE '{}': TTL column '{}' must stay INT or TEXT",
+                        table_name, column
+                    )));
+                }
+                Ok(_) => {}
+            }
+        }
+        Statement::CreateMaterializedView { view_name, query, .. } => {
+            if db.get_table(view_name).is_ok() {
+                return Err(DbError::TableExists(view_name.clone()));
+            }
+            problems.extend(db.limits().check_identifier(view_name, "Table").err());
+            check_name(view_name, "view", problems);
+            check_statement(query, db, problems)?;
+            if let Some(output) = output_columns(query, db) {
+                let columns: Vec<Column> = output.into_iter()
+                    .map(|(name, data_type)| Column::new(name, data_type.unwrap_or(DataType::Text)))
+                    .collect();
+                problems.extend(view::check_columns(view_name, &columns).err());
+            }
+        }
+        Statement::RefreshMaterializedView { view_name } => {
+            let Some(sql) = db.view_query(view_name) else {
+                table_columns(db, view_name)?;
+                return Err(DbError::Plan(format!("'{}' is not a materialized view", view_name)));
+            };
+            // The tables the view was made from may have changed since
+            let in_view = |e: DbError| DbError::Plan(format!("In the query of materialized view '{}': {}", view_name, e));
+            let query = parser::parse(sql).map_err(in_view)?;
+            let mut query_problems = Vec::new();
+            check_statement(&query, db, &mut query_problems).map_err(in_view)?;
+            problems.extend(query_problems.into_iter().map(in_view));
+        }
+        Statement::Set { name, value } => {
+            let mut settings = Settings::default();
+            let result = match value {
+                Some(value) => eval::evaluate_constant(value).and_then(|value| settings.set(name, &value)),
+                None => settings.get(name).map(|_| ()),
+            };
+            problems.extend(result.err());
+        }
+        // Other variables in the value are only known when it runs
+        Statement::SetVariable { name, value } => {
+            if let Err(e) = eval::infer_type(value, &[]) {
+                problems.push(DbError::Plan(format!("In the value of @{}: {}", name, e)));
+            }
+        }
+        // Whether a transaction is open is known only when it runs
+        Statement::SetConstraints { .. } => {}
+        Statement::Explain { statement, .. } => check_statement(statement, db, problems)?,
+        Statement::Analyze { table_name } => {
+            if let Some(name) = table_name {
+                writable_columns(db, name)?;
+            }
+        }
+        Statement::Purge { table_name } => {
+            if let Some(name) = table_name
+                && !writable_columns(db, name)?.iter().any(|c| c.ttl.is_some())
+            {
+                problems.push(DbError::Plan(format!("Table '{}' has no TTL column to purge by", name)));
+            }
+        }
+        Statement::Copy { table_name, format, .. } => {
+            table_columns(db, table_name)?;
+            if format != "parquet" && OutputMode::from_name(format).is_none() {
+                problems.push(DbError::Plan(format!(
+                    "Unknown COPY format '{}'; use parquet, csv, json, or another .mode name",
+                    format
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check that a new table or column name is not a SQL keyword
+fn check_name(name: &str, kind: &str, problems: &mut Vec<DbError>) {
+    if FUTURE_KEYWORDS.iter().any(|k| k.eq_ignore_ascii_case(name)) {
+        problems.push(DbError::Plan(format!(
+            "'{}' is a reserved word and cannot be used as a {} name",
+            name, kind
+        )));
+    }
+}
+
+/// Look up a table's columns
+fn table_columns<'a>(db: &'a Database, table_name: &str) -> Result<&'a [Column], DbError> {
+    db.get_table(table_name).map(|t| t.columns.as_slice())
+}
+
+/// Look up the columns of a table that statements may change, which the
+/// `__audit` table and external tables are not
+fn writable_columns<'a>(db: &'a Database, table_name: &str) -> Result<&'a [Column], DbError> {
+    if table_name == AUDIT_TABLE {
+        return Err(DbError::Plan(format!("Table '{}' is read-only", table_name)));
+    }
+    if let Some(location) = db.external_location(table_name) {
+        return Err(DbError::Plan(format!("Table '{}' is read-only: it reads the file '{}'", table_name, location)));
+    }
+    table_columns(db, table_name)
+}
+
+/// Look up the columns of a table whose rows statements may change, which
+/// a materialized view's are not: only REFRESH replaces them
+fn modifiable_columns<'a>(db: &'a Database, table_name: &str) -> Result<&'a [Column], DbError> {
+    if db.view_query(table_name).is_some() {
+        return Err(DbError::Plan(format!(
+            "Materialized view '{}' can only be changed by REFRESH MATERIALIZED VIEW",
+            table_name
+        )));
+    }
+    writable_columns(db, table_name)
+}
+
+/// Resolve a column name against a table's columns
+fn resolve_column<'a>(columns: &'a [Column], name: &str) -> Result<&'a Column, DbError> {
+    columns.iter()
+        .find(|c| c.name == name)
+        .ok_or_else(|| DbError::ColumnNotFound(name.to_string()))
+}
+
+/// Check the grouping, select list, and filter of a SELECT
+fn check_select(
+    selected: &[SelectItem],
+    group_by: &[String],
+    order_by: &[OrderBy],
+    where_clause: Option<&WhereClause>,
+    columns: &[Column],
+    table_name: &str,
+    problems: &mut Vec<DbError>,
+) {
+    for name in group_by {
+        if let Err(e) = resolve_column(columns, name) {
+            problems.push(in_clause("GROUP BY", "of", table_name, &e));
+        }
+    }
+    let selected = &match super::expand_all_columns(selected.to_vec(), table_name, columns) {
+        Ok(expanded) => expanded,
+        Err(e) => {
+            problems.push(in_clause("SELECT list", "of", table_name, &e));
+            return;
+        }
+    };
+    // An aggregate in ORDER BY aggregates the query, as one in the SELECT list does
+    let aggregating = !group_by.is_empty()
+        || selected.iter()
+            .chain(order_by.iter().map(|key| &key.item))
+            .any(|item| matches!(item, SelectItem::Aggregate(_)));
+    check_select_list(selected, group_by, aggregating, columns, table_name, problems);
+    check_order_by(order_by, selected, group_by, aggregating, columns, table_name, problems);
+    check_filter(where_clause, columns, table_name, problems);
+}
+
+/// Check a value assigned to a column by INSERT or UPDATE: a literal's type
+/// and size, or the type of an expression over the row's `columns`
+fn check_assignment(expr: &Expr, target: &Column, columns: &[Column], db: &Database) -> Result<(), DbError> {
+    if let Expr::Literal(value) = expr {
+        eval::check_value_type(value, target)?;
+        return db.limits().check_value(value, target);
+    }
+    let data_type = eval::infer_type(expr, columns)?;
+    check_assigned_type(data_type, target, &format!("'{}'", expr)).map_or(Ok(()), Err)
+}
+
+/// Check that a computed value of type `data_type` (None when unknown) can be
+/// stored in a column, under the same casts as `eval::check_value_type`
+fn check_assigned_type(data_type: Option<DataType>, column: &Column, source: &dyn Display) -> Option<DbError> {
+    let data_type = data_type?;
+    (!eval::widens_to(&data_type, &column.data_type)).then(|| DbError::Type(format!(
+        "Type mismatch for column '{}': expected {}, got {} from {}",
+        column.name, column.data_type, data_type, source
+    )))
+}
+
+/// Names and types of the columns a query produces (None where the type is
+/// unknown), or None if one of them does not resolve (which the query's own
+/// checks report)
+pub fn output_columns(query: &Statement, db: &Database) -> Option<Vec<(String, Option<DataType>)>> {
+    let (table_name, series, selected) = match query {
+        Statement::Select { table_name, series, columns, .. } => (table_name, series, columns),
+        Statement::SetOperation { left, .. } => return output_columns(left, db),
+        _ => return None,
+    };
+    let columns = match series {
+        Some(series) => series.columns(),
+        None if table_name.is_empty() => Vec::new(),
+        None => table_columns(db, table_name).ok()?.to_vec(),
+    };
+    if selected.is_empty() {
+        return Some(columns.into_iter().map(|c| (c.name, Some(c.data_type))).collect());
+    }
+    super::expand_all_columns(selected.clone(), table_name, &columns).ok()?
+        .iter()
+        .map(|item| {
+            let data_type = match item {
+                SelectItem::Column(name) => Some(resolve_column(&columns, name).ok()?.data_type.clone()),
+                SelectItem::Aggregate(aggregate) => Some(eval::aggregate_type(aggregate, &columns).ok()?),
+                SelectItem::Expr(expr) => eval::infer_type(expr, &columns).ok()?,
+                SelectItem::AllColumns(_) => None,
+            };
+            Some((item.to_string(), data_type))
+        })
+        .collect()
+}
+
+/// Check that both queries of a set operation produce matching columns,
+/// and that its ORDER BY refers to them
+fn check_set_operation(
+    operator: SetOperator,
+    left: &Statement,
+    right: &Statement,
+    order_by: &[OrderBy],
+    db: &Database,
+    problems: &mut Vec<DbError>,
+) {
+    let (Some(left), Some(right)) = (output_columns(left, db), output_columns(right, db)) else {
+        return;
+    };
+    if left.len() != right.len() {
+        problems.push(DbError::Plan(format!(
+            "Each query in a {} must have the same number of columns, got {} and {}",
+            operator, left.len(), right.len()
+        )));
+        return;
+    }
+    for (i, ((name, left_type), (_, right_type))) in left.iter().zip(&right).enumerate() {
+        if let (Some(left_type), Some(right_type)) = (left_type, right_type)
+            && left_type != right_type
+        {
+            problems.push(DbError::Type(format!(
+                "{} column {} ('{}') is {} in the first query and {} in the second",
+                operator, i + 1, name, left_type, right_type
+            )));
+        }
+    }
+
+    // Keys refer to the output columns; an aggregate only by its column name
+    let columns: Vec<Column> = left.into_iter()
+        .map(|(name, data_type)| Column::new(name, data_type.unwrap_or(DataType::Text)))
+        .collect();
+    match super::resolve_ordinals(order_by.to_vec(), &[], &columns) {
+        Ok(keys) => {
+            for key in keys {
+                let item = match key.item {
+                    SelectItem::Aggregate(aggregate) => SelectItem::Column(aggregate.to_string()),
+                    item => item,
+                };
+                if let Err(e) = check_item(&item, &[], false, &columns) {
+                    problems.push(in_clause("ORDER BY", "of", "", &e));
+                }
+            }
+        }
+        Err(e) => problems.push(in_clause("ORDER BY", "of", "", &e)),
+    }
+}
+
+/// Check a SELECT list. When aggregating, every column outside an aggregate
+/// must be grouped on.
+fn check_select_list(
+    selected: &[SelectItem],
+    group_by: &[String],
+    aggregating: bool,
+    columns: &[Column],
+    table_name: &str,
+    problems: &mut Vec<DbError>,
+) {
+    let context = |e: &dyn Display| in_clause("SELECT list", "of", table_name, e);
+    if aggregating && selected.is_empty() {
+        problems.push(context(&"SELECT * cannot be used with GROUP BY"));
+    }
+    for item in selected {
+        if let Err(e) = check_item(item, group_by, aggregating, columns) {
+            problems.push(context(&e));
+        }
+    }
+}
+
+/// Check ORDER BY keys like SELECT list entries, and that ordinals are in
+/// range of the SELECT list
+fn check_order_by(
+    order_by: &[OrderBy],
+    selected: &[SelectItem],
+    group_by: &[String],
+    aggregating: bool,
+    columns: &[Column],
+    table_name: &str,
+    problems: &mut Vec<DbError>,
+) {
+    let context = |e: &dyn Display| in_clause("ORDER BY", "of", table_name, e);
+    match super::resolve_ordinals(order_by.to_vec(), selected, columns) {
+        Ok(keys) => {
+            // Ordinals name SELECT list entries, which are checked there
+            for (key, _) in keys.iter().zip(order_by).filter(|(_, key)| key.ordinal().is_none()) {
+                if let Err(e) = check_item(&key.item, group_by, aggregating, columns) {
+                    problems.push(context(&e));
+                }
+            }
+        }
+        Err(e) => problems.push(context(&e)),
+    }
+}
+
+/// Check one SELECT list or ORDER BY entry against the source columns
+fn check_item(item: &SelectItem, group_by: &[String], aggregating: bool, columns: &[Column]) -> Result<(), DbError> {
+    let ungrouped = |name: &str| DbError::Plan(format!(
+        "Column '{}' must appear in GROUP BY or be used in an aggregate",
+        name
+    ));
+    match item {
+        SelectItem::AllColumns(_) => {
+            return Err(DbError::Plan(format!("'{}' can only be used in the SELECT list", item)));
+        }
+        SelectItem::Column(name) => {
+            resolve_column(columns, name)?;
+            if aggregating && !group_by.contains(name) {
+                return Err(ungrouped(name));
+            }
+        }
+        SelectItem::Aggregate(aggregate) => {
+            eval::aggregate_type(aggregate, columns)?;
+        }
+        SelectItem::Expr(expr) => {
+            eval::infer_type(expr, columns)?;
+            if aggregating
+                && let Some(name) = eval::referenced_columns(expr).into_iter().find(|c| !group_by.iter().any(|g| g == c))
+            {
+                return Err(ungrouped(name));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check that both sides of a WHERE clause resolve and can be compared
+fn check_filter(filter: Option<&WhereClause>, columns: &[Column], table_name: &str, problems: &mut Vec<DbError>) {
+    let Some(predicate) = filter else {
+        return;
+    };
+    let context = |e: &dyn Display| in_clause("WHERE clause", "on", table_name, e);
+    if predicate.compares_rows() {
+        if let Err(e) = eval::check_row_comparison(predicate, columns) {
+            problems.push(context(&format!("{} in '{}'", e, predicate)));
+        }
+        return;
+    }
+
+    let left = eval::infer_type(&predicate.left, columns).map_err(|e| context(&e));
+    let right = eval::infer_type(&predicate.right, columns).map_err(|e| context(&e));
+
+    match (left, right) {
+        (Ok(left), Ok(right)) => {
+            if let Err(e) = eval::check_comparison(left.as_ref(), &predicate.operator, right.as_ref()) {
+                problems.push(context(&format!("{} in '{}'", e, predicate)));
+            }
+        }
+        (left, right) => problems.extend(left.err().into_iter().chain(right.err())),
+    }
+}
+
+/// Prefix an error with the clause it was found in and, unless the query
+/// has no FROM, the table it reads
+fn in_clause(clause: &str, preposition: &str, table_name: &str, e: &dyn Display) -> DbError {
+    if table_name.is_empty() {
+        DbError::Plan(format!("In {}: {}", clause, e))
+    } else {
+        DbError::Plan(format!("In {} {} '{}': {}", clause, preposition, table_name, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    #[test]
+    fn test_reports_every_problem() {
+        let db = Database::new();
+        let statement = parse("CREATE TABLE t (a INT, offset TEXT, a INT)").unwrap();
+        assert_eq!(analyze(&statement, &db), Err(DbError::Plan(
+            "'offset' is a reserved word and cannot be used as a column name\n\
+             Duplicate column 'a' in CREATE TABLE 't'".to_string()
+        )));
+
+        let statement = parse("SELECT a FROM missing").unwrap();
+        assert_eq!(analyze(&statement, &db), Err(DbError::TableNotFound("missing".to_string())));
+    }
+}
@@ -0,0 +1,116 @@
+// Plan cache - reuses plans for repeated SQL text
+
+use super::Plan;
+use crate::storage::Database;
+use std::collections::HashMap;
+
+const DEFAULT_CAPACITY: usize = 128;
+
+/// Cache of planned statements keyed by SQL text. Entries are tagged with
+/// the database schema version they were planned against and discarded once
+/// the schema changes (e.g. a new index makes a better plan possible).
+pub struct PlanCache {
+    entries: HashMap<String, CacheEntry>,
+    capacity: usize,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+struct CacheEntry {
+    plan: Plan,
+    schema_version: u64,
+    last_used: u64,
+}
+
+impl PlanCache {
+    /// Create a cache holding at most `capacity` plans
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            capacity,
+            clock: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Look up a cached plan for SQL text, if still valid for the schema
+    pub fn get(&mut self, sql: &str, db: &Database) -> Option<Plan> {
+        let key = cache_key(sql);
+        self.clock += 1;
+
+        match self.entries.get_mut(key) {
+            Some(entry) if entry.schema_version == db.schema_version() => {
+                entry.last_used = self.clock;
+                self.hits += 1;
+                Some(entry.plan.clone())
+            }
+            Some(_) => {
+                self.entries.remove(key);
+                self.misses += 1;
+                None
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Remember the plan for SQL text, evicting the least recently used
+    /// entry when full. DDL plans are not cached.
+    pub fn insert(&mut self, sql: &str, plan: &Plan, db: &Database) {
+        if !plan.is_cacheable() || self.capacity == 0 {
+            return;
+        }
+
+        let key = cache_key(sql);
+        if !self.entries.contains_key(key) && self.entries.len() >= self.capacity {
+            let oldest = self.entries.iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        self.clock += 1;
+        self.entries.insert(key.to_string(), CacheEntry {
+            plan: plan.clone(),
+            schema_version: db.schema_version(),
+            last_used: self.clock,
+        });
+    }
+
+    /// Drop all cached plans
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Number of cached plans
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check whether the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Number of lookups that found a valid plan, and that did not
+    pub fn stats(&self) -> (u64, u64) {
+        (self.hits, self.misses)
+    }
+}
+
+impl Default for PlanCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+/// Normalize SQL text so trivial differences don't miss the cache
+pub fn cache_key(sql: &str) -> &str {
+    sql.trim().trim_end_matches(';').trim_end()
+}
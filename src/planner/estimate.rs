@@ -0,0 +1,92 @@
+// Cardinality estimation - predicts row counts from ANALYZE statistics
+
+use super::PhysicalPlan;
+use crate::parser::{Expr, Operator, SetOperator, Value, WhereClause};
+use crate::storage::Database;
+
+/// Selectivities assumed when no statistics apply (the classic System R defaults)
+const DEFAULT_EQUALS_SELECTIVITY: f64 = 0.1;
+const DEFAULT_RANGE_SELECTIVITY: f64 = 1.0 / 3.0;
+
+/// Fraction of input rows assumed to start a new group when a grouping
+/// column has no statistics
+const DEFAULT_GROUP_FRACTION: f64 = 0.1;
+
+/// Estimate the fraction of a table's rows that satisfy a predicate
+pub fn selectivity(predicate: &WhereClause, table_name: &str, db: &Database) -> f64 {
+    if let (Expr::Column(column), Expr::Literal(value)) = (&predicate.left, &predicate.right)
+        && let Some(stats) = db.table_stats(table_name)
+        && let Some(column_stats) = stats.columns.get(column)
+    {
+        return column_stats.selectivity(&predicate.operator, value, stats.row_count);
+    }
+
+    match predicate.operator {
+        Operator::Equals | Operator::Is | Operator::Contains => DEFAULT_EQUALS_SELECTIVITY,
+        Operator::Any => match &predicate.right {
+            Expr::Literal(Value::Array(elements)) => (elements.len() as f64 * DEFAULT_EQUALS_SELECTIVITY).min(1.0),
+            _ => DEFAULT_EQUALS_SELECTIVITY,
+        },
+        Operator::In => match &predicate.right {
+            Expr::Row(rows) => (rows.len() as f64 * DEFAULT_EQUALS_SELECTIVITY).min(1.0),
+            _ => DEFAULT_EQUALS_SELECTIVITY,
+        },
+        Operator::NotEquals | Operator::IsNot => 1.0 - DEFAULT_EQUALS_SELECTIVITY,
+        _ => DEFAULT_RANGE_SELECTIVITY,
+    }
+}
+
+/// Estimate the number of rows a plan produces. The current table size is
+/// used as the base so estimates stay sensible as tables grow after ANALYZE.
+pub fn estimate_rows(plan: &PhysicalPlan, db: &Database) -> f64 {
+    let table_rows = |name: &str| {
+        db.get_table(name).map(|t| t.rows.len() as f64).unwrap_or(0.0)
+    };
+
+    match plan {
+        PhysicalPlan::SeqScan { table_name, limit, .. } => {
+            let rows = table_rows(table_name);
+            limit.map_or(rows, |limit| rows.min(limit as f64))
+        }
+        PhysicalPlan::Empty { .. } => 0.0,
+        PhysicalPlan::SampleScan { table_name, count } => table_rows(table_name).min(*count as f64),
+        PhysicalPlan::Series(series) => series.len() as f64,
+        PhysicalPlan::SingleRow => 1.0,
+        PhysicalPlan::TableCount { .. } => 1.0,
+        PhysicalPlan::IndexScan { table_name, predicate, .. }
+        | PhysicalPlan::ParallelScan { table_name, predicate, .. } => {
+            table_rows(table_name) * selectivity(predicate, table_name, db)
+        }
+        PhysicalPlan::Filter { input, predicate } => {
+            estimate_rows(input, db) * selectivity(predicate, input.table_name(), db)
+        }
+        PhysicalPlan::Project { input, .. }
+        | PhysicalPlan::Compute { input, .. }
+        | PhysicalPlan::Sort { input, .. } => estimate_rows(input, db),
+        PhysicalPlan::Limit { input, count } => estimate_rows(input, db).min(*count as f64),
+        PhysicalPlan::SetOperation { operator, left, right, .. } => {
+            let (left, right) = (estimate_rows(left, db), estimate_rows(right, db));
+            match operator {
+                SetOperator::Union => left + right,
+                SetOperator::Intersect => left.min(right),
+                SetOperator::Except => left,
+            }
+        }
+        PhysicalPlan::HashAggregate { input, group_by, .. } => {
+            if group_by.is_empty() {
+                return 1.0;
+            }
+            let input_rows = estimate_rows(input, db);
+            // One row per distinct combination of grouping values, which
+            // cannot exceed the number of input rows
+            let stats = db.table_stats(input.table_name());
+            let groups = group_by.iter()
+                .map(|column| {
+                    stats.and_then(|s| s.columns.get(column))
+                        .map_or(input_rows * DEFAULT_GROUP_FRACTION, |c| c.distinct as f64)
+                })
+                .product::<f64>();
+            groups.min(input_rows)
+        }
+    }
+}
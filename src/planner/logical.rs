@@ -0,0 +1,323 @@
+// Logical plan - relational operators describing what a query computes
+
+use crate::parser::{Aggregate, Expr, OrderBy, SelectItem, Series, SetOperator, WhereClause};
+use std::fmt;
+
+/// Logical query plan
+#[derive(Debug, Clone)]
+pub enum LogicalPlan {
+    Scan {
+        table_name: String,
+        projection: Option<Vec<String>>, // None reads every column
+    },
+    Empty {
+        table_name: String, // Supplies the output columns; no rows are read
+    },
+    /// `count` rows of a table chosen at random, for SAMPLE
+    Sample {
+        table_name: String,
+        count: usize,
+    },
+    Series(Series),
+    /// One row with no columns, the source of a SELECT without FROM
+    SingleRow,
+    Filter {
+        input: Box<LogicalPlan>,
+        predicate: WhereClause,
+    },
+    Project {
+        input: Box<LogicalPlan>,
+        columns: Vec<String>,
+    },
+    /// A projection that computes expressions, e.g. `price * 2`, per row
+    Compute {
+        input: Box<LogicalPlan>,
+        exprs: Vec<Expr>,
+    },
+    Aggregate {
+        input: Box<LogicalPlan>,
+        group_by: Vec<String>,
+        aggregates: Vec<Aggregate>, // Output after the grouping columns
+    },
+    /// Combines the rows of two queries with the same number of columns
+    SetOperation {
+        operator: SetOperator,
+        all: bool,
+        left: Box<LogicalPlan>,
+        right: Box<LogicalPlan>,
+    },
+    /// Orders its input by keys evaluated over the input's columns
+    Sort {
+        input: Box<LogicalPlan>,
+        keys: Vec<SortKey>,
+    },
+    Limit {
+        input: Box<LogicalPlan>,
+        count: usize,
+    },
+}
+
+/// An expression to sort by, and its direction
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortKey {
+    pub expr: Expr,
+    pub descending: bool,
+}
+
+impl fmt::Display for SortKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.expr)?;
+        if self.descending {
+            write!(f, " DESC")?;
+        }
+        Ok(())
+    }
+}
+
+/// Build the logical plan for a SELECT
+pub fn select(
+    table_name: String,
+    columns: Vec<SelectItem>,
+    where_clause: Option<WhereClause>,
+    group_by: Vec<String>,
+    order_by: Vec<OrderBy>,
+    limit: Option<usize>,
+) -> LogicalPlan {
+    let source = LogicalPlan::Scan {
+        table_name,
+        projection: None,
+    };
+    select_from(source, columns, where_clause, group_by, order_by, limit)
+}
+
+/// Build the logical plan for a SELECT reading from `source`, a table scan
+/// or a series. ORDER BY ordinals must already be replaced by the SELECT
+/// list entries they refer to.
+pub fn select_from(
+    source: LogicalPlan,
+    columns: Vec<SelectItem>,
+    where_clause: Option<WhereClause>,
+    group_by: Vec<String>,
+    order_by: Vec<OrderBy>,
+    limit: Option<usize>,
+) -> LogicalPlan {
+    let plan = select_list(source, columns, where_clause, group_by, order_by);
+    limit_to(plan, limit)
+}
+
+/// Build the logical plan for a set operation. ORDER BY keys refer to the
+/// output columns, which are named after the left query's.
+pub fn set_operation(
+    operator: SetOperator,
+    all: bool,
+    left: LogicalPlan,
+    right: LogicalPlan,
+    order_by: Vec<OrderBy>,
+    limit: Option<usize>,
+) -> LogicalPlan {
+    let plan = LogicalPlan::SetOperation {
+        operator,
+        all,
+        left: Box::new(left),
+        right: Box::new(right),
+    };
+    limit_to(sort(plan, order_by), limit)
+}
+
+fn limit_to(plan: LogicalPlan, limit: Option<usize>) -> LogicalPlan {
+    match limit {
+        Some(count) => LogicalPlan::Limit {
+            input: Box::new(plan),
+            count,
+        },
+        None => plan,
+    }
+}
+
+/// Build the scan, filter, aggregation, sort, and projection for a SELECT.
+/// The sort runs before the projection, so it can use columns the SELECT
+/// list leaves out; when aggregating, it sorts the groups.
+fn select_list(
+    source: LogicalPlan,
+    columns: Vec<SelectItem>,
+    where_clause: Option<WhereClause>,
+    group_by: Vec<String>,
+    order_by: Vec<OrderBy>,
+) -> LogicalPlan {
+    let mut plan = source;
+
+    if let Some(predicate) = where_clause {
+        plan = LogicalPlan::Filter {
+            input: Box::new(plan),
+            predicate,
+        };
+    }
+
+    let mut aggregates: Vec<Aggregate> = Vec::new();
+    for item in columns.iter().chain(order_by.iter().map(|key| &key.item)) {
+        if let SelectItem::Aggregate(aggregate) = item
+            && !aggregates.contains(aggregate)
+        {
+            aggregates.push(aggregate.clone());
+        }
+    }
+
+    let names: Vec<String> = columns.iter().map(|item| item.to_string()).collect();
+    let computed = columns.iter().any(|item| matches!(item, SelectItem::Expr(_)));
+    if !aggregates.is_empty() || !group_by.is_empty() {
+        // The aggregate outputs the grouping columns then each aggregate; a
+        // projection restores the SELECT list order when it differs
+        let output: Vec<String> = group_by.iter()
+            .cloned()
+            .chain(aggregates.iter().map(|a| a.to_string()))
+            .collect();
+        plan = LogicalPlan::Aggregate {
+            input: Box::new(plan),
+            group_by,
+            aggregates,
+        };
+        plan = sort(plan, order_by);
+        if computed {
+            return compute(plan, columns);
+        }
+        if names != output {
+            plan = LogicalPlan::Project {
+                input: Box::new(plan),
+                columns: names,
+            };
+        }
+        return plan;
+    }
+
+    plan = sort(plan, order_by);
+    if computed {
+        return compute(plan, columns);
+    }
+
+    // An empty column list means SELECT *, which needs no projection
+    if !names.is_empty() {
+        plan = LogicalPlan::Project {
+            input: Box::new(plan),
+            columns: names,
+        };
+    }
+
+    plan
+}
+
+/// Compute a SELECT list over `plan`
+fn compute(plan: LogicalPlan, columns: Vec<SelectItem>) -> LogicalPlan {
+    LogicalPlan::Compute {
+        input: Box::new(plan),
+        exprs: columns.into_iter().map(item_expr).collect(),
+    }
+}
+
+/// Sort `plan` by the ORDER BY keys, if there are any
+fn sort(plan: LogicalPlan, order_by: Vec<OrderBy>) -> LogicalPlan {
+    if order_by.is_empty() {
+        return plan;
+    }
+    let keys = order_by.into_iter()
+        .map(|key| SortKey { expr: item_expr(key.item), descending: key.descending })
+        .collect();
+    LogicalPlan::Sort {
+        input: Box::new(plan),
+        keys,
+    }
+}
+
+/// A SELECT list entry as an expression. Aggregates refer to the
+/// aggregate's output columns by name.
+fn item_expr(item: SelectItem) -> Expr {
+    match item {
+        SelectItem::Column(name) => Expr::Column(name),
+        SelectItem::Aggregate(aggregate) => Expr::Column(aggregate.to_string()),
+        SelectItem::Expr(expr) => expr,
+        // Expanded by the planner before the plan is built
+        SelectItem::AllColumns(table_name) => Expr::Column(format!("{}.*", table_name)),
+    }
+}
+
+impl fmt::Display for LogicalPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_indented(f, 0)
+    }
+}
+
+impl LogicalPlan {
+    fn fmt_indented(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        let indent = "  ".repeat(depth);
+        match self {
+            LogicalPlan::Scan { table_name, projection } => {
+                writeln!(f, "{}Scan {}{}", indent, table_name, format_projection(projection))
+            }
+            LogicalPlan::Empty { table_name } => writeln!(f, "{}Empty {}", indent, table_name),
+            LogicalPlan::Sample { table_name, count } => writeln!(f, "{}Sample {} ({} rows)", indent, table_name, count),
+            LogicalPlan::Series(series) => writeln!(f, "{}Series {}", indent, series),
+            LogicalPlan::SingleRow => writeln!(f, "{}SingleRow", indent),
+            LogicalPlan::Filter { input, predicate } => {
+                writeln!(f, "{}Filter {}", indent, predicate)?;
+                input.fmt_indented(f, depth + 1)
+            }
+            LogicalPlan::Project { input, columns } => {
+                writeln!(f, "{}Project {}", indent, columns.join(", "))?;
+                input.fmt_indented(f, depth + 1)
+            }
+            LogicalPlan::Compute { input, exprs } => {
+                writeln!(f, "{}Compute {}", indent, format_exprs(exprs))?;
+                input.fmt_indented(f, depth + 1)
+            }
+            LogicalPlan::Aggregate { input, group_by, aggregates } => {
+                writeln!(f, "{}Aggregate{}", indent, format_aggregate(group_by, aggregates))?;
+                input.fmt_indented(f, depth + 1)
+            }
+            LogicalPlan::SetOperation { operator, all, left, right } => {
+                writeln!(f, "{}SetOperation {}{}", indent, operator, if *all { " ALL" } else { "" })?;
+                left.fmt_indented(f, depth + 1)?;
+                right.fmt_indented(f, depth + 1)
+            }
+            LogicalPlan::Sort { input, keys } => {
+                writeln!(f, "{}Sort {}", indent, format_sort_keys(keys))?;
+                input.fmt_indented(f, depth + 1)
+            }
+            LogicalPlan::Limit { input, count } => {
+                writeln!(f, "{}Limit {}", indent, count)?;
+                input.fmt_indented(f, depth + 1)
+            }
+        }
+    }
+}
+
+/// Format an aggregation as " COUNT(*), SUM(x) GROUP BY a, b"
+pub fn format_aggregate(group_by: &[String], aggregates: &[Aggregate]) -> String {
+    let mut text = String::new();
+    if !aggregates.is_empty() {
+        let names: Vec<String> = aggregates.iter().map(|a| a.to_string()).collect();
+        text.push(' ');
+        text.push_str(&names.join(", "));
+    }
+    if !group_by.is_empty() {
+        text.push_str(" GROUP BY ");
+        text.push_str(&group_by.join(", "));
+    }
+    text
+}
+
+/// Format expressions as a comma-separated list
+pub fn format_exprs(exprs: &[Expr]) -> String {
+    exprs.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+/// Format sort keys as a comma-separated list
+pub fn format_sort_keys(keys: &[SortKey]) -> String {
+    keys.iter().map(|k| k.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+/// Format a scan projection as " [a, b]", or nothing when reading all columns
+pub fn format_projection(projection: &Option<Vec<String>>) -> String {
+    match projection {
+        Some(columns) => format!(" [{}]", columns.join(", ")),
+        None => String::new(),
+    }
+}
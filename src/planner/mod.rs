@@ -1,26 +1,70 @@
 // Planner module - converts AST into execution plans
+//
+// SELECT statements go through two stages: a logical plan of relational
+// operators, then a physical plan that picks concrete access methods
+// (e.g. an index scan instead of a sequential scan plus filter).
 
-use crate::parser::Statement;
+use crate::error::DbError;
+use crate::eval;
+use crate::parser::{Column, DataType, OrderBy, SelectItem, Statement, Value, WhereClause};
+use crate::storage::Database;
+use std::collections::HashMap;
+
+pub mod analyzer;
+pub mod cache;
+pub mod estimate;
+pub mod logical;
+pub mod optimizer;
+pub mod physical;
+
+pub use cache::PlanCache;
+pub use logical::LogicalPlan;
+pub use physical::PhysicalPlan;
 
 /// Query execution plan
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Plan {
     CreateTable {
         table_name: String,
         columns: Vec<crate::parser::Column>,
+        append_only: bool,
+    },
+    CreateTableLike {
+        table_name: String,
+        source: String,
+        with_data: bool,
+    },
+    CreateExternalTable {
+        table_name: String,
+        columns: Option<Vec<crate::parser::Column>>,
+        location: String,
     },
     CreateIndex {
+        index_name: Option<String>,
         table_name: String,
-        column_name: String,
+        expr: crate::parser::Expr,
+        unique: bool,
     },
     Insert {
         table_name: String,
-        values: Vec<crate::parser::Value>,
+        values: Vec<crate::parser::Expr>,
     },
-    Scan {
+    InsertSelect {
         table_name: String,
-        columns: Vec<String>,
-        filter: Option<crate::parser::WhereClause>,
+        query: PhysicalPlan,
+    },
+    Query(PhysicalPlan),
+    Explain {
+        plan: PhysicalPlan,
+        analyze: bool,
+    },
+    Analyze {
+        table_name: Option<String>,
+    },
+    Copy {
+        table_name: String,
+        path: String,
+        format: String,
     },
     Delete {
         table_name: String,
@@ -29,43 +73,247 @@ pub enum Plan {
     Update {
         table_name: String,
         column: String,
-        value: crate::parser::Value,
+        value: crate::parser::Expr,
         filter: Option<crate::parser::WhereClause>,
     },
+    AlterColumnType {
+        table_name: String,
+        column: String,
+        data_type: DataType,
+    },
+    Purge {
+        table_name: Option<String>,
+    },
+    CreateMaterializedView {
+        view_name: String,
+        query: PhysicalPlan,
+        sql: String,
+    },
+    /// Change a session setting, or show it when `value` is None
+    Set {
+        name: String,
+        value: Option<crate::parser::Value>,
+    },
+    /// Set a session variable to the value of `value`, which may refer to
+    /// other variables
+    SetVariable {
+        name: String,
+        value: crate::parser::Expr,
+    },
+    RefreshMaterializedView {
+        view_name: String,
+        query: PhysicalPlan,
+    },
+    SetConstraints {
+        deferred: bool,
+    },
 }
 
-/// Convert Statement to Plan
-pub fn plan(statement: Statement) -> Result<Plan, String> {
+impl Plan {
+    /// Give the session variables the plan refers to their current values,
+    /// before it runs
+    pub fn bind_variables(&mut self, variables: &HashMap<String, Value>) {
+        let bind_filter = |filter: &mut Option<WhereClause>| {
+            if let Some(filter) = filter {
+                eval::bind_variables(&mut filter.left, variables);
+                eval::bind_variables(&mut filter.right, variables);
+            }
+        };
+        match self {
+            Plan::Insert { values, .. } => values.iter_mut().for_each(|value| eval::bind_variables(value, variables)),
+            Plan::Update { value, filter, .. } => {
+                eval::bind_variables(value, variables);
+                bind_filter(filter);
+            }
+            Plan::Delete { filter, .. } => bind_filter(filter),
+            Plan::SetVariable { value, .. } => eval::bind_variables(value, variables),
+            Plan::InsertSelect { query, .. } | Plan::Query(query) | Plan::Explain { plan: query, .. } => {
+                query.bind_variables(variables);
+            }
+            _ => {}
+        }
+    }
+
+    /// Whether the plan can be reused for later executions of the same SQL.
+    /// DDL is excluded since replaying it would only fail.
+    pub fn is_cacheable(&self) -> bool {
+        !matches!(
+            self,
+            Plan::CreateTable { .. }
+                | Plan::CreateTableLike { .. }
+                | Plan::CreateExternalTable { .. }
+                | Plan::CreateIndex { .. }
+                | Plan::CreateMaterializedView { .. }
+                | Plan::AlterColumnType { .. }
+                | Plan::Analyze { .. }
+        )
+    }
+}
+
+/// Convert Statement to Plan, after checking it against the schema
+pub fn plan(statement: Statement, db: &Database) -> Result<Plan, DbError> {
+    let _span = tracing::debug_span!("plan").entered();
+    analyzer::analyze(&statement, db)?;
+
     match statement {
-        Statement::CreateTable { table_name, columns } => {
-            Ok(Plan::CreateTable { table_name, columns })
+        Statement::CreateTable { table_name, columns, append_only } => {
+            Ok(Plan::CreateTable { table_name, columns, append_only })
         }
-        Statement::CreateIndex { table_name, column_name } => {
-            Ok(Plan::CreateIndex { table_name, column_name })
+        Statement::SetConstraints { deferred } => Ok(Plan::SetConstraints { deferred }),
+        Statement::CreateTableLike { table_name, source, with_data } => {
+            Ok(Plan::CreateTableLike { table_name, source, with_data })
+        }
+        Statement::CreateExternalTable { table_name, columns, location } => {
+            Ok(Plan::CreateExternalTable { table_name, columns, location })
+        }
+        Statement::CreateIndex { index_name, table_name, expr, unique } => {
+            Ok(Plan::CreateIndex { index_name, table_name, expr, unique })
         }
         Statement::Insert { table_name, values } => {
             Ok(Plan::Insert { table_name, values })
         }
-        Statement::Select { table_name, columns, where_clause } => {
-            Ok(Plan::Scan {
-                table_name,
-                columns,
-                filter: where_clause,
-            })
+        Statement::InsertSelect { table_name, query } => match plan(*query, db)? {
+            Plan::Query(query) => Ok(Plan::InsertSelect { table_name, query }),
+            _ => Err(DbError::Plan("INSERT ... SELECT needs a query".to_string())),
+        },
+        Statement::CreateMaterializedView { view_name, query, sql } => match plan(*query, db)? {
+            Plan::Query(query) => Ok(Plan::CreateMaterializedView { view_name, query, sql }),
+            _ => Err(DbError::Plan("CREATE MATERIALIZED VIEW needs a query".to_string())),
+        },
+        Statement::RefreshMaterializedView { view_name } => {
+            let sql = db.view_query(&view_name)
+                .ok_or_else(|| DbError::Plan(format!("'{}' is not a materialized view", view_name)))?;
+            match plan(crate::parser::parse(sql)?, db)? {
+                Plan::Query(query) => Ok(Plan::RefreshMaterializedView { view_name, query }),
+                _ => Err(DbError::Plan(format!("Materialized view '{}' has no query", view_name))),
+            }
+        }
+        query @ (Statement::Select { .. } | Statement::SetOperation { .. }) => {
+            let logical = optimizer::optimize(logical_query(query, db)?);
+            Ok(Plan::Query(physical::plan(logical, db)))
         }
         Statement::Delete { table_name, where_clause } => {
+            let where_clause = coerce_filter(where_clause, &table_name, db)?;
             Ok(Plan::Delete {
                 table_name,
-                filter: where_clause,
+                filter: optimizer::simplify_filter(where_clause),
             })
         }
         Statement::Update { table_name, column, value, where_clause } => {
+            let where_clause = coerce_filter(where_clause, &table_name, db)?;
             Ok(Plan::Update {
                 table_name,
                 column,
-                value,
-                filter: where_clause,
+                value: optimizer::fold_constants(value),
+                filter: optimizer::simplify_filter(where_clause),
             })
         }
+        Statement::Explain { statement, analyze } => match plan(*statement, db)? {
+            Plan::Query(physical) => Ok(Plan::Explain { plan: physical, analyze }),
+            _ => Err(DbError::Plan("EXPLAIN is only supported for SELECT".to_string())),
+        },
+        Statement::Analyze { table_name } => Ok(Plan::Analyze { table_name }),
+        Statement::Purge { table_name } => Ok(Plan::Purge { table_name }),
+        Statement::Set { name, value } => Ok(Plan::Set {
+            name,
+            value: value.as_ref().map(crate::eval::evaluate_constant).transpose()?,
+        }),
+        Statement::SetVariable { name, value } => Ok(Plan::SetVariable { name, value }),
+        Statement::Copy { table_name, path, format } => Ok(Plan::Copy { table_name, path, format }),
+        Statement::AlterColumnType { table_name, column, data_type } => {
+            Ok(Plan::AlterColumnType { table_name, column, data_type })
+        }
     }
-}
\ No newline at end of file
+}
+
+/// The logical plan for a SELECT, or for SELECTs combined by set operations
+fn logical_query(query: Statement, db: &Database) -> Result<LogicalPlan, DbError> {
+    match query {
+        Statement::Select { table_name, series, sample, columns, where_clause, group_by, order_by, limit } => {
+            let source_columns = match &series {
+                Some(series) => series.columns(),
+                None if table_name.is_empty() => Vec::new(),
+                None => db.get_table(&table_name)?.columns.clone(),
+            };
+            let columns = expand_all_columns(columns, &table_name, &source_columns)?;
+            let order_by = resolve_ordinals(order_by, &columns, &source_columns)?;
+            let (source, where_clause) = match series {
+                Some(series) => {
+                    let where_clause = where_clause.map(|p| eval::coerce_filter(p, &source_columns));
+                    (LogicalPlan::Series(series), where_clause)
+                }
+                None if table_name.is_empty() => (LogicalPlan::SingleRow, where_clause),
+                None if let Some(count) = sample => {
+                    let where_clause = coerce_filter(where_clause, &table_name, db)?;
+                    (LogicalPlan::Sample { table_name, count }, where_clause)
+                }
+                None => {
+                    let where_clause = coerce_filter(where_clause, &table_name, db)?;
+                    (LogicalPlan::Scan { table_name, projection: None }, where_clause)
+                }
+            };
+            Ok(logical::select_from(source, columns, where_clause, group_by, order_by, limit))
+        }
+        Statement::SetOperation { operator, all, left, right, order_by, limit } => {
+            let output: Vec<Column> = analyzer::output_columns(&left, db)
+                .ok_or_else(|| DbError::Plan(format!("The first query of the {} does not resolve", operator)))?
+                .into_iter()
+                .map(|(name, data_type)| Column::new(name, data_type.unwrap_or(DataType::Text)))
+                .collect();
+            let order_by = resolve_ordinals(order_by, &[], &output)?;
+            let left = logical_query(*left, db)?;
+            let right = logical_query(*right, db)?;
+            Ok(logical::set_operation(operator, all, left, right, order_by, limit))
+        }
+        _ => Err(DbError::Plan("Expected a query".to_string())),
+    }
+}
+
+/// Replace each `t.*` in a SELECT list with the columns of `t`, which must
+/// be the table the query reads
+pub fn expand_all_columns(selected: Vec<SelectItem>, table_name: &str, columns: &[Column]) -> Result<Vec<SelectItem>, DbError> {
+    let mut expanded = Vec::with_capacity(selected.len());
+    for item in selected {
+        match item {
+            SelectItem::AllColumns(name) if name == table_name => {
+                expanded.extend(columns.iter().map(|c| SelectItem::Column(c.name.clone())));
+            }
+            SelectItem::AllColumns(name) => {
+                return Err(DbError::Plan(format!("'{}.*' does not name the table in FROM", name)));
+            }
+            item => expanded.push(item),
+        }
+    }
+    Ok(expanded)
+}
+
+/// Replace ORDER BY ordinals with the SELECT list entries they refer to.
+/// `source` supplies the columns of a SELECT *.
+pub fn resolve_ordinals(order_by: Vec<OrderBy>, selected: &[SelectItem], source: &[Column]) -> Result<Vec<OrderBy>, DbError> {
+    order_by.into_iter()
+        .map(|key| {
+            let Some(n) = key.ordinal() else {
+                return Ok(key);
+            };
+            let count = if selected.is_empty() { source.len() } else { selected.len() };
+            let position = usize::try_from(n)
+                .ok()
+                .filter(|p| (1..=count).contains(p))
+                .ok_or_else(|| DbError::Plan(format!("Position {} is not in the SELECT list of {} column(s)", n, count)))?;
+            let item = match selected.get(position - 1) {
+                Some(item) => item.clone(),
+                None => SelectItem::Column(source[position - 1].name.clone()),
+            };
+            Ok(OrderBy { item, descending: key.descending })
+        })
+        .collect()
+}
+
+/// Give numeric literals in a WHERE clause the type of what they are compared with
+fn coerce_filter(filter: Option<WhereClause>, table_name: &str, db: &Database) -> Result<Option<WhereClause>, DbError> {
+    let Some(predicate) = filter else {
+        return Ok(None);
+    };
+    let table = db.get_table(table_name)?;
+    Ok(Some(eval::coerce_filter(predicate, &table.columns)))
+}
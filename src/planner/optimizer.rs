@@ -0,0 +1,400 @@
+// Optimizer - rewrite rules applied to logical plans
+
+use super::logical::LogicalPlan;
+use crate::eval;
+use crate::parser::{Expr, Series, WhereClause};
+
+/// Outcome of simplifying a predicate at plan time
+#[derive(Debug)]
+pub enum Simplified {
+    AlwaysTrue,
+    AlwaysFalse,
+    Predicate(WhereClause),
+}
+
+/// Apply all rewrite rules to a logical plan
+pub fn optimize(plan: LogicalPlan) -> LogicalPlan {
+    let plan = simplify_filters(plan);
+    push_down_projection(plan)
+}
+
+/// Fold constants in a predicate and decide it outright when both sides are
+/// constant. A constant on the left is moved to the right (`10 < price`
+/// becomes `price > 10`) so index matching only has to look one way.
+pub fn simplify(predicate: WhereClause) -> Simplified {
+    let mut left = fold_constants(predicate.left);
+    let mut right = fold_constants(predicate.right);
+    let mut operator = predicate.operator;
+
+    match (&left, &right) {
+        (Expr::Literal(a), Expr::Literal(b)) => {
+            return if eval::compare_values(a, &operator, b) {
+                Simplified::AlwaysTrue
+            } else {
+                Simplified::AlwaysFalse
+            };
+        }
+        (Expr::Literal(_), _) => {
+            if let Some(flipped) = operator.flip() {
+                std::mem::swap(&mut left, &mut right);
+                operator = flipped;
+            }
+        }
+        _ => {}
+    }
+
+    Simplified::Predicate(WhereClause { left, operator, right })
+}
+
+/// Simplify an optional DML filter; an always-true filter is removed
+pub fn simplify_filter(filter: Option<WhereClause>) -> Option<WhereClause> {
+    let predicate = filter?;
+    match simplify(predicate.clone()) {
+        Simplified::AlwaysTrue => None,
+        Simplified::Predicate(predicate) => Some(predicate),
+        // Keep the original; it matches nothing and costs a cheap check per row
+        Simplified::AlwaysFalse => Some(predicate),
+    }
+}
+
+/// Replace sub-expressions that reference no columns with their value.
+/// Expressions that fail to evaluate (e.g. 1 / 0) are left for execution
+/// to report.
+pub fn fold_constants(expr: Expr) -> Expr {
+    let folded = match expr {
+        Expr::Function { name, args } => Expr::Function {
+            name,
+            args: args.into_iter().map(fold_constants).collect(),
+        },
+        Expr::Binary { left, op, right } => Expr::Binary {
+            left: Box::new(fold_constants(*left)),
+            op,
+            right: Box::new(fold_constants(*right)),
+        },
+        Expr::Array(elements) => Expr::Array(elements.into_iter().map(fold_constants).collect()),
+        // A row stays a row, so its elements keep their own types
+        Expr::Row(elements) => return Expr::Row(elements.into_iter().map(fold_constants).collect()),
+        Expr::Subscript { array, index } => Expr::Subscript {
+            array: Box::new(fold_constants(*array)),
+            index: Box::new(fold_constants(*index)),
+        },
+        other => return other,
+    };
+
+    let constant = match &folded {
+        // Registered functions may not be pure, so only built-ins are folded,
+        // and not those like RANDOM() that return a new value on every call
+        Expr::Function { name, args } => {
+            eval::is_builtin(name)
+                && !eval::is_volatile(&folded)
+                && args.iter().all(|a| matches!(a, Expr::Literal(_)))
+        }
+        Expr::Binary { left, right, .. } | Expr::Subscript { array: left, index: right } => {
+            matches!(**left, Expr::Literal(_)) && matches!(**right, Expr::Literal(_))
+        }
+        Expr::Array(elements) => elements.iter().all(|e| matches!(e, Expr::Literal(_))),
+        _ => false,
+    };
+
+    if constant && let Ok(value) = eval::evaluate_constant(&folded) {
+        return Expr::Literal(value);
+    }
+    folded
+}
+
+/// Simplify every filter in the plan, removing always-true filters and
+/// replacing always-false ones with an empty result
+fn simplify_filters(plan: LogicalPlan) -> LogicalPlan {
+    match plan {
+        LogicalPlan::Filter { input, predicate } => {
+            let input = simplify_filters(*input);
+            match simplify(predicate.clone()) {
+                Simplified::AlwaysTrue => input,
+                // A series or single row has no table to take the output
+                // columns from, so it keeps the filter, which rejects each
+                // row cheaply
+                Simplified::AlwaysFalse if matches!(input, LogicalPlan::Series(_) | LogicalPlan::SingleRow) => {
+                    LogicalPlan::Filter {
+                        input: Box::new(input),
+                        predicate,
+                    }
+                }
+                Simplified::AlwaysFalse => LogicalPlan::Empty {
+                    table_name: scanned_table(&input).to_string(),
+                },
+                Simplified::Predicate(predicate) => LogicalPlan::Filter {
+                    input: Box::new(input),
+                    predicate,
+                },
+            }
+        }
+        LogicalPlan::Project { input, columns } => LogicalPlan::Project {
+            input: Box::new(simplify_filters(*input)),
+            columns,
+        },
+        LogicalPlan::Compute { input, exprs } => LogicalPlan::Compute {
+            input: Box::new(simplify_filters(*input)),
+            exprs,
+        },
+        LogicalPlan::Aggregate { input, group_by, aggregates } => LogicalPlan::Aggregate {
+            input: Box::new(simplify_filters(*input)),
+            group_by,
+            aggregates,
+        },
+        LogicalPlan::Sort { input, keys } => LogicalPlan::Sort {
+            input: Box::new(simplify_filters(*input)),
+            keys,
+        },
+        LogicalPlan::SetOperation { operator, all, left, right } => LogicalPlan::SetOperation {
+            operator,
+            all,
+            left: Box::new(simplify_filters(*left)),
+            right: Box::new(simplify_filters(*right)),
+        },
+        LogicalPlan::Limit { input, count } => LogicalPlan::Limit {
+            input: Box::new(simplify_filters(*input)),
+            count,
+        },
+        other => other,
+    }
+}
+
+/// Name of the table a plan reads from
+fn scanned_table(plan: &LogicalPlan) -> &str {
+    match plan {
+        LogicalPlan::Scan { table_name, .. }
+        | LogicalPlan::Empty { table_name }
+        | LogicalPlan::Sample { table_name, .. } => table_name,
+        LogicalPlan::Series(_) => Series::NAME,
+        LogicalPlan::SingleRow => "",
+        LogicalPlan::Filter { input, .. }
+        | LogicalPlan::Project { input, .. }
+        | LogicalPlan::Compute { input, .. }
+        | LogicalPlan::Aggregate { input, .. }
+        | LogicalPlan::Sort { input, .. }
+        | LogicalPlan::Limit { input, .. } => scanned_table(input),
+        LogicalPlan::SetOperation { left, .. } => scanned_table(left),
+    }
+}
+
+/// Make the scan under a projection read only the columns the query needs
+/// (the projected columns plus any the filter references), so full rows are
+/// never cloned. If the scan then produces exactly the projected columns, the
+/// projection itself is dropped. A computed projection's scan reads only the
+/// columns its expressions use, and an aggregate's scan only its grouping
+/// and aggregated columns. A sort between a projection and its scan keeps
+/// the columns its keys use.
+fn push_down_projection(plan: LogicalPlan) -> LogicalPlan {
+    match plan {
+        LogicalPlan::Limit { input, count } => LogicalPlan::Limit {
+            input: Box::new(push_down_projection(*input)),
+            count,
+        },
+        LogicalPlan::Sort { input, keys } => LogicalPlan::Sort {
+            input: Box::new(push_down_projection(*input)),
+            keys,
+        },
+        LogicalPlan::SetOperation { operator, all, left, right } => LogicalPlan::SetOperation {
+            operator,
+            all,
+            left: Box::new(push_down_projection(*left)),
+            right: Box::new(push_down_projection(*right)),
+        },
+        LogicalPlan::Project { input, columns } => match *input {
+            aggregated if is_aggregated(&aggregated) => LogicalPlan::Project {
+                input: Box::new(push_down_projection(aggregated)),
+                columns,
+            },
+            input => match narrow_scan(input, columns.clone()) {
+                (input, Some(produced)) if produced == columns => input,
+                (input, _) => LogicalPlan::Project {
+                    input: Box::new(input),
+                    columns,
+                },
+            },
+        },
+        LogicalPlan::Compute { input, exprs } => {
+            let input = match *input {
+                aggregated if is_aggregated(&aggregated) => push_down_projection(aggregated),
+                input => {
+                    let mut required: Vec<String> = Vec::new();
+                    for name in exprs.iter().flat_map(eval::referenced_columns) {
+                        if !required.iter().any(|c| c == name) {
+                            required.push(name.to_string());
+                        }
+                    }
+                    narrow_scan(input, required).0
+                }
+            };
+            LogicalPlan::Compute {
+                input: Box::new(input),
+                exprs,
+            }
+        }
+        LogicalPlan::Aggregate { input, group_by, aggregates } => {
+            let mut required = group_by.clone();
+            for name in aggregates.iter().filter_map(|a| a.column.as_ref()) {
+                if !required.contains(name) {
+                    required.push(name.clone());
+                }
+            }
+            LogicalPlan::Aggregate {
+                input: Box::new(narrow_scan(*input, required).0),
+                group_by,
+                aggregates,
+            }
+        }
+        other => other,
+    }
+}
+
+/// Whether `plan` is an aggregation, possibly under a sort of its groups
+fn is_aggregated(plan: &LogicalPlan) -> bool {
+    match plan {
+        LogicalPlan::Aggregate { .. } => true,
+        LogicalPlan::Sort { input, .. } => is_aggregated(input),
+        _ => false,
+    }
+}
+
+/// Restrict the scan at the bottom of `plan` (directly or under a filter and
+/// a sort) to the `required` columns plus those the filter and sort keys
+/// reference. Returns the new plan and the columns it produces, or None if
+/// the plan was left unchanged.
+fn narrow_scan(plan: LogicalPlan, mut required: Vec<String>) -> (LogicalPlan, Option<Vec<String>>) {
+    match plan {
+        LogicalPlan::Sort { input, keys } => {
+            for name in keys.iter().flat_map(|k| eval::referenced_columns(&k.expr)) {
+                if !required.iter().any(|c| c == name) {
+                    required.push(name.to_string());
+                }
+            }
+            let (input, produced) = narrow_scan(*input, required);
+            (LogicalPlan::Sort { input: Box::new(input), keys }, produced)
+        }
+        LogicalPlan::Scan { table_name, projection: None } => (
+            LogicalPlan::Scan {
+                table_name,
+                projection: Some(required.clone()),
+            },
+            Some(required),
+        ),
+        LogicalPlan::Filter { input, predicate } => match *input {
+            LogicalPlan::Scan { table_name, projection: None } => {
+                let filter_columns = eval::referenced_columns(&predicate.left)
+                    .into_iter()
+                    .chain(eval::referenced_columns(&predicate.right));
+                for name in filter_columns {
+                    if !required.iter().any(|c| c == name) {
+                        required.push(name.to_string());
+                    }
+                }
+                let plan = LogicalPlan::Filter {
+                    input: Box::new(LogicalPlan::Scan {
+                        table_name,
+                        projection: Some(required.clone()),
+                    }),
+                    predicate,
+                };
+                (plan, Some(required))
+            }
+            other => (
+                LogicalPlan::Filter {
+                    input: Box::new(other),
+                    predicate,
+                },
+                None,
+            ),
+        },
+        other => (other, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{BinaryOp, Operator, SelectItem, Value};
+    use crate::planner::logical;
+
+    #[test]
+    fn test_projection_pushed_into_scan() {
+        let plan = logical::select(
+            "users".to_string(),
+            vec![SelectItem::Column("name".to_string())],
+            None,
+            Vec::new(),
+            Vec::new(),
+            None,
+        );
+
+        match optimize(plan) {
+            LogicalPlan::Scan { projection, .. } => {
+                assert_eq!(projection, Some(vec!["name".to_string()]));
+            }
+            other => panic!("expected a bare scan, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_projection_keeps_filter_columns() {
+        let predicate = WhereClause {
+            left: Expr::Column("age".to_string()),
+            operator: Operator::GreaterThan,
+            right: Expr::Literal(Value::Int(30)),
+        };
+        let plan = logical::select(
+            "users".to_string(),
+            vec![SelectItem::Column("name".to_string())],
+            Some(predicate),
+            Vec::new(),
+            Vec::new(),
+            None,
+        );
+
+        let LogicalPlan::Project { input, columns } = optimize(plan) else {
+            panic!("expected the projection to remain");
+        };
+        assert_eq!(columns, vec!["name".to_string()]);
+
+        let LogicalPlan::Filter { input, .. } = *input else {
+            panic!("expected a filter");
+        };
+        let LogicalPlan::Scan { projection, .. } = *input else {
+            panic!("expected a scan");
+        };
+        assert_eq!(projection, Some(vec!["name".to_string(), "age".to_string()]));
+    }
+
+    #[test]
+    fn test_constant_folding() {
+        // 10 + 5 < price  =>  price > 15
+        let predicate = WhereClause {
+            left: Expr::Binary {
+                left: Box::new(Expr::Literal(Value::Int(10))),
+                op: BinaryOp::Add,
+                right: Box::new(Expr::Literal(Value::Int(5))),
+            },
+            operator: Operator::LessThan,
+            right: Expr::Column("price".to_string()),
+        };
+
+        let Simplified::Predicate(simplified) = simplify(predicate) else {
+            panic!("expected a remaining predicate");
+        };
+        assert_eq!(simplified.left, Expr::Column("price".to_string()));
+        assert_eq!(simplified.operator, Operator::GreaterThan);
+        assert_eq!(simplified.right, Expr::Literal(Value::Int(15)));
+    }
+
+    #[test]
+    fn test_always_false_filter_is_empty() {
+        let predicate = WhereClause {
+            left: Expr::Literal(Value::Int(1)),
+            operator: Operator::Equals,
+            right: Expr::Literal(Value::Int(2)),
+        };
+        let plan = logical::select("users".to_string(), Vec::new(), Some(predicate), Vec::new(), Vec::new(), None);
+
+        assert!(matches!(optimize(plan), LogicalPlan::Empty { .. }));
+    }
+}
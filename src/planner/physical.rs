@@ -0,0 +1,432 @@
+// Physical plan - concrete operators chosen from a logical plan
+
+use super::estimate;
+use super::logical::{self, LogicalPlan, SortKey};
+use crate::eval;
+use crate::parser::{Aggregate, AggregateFunction, Expr, Series, SetOperator, Value, WhereClause};
+use crate::storage::Database;
+use std::collections::HashMap;
+use std::fmt;
+use tracing::debug;
+
+/// Largest estimated fraction of matching rows for which an index scan is
+/// chosen over a sequential scan
+const INDEX_SELECTIVITY_THRESHOLD: f64 = 0.3;
+
+/// Smallest table for which a filtered scan is split across threads
+pub const PARALLEL_SCAN_MIN_ROWS: usize = 10_000;
+
+/// Most threads a parallel scan uses
+const MAX_SCAN_WORKERS: usize = 8;
+
+/// Physical query plan
+#[derive(Debug, Clone)]
+pub enum PhysicalPlan {
+    SeqScan {
+        table_name: String,
+        projection: Option<Vec<String>>,
+        limit: Option<usize>, // Stop after this many rows
+    },
+    Empty {
+        table_name: String,
+    },
+    /// Reads `count` rows at random positions, in table order
+    SampleScan {
+        table_name: String,
+        count: usize,
+    },
+    /// Generates the values of generate_series(...)
+    Series(Series),
+    /// One row with no columns, for a SELECT without FROM
+    SingleRow,
+    /// COUNT(*) of a whole table, read from its row count
+    TableCount {
+        table_name: String,
+        aggregates: Vec<Aggregate>,
+    },
+    IndexScan {
+        table_name: String,
+        index_name: String,
+        predicate: WhereClause,
+        projection: Option<Vec<String>>,
+    },
+    ParallelScan {
+        table_name: String,
+        predicate: WhereClause,
+        projection: Option<Vec<String>>,
+        workers: usize,
+    },
+    Filter {
+        input: Box<PhysicalPlan>,
+        predicate: WhereClause,
+    },
+    Project {
+        input: Box<PhysicalPlan>,
+        columns: Vec<String>,
+    },
+    Compute {
+        input: Box<PhysicalPlan>,
+        exprs: Vec<Expr>,
+    },
+    HashAggregate {
+        input: Box<PhysicalPlan>,
+        group_by: Vec<String>,
+        aggregates: Vec<Aggregate>,
+    },
+    /// UNION ALL appends its inputs; the other set operations match rows
+    /// through a hash table
+    SetOperation {
+        operator: SetOperator,
+        all: bool,
+        left: Box<PhysicalPlan>,
+        right: Box<PhysicalPlan>,
+    },
+    Sort {
+        input: Box<PhysicalPlan>,
+        keys: Vec<SortKey>,
+    },
+    Limit {
+        input: Box<PhysicalPlan>,
+        count: usize,
+    },
+}
+
+/// Choose physical operators for a logical plan
+pub fn plan(logical: LogicalPlan, db: &Database) -> PhysicalPlan {
+    plan_node(logical, db, false)
+}
+
+/// Choose operators for one node. `streaming` is set under a LIMIT, where
+/// operators that produce rows incrementally let execution stop early.
+fn plan_node(logical: LogicalPlan, db: &Database, streaming: bool) -> PhysicalPlan {
+    match logical {
+        LogicalPlan::Scan { table_name, projection } => {
+            PhysicalPlan::SeqScan { table_name, projection, limit: None }
+        }
+        LogicalPlan::Empty { table_name } => PhysicalPlan::Empty { table_name },
+        LogicalPlan::Sample { table_name, count } => PhysicalPlan::SampleScan { table_name, count },
+        LogicalPlan::Series(series) => PhysicalPlan::Series(series),
+        LogicalPlan::SingleRow => PhysicalPlan::SingleRow,
+        LogicalPlan::Filter { input, predicate } => {
+            // A filter directly over a scan comparing an expression with a
+            // constant can be answered by an index on the same expression
+            if let LogicalPlan::Scan { table_name, projection } = input.as_ref()
+                && let Some(index) = db.index_for(table_name, &predicate)
+            {
+                if index_is_worthwhile(&predicate, table_name, db) {
+                    debug!(table = %table_name, index = %index.name, %predicate, "using index scan");
+                    return PhysicalPlan::IndexScan {
+                        table_name: table_name.clone(),
+                        index_name: index.name.clone(),
+                        predicate,
+                        projection: projection.clone(),
+                    };
+                }
+                debug!(
+                    table = %table_name, index = %index.name, %predicate,
+                    "index not used: predicate matches too much of the table"
+                );
+            }
+
+            // A filtered scan of a large table is split across threads, unless
+            // a LIMIT may be satisfied after reading only part of it
+            if !streaming
+                && let LogicalPlan::Scan { table_name, projection } = input.as_ref()
+                && let Some(workers) = scan_workers(table_name, db)
+            {
+                return PhysicalPlan::ParallelScan {
+                    table_name: table_name.clone(),
+                    predicate,
+                    projection: projection.clone(),
+                    workers,
+                };
+            }
+
+            PhysicalPlan::Filter {
+                input: Box::new(plan_node(*input, db, streaming)),
+                predicate,
+            }
+        }
+        LogicalPlan::Project { input, columns } => PhysicalPlan::Project {
+            input: Box::new(plan_node(*input, db, streaming)),
+            columns,
+        },
+        LogicalPlan::Compute { input, exprs } => PhysicalPlan::Compute {
+            input: Box::new(plan_node(*input, db, streaming)),
+            exprs,
+        },
+        LogicalPlan::Aggregate { input, group_by, aggregates } => {
+            // COUNT(*) of an unfiltered table needs no rows, only their number
+            if let LogicalPlan::Scan { table_name, .. } = input.as_ref()
+                && group_by.is_empty()
+                && !aggregates.is_empty()
+                && aggregates.iter().all(|a| a.function == AggregateFunction::Count && a.column.is_none())
+            {
+                return PhysicalPlan::TableCount { table_name: table_name.clone(), aggregates };
+            }
+            // Aggregation reads its whole input regardless of any LIMIT above
+            PhysicalPlan::HashAggregate {
+                input: Box::new(plan_node(*input, db, false)),
+                group_by,
+                aggregates,
+            }
+        }
+        LogicalPlan::SetOperation { operator, all, left, right } => PhysicalPlan::SetOperation {
+            operator,
+            all,
+            left: Box::new(plan_node(*left, db, streaming)),
+            right: Box::new(plan_node(*right, db, streaming)),
+        },
+        // Sorting reads its whole input regardless of any LIMIT above
+        LogicalPlan::Sort { input, keys } => PhysicalPlan::Sort {
+            input: Box::new(plan_node(*input, db, false)),
+            keys,
+        },
+        LogicalPlan::Limit { input, count } => PhysicalPlan::Limit {
+            input: Box::new(push_limit_into_scan(plan_node(*input, db, true), count)),
+            count,
+        },
+    }
+}
+
+/// Let a sequential scan that feeds a LIMIT without filtering (projections
+/// keep every row) stop after `count` rows instead of reading whole batches
+fn push_limit_into_scan(plan: PhysicalPlan, count: usize) -> PhysicalPlan {
+    match plan {
+        PhysicalPlan::SeqScan { table_name, projection, limit: None } => {
+            PhysicalPlan::SeqScan { table_name, projection, limit: Some(count) }
+        }
+        PhysicalPlan::Project { input, columns } => PhysicalPlan::Project {
+            input: Box::new(push_limit_into_scan(*input, count)),
+            columns,
+        },
+        PhysicalPlan::Compute { input, exprs } => PhysicalPlan::Compute {
+            input: Box::new(push_limit_into_scan(*input, count)),
+            exprs,
+        },
+        other => other,
+    }
+}
+
+/// Without statistics an available index is always used. With statistics,
+/// a sequential scan is preferred when the predicate matches a large share
+/// of the table, since fetching rows through the index then costs more.
+fn index_is_worthwhile(predicate: &WhereClause, table_name: &str, db: &Database) -> bool {
+    db.table_stats(table_name).is_none()
+        || estimate::selectivity(predicate, table_name, db) <= INDEX_SELECTIVITY_THRESHOLD
+}
+
+/// Number of threads to scan a table with, or None if it is too small or
+/// only one core is available
+fn scan_workers(table_name: &str, db: &Database) -> Option<usize> {
+    let rows = db.get_table(table_name).ok()?.rows.len();
+    if rows < PARALLEL_SCAN_MIN_ROWS {
+        return None;
+    }
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let workers = cores.min(MAX_SCAN_WORKERS).min(rows / (PARALLEL_SCAN_MIN_ROWS / 4));
+    (workers > 1).then_some(workers)
+}
+
+impl fmt::Display for PhysicalPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.render(&|_| String::new()))
+    }
+}
+
+impl PhysicalPlan {
+    /// Render the plan as an indented tree, appending `annotate(node)` to
+    /// each operator's line (e.g. row estimates for EXPLAIN)
+    pub fn render(&self, annotate: &dyn Fn(&PhysicalPlan) -> String) -> String {
+        let mut output = String::new();
+        self.render_into(&mut output, annotate, 0);
+        output
+    }
+
+    fn render_into(&self, output: &mut String, annotate: &dyn Fn(&PhysicalPlan) -> String, depth: usize) {
+        output.push_str(&"  ".repeat(depth));
+        output.push_str(&self.describe());
+        output.push_str(&annotate(self));
+        output.push('\n');
+
+        for input in self.inputs() {
+            input.render_into(output, annotate, depth + 1);
+        }
+    }
+
+    /// One-line description of this operator
+    fn describe(&self) -> String {
+        match self {
+            PhysicalPlan::SeqScan { table_name, projection, limit } => {
+                let mut text = format!("SeqScan {}{}", table_name, logical::format_projection(projection));
+                if let Some(limit) = limit {
+                    text.push_str(&format!(" limit={}", limit));
+                }
+                text
+            }
+            PhysicalPlan::Empty { table_name } => {
+                format!("Empty {} (filter is always false)", table_name)
+            }
+            PhysicalPlan::SampleScan { table_name, count } => format!("SampleScan {} ({} rows)", table_name, count),
+            PhysicalPlan::Series(series) => format!("SeriesScan {}", series),
+            PhysicalPlan::SingleRow => "SingleRow".to_string(),
+            PhysicalPlan::TableCount { table_name, aggregates } => {
+                format!("TableCount {}{}", table_name, logical::format_aggregate(&[], aggregates))
+            }
+            PhysicalPlan::IndexScan { table_name, index_name, predicate, projection } => format!(
+                "IndexScan {} using {} ({}){}",
+                table_name, index_name, predicate, logical::format_projection(projection)
+            ),
+            PhysicalPlan::ParallelScan { table_name, predicate, projection, workers } => format!(
+                "ParallelScan {} ({}){} workers={}",
+                table_name, predicate, logical::format_projection(projection), workers
+            ),
+            PhysicalPlan::Filter { predicate, .. } => format!("Filter {}", predicate),
+            PhysicalPlan::Project { columns, .. } => format!("Project {}", columns.join(", ")),
+            PhysicalPlan::Compute { exprs, .. } => format!("Compute {}", logical::format_exprs(exprs)),
+            PhysicalPlan::HashAggregate { group_by, aggregates, .. } => {
+                format!("HashAggregate{}", logical::format_aggregate(group_by, aggregates))
+            }
+            PhysicalPlan::SetOperation { operator: SetOperator::Union, all: true, .. } => "Append".to_string(),
+            PhysicalPlan::SetOperation { operator, all, .. } => {
+                format!("HashSetOp {}{}", operator, if *all { " ALL" } else { "" })
+            }
+            PhysicalPlan::Sort { keys, .. } => format!("Sort {}", logical::format_sort_keys(keys)),
+            PhysicalPlan::Limit { count, .. } => format!("Limit {}", count),
+        }
+    }
+
+    /// The operators feeding this one
+    pub fn inputs(&self) -> Vec<&PhysicalPlan> {
+        match self {
+            PhysicalPlan::Filter { input, .. }
+            | PhysicalPlan::Project { input, .. }
+            | PhysicalPlan::Compute { input, .. }
+            | PhysicalPlan::HashAggregate { input, .. }
+            | PhysicalPlan::Sort { input, .. }
+            | PhysicalPlan::Limit { input, .. } => vec![input],
+            PhysicalPlan::SetOperation { left, right, .. } => vec![left, right],
+            PhysicalPlan::SeqScan { .. }
+            | PhysicalPlan::Empty { .. }
+            | PhysicalPlan::SampleScan { .. }
+            | PhysicalPlan::Series(_)
+            | PhysicalPlan::SingleRow
+            | PhysicalPlan::TableCount { .. }
+            | PhysicalPlan::IndexScan { .. }
+            | PhysicalPlan::ParallelScan { .. } => Vec::new(),
+        }
+    }
+
+    /// Give the session variables this plan and its inputs refer to their
+    /// current values
+    pub fn bind_variables(&mut self, variables: &HashMap<String, Value>) {
+        match self {
+            PhysicalPlan::IndexScan { predicate, .. }
+            | PhysicalPlan::ParallelScan { predicate, .. }
+            | PhysicalPlan::Filter { predicate, .. } => {
+                eval::bind_variables(&mut predicate.left, variables);
+                eval::bind_variables(&mut predicate.right, variables);
+            }
+            PhysicalPlan::Compute { exprs, .. } => {
+                exprs.iter_mut().for_each(|expr| eval::bind_variables(expr, variables));
+            }
+            PhysicalPlan::Sort { keys, .. } => {
+                keys.iter_mut().for_each(|key| eval::bind_variables(&mut key.expr, variables));
+            }
+            _ => {}
+        }
+        match self {
+            PhysicalPlan::Filter { input, .. }
+            | PhysicalPlan::Project { input, .. }
+            | PhysicalPlan::Compute { input, .. }
+            | PhysicalPlan::HashAggregate { input, .. }
+            | PhysicalPlan::Sort { input, .. }
+            | PhysicalPlan::Limit { input, .. } => input.bind_variables(variables),
+            PhysicalPlan::SetOperation { left, right, .. } => {
+                left.bind_variables(variables);
+                right.bind_variables(variables);
+            }
+            _ => {}
+        }
+    }
+
+    /// Name of the table this plan reads from
+    pub fn table_name(&self) -> &str {
+        match self {
+            PhysicalPlan::Series(_) => Series::NAME,
+            PhysicalPlan::SingleRow => "",
+            PhysicalPlan::SeqScan { table_name, .. }
+            | PhysicalPlan::Empty { table_name }
+            | PhysicalPlan::SampleScan { table_name, .. }
+            | PhysicalPlan::TableCount { table_name, .. }
+            | PhysicalPlan::IndexScan { table_name, .. }
+            | PhysicalPlan::ParallelScan { table_name, .. } => table_name,
+            PhysicalPlan::Filter { input, .. }
+            | PhysicalPlan::Project { input, .. }
+            | PhysicalPlan::Compute { input, .. }
+            | PhysicalPlan::HashAggregate { input, .. }
+            | PhysicalPlan::Sort { input, .. }
+            | PhysicalPlan::Limit { input, .. } => input.table_name(),
+            PhysicalPlan::SetOperation { left, .. } => left.table_name(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{self, Column, DataType};
+    use crate::planner::{self, Plan};
+
+    fn users() -> Database {
+        let mut db = Database::new();
+        db.set_persistent(false);
+        let columns = vec![
+            Column::new("name".to_string(), DataType::Text),
+            Column::new("age".to_string(), DataType::Int),
+        ];
+        db.create_table("users".to_string(), columns).unwrap();
+        for age in 0..10 {
+            db.insert_row("users", vec![Value::Text(format!("user{}", age)), Value::Int(age)]).unwrap();
+        }
+        db
+    }
+
+    fn explain(sql: &str, db: &Database) -> String {
+        let Plan::Query(physical) = planner::plan(parser::parse(sql).unwrap(), db).unwrap() else {
+            panic!("expected a query plan for {}", sql);
+        };
+        physical.render(&|_| String::new())
+    }
+
+    #[test]
+    fn test_render_seq_scan() {
+        let db = users();
+        assert_eq!(explain("SELECT * FROM users", &db), "SeqScan users\n");
+    }
+
+    #[test]
+    fn test_render_filter_becomes_index_scan() {
+        let mut db = users();
+        assert_eq!(
+            explain("SELECT * FROM users WHERE age = 3", &db),
+            "Filter age = 3\n  SeqScan users\n"
+        );
+
+        let age = Expr::Column("age".to_string());
+        db.create_index("users", &age, Some("users_age".to_string()), false).unwrap();
+        assert_eq!(
+            explain("SELECT * FROM users WHERE age = 3", &db),
+            "IndexScan users using users_age (age = 3)\n"
+        );
+    }
+
+    #[test]
+    fn test_render_project_over_scan() {
+        let db = users();
+        assert_eq!(
+            explain("SELECT name FROM users WHERE age = 3", &db),
+            "Project name\n  Filter age = 3\n    SeqScan users [name, age]\n"
+        );
+    }
+}
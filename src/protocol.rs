@@ -0,0 +1,362 @@
+// Protocol - messages of the PostgreSQL frontend/backend protocol
+//
+// The subset of protocol version 3 spoken by the `server` feature's pgwire
+// server and the `client` feature's `Client`: the startup handshake, simple
+// queries, and their replies. After the startup packet, every message is a
+// type byte, a length that counts itself, and a body. Values are sent as text.
+
+use crate::error::DbError;
+use std::io::{self, Read, Write};
+
+pub const PROTOCOL_VERSION_3: i32 = 196_608;
+pub const SSL_REQUEST: i32 = 80_877_103;
+pub const GSSENC_REQUEST: i32 = 80_877_104;
+pub const CANCEL_REQUEST: i32 = 80_877_102;
+
+/// Largest message accepted from the other side
+pub const MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Type OIDs used in RowDescription
+pub const INT8_OID: i32 = 20;
+pub const TEXT_OID: i32 = 25;
+pub const FLOAT8_OID: i32 = 701;
+pub const INT8_ARRAY_OID: i32 = 1016;
+pub const FLOAT8_ARRAY_OID: i32 = 1022;
+pub const TEXT_ARRAY_OID: i32 = 1009;
+
+/// A result column in a RowDescription
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldDescription {
+    pub name: String,
+    pub type_oid: i32,
+}
+
+/// Messages sent by the server
+#[derive(Debug, Clone, PartialEq)]
+pub enum BackendMessage {
+    AuthenticationOk,
+    ParameterStatus { name: String, value: String },
+    BackendKeyData { process_id: i32, secret_key: i32 },
+    ReadyForQuery,
+    RowDescription(Vec<FieldDescription>),
+    /// Values in text format, `None` for NULL
+    DataRow(Vec<Option<String>>),
+    /// The command tag, e.g. `SELECT 2` or `INSERT 0 1`
+    CommandComplete(String),
+    EmptyQueryResponse,
+    ErrorResponse { code: String, message: String },
+    /// A message outside this subset, such as a notice, by type byte
+    Other(u8),
+}
+
+/// Messages sent by the client after startup
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrontendMessage {
+    Query(String),
+    Sync,
+    Flush,
+    Terminate,
+    /// A message outside this subset, such as the extended query protocol's
+    /// Parse or Bind, by type byte
+    Other(u8),
+}
+
+impl BackendMessage {
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        let mut body = Vec::new();
+        let tag = match self {
+            BackendMessage::AuthenticationOk => {
+                body.extend_from_slice(&0i32.to_be_bytes());
+                b'R'
+            }
+            BackendMessage::ParameterStatus { name, value } => {
+                put_str(&mut body, name);
+                put_str(&mut body, value);
+                b'S'
+            }
+            BackendMessage::BackendKeyData { process_id, secret_key } => {
+                body.extend_from_slice(&process_id.to_be_bytes());
+                body.extend_from_slice(&secret_key.to_be_bytes());
+                b'K'
+            }
+            BackendMessage::ReadyForQuery => {
+                body.push(b'I'); // Idle, not in a transaction block
+                b'Z'
+            }
+            BackendMessage::RowDescription(fields) => {
+                body.extend_from_slice(&(fields.len() as i16).to_be_bytes());
+                for field in fields {
+                    put_str(&mut body, &field.name);
+                    body.extend_from_slice(&0i32.to_be_bytes()); // Table OID
+                    body.extend_from_slice(&0i16.to_be_bytes()); // Column number
+                    body.extend_from_slice(&field.type_oid.to_be_bytes());
+                    body.extend_from_slice(&(-1i16).to_be_bytes()); // Type size: variable
+                    body.extend_from_slice(&(-1i32).to_be_bytes()); // Type modifier
+                    body.extend_from_slice(&0i16.to_be_bytes()); // Text format
+                }
+                b'T'
+            }
+            BackendMessage::DataRow(values) => {
+                body.extend_from_slice(&(values.len() as i16).to_be_bytes());
+                for value in values {
+                    match value {
+                        Some(text) => {
+                            body.extend_from_slice(&(text.len() as i32).to_be_bytes());
+                            body.extend_from_slice(text.as_bytes());
+                        }
+                        None => body.extend_from_slice(&(-1i32).to_be_bytes()),
+                    }
+                }
+                b'D'
+            }
+            BackendMessage::CommandComplete(tag) => {
+                put_str(&mut body, tag);
+                b'C'
+            }
+            BackendMessage::EmptyQueryResponse => b'I',
+            BackendMessage::ErrorResponse { code, message } => {
+                for (field, value) in [(b'S', "ERROR"), (b'V', "ERROR"), (b'C', code), (b'M', message)] {
+                    body.push(field);
+                    put_str(&mut body, value);
+                }
+                body.push(0);
+                b'E'
+            }
+            BackendMessage::Other(tag) => *tag,
+        };
+        write_message(writer, tag, &body)
+    }
+
+    pub fn read(reader: &mut impl Read) -> io::Result<Self> {
+        let mut tag = [0u8; 1];
+        reader.read_exact(&mut tag)?;
+        let body = read_body(reader)?;
+        let mut body = Cursor(&body);
+        Ok(match tag[0] {
+            b'R' if body.i32()? == 0 => BackendMessage::AuthenticationOk,
+            b'R' => return Err(invalid("Server requested unsupported authentication")),
+            b'S' => BackendMessage::ParameterStatus { name: body.str()?, value: body.str()? },
+            b'K' => BackendMessage::BackendKeyData { process_id: body.i32()?, secret_key: body.i32()? },
+            b'Z' => BackendMessage::ReadyForQuery,
+            b'T' => {
+                let count = body.i16()?;
+                let mut fields = Vec::new();
+                for _ in 0..count {
+                    let name = body.str()?;
+                    body.bytes(6)?; // Table OID and column number
+                    let type_oid = body.i32()?;
+                    body.bytes(8)?; // Type size, modifier, and format
+                    fields.push(FieldDescription { name, type_oid });
+                }
+                BackendMessage::RowDescription(fields)
+            }
+            b'D' => {
+                let count = body.i16()?;
+                let mut values = Vec::new();
+                for _ in 0..count {
+                    let len = body.i32()?;
+                    let value = match usize::try_from(len) {
+                        Ok(len) => Some(String::from_utf8_lossy(body.bytes(len)?).into_owned()),
+                        Err(_) => None,
+                    };
+                    values.push(value);
+                }
+                BackendMessage::DataRow(values)
+            }
+            b'C' => BackendMessage::CommandComplete(body.str()?),
+            b'I' => BackendMessage::EmptyQueryResponse,
+            b'E' => {
+                let (mut code, mut message) = (String::new(), String::new());
+                loop {
+                    let field = body.bytes(1)?[0];
+                    if field == 0 {
+                        break;
+                    }
+                    let value = body.str()?;
+                    match field {
+                        b'C' => code = value,
+                        b'M' => message = value,
+                        _ => {}
+                    }
+                }
+                BackendMessage::ErrorResponse { code, message }
+            }
+            other => BackendMessage::Other(other),
+        })
+    }
+}
+
+impl FrontendMessage {
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        match self {
+            FrontendMessage::Query(sql) => {
+                let mut body = Vec::new();
+                put_str(&mut body, sql);
+                write_message(writer, b'Q', &body)
+            }
+            FrontendMessage::Sync => write_message(writer, b'S', &[]),
+            FrontendMessage::Flush => write_message(writer, b'H', &[]),
+            FrontendMessage::Terminate => write_message(writer, b'X', &[]),
+            FrontendMessage::Other(tag) => write_message(writer, *tag, &[]),
+        }
+    }
+
+    /// Read the next message, or `None` once the client has disconnected
+    pub fn read(reader: &mut impl Read) -> io::Result<Option<Self>> {
+        let mut tag = [0u8; 1];
+        if reader.read(&mut tag)? == 0 {
+            return Ok(None);
+        }
+        let body = read_body(reader)?;
+        Ok(Some(match tag[0] {
+            b'Q' => {
+                let sql = body.strip_suffix(&[0]).unwrap_or(&body);
+                FrontendMessage::Query(String::from_utf8_lossy(sql).into_owned())
+            }
+            b'S' => FrontendMessage::Sync,
+            b'H' => FrontendMessage::Flush,
+            b'X' => FrontendMessage::Terminate,
+            other => FrontendMessage::Other(other),
+        }))
+    }
+}
+
+/// Send a protocol 3.0 startup packet
+pub fn write_startup(writer: &mut impl Write, user: &str) -> io::Result<()> {
+    let mut body = PROTOCOL_VERSION_3.to_be_bytes().to_vec();
+    put_str(&mut body, "user");
+    put_str(&mut body, user);
+    body.push(0);
+    writer.write_all(&(body.len() as i32 + 4).to_be_bytes())?;
+    writer.write_all(&body)
+}
+
+/// Read a startup packet and return its code: a protocol version or a
+/// request such as `SSL_REQUEST`
+pub fn read_startup(reader: &mut impl Read) -> io::Result<i32> {
+    let body = read_body(reader)?;
+    Cursor(&body).i32().map_err(|_| invalid("Startup message too short"))
+}
+
+/// SQLSTATE code for an error
+pub fn sqlstate(error: &DbError) -> &'static str {
+    match error {
+        DbError::Parse(_) => "42601",
+        DbError::TableNotFound(_) => "42P01",
+        DbError::TableExists(_) => "42P07",
+        DbError::ColumnNotFound(_) => "42703",
+        DbError::Type(_) => "42804",
+        DbError::ConstraintViolation(_) => "23505",
+        DbError::Io(_) => "58030",
+        DbError::Cancelled => "57014",
+        DbError::Timeout(_) => "57014",
+        DbError::MemoryLimit(_) => "53200",
+        DbError::Plan(_) | DbError::Execution(_) => "XX000",
+    }
+}
+
+/// An error reported by the server, as the closest kind that carries its
+/// message unchanged
+pub fn error_from_sqlstate(code: &str, message: String) -> DbError {
+    match code {
+        "42601" => DbError::Parse(message),
+        "42804" => DbError::Type(message),
+        "23505" => DbError::ConstraintViolation(message),
+        "58030" => DbError::Io(message),
+        _ if code.starts_with("42") => DbError::Plan(message),
+        _ => DbError::Execution(message),
+    }
+}
+
+/// Write a message: its type byte, then its length (counting itself) and body
+fn write_message(writer: &mut impl Write, tag: u8, body: &[u8]) -> io::Result<()> {
+    writer.write_all(&[tag])?;
+    writer.write_all(&(body.len() as i32 + 4).to_be_bytes())?;
+    writer.write_all(body)
+}
+
+/// Append a NUL-terminated string
+fn put_str(body: &mut Vec<u8>, s: &str) {
+    body.extend_from_slice(s.as_bytes());
+    body.push(0);
+}
+
+/// Read a length-prefixed message body
+fn read_body(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len)?;
+    let len = i32::from_be_bytes(len);
+    let len = usize::try_from(len - 4)
+        .ok()
+        .filter(|&len| len <= MAX_MESSAGE_SIZE)
+        .ok_or_else(|| invalid(&format!("Invalid message length {}", len)))?;
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body)?;
+    Ok(body)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Reads fields from the front of a message body
+struct Cursor<'a>(&'a [u8]);
+
+impl<'a> Cursor<'a> {
+    fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(invalid("Message too short"));
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn i16(&mut self) -> io::Result<i16> {
+        let bytes = self.bytes(2)?;
+        Ok(i16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        let bytes = self.bytes(4)?;
+        Ok(i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// A NUL-terminated string
+    fn str(&mut self) -> io::Result<String> {
+        let end = self.0.iter().position(|&b| b == 0).ok_or_else(|| invalid("Unterminated string"))?;
+        let s = String::from_utf8_lossy(self.bytes(end)?).into_owned();
+        self.bytes(1)?;
+        Ok(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages_round_trip() {
+        let messages = [
+            BackendMessage::AuthenticationOk,
+            BackendMessage::RowDescription(vec![FieldDescription { name: "id".to_string(), type_oid: INT8_OID }]),
+            BackendMessage::DataRow(vec![Some("1".to_string()), None]),
+            BackendMessage::CommandComplete("SELECT 1".to_string()),
+            BackendMessage::ErrorResponse { code: "42P01".to_string(), message: "No".to_string() },
+            BackendMessage::ReadyForQuery,
+        ];
+        let mut out = Vec::new();
+        for message in &messages {
+            message.write(&mut out).unwrap();
+        }
+        let mut input = &out[..];
+        for message in &messages {
+            assert_eq!(&BackendMessage::read(&mut input).unwrap(), message);
+        }
+
+        let mut out = Vec::new();
+        FrontendMessage::Query("SELECT 1".to_string()).write(&mut out).unwrap();
+        let read = FrontendMessage::read(&mut &out[..]).unwrap();
+        assert_eq!(read, Some(FrontendMessage::Query("SELECT 1".to_string())));
+    }
+}
@@ -1,72 +1,267 @@
-use std::io::{self, Write};
-use crate::parser;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use crate::executor::{self, CancelToken, DryRun, ExecutionResult, ExecutionStats, OutputMode, OutputOptions, ResultCache, Settings};
+use crate::color::{self, GREEN, RED};
+use crate::completion::SqlHelper;
+use crate::error::DbError;
+use crate::import;
+use crate::pager;
+use crate::parser::{self, split_statements, Value};
+use crate::planner::{self, Plan, PlanCache};
+use crate::storage::audit::DEFAULT_AUDIT_LOG_SIZE;
 use crate::storage::Database;
+use rustyline::error::ReadlineError;
+use rustyline::history::FileHistory;
+use rustyline::Editor;
 
 /// REPL (Read-Eval-Print Loop) for the database
 pub struct Repl {
     running: bool,
     database: Database,
+    plan_cache: PlanCache,
+    result_cache: ResultCache,
+    cancel: CancelToken,
+    executing: Arc<AtomicBool>, // Whether a statement is running, for the Ctrl+C handler
+    settings: Settings, // Timeout, memory limit, output, safe mode, and autocommit
+    pager: bool,
+    timer: bool, // Report parse, plan, and execute times
+    stats: bool, // Report rows scanned and returned, index hits, and bytes written
+    echo: bool, // Print each command before running it
+    quiet: bool, // Leave out success messages
+    force: bool, // Run such statements without asking, as when not interactive
 }
 
 impl Repl {
     /// Create a new REPL instance
     pub fn new() -> Self {
+        Self::open(true)
+    }
+
+    /// Create a REPL that does not report loading the database, for running
+    /// commands from scripts
+    pub fn new_quiet() -> Self {
+        Self::open(false)
+    }
+
+    fn open(verbose: bool) -> Self {
         // Try to load existing database from disk
         let database = match Database::load_from_disk() {
             Ok(db) => {
                 let table_count = db.list_tables().len();
-                if table_count > 0 {
+                if verbose && table_count > 0 {
                     println!("Loaded {} existing table(s) from disk", table_count);
                 }
                 db
             }
             Err(e) => {
                 eprintln!("Could not load database from disk: {}", e);
-                println!("Starting with empty database");
+                if verbose {
+                    println!("Starting with empty database");
+                }
                 Database::new()
             }
         };
 
+        let cancel = CancelToken::new();
+        let executing = Arc::new(AtomicBool::new(false));
+        install_interrupt_handler(cancel.clone(), Arc::clone(&executing));
+
         Self { 
             running: true,
             database,
+            plan_cache: PlanCache::default(),
+            result_cache: ResultCache::default(),
+            cancel,
+            executing,
+            settings: Settings {
+                output: OutputOptions {
+                    // Color only a terminal, and respect the NO_COLOR convention
+                    color: io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+                    ..OutputOptions::default()
+                },
+                ..Settings::default()
+            },
+            pager: true,
+            timer: false,
+            stats: false,
+            echo: false,
+            quiet: false,
+            force: false,
         }
     }
 
     /// Main REPL loop
     pub fn run(&mut self) -> io::Result<()> {
+        // Line editing with history kept across sessions and tab completion
+        let mut editor: Editor<SqlHelper, FileHistory> = Editor::new().map_err(io::Error::other)?;
+        editor.set_helper(Some(SqlHelper::default()));
+        let history = history_path();
+        if let Some(path) = &history {
+            // A missing history file just means a first session
+            let _ = editor.load_history(path);
+        }
+
+        // SQL typed so far that has not yet been ended with ; or \G
+        let mut pending = String::new();
+
         while self.running {
-            // Print prompt
-            print!("mydb> ");
-            io::stdout().flush()?;
+            if let Some(helper) = editor.helper_mut() {
+                helper.refresh(&self.database);
+            }
 
-            // Read user input
-            let mut input = String::new();
-            io::stdin().read_line(&mut input)?;
+            // Prompt, or the continuation prompt inside a statement
+            let prompt = if pending.is_empty() { "mydb> " } else { " ...> " };
 
-            let input = input.trim();
+            // Read user input; Ctrl+C discards the line and any unfinished
+            // statement, Ctrl+D ends the session
+            let line = match editor.readline(prompt) {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => {
+                    pending.clear();
+                    continue;
+                }
+                Err(ReadlineError::Eof) => break,
+                Err(e) => return Err(io::Error::other(e)),
+            };
+
+            let input = line.trim();
 
             // Skip empty lines
             if input.is_empty() {
                 continue;
             }
 
-            // Handle meta commands (starting with .)
-            if input.starts_with('.') {
+            // Handle meta commands (starting with .) between statements
+            if pending.is_empty() && input.starts_with('.') {
+                let _ = editor.add_history_entry(input);
                 self.handle_meta_command(input);
                 continue;
             }
 
-            // Handle SQL commands
-            self.handle_sql_command(input);
+            // Handle each SQL statement the input completes
+            pending.push_str(input);
+            pending.push('\n');
+            let (statements, rest) = split_statements(&pending);
+            if rest.is_empty() {
+                // Recall a multi-line statement as one history entry
+                let _ = editor.add_history_entry(pending.trim_end().replace('\n', " "));
+            }
+            pending = rest;
+            for statement in statements {
+                self.handle_sql_command(&statement);
+            }
         }
 
+        self.finish();
+        if let Some(path) = &history
+            && let Err(e) = editor.save_history(path)
+        {
+            eprintln!("Could not save history: {}", e);
+        }
         Ok(())
     }
 
+    /// Write the changes held in memory while autocommit is off, before the
+    /// session ends. Returns false if they could not be written.
+    pub fn finish(&mut self) -> bool {
+        match self.database.flush() {
+            Ok(_) => true,
+            Err(e) => {
+                self.print_error(&format!("✗ {}", e));
+                false
+            }
+        }
+    }
+
+    /// Run a meta command or one or more SQL statements without the prompt;
+    /// a final statement need not end with `;`. Returns false if any
+    /// statement failed.
+    pub fn run_command(&mut self, input: &str) -> bool {
+        let input = input.trim();
+        if input.starts_with('.') {
+            self.handle_meta_command(input);
+            return true;
+        }
+
+        let (mut statements, rest) = split_statements(input);
+        if !rest.is_empty() {
+            statements.push(rest);
+        }
+        let mut ok = true;
+        for statement in statements {
+            ok &= self.handle_sql_command(&statement);
+        }
+        ok
+    }
+
+    /// Run the startup file: `init` if given, otherwise `~/.minisqlrc` when
+    /// it exists. Returns false if the file could not be read or any of its
+    /// statements failed.
+    pub fn run_startup_file(&mut self, init: Option<&Path>) -> bool {
+        let path = match init {
+            Some(path) => path.to_path_buf(),
+            None => match rc_path() {
+                Some(path) if path.exists() => path,
+                _ => return true,
+            },
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(text) => self.run_script(&text),
+            Err(e) => {
+                eprintln!("Could not read '{}': {}", path.display(), e);
+                false
+            }
+        }
+    }
+
+    /// Run meta commands and SQL statements from a script, one meta command
+    /// per line. Lines starting with `--` are comments. Returns false if any
+    /// statement failed.
+    pub fn run_script(&mut self, text: &str) -> bool {
+        let mut pending = String::new();
+        let mut ok = true;
+        for line in text.lines() {
+            if !self.running {
+                break;
+            }
+            let input = line.trim();
+            if pending.is_empty() && (input.is_empty() || input.starts_with("--")) {
+                continue;
+            }
+            if pending.is_empty() && input.starts_with('.') {
+                self.handle_meta_command(input);
+                continue;
+            }
+
+            pending.push_str(input);
+            pending.push('\n');
+            let (statements, rest) = split_statements(&pending);
+            pending = rest;
+            for statement in statements {
+                ok &= self.handle_sql_command(&statement);
+            }
+        }
+        // A last statement need not end with ;
+        if self.running && !pending.trim().is_empty() {
+            ok &= self.handle_sql_command(pending.trim());
+        }
+        ok
+    }
+
     /// Handle meta commands like .exit, .help
     fn handle_meta_command(&mut self, command: &str) {
-        match command {
+        let mut parts = command.split_whitespace();
+        let name = parts.next().unwrap_or_default();
+        let args: Vec<&str> = parts.collect();
+
+        if self.echo {
+            println!("{}", command);
+        }
+
+        match name {
             ".exit" | ".quit" => {
                 println!("Goodbye!");
                 self.running = false;
@@ -81,41 +276,568 @@ impl Repl {
                 } else {
                     println!("Tables:");
                     for table in tables {
-                        println!("  - {}", table);
+                        match self.database.view_query(&table) {
+                            Some(_) => println!("  - {} (materialized view)", table),
+                            None => println!("  - {}", table),
+                        }
                     }
                 }
             }
+            ".indexes" => {
+                let table = args.first().map(|t| parser::normalize_identifier(t));
+                self.show_indexes(table.as_deref());
+            }
+            ".stats" => match args.first().copied() {
+                None => self.show_stats(),
+                Some("on") => self.stats = true,
+                Some("off") => self.stats = false,
+                Some(_) => println!("Usage: .stats [on|off]"),
+            },
+            ".timeout" => {
+                self.set_timeout(args.first().copied());
+            }
+            ".memlimit" => {
+                self.set_memory_limit(args.first().copied());
+            }
+            ".limits" => {
+                self.set_limit(&args);
+            }
+            ".mode" => {
+                self.set_output_mode(args.first().copied());
+            }
+            ".nullvalue" => {
+                self.set_null_value(args.first().copied());
+            }
+            ".floatprec" => {
+                self.set_float_precision(args.first().copied());
+            }
+            ".maxwidth" => {
+                self.set_max_width(args.first().copied());
+            }
+            ".import" => match args.as_slice() {
+                [path, rest @ ..] if import::is_sqlite_file(path) && rest.len() <= 1 => {
+                    match import::import_sqlite(&mut self.database, path, rest.first().copied()) {
+                        Ok(tables) => {
+                            for (table, count) in tables {
+                                self.print_success(&format!("Imported {} row(s) into '{}'", count, table));
+                            }
+                        }
+                        Err(e) => self.print_error(&format!("✗ Import error: {}", e)),
+                    }
+                }
+                [path, table] => match import::import_file(&mut self.database, path, table) {
+                    Ok(count) => self.print_success(&format!(
+                        "Imported {} row(s) into '{}'", count, parser::normalize_identifier(table)
+                    )),
+                    Err(e) => self.print_error(&format!("✗ Import error: {}", e)),
+                },
+                _ => println!("Usage: .import <file> <table> | .import <file.sqlite> [table]"),
+            },
+            ".export" => {
+                // The query is the raw rest of the line, so its spacing and
+                // string literals are kept intact
+                let rest = command[name.len()..].trim_start();
+                let parsed = rest.split_once(char::is_whitespace).and_then(|(path, rest)| {
+                    let (mode, sql) = rest.trim_start().split_once(char::is_whitespace)?;
+                    Some((path, mode, sql.trim()))
+                });
+                match parsed {
+                    Some((path, mode, sql)) if !sql.is_empty() => self.export(path, mode, sql),
+                    _ => println!("Usage: .export <file> <format> <query>"),
+                }
+            }
+            ".timer" => match args.first().copied() {
+                None => println!("Timer: {}", if self.timer { "on" } else { "off" }),
+                Some("on") => self.timer = true,
+                Some("off") => self.timer = false,
+                Some(_) => println!("Usage: .timer on|off"),
+            },
+            ".echo" => match args.first().copied() {
+                None => println!("Echo: {}", if self.echo { "on" } else { "off" }),
+                Some("on") => self.echo = true,
+                Some("off") => self.echo = false,
+                Some(_) => println!("Usage: .echo on|off"),
+            },
+            ".audit" => self.set_audit(&args),
+            ".clone" => match args.as_slice() {
+                [source, table] => {
+                    self.handle_sql_command(&format!("CREATE TABLE {} LIKE {} WITH DATA", table, source));
+                }
+                _ => println!("Usage: .clone <table> <new_table>"),
+            },
+            ".safe" => match args.first().copied() {
+                None => println!("Safe mode: {}", if self.settings.safe { "on" } else { "off" }),
+                Some("on") => self.settings.safe = true,
+                Some("off") => self.settings.safe = false,
+                Some(_) => println!("Usage: .safe on|off"),
+            },
+            ".pager" => match args.first().copied() {
+                None => println!("Pager: {}", if self.pager { "on" } else { "off" }),
+                Some("on") => self.pager = true,
+                Some("off") => self.pager = false,
+                Some(_) => println!("Usage: .pager on|off"),
+            },
             _ => {
                 println!("Unknown command: {}. Type .help for available commands.", command);
             }
         }
     }
 
-    /// Handle SQL commands
-    fn handle_sql_command(&mut self, sql: &str) {
-        match parser::parse(sql) {
+    /// Show or change the statement timeout (in milliseconds, or "off")
+    fn set_timeout(&mut self, arg: Option<&str>) {
+        match arg {
+            None => match self.settings.statement_timeout {
+                Some(timeout) => println!("Statement timeout: {} ms", timeout.as_millis()),
+                None => println!("Statement timeout: off"),
+            },
+            Some("off") | Some("0") => {
+                self.settings.statement_timeout = None;
+                println!("Statement timeout disabled");
+            }
+            Some(ms) => match ms.parse::<u64>() {
+                Ok(ms) => {
+                    self.settings.statement_timeout = Some(Duration::from_millis(ms));
+                    println!("Statement timeout set to {} ms", ms);
+                }
+                Err(_) => println!("Usage: .timeout <milliseconds>|off"),
+            },
+        }
+    }
+
+    /// Show or change the per-query memory limit (in megabytes, or "off")
+    fn set_memory_limit(&mut self, arg: Option<&str>) {
+        const MB: usize = 1024 * 1024;
+        match arg {
+            None => match self.settings.memory_limit {
+                Some(limit) => println!("Memory limit: {} MB", limit / MB),
+                None => println!("Memory limit: off"),
+            },
+            Some("off") | Some("0") => {
+                self.settings.memory_limit = None;
+                println!("Memory limit disabled");
+            }
+            Some(mb) => match mb.parse::<usize>() {
+                Ok(mb) => {
+                    self.settings.memory_limit = Some(mb.saturating_mul(MB));
+                    println!("Memory limit set to {} MB", mb);
+                }
+                Err(_) => println!("Usage: .memlimit <megabytes>|off"),
+            },
+        }
+    }
+
+    /// Show the size limits, or change one: `.limits text 1048576`
+    fn set_limit(&mut self, args: &[&str]) {
+        let mut limits = self.database.limits();
+        let usage = "Usage: .limits [identifier|text|columns <n>]";
+        match args {
+            [] => {
+                println!("Identifier length: {} bytes", limits.max_identifier_length);
+                println!("TEXT value size: {}", format_bytes(limits.max_text_length as u64));
+                println!("Columns per table: {}", limits.max_columns);
+            }
+            [name, value] => {
+                let Ok(value) = value.parse::<usize>() else {
+                    println!("{}", usage);
+                    return;
+                };
+                let limit = match *name {
+                    "identifier" => &mut limits.max_identifier_length,
+                    "text" => &mut limits.max_text_length,
+                    "columns" => &mut limits.max_columns,
+                    _ => {
+                        println!("{}", usage);
+                        return;
+                    }
+                };
+                *limit = value;
+                self.database.set_limits(limits);
+                println!("{} limit set to {}", name, value);
+            }
+            _ => println!("{}", usage),
+        }
+    }
+
+    /// Show the audit log state, or turn it on (with an optional size in
+    /// megabytes at which it is rotated) or off
+    fn set_audit(&mut self, args: &[&str]) {
+        const MB: u64 = 1024 * 1024;
+        match args {
+            [] => println!("Audit log: {}", if self.database.audit_enabled() { "on" } else { "off" }),
+            ["off"] => self.database.disable_audit(),
+            ["on", size @ ..] => {
+                let max_bytes = match size {
+                    [] => DEFAULT_AUDIT_LOG_SIZE,
+                    [mb] => match mb.parse::<u64>() {
+                        Ok(mb) if mb > 0 => mb.saturating_mul(MB),
+                        _ => return println!("Usage: .audit on [megabytes]|off"),
+                    },
+                    _ => return println!("Usage: .audit on [megabytes]|off"),
+                };
+                if let Err(e) = self.database.enable_audit(max_bytes) {
+                    self.print_error(&format!("✗ {}", e));
+                }
+            }
+            _ => println!("Usage: .audit on [megabytes]|off"),
+        }
+    }
+
+    /// Show or change how result sets are printed
+    fn set_output_mode(&mut self, arg: Option<&str>) {
+        match arg {
+            None => println!("Output mode: {}", self.settings.output.mode.name()),
+            Some(name) => match OutputMode::from_name(name) {
+                Some(mode) => self.settings.output.mode = mode,
+                None => println!("Usage: .mode table|csv|json|vertical|markdown|html"),
+            },
+        }
+    }
+
+    /// Show or change the text printed for NULL values
+    fn set_null_value(&mut self, arg: Option<&str>) {
+        match arg {
+            None => println!("NULL value: \"{}\"", self.settings.output.null_value),
+            Some(text) => self.settings.output.null_value = text.to_string(),
+        }
+    }
+
+    /// Show or change the decimal places printed for floats ("full" for all)
+    fn set_float_precision(&mut self, arg: Option<&str>) {
+        match arg {
+            None => match self.settings.output.float_precision {
+                Some(precision) => println!("Float precision: {}", precision),
+                None => println!("Float precision: full"),
+            },
+            Some("full") => self.settings.output.float_precision = None,
+            Some(digits) => match digits.parse::<usize>() {
+                Ok(precision) => self.settings.output.float_precision = Some(precision),
+                Err(_) => println!("Usage: .floatprec <digits>|full"),
+            },
+        }
+    }
+
+    /// Show or change the widest table cell before values are truncated
+    fn set_max_width(&mut self, arg: Option<&str>) {
+        match arg {
+            None => match self.settings.output.max_width {
+                Some(width) => println!("Max column width: {}", width),
+                None => println!("Max column width: off"),
+            },
+            Some("off") | Some("0") => self.settings.output.max_width = None,
+            Some(width) => match width.parse::<usize>() {
+                Ok(width) => self.settings.output.max_width = Some(width),
+                Err(_) => println!("Usage: .maxwidth <characters>|off"),
+            },
+        }
+    }
+
+    /// Show all indexes, or only those on the given table
+    fn show_indexes(&self, table: Option<&str>) {
+        if let Some(table) = table
+            && !self.database.list_tables().iter().any(|t| t == table)
+        {
+            println!("Table '{}' does not exist", table);
+            return;
+        }
+
+        let rows: Vec<Vec<Value>> = self.database.list_indexes().into_iter()
+            .filter(|info| table.is_none_or(|t| info.table == t))
+            .map(|info| vec![
+                Value::Text(info.name),
+                Value::Text(info.table),
+                Value::Text(info.columns.join(", ")),
+                Value::Text(if info.unique { "yes" } else { "no" }.to_string()),
+                Value::Int(info.entries as i64),
+                Value::Int(info.distinct_keys as i64),
+            ])
+            .collect();
+
+        if rows.is_empty() {
+            println!("No indexes");
+            return;
+        }
+
+        let columns = ["name", "table", "columns", "unique", "entries", "keys"]
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        self.print_output(&executor::format_results(ExecutionResult::Rows { columns, rows }, &self.settings.output));
+    }
+
+    /// Print each table's row count, approximate memory use, data file size,
+    /// and number of indexes
+    fn show_stats(&self) {
+        let rows: Vec<Vec<Value>> = self.database.table_usage().into_iter()
+            .map(|usage| vec![
+                Value::Text(usage.table),
+                Value::Int(usage.rows as i64),
+                Value::Text(format_bytes(usage.memory as u64)),
+                usage.disk.map_or(Value::Null, |size| Value::Text(format_bytes(size))),
+                Value::Int(usage.indexes as i64),
+            ])
+            .collect();
+
+        if rows.is_empty() {
+            println!("No tables in database");
+            return;
+        }
+
+        let columns = ["table", "rows", "memory", "disk", "indexes"]
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        self.print_output(&executor::format_results(ExecutionResult::Rows { columns, rows }, &self.settings.output));
+    }
+
+    /// Print a success message in green, unless quiet
+    fn print_success(&self, message: &str) {
+        if !self.quiet {
+            println!("{}", color::paint(message, GREEN, self.settings.output.color));
+        }
+    }
+
+    /// Leave out success messages, for readable script output
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.quiet = quiet;
+    }
+
+    /// Print an error message, in red when color is enabled
+    fn print_error(&self, message: &str) {
+        println!("{}", color::paint(message, RED, self.settings.output.color));
+    }
+
+    /// Turn ANSI colors in output on or off
+    pub fn set_color(&mut self, enabled: bool) {
+        self.settings.output.color = enabled;
+    }
+
+    /// Print command output, through the pager when it is enabled
+    fn print_output(&self, text: &str) {
+        if self.pager {
+            pager::print_paged(text);
+        } else {
+            println!("{}", text);
+        }
+    }
+
+    /// Handle SQL commands, returning whether the statement succeeded
+    fn handle_sql_command(&mut self, sql: &str) -> bool {
+        if self.echo {
+            let terminator = if sql.ends_with("\\G") { "" } else { ";" };
+            println!("{}{}", sql, terminator);
+        }
+
+        // A trailing \G prints this statement's results vertically
+        let (sql, output) = match sql.strip_suffix("\\G") {
+            Some(sql) => (sql.trim_end(), OutputOptions { mode: OutputMode::Vertical, ..self.settings.output.clone() }),
+            None => (sql, self.settings.output.clone()),
+        };
+
+        let _span = tracing::info_span!("statement", sql).entered();
+        let Some((plan, prepare_time)) = self.prepare(sql) else {
+            return false;
+        };
+        if !self.confirm(&plan) {
+            return false;
+        }
+        let start = Instant::now();
+        let before = self.database.access_counts();
+        let result = self.execute_cached(sql, plan);
+        let execute_time = start.elapsed();
+        let stats = ExecutionStats::measure(&self.database, &before, result.as_ref().ok());
+
+        let rows = result.as_ref().ok().and_then(ExecutionResult::row_count);
+        match &result {
+            Ok(_) => tracing::debug!(rows, elapsed = ?execute_time, cached = prepare_time.is_none(), "statement executed"),
+            Err(e) => tracing::debug!(error = %e, elapsed = ?execute_time, "statement failed"),
+        }
+        if let Err(e) = self.database.record_statement(sql, rows, execute_time, result.as_ref().err()) {
+            self.print_error(&format!("✗ {}", e));
+        }
+
+        let ok = result.is_ok();
+        match result {
+            Ok(ExecutionResult::Success(message) | ExecutionResult::Changed { message, .. }) => {
+                self.print_success(&message);
+            }
+            Ok(result) => {
+                let output = executor::format_results(result, &output);
+                self.print_output(&output);
+            }
+            Err(e) => {
+                self.print_error(&format!("✗ Execution error: {}", e));
+            }
+        }
+
+        if self.timer {
+            let prepare = match prepare_time {
+                Some((parse, plan)) => format!("parse {:.3} ms, plan {:.3} ms", millis(parse), millis(plan)),
+                None => "plan cached".to_string(),
+            };
+            println!("Run time: {}, execute {:.3} ms", prepare, millis(execute_time));
+        }
+        if self.stats {
+            println!("{}", stats);
+        }
+        ok
+    }
+
+    /// Plan SQL, reusing the plan if this exact SQL was planned before.
+    /// Errors are printed.
+    fn prepare(&mut self, sql: &str) -> Option<(Plan, PrepareTime)> {
+        match self.plan_cache.get(sql, &self.database) {
+            Some(plan) => Some((plan, None)),
+            None => self.plan_sql(sql),
+        }
+    }
+
+    /// Execute a plan, answering a query from the result cache when none of
+    /// the tables it reads has changed since it last ran
+    fn execute_cached(&mut self, sql: &str, plan: Plan) -> Result<ExecutionResult, DbError> {
+        if !matches!(plan, Plan::Query(_)) {
+            return self.execute_plan(plan);
+        }
+        if let Some(result) = self.result_cache.get(sql, &self.database) {
+            return Ok(result);
+        }
+        let Plan::Query(query) = &plan else {
+            return self.execute_plan(plan);
+        };
+        let query = query.clone();
+        let result = self.execute_plan(plan)?;
+        self.result_cache.insert(sql, &query, &result, &self.database);
+        Ok(result)
+    }
+
+    /// Execute a plan under the session's settings; Ctrl+C, the timeout, or
+    /// the memory limit stops it
+    fn execute_plan(&mut self, plan: Plan) -> Result<ExecutionResult, DbError> {
+        self.cancel.reset();
+        self.executing.store(true, Ordering::SeqCst);
+        let result = executor::execute_in_session(plan, &mut self.database, &mut self.settings, self.cancel.clone());
+        self.executing.store(false, Ordering::SeqCst);
+        result
+    }
+
+    /// In safe mode, ask before running a DELETE or UPDATE without a WHERE
+    /// clause. Without a terminal to ask on, such statements need --force.
+    fn confirm(&self, plan: &Plan) -> bool {
+        let warning = match plan {
+            Plan::Delete { table_name, filter: None } => {
+                format!("DELETE without WHERE removes every row of '{}'", table_name)
+            }
+            Plan::Update { table_name, filter: None, .. } => {
+                format!("UPDATE without WHERE changes every row of '{}'", table_name)
+            }
+            _ => return true,
+        };
+        if !self.settings.safe || self.force || self.settings.dry_run != DryRun::Off {
+            return true;
+        }
+        if !io::stdin().is_terminal() {
+            self.print_error(&format!("✗ Safe mode: {}; pass --force to run it non-interactively", warning));
+            return false;
+        }
+
+        print!("{}. Continue? [y/N] ", warning);
+        let _ = io::stdout().flush();
+        let mut answer = String::new();
+        if io::stdin().read_line(&mut answer).is_ok() && answer.trim().eq_ignore_ascii_case("y") {
+            return true;
+        }
+        println!("Cancelled");
+        false
+    }
+
+    /// Ask before statements that change every row of a table
+    pub fn set_safe(&mut self, safe: bool) {
+        self.settings.safe = safe;
+    }
+
+    /// Report what UPDATE and DELETE would change instead of changing it
+    pub fn set_dry_run(&mut self, dry_run: DryRun) {
+        self.settings.dry_run = dry_run;
+    }
+
+    /// Run statements safe mode would ask about without asking
+    pub fn set_force(&mut self, force: bool) {
+        self.force = force;
+    }
+
+    /// Write changes to disk, or keep them in memory only
+    pub fn set_persistent(&mut self, persistent: bool) {
+        self.database.set_persistent(persistent);
+    }
+
+    /// Run a query and write its results to a file in the given output mode
+    fn export(&mut self, path: &str, mode: &str, sql: &str) {
+        // Parquet has its own writer; None stands for it below
+        let mode = match OutputMode::from_name(mode) {
+            Some(mode) => Some(mode),
+            None if mode.eq_ignore_ascii_case("parquet") => None,
+            None => {
+                println!("Unknown format: {}. Use csv, json, parquet, or another .mode name.", mode);
+                return;
+            }
+        };
+        let Some((plan, _)) = self.prepare(sql.trim_end().trim_end_matches(';')) else {
+            return;
+        };
+        if !matches!(plan, Plan::Query(_)) {
+            self.print_error("✗ Export error: only queries returning rows can be exported");
+            return;
+        }
+
+        let result = self.execute_plan(plan).and_then(|result| {
+            let ExecutionResult::Rows { columns, rows } = &result else {
+                return Err(DbError::Plan("Query returned no result set".to_string()));
+            };
+            let count = rows.len();
+            let Some(mode) = mode else {
+                executor::write_parquet(path, &executor::infer_columns(columns, rows), rows)?;
+                return Ok(count);
+            };
+            let options = OutputOptions { mode, color: false, ..self.settings.output.clone() };
+            let mut text = executor::format_results(result, &options);
+            if !text.ends_with('\n') {
+                text.push('\n');
+            }
+            std::fs::write(path, text)
+                .map_err(|e| DbError::Io(format!("Failed to write '{}': {}", path, e)))?;
+            Ok(count)
+        });
+
+        match result {
+            Ok(count) => self.print_success(&format!("Exported {} row(s) to '{}'", count, path)),
+            Err(e) => self.print_error(&format!("✗ Export error: {}", e)),
+        }
+    }
+
+    /// Parse and plan SQL, caching the plan. Errors are printed.
+    fn plan_sql(&mut self, sql: &str) -> Option<(Plan, PrepareTime)> {
+        let start = Instant::now();
+        match parser::parse_with_position(sql) {
             Ok(statement) => {
+                let parse_time = start.elapsed();
+                let start = Instant::now();
+
                 // Convert statement to plan
-                match crate::planner::plan(statement) {
+                match planner::plan(statement, &self.database) {
                     Ok(plan) => {
-                        // Execute plan
-                        match crate::executor::execute(plan, &mut self.database) {
-                            Ok(result) => {
-                                let output = crate::executor::format_results(result);
-                                println!("{}", output);
-                            }
-                            Err(e) => {
-                                println!("✗ Execution error: {}", e);
-                            }
-                        }
+                        let plan_time = start.elapsed();
+                        self.plan_cache.insert(sql, &plan, &self.database);
+                        Some((plan, Some((parse_time, plan_time))))
                     }
                     Err(e) => {
-                        println!("✗ Planning error: {}", e);
+                        self.print_error(&format!("✗ Planning error: {}", e));
+                        None
                     }
                 }
             }
-            Err(e) => {
-                println!("✗ Parse error: {}", e);
+            Err((e, position)) => {
+                self.print_error(&format!("✗ Parse error: {}", e));
+                println!("{}", parser::show_position(sql, position));
+                None
             }
         }
     }
@@ -126,16 +848,94 @@ impl Repl {
         println!("  .help          - Show this help message");
         println!("  .exit/.quit    - Exit the database");
         println!("  .tables        - List all tables");
+        println!("  .indexes [tbl] - List indexes, optionally for one table");
+        println!("  .stats         - Show row counts, memory and disk use, and index counts per table");
+        println!("  .stats on|off  - Report rows scanned and returned, index hits, and bytes written for each statement");
+        println!("  .timeout [ms]  - Show or set the statement timeout (\"off\" to disable)");
+        println!("  .memlimit [MB] - Show or set the per-query memory limit (\"off\" to disable)");
+        println!("  .limits [identifier|text|columns <n>] - Show or set name length, TEXT size, and column count limits");
+        println!("  .mode [mode]   - Show or set the output mode: table, csv, json, vertical, markdown, or html");
+        println!("  .nullvalue [s] - Show or set the text shown for NULL");
+        println!("  .floatprec [n] - Show or set decimal places for floats (\"full\" for all)");
+        println!("  .maxwidth [n]  - Show or set the widest table column before truncation (\"off\" to disable)");
+        println!("  .pager on|off  - Page results taller than the terminal");
+        println!("  .timer on|off  - Report parse, plan, and execute times for each statement");
+        println!("  .echo on|off   - Print each command before its output");
+        println!("  .safe on|off   - Ask before DELETE or UPDATE without WHERE");
+        println!("  .audit on [MB]|off - Log executed statements, queryable as the __audit table");
+        println!("  SET name = value; / PRAGMA name; - Change or show autocommit, output_mode, safe_mode, memory_limit, statement_timeout");
+        println!("  .clone TABLE NEW - Copy a table's schema, indexes, and rows to a new table");
+        println!("  .import FILE TABLE - Load a CSV or TSV file into a table, creating it if needed");
+        println!("  .import DB [TABLE] - Load the tables (or one table) of a .sqlite/.db file");
+        println!("  .export FILE FORMAT QUERY - Write a query's results to a file (csv, json, parquet, ...)");
         println!("\nSupported SQL:");
-        println!("  CREATE TABLE table_name (col1 TYPE, col2 TYPE, ...)");
-        println!("  INSERT INTO table_name VALUES (val1, val2, ...)");
-        println!("  SELECT * FROM table_name");
-        println!("  SELECT col1, col2 FROM table_name WHERE col = value");
+        println!("  CREATE TABLE table_name (col1 TYPE, col2 TYPE, ...);");
+        println!("  INSERT INTO table_name VALUES (val1, val2, ...);");
+        println!("  SELECT * FROM table_name;");
+        println!("  SELECT col1, col2 FROM table_name WHERE col = value;");
+        println!("\nEnd each statement with ; (it may span lines), or with \\G to print its rows vertically");
     }
 }
 
+/// Make Ctrl+C cancel the running statement. With no statement running it
+/// is ignored here; at the prompt the line editor sees it as a keypress and
+/// discards the current input instead.
+fn install_interrupt_handler(cancel: CancelToken, executing: Arc<AtomicBool>) {
+    let result = ctrlc::set_handler(move || {
+        if executing.load(Ordering::SeqCst) {
+            cancel.cancel();
+        }
+    });
+    if let Err(e) = result {
+        eprintln!("Could not install Ctrl+C handler: {}", e);
+    }
+}
+
+/// Time spent parsing and planning a statement; None when its plan was cached
+type PrepareTime = Option<(Duration, Duration)>;
+
+/// Byte count in the largest unit that keeps it at least 1
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Meta commands offered by tab completion
+pub const META_COMMANDS: &[&str] = &[
+    ".help", ".exit", ".quit", ".tables", ".indexes", ".stats", ".timeout", ".memlimit", ".limits",
+    ".mode", ".nullvalue", ".floatprec", ".maxwidth", ".pager", ".import", ".export", ".timer",
+    ".echo", ".safe", ".audit", ".clone",
+];
+
+/// File in the home directory that keeps command history between sessions
+const HISTORY_FILE: &str = ".mydb_history";
+
+/// File in the home directory run at startup, before the prompt appears
+const RC_FILE: &str = ".minisqlrc";
+
+fn rc_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(RC_FILE))
+}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
+
 impl Default for Repl {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
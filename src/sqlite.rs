@@ -0,0 +1,428 @@
+// SQLite - reading tables out of SQLite database files
+//
+// Just enough of the SQLite file format to load ordinary tables: the database
+// header, table b-tree pages (following overflow pages for large rows), and
+// the record format. Column types come from each table's CREATE TABLE
+// statement using SQLite's type affinity rules; columns declared without a
+// recognizable type are typed by their values, as `.import` does for CSV.
+// WITHOUT ROWID tables, BLOB values, and UTF-16 databases are not supported.
+
+use crate::error::DbError;
+use crate::parser::{Column, DataType, Value};
+
+const MAGIC: &[u8] = b"SQLite format 3\0";
+
+/// Page 1 starts with the database header before its b-tree page header
+const HEADER_SIZE: usize = 100;
+
+const LEAF_TABLE_PAGE: u8 = 0x0D;
+const INTERIOR_TABLE_PAGE: u8 = 0x05;
+
+/// A table read from a SQLite file
+#[derive(Debug)]
+pub struct SqliteTable {
+    pub name: String,
+    pub columns: Vec<Column>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+/// Read every table in a SQLite database file, in the order they were created
+pub fn read_tables(path: &str) -> Result<Vec<SqliteTable>, DbError> {
+    let data = std::fs::read(path)
+        .map_err(|e| DbError::Io(format!("Failed to read '{}': {}", path, e)))?;
+    let file = SqliteFile::open(&data)?;
+
+    let mut tables = Vec::new();
+    for (_, payload) in file.table_payloads(1)? {
+        // sqlite_schema rows are (type, name, tbl_name, rootpage, sql)
+        let entry = decode_record(&payload)?;
+        let [Value::Text(kind), Value::Text(name), _, Value::Int(root), Value::Text(sql), ..] = entry.as_slice() else {
+            continue;
+        };
+        if kind != "table" || name.starts_with("sqlite_") {
+            continue;
+        }
+        let table = read_table(&file, name, *root, sql).map_err(|e| e.context(format!("Table '{}'", name)))?;
+        tables.push(table);
+    }
+    Ok(tables)
+}
+
+fn read_table(file: &SqliteFile, name: &str, root: i64, sql: &str) -> Result<SqliteTable, DbError> {
+    let definition = parse_create_table(sql)?;
+    if definition.without_rowid {
+        return Err(DbError::Parse("WITHOUT ROWID tables are not supported".to_string()));
+    }
+    let root = u32::try_from(root).map_err(|_| corrupt(format!("Invalid root page {}", root)))?;
+
+    let mut records = Vec::new();
+    for (n, (rowid, payload)) in file.table_payloads(root)?.into_iter().enumerate() {
+        let mut values = decode_record(&payload).map_err(|e| e.context(format!("Row {}", n + 1)))?;
+        values.resize(definition.columns.len(), Value::Null);
+        // An INTEGER PRIMARY KEY column is stored as the rowid, with NULL in the record
+        if let Some(i) = definition.rowid_column {
+            values[i] = Value::Int(rowid);
+        }
+        records.push(values);
+    }
+
+    let columns: Vec<Column> = definition.columns.into_iter()
+        .enumerate()
+        .map(|(i, (mut column, affinity))| {
+            column.data_type = affinity.unwrap_or_else(|| infer_type(records.iter().map(|r| &r[i])));
+            column
+        })
+        .collect();
+    let rows = records.into_iter()
+        .enumerate()
+        .map(|(n, values)| convert_row(values, &columns).map_err(|e| e.context(format!("Row {}", n + 1))))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(SqliteTable { name: name.to_string(), columns, rows })
+}
+
+/// The pages of a SQLite file held in memory
+struct SqliteFile<'a> {
+    data: &'a [u8],
+    page_size: usize,
+    usable_size: usize,
+}
+
+impl<'a> SqliteFile<'a> {
+    fn open(data: &'a [u8]) -> Result<Self, DbError> {
+        if data.len() < HEADER_SIZE || !data.starts_with(MAGIC) {
+            return Err(DbError::Parse("Not a SQLite database file".to_string()));
+        }
+        let page_size = match read_u16(data, 16)? {
+            1 => 65_536,
+            n => n,
+        };
+        if page_size < 512 || !page_size.is_power_of_two() {
+            return Err(corrupt(format!("Invalid page size {}", page_size)));
+        }
+        if read_u32(data, 56)? > 1 {
+            return Err(DbError::Parse("UTF-16 SQLite databases are not supported".to_string()));
+        }
+        Ok(Self { data, page_size, usable_size: page_size - data[20] as usize })
+    }
+
+    fn page(&self, number: u32) -> Result<&'a [u8], DbError> {
+        (number as usize).checked_sub(1)
+            .map(|n| n * self.page_size)
+            .and_then(|start| self.data.get(start..start + self.page_size))
+            .ok_or_else(|| corrupt(format!("Page {} is out of range", number)))
+    }
+
+    /// Every (rowid, record) in the table b-tree rooted at `root`, in rowid order
+    fn table_payloads(&self, root: u32) -> Result<Vec<(i64, Vec<u8>)>, DbError> {
+        let mut payloads = Vec::new();
+        let mut pending = vec![root];
+        let mut visited = 0;
+        while let Some(number) = pending.pop() {
+            visited += 1;
+            if visited > self.data.len() / self.page_size {
+                return Err(corrupt("B-tree pages form a cycle".to_string()));
+            }
+            let page = self.page(number)?;
+            let header = if number == 1 { HEADER_SIZE } else { 0 };
+            let cells = read_u16(page, header + 3)?;
+            match page[header] {
+                LEAF_TABLE_PAGE => {
+                    for i in 0..cells {
+                        let offset = read_u16(page, header + 8 + 2 * i)?;
+                        let cell = page.get(offset..).ok_or_else(|| corrupt(format!("Bad cell on page {}", number)))?;
+                        let (size, size_len) = read_varint(cell)?;
+                        let (rowid, rowid_len) = read_varint(&cell[size_len..])?;
+                        payloads.push((rowid, self.payload(&cell[size_len + rowid_len..], size as usize)?));
+                    }
+                }
+                INTERIOR_TABLE_PAGE => {
+                    // Children are popped in order: left to right, then the
+                    // right-most pointer
+                    pending.push(read_u32(page, header + 8)?);
+                    for i in (0..cells).rev() {
+                        let offset = read_u16(page, header + 12 + 2 * i)?;
+                        pending.push(read_u32(page, offset)?);
+                    }
+                }
+                kind => return Err(corrupt(format!("Page {} has unexpected type {}", number, kind))),
+            }
+        }
+        Ok(payloads)
+    }
+
+    /// A cell's payload of `size` bytes, the part not stored in the cell
+    /// itself read from its chain of overflow pages
+    fn payload(&self, cell: &[u8], size: usize) -> Result<Vec<u8>, DbError> {
+        let usable = self.usable_size;
+        let max_local = usable - 35;
+        let local = if size <= max_local {
+            size
+        } else {
+            let min_local = (usable - 12) * 32 / 255 - 23;
+            let local = min_local + (size - min_local) % (usable - 4);
+            if local <= max_local { local } else { min_local }
+        };
+
+        let mut payload = cell.get(..local).ok_or_else(|| corrupt("Cell overruns its page".to_string()))?.to_vec();
+        if local < size {
+            let mut next = read_u32(cell, local)?;
+            while payload.len() < size {
+                let page = self.page(next)?;
+                next = read_u32(page, 0)?;
+                let len = (size - payload.len()).min(usable - 4);
+                payload.extend_from_slice(&page[4..4 + len]);
+            }
+        }
+        Ok(payload)
+    }
+}
+
+/// Decode a record: a header of serial types followed by the values
+fn decode_record(payload: &[u8]) -> Result<Vec<Value>, DbError> {
+    let (header_size, mut pos) = read_varint(payload)?;
+    let header_size = header_size as usize;
+    let mut serial_types = Vec::new();
+    while pos < header_size {
+        let header = payload.get(pos..header_size).ok_or_else(|| corrupt("Bad record header".to_string()))?;
+        let (serial_type, len) = read_varint(header)?;
+        serial_types.push(serial_type);
+        pos += len;
+    }
+
+    let mut body = header_size;
+    serial_types.into_iter()
+        .map(|serial_type| {
+            let len = match serial_type {
+                0 | 8 | 9 => 0,
+                1..=4 => serial_type as usize,
+                5 => 6,
+                6 | 7 => 8,
+                n if n >= 12 => (n as usize - 12) / 2,
+                n => return Err(corrupt(format!("Invalid serial type {}", n))),
+            };
+            let bytes = payload.get(body..body + len).ok_or_else(|| corrupt("Record overruns its payload".to_string()))?;
+            body += len;
+            Ok(match serial_type {
+                0 => Value::Null,
+                1..=6 => {
+                    // Big-endian two's complement, sign-extended
+                    let initial = if bytes[0] & 0x80 != 0 { -1 } else { 0 };
+                    Value::Int(bytes.iter().fold(initial, |n, &b| n << 8 | b as i64))
+                }
+                7 => Value::Float(f64::from_be_bytes(bytes.try_into().unwrap_or_default())),
+                8 => Value::Int(0),
+                9 => Value::Int(1),
+                n if n % 2 == 1 => Value::Text(String::from_utf8_lossy(bytes).into_owned()),
+                _ => return Err(DbError::Type("BLOB values are not supported".to_string())),
+            })
+        })
+        .collect()
+}
+
+/// A variable-length integer: up to 8 bytes of 7 bits each, high bit set on
+/// all but the last, then a ninth byte of 8 bits. Returns it and its length.
+fn read_varint(bytes: &[u8]) -> Result<(i64, usize), DbError> {
+    let mut value: u64 = 0;
+    for (i, &byte) in bytes.iter().take(9).enumerate() {
+        if i == 8 {
+            return Ok(((value << 8 | byte as u64) as i64, 9));
+        }
+        value = value << 7 | (byte & 0x7F) as u64;
+        if byte & 0x80 == 0 {
+            return Ok((value as i64, i + 1));
+        }
+    }
+    Err(corrupt("Truncated varint".to_string()))
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> Result<usize, DbError> {
+    match bytes.get(at..at + 2) {
+        Some(b) => Ok(u16::from_be_bytes([b[0], b[1]]) as usize),
+        None => Err(corrupt("Unexpected end of page".to_string())),
+    }
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> Result<u32, DbError> {
+    match bytes.get(at..at + 4) {
+        Some(b) => Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]])),
+        None => Err(corrupt("Unexpected end of page".to_string())),
+    }
+}
+
+fn corrupt(message: String) -> DbError {
+    DbError::Io(format!("Malformed SQLite file: {}", message))
+}
+
+/// Columns of a CREATE TABLE statement, each with the type given by its
+/// declared type's affinity (None to type it by its values)
+struct TableDefinition {
+    columns: Vec<(Column, Option<DataType>)>,
+    /// The INTEGER PRIMARY KEY column, which holds the rowid
+    rowid_column: Option<usize>,
+    without_rowid: bool,
+}
+
+fn parse_create_table(sql: &str) -> Result<TableDefinition, DbError> {
+    let (Some(open), Some(close)) = (sql.find('('), sql.rfind(')')) else {
+        return Err(DbError::Parse(format!("Unsupported table definition: {}", sql)));
+    };
+    let options: Vec<String> = sql[close + 1..].split_whitespace().map(str::to_uppercase).collect();
+    let without_rowid = options.join(" ").contains("WITHOUT ROWID");
+
+    let mut columns = Vec::new();
+    let mut declared_types = Vec::new();
+    let mut table_primary_key = None;
+    for definition in split_definitions(&sql[open + 1..close]) {
+        let definition = definition.trim();
+        let keyword = definition.split(|c: char| !c.is_alphanumeric()).next().unwrap_or("").to_uppercase();
+        if ["CONSTRAINT", "PRIMARY", "UNIQUE", "CHECK", "FOREIGN"].contains(&keyword.as_str()) {
+            // A table constraint; a single-column PRIMARY KEY marks that column
+            if definition.to_uppercase().contains("PRIMARY KEY")
+                && let (Some(start), Some(end)) = (definition.find('('), definition.find(')'))
+                && !definition[start + 1..end].contains(',')
+            {
+                table_primary_key = Some(unquote(definition[start + 1..end].trim()));
+            }
+            continue;
+        }
+
+        let (name, rest) = split_name(definition);
+        let upper = rest.to_uppercase();
+        // The declared type is everything before the first constraint keyword
+        let type_words: Vec<&str> = upper.split_whitespace()
+            .take_while(|w| !["CONSTRAINT", "PRIMARY", "NOT", "NULL", "UNIQUE", "CHECK", "DEFAULT",
+                "COLLATE", "REFERENCES", "GENERATED", "AS"].contains(w))
+            .collect();
+        let declared_type = type_words.join(" ");
+        let mut column = Column::new(name, affinity(&declared_type).unwrap_or(DataType::Text));
+        column.primary_key = upper.contains("PRIMARY KEY");
+        column.unique = upper.contains("UNIQUE");
+        declared_types.push(declared_type.clone());
+        columns.push((column, affinity(&declared_type)));
+    }
+
+    if let Some(key) = table_primary_key
+        && let Some((column, _)) = columns.iter_mut().find(|(c, _)| c.name.eq_ignore_ascii_case(&key))
+    {
+        column.primary_key = true;
+    }
+    let rowid_column = columns.iter()
+        .position(|(c, _)| c.primary_key)
+        .filter(|&i| declared_types[i] == "INTEGER" && columns.iter().filter(|(c, _)| c.primary_key).count() == 1);
+    Ok(TableDefinition { columns, rowid_column, without_rowid })
+}
+
+/// Split a table body at commas outside parentheses and quotes
+fn split_definitions(body: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut depth, mut quote, mut start) = (0, None, 0);
+    for (i, c) in body.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"' | '`') => quote = Some(c),
+            (None, '[') => quote = Some(']'),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                parts.push(&body[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&body[start..]);
+    parts
+}
+
+/// Split a column definition into its (unquoted) name and the rest
+fn split_name(definition: &str) -> (String, &str) {
+    let end = match definition.chars().next() {
+        Some(open @ ('"' | '`' | '[')) => {
+            let close = if open == '[' { ']' } else { open };
+            definition[1..].find(close).map_or(definition.len(), |i| i + 2)
+        }
+        _ => definition.find(char::is_whitespace).unwrap_or(definition.len()),
+    };
+    (unquote(&definition[..end]), definition[end..].trim())
+}
+
+fn unquote(name: &str) -> String {
+    let quoted = name.len() >= 2
+        && matches!((name.chars().next(), name.chars().last()), (Some('"'), Some('"')) | (Some('`'), Some('`')) | (Some('['), Some(']')));
+    if quoted { name[1..name.len() - 1].to_string() } else { name.to_string() }
+}
+
+/// The type for a declared column type, by SQLite's affinity rules. BLOB and
+/// NUMERIC affinity columns can hold values of any type, so they get None.
+fn affinity(declared_type: &str) -> Option<DataType> {
+    if declared_type.contains("INT") {
+        Some(DataType::Int)
+    } else if ["CHAR", "CLOB", "TEXT"].iter().any(|t| declared_type.contains(t)) {
+        Some(DataType::Text)
+    } else if ["REAL", "FLOA", "DOUB"].iter().any(|t| declared_type.contains(t)) {
+        Some(DataType::Float)
+    } else {
+        None
+    }
+}
+
+/// INT if every value is an integer, FLOAT if every value is a number, TEXT otherwise
+fn infer_type<'a>(values: impl Iterator<Item = &'a Value> + Clone) -> DataType {
+    let mut values = values.filter(|v| **v != Value::Null);
+    if values.clone().all(|v| matches!(v, Value::Int(_))) {
+        DataType::Int
+    } else if values.all(|v| matches!(v, Value::Int(_) | Value::Float(_))) {
+        DataType::Float
+    } else {
+        DataType::Text
+    }
+}
+
+/// Convert a row's values to the column types
+fn convert_row(values: Vec<Value>, columns: &[Column]) -> Result<Vec<Value>, DbError> {
+    values.into_iter()
+        .zip(columns)
+        .map(|(value, column)| match (&column.data_type, value) {
+            (_, Value::Null) => Ok(Value::Null),
+            (DataType::Int, Value::Int(n)) => Ok(Value::Int(n)),
+            (DataType::Float, Value::Int(n)) => Ok(Value::Float(n as f64)),
+            (DataType::Float, Value::Float(f)) => Ok(Value::Float(f)),
+            (DataType::Text, Value::Int(n)) => Ok(Value::Text(n.to_string())),
+            (DataType::Text, Value::Float(f)) => Ok(Value::Text(f.to_string())),
+            (DataType::Text, Value::Text(s)) => Ok(Value::Text(s)),
+            (data_type, value) => Err(DbError::Type(format!(
+                "{:?} is not a valid {:?} for column '{}'",
+                value, data_type, column.name
+            ))),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records_and_definitions() {
+        assert_eq!(read_varint(&[0x81, 0x00]).unwrap(), (128, 2));
+
+        // Header of 4 bytes: NULL, 1-byte int, 3-character text
+        let record = [4, 0, 1, 19, 0xFE, b'a', b'b', b'c'];
+        assert_eq!(decode_record(&record).unwrap(), vec![
+            Value::Null,
+            Value::Int(-2),
+            Value::Text("abc".to_string()),
+        ]);
+
+        let definition = parse_create_table(
+            "CREATE TABLE \"t\" (id INTEGER PRIMARY KEY, name VARCHAR(20) NOT NULL, price DECIMAL(10, 2), UNIQUE (name))"
+        ).unwrap();
+        let names: Vec<&str> = definition.columns.iter().map(|(c, _)| c.name.as_str()).collect();
+        assert_eq!(names, ["id", "name", "price"]);
+        let types: Vec<Option<DataType>> = definition.columns.iter().map(|(_, t)| t.clone()).collect();
+        assert_eq!(types, [Some(DataType::Int), Some(DataType::Text), None]);
+        assert_eq!(definition.rowid_column, Some(0));
+        assert!(!definition.without_rowid);
+    }
+}
@@ -0,0 +1,192 @@
+// Alter - changing the type of a table's column
+//
+// `ALTER TABLE t ALTER COLUMN c TYPE type` converts every value in the column
+// before changing anything, so a value that does not fit leaves the table as
+// it was, and the error lists the rows holding such values. Numbers become
+// TEXT as they are displayed, TEXT becomes a number only when it holds one,
+// and FLOAT becomes INT only without a fractional part. Arrays convert
+// element by element, and to and from TEXT in their '{1,2}' form. The indexes over the
+// column are then rebuilt and the table file rewritten.
+
+use super::{stats, Database};
+use crate::error::DbError;
+use crate::eval;
+use crate::parser::{array, DataType, Expr, Value};
+
+/// Rows listed in the error for values that do not convert
+const MAX_LISTED_ROWS: usize = 5;
+
+impl Database {
+    /// Change the type of a column, converting its values. Returns how many
+    /// non-NULL values were converted.
+    pub fn alter_column_type(&mut self, table_name: &str, column_name: &str, data_type: DataType) -> Result<usize, DbError> {
+        let table = self.tables.get(table_name)
+            .ok_or_else(|| DbError::TableNotFound(table_name.to_string()))?;
+        let position = table.get_column_index(column_name)
+            .ok_or_else(|| DbError::ColumnNotFound(column_name.to_string()))?;
+        if table.columns[position].data_type == data_type {
+            return Ok(0);
+        }
+
+        // Expression indexes must still type-check over the new type
+        let mut columns = table.columns.clone();
+        columns[position].data_type = data_type.clone();
+        if let Some(table_indexes) = self.indexes.get(table_name) {
+            for index in table_indexes.values().filter(|i| i.column_index == position) {
+                if !matches!(index.expr, Expr::Column(_)) && eval::infer_type(&index.expr, &columns).is_err() {
+                    return Err(DbError::Plan(format!(
+                        "Cannot change column '{}' to {}: index '{}' on '{}' needs its current type",
+                        column_name, data_type, index.name, index.expr
+                    )));
+                }
+            }
+        }
+
+        let mut values = Vec::with_capacity(table.rows.len());
+        let mut failed = Vec::new();
+        for (i, row) in table.rows.iter().enumerate() {
+            match convert(&row[position], &data_type) {
+                Some(value) => values.push(value),
+                None => failed.push(i),
+            }
+        }
+        if !failed.is_empty() {
+            let mut listed: Vec<String> = failed.iter()
+                .take(MAX_LISTED_ROWS)
+                .map(|&i| format!("row {} ({})", i + 1, quote(&table.rows[i][position])))
+                .collect();
+            if failed.len() > MAX_LISTED_ROWS {
+                listed.push(format!("and {} more", failed.len() - MAX_LISTED_ROWS));
+            }
+            return Err(DbError::Type(format!(
+                "Cannot change column '{}' of table '{}' to {}; {} value(s) do not convert: {}",
+                column_name, table_name, data_type, failed.len(), listed.join(", ")
+            )));
+        }
+        let converted = values.iter().filter(|v| **v != Value::Null).count();
+
+        // Swap the new values in, keeping the old ones in case a unique index
+        // turns out to have duplicates, e.g. TEXT '1' and '01' as INT
+        let table = self.tables.get_mut(table_name)
+            .ok_or_else(|| DbError::TableNotFound(table_name.to_string()))?;
+        let old_columns = std::mem::replace(&mut table.columns, columns);
+        for (row, value) in table.rows.iter_mut().zip(values.iter_mut()) {
+            std::mem::swap(&mut row[position], value);
+        }
+        if let Some(table_indexes) = self.indexes.get_mut(table_name) {
+            let mut duplicate = None;
+            for index in table_indexes.values_mut().filter(|i| i.column_index == position) {
+                index.build(&table.rows);
+                if index.unique && duplicate.is_none() {
+                    duplicate = index.duplicate_key().map(|key| (index.name.clone(), key.clone()));
+                }
+            }
+            if let Some((index_name, key)) = duplicate {
+                table.columns = old_columns;
+                for (row, value) in table.rows.iter_mut().zip(values) {
+                    row[position] = value;
+                }
+                for index in table_indexes.values_mut().filter(|i| i.column_index == position) {
+                    index.build(&table.rows);
+                }
+                return Err(DbError::ConstraintViolation(format!(
+                    "Cannot change column '{}' of table '{}': unique index '{}' would have duplicate value {}",
+                    column_name, table_name, index_name, key.to_value()
+                )));
+            }
+        }
+
+        if self.stats.contains_key(table_name) {
+            let table_stats = stats::analyze(table);
+            self.stats.insert(table_name.to_string(), table_stats);
+        }
+        self.schema_version += 1;
+        self.save_table(table_name)?;
+        Ok(converted)
+    }
+}
+
+/// A value converted to a type, or None if it has no value of that type
+fn convert(value: &Value, data_type: &DataType) -> Option<Value> {
+    match (value, data_type) {
+        (Value::Null, _) => Some(Value::Null),
+        (Value::Int(n), DataType::Int) => Some(Value::Int(*n)),
+        (Value::Int(n), DataType::Float) => Some(Value::Float(*n as f64)),
+        (Value::Float(f), DataType::Int) => float_to_int(*f),
+        (Value::Float(f), DataType::Float) => Some(Value::Float(*f)),
+        (Value::Int(n), DataType::Text) => Some(Value::Text(n.to_string())),
+        (Value::Float(f), DataType::Text) => Some(Value::Text(f.to_string())),
+        (Value::Text(s), DataType::Int) => s.trim().parse().ok().map(Value::Int),
+        (Value::Text(s), DataType::Float) => s.trim().parse().ok().map(Value::Float),
+        (Value::Text(s), DataType::Text) => Some(Value::Text(s.clone())),
+        // Arrays convert to and from their text form, e.g. '{1,2}', and
+        // element by element to another array type
+        (Value::Array(values), DataType::Text) => Some(Value::Text(array::format_array(values))),
+        (Value::Text(s), DataType::Array(_)) => array::parse_array(s, data_type).ok().map(Value::Array),
+        (Value::Array(values), DataType::Array(element)) => values.iter()
+            .map(|value| convert(value, element))
+            .collect::<Option<Vec<_>>>()
+            .map(Value::Array),
+        (Value::Array(_), _) | (_, DataType::Array(_)) => None,
+    }
+}
+
+/// A whole FLOAT in the range of INT
+fn float_to_int(f: f64) -> Option<Value> {
+    // i64::MAX as f64 rounds up to 2^63, which is out of range
+    let in_range = f >= i64::MIN as f64 && f < i64::MAX as f64;
+    (f.fract() == 0.0 && in_range).then_some(Value::Int(f as i64))
+}
+
+/// A value as written in SQL, for error messages
+fn quote(value: &Value) -> String {
+    match value {
+        Value::Text(s) => format!("'{}'", s),
+        Value::Int(n) => n.to_string(),
+        Value::Float(f) => f.to_string(),
+        Value::Array(values) => format!("'{}'", array::format_array(values)),
+        Value::Null => "NULL".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Column;
+
+    #[test]
+    fn test_alter_column_type() {
+        let mut db = Database::new();
+        db.set_persistent(false);
+        let mut code = Column::new("code".to_string(), DataType::Text);
+        code.unique = true;
+        db.create_table("items".to_string(), vec![code, Column::new("price".to_string(), DataType::Float)]).unwrap();
+        for (code, price) in [("1", 2.0), (" 2", 3.0), ("x", 4.5)] {
+            db.insert_row("items", vec![Value::Text(code.to_string()), Value::Float(price)]).unwrap();
+        }
+
+        // 'x' does not convert, so nothing changes
+        let err = db.alter_column_type("items", "code", DataType::Int).unwrap_err();
+        assert!(err.to_string().contains("row 3 ('x')"), "{}", err);
+        assert_eq!(db.get_table("items").unwrap().columns[0].data_type, DataType::Text);
+
+        // 4.5 has a fractional part
+        assert!(db.alter_column_type("items", "price", DataType::Int).is_err());
+        db.update_rows("items", "price", &Expr::Literal(Value::Int(4)), None).unwrap();
+        assert_eq!(db.alter_column_type("items", "price", DataType::Int), Ok(3));
+        assert_eq!(db.get_table("items").unwrap().rows[1][1], Value::Int(4));
+
+        db.delete_rows("items", None).unwrap();
+        db.insert_row("items", vec![Value::Text("1".to_string()), Value::Int(1)]).unwrap();
+        db.insert_row("items", vec![Value::Text("01".to_string()), Value::Int(1)]).unwrap();
+        assert_eq!(
+            db.alter_column_type("items", "code", DataType::Int),
+            Err(DbError::ConstraintViolation(
+                "Cannot change column 'code' of table 'items': unique index 'items_code_key' would have duplicate value 1"
+                    .to_string()
+            ))
+        );
+        assert_eq!(db.get_table("items").unwrap().rows[1][0], Value::Text("01".to_string()));
+        assert!(db.find_index("items", &Expr::Column("code".to_string())).unwrap().lookup(&Value::Text("01".to_string())).is_some());
+    }
+}
@@ -0,0 +1,179 @@
+// Audit - append-only log of executed statements
+//
+// When enabled, each executed statement is appended to `data/audit.log` in
+// the table file row format, with when it ran, how many rows it returned or
+// changed, how long it took, and its error if it failed. Once the log would
+// grow past its size limit it is renamed to `audit.log.1` (replacing the
+// previous one) and a new log is started. Both files are kept in memory as
+// the read-only `__audit` table, so the log can be queried with SQL.
+
+use super::{disk, Database, Table};
+use crate::error::DbError;
+use crate::parser::{Column, DataType, Value};
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Name of the virtual table holding the log
+pub const AUDIT_TABLE: &str = "__audit";
+
+/// Size at which the log is rotated when no other limit is given
+pub const DEFAULT_AUDIT_LOG_SIZE: u64 = 1024 * 1024;
+
+const AUDIT_FILE: &str = "audit.log";
+const ROTATED_AUDIT_FILE: &str = "audit.log.1";
+
+/// An open audit log and its entries
+pub struct AuditLog {
+    max_bytes: u64,
+    size: u64,            // Bytes in the current file
+    rotated_rows: usize,  // Entries from the rotated file, at the start of `table`
+    table: Table,
+}
+
+impl AuditLog {
+    /// Open the log, loading the entries already written
+    fn open(max_bytes: u64) -> io::Result<Self> {
+        disk::init_data_dir()?;
+        let mut table = Table::new(AUDIT_TABLE.to_string(), audit_columns());
+        let rotated_rows = read_entries(&disk::data_path(ROTATED_AUDIT_FILE), &mut table)?;
+        read_entries(&disk::data_path(AUDIT_FILE), &mut table)?;
+        let size = fs::metadata(disk::data_path(AUDIT_FILE)).map_or(0, |m| m.len());
+        Ok(Self { max_bytes, size, rotated_rows, table })
+    }
+
+    /// Append an entry, rotating the file first if it would grow too large
+    fn append(&mut self, row: Vec<Value>) -> io::Result<()> {
+        let line = format!("{}\n", disk::format_row(&row));
+        let path = disk::data_path(AUDIT_FILE);
+        if self.size > 0 && self.size + line.len() as u64 > self.max_bytes {
+            fs::rename(&path, disk::data_path(ROTATED_AUDIT_FILE))?;
+            self.table.rows.drain(..self.rotated_rows);
+            self.rotated_rows = self.table.rows.len();
+            self.size = 0;
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        self.table.rows.push(row);
+        Ok(())
+    }
+
+    /// The entries, as the `__audit` table
+    pub fn table(&self) -> &Table {
+        &self.table
+    }
+}
+
+/// Columns of the `__audit` table
+fn audit_columns() -> Vec<Column> {
+    vec![
+        Column::new("time".to_string(), DataType::Text),
+        Column::new("statement".to_string(), DataType::Text),
+        Column::new("rows".to_string(), DataType::Int),
+        Column::new("duration_ms".to_string(), DataType::Float),
+        Column::new("error".to_string(), DataType::Text),
+    ]
+}
+
+/// Add the entries of one log file to the table, returning how many there
+/// were. A missing file has none; unreadable lines are skipped.
+fn read_entries(path: &Path, table: &mut Table) -> io::Result<usize> {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let before = table.rows.len();
+    for line in BufReader::new(file).lines() {
+        if let Ok(row) = disk::parse_row(&line?, &table.columns) {
+            table.rows.push(row);
+        }
+    }
+    Ok(table.rows.len() - before)
+}
+
+/// Format a time as `YYYY-MM-DD HH:MM:SS` in UTC
+pub fn format_utc(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year, month, day, rem / 3600, rem % 3600 / 60, rem % 60
+    )
+}
+
+impl Database {
+    /// Start recording statements passed to `record_statement`, rotating the
+    /// log once it would exceed `max_bytes`
+    pub fn enable_audit(&mut self, max_bytes: u64) -> Result<(), DbError> {
+        if !self.persistent {
+            return Err(DbError::Io("Persistence unavailable: the audit log cannot be written".to_string()));
+        }
+        let log = AuditLog::open(max_bytes)
+            .map_err(|e| DbError::Io(format!("Failed to open audit log: {}", e)))?;
+        self.audit = Some(log);
+        self.schema_version += 1;
+        Ok(())
+    }
+
+    /// Stop recording statements; the log files are kept
+    pub fn disable_audit(&mut self) {
+        if self.audit.take().is_some() {
+            self.schema_version += 1;
+        }
+    }
+
+    pub fn audit_enabled(&self) -> bool {
+        self.audit.is_some()
+    }
+
+    /// Append an executed statement to the audit log, if it is enabled.
+    /// `rows` is how many rows it returned or changed.
+    pub fn record_statement(
+        &mut self,
+        statement: &str,
+        rows: Option<usize>,
+        duration: Duration,
+        error: Option<&DbError>,
+    ) -> Result<(), DbError> {
+        let Some(log) = &mut self.audit else {
+            return Ok(());
+        };
+        let row = vec![
+            Value::Text(format_utc(SystemTime::now())),
+            Value::Text(statement.to_string()),
+            rows.map_or(Value::Null, |n| Value::Int(n as i64)),
+            Value::Float(duration.as_secs_f64() * 1000.0),
+            error.map_or(Value::Null, |e| Value::Text(e.to_string())),
+        ];
+        log.append(row)
+            .map_err(|e| DbError::Io(format!("Failed to write audit log: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_utc() {
+        assert_eq!(format_utc(UNIX_EPOCH), "1970-01-01 00:00:00");
+        let leap_day = UNIX_EPOCH + Duration::from_secs(951_827_696);
+        assert_eq!(format_utc(leap_day), "2000-02-29 12:34:56");
+    }
+}
@@ -1,28 +1,44 @@
 // B-tree index implementation for fast lookups
 
 use std::collections::BTreeMap;
-use crate::parser::Value;
+use std::mem;
+use std::ops::Bound;
+use crate::eval;
+use crate::parser::{Expr, Operator, Value};
 
-/// Index on a specific column
+/// Index on a specific column, or on an expression over a single column
+#[derive(Clone)]
 pub struct Index {
+    pub name: String,
     pub column_name: String,
     pub column_index: usize,
+    pub expr: Expr,
+    pub unique: bool,
     // Maps value to row indices
     pub tree: BTreeMap<IndexKey, Vec<usize>>,
 }
 
-/// Wrapper for Value that implements Ord for use in BTreeMap
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+/// Wrapper for Value that implements Ord for use in BTreeMap (and Hash for
+/// hash tables keyed by values)
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum IndexKey {
     Int(i64),
     Text(String),
     Float(OrderedFloat),
+    Array(Vec<IndexKey>),
     Null,
 }
 
-/// Wrapper for f64 to make it Ord (treats NaN as less than everything)
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub struct OrderedFloat(f64);
+/// Wrapper for f64 to make it Ord. NaN equals itself and sorts after every
+/// number, as in PostgreSQL, so it has one place in an index.
+#[derive(Debug, Clone, Copy)]
+pub struct OrderedFloat(pub f64);
+
+impl PartialEq for OrderedFloat {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == std::cmp::Ordering::Equal
+    }
+}
 
 impl Eq for OrderedFloat {}
 
@@ -34,7 +50,24 @@ impl PartialOrd for OrderedFloat {
 
 impl Ord for OrderedFloat {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.0.partial_cmp(&other.0).unwrap_or(std::cmp::Ordering::Less)
+        match (self.0.is_nan(), other.0.is_nan()) {
+            (false, false) => self.0.partial_cmp(&other.0).unwrap_or(std::cmp::Ordering::Equal),
+            (nan, other_nan) => nan.cmp(&other_nan),
+        }
+    }
+}
+
+impl std::hash::Hash for OrderedFloat {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        // 0.0 and -0.0 are equal, as are all NaNs, so they must hash alike
+        let value = if self.0 == 0.0 {
+            0.0
+        } else if self.0.is_nan() {
+            f64::NAN
+        } else {
+            self.0
+        };
+        value.to_bits().hash(state);
     }
 }
 
@@ -44,49 +77,170 @@ impl From<&Value> for IndexKey {
             Value::Int(n) => IndexKey::Int(*n),
             Value::Text(s) => IndexKey::Text(s.clone()),
             Value::Float(f) => IndexKey::Float(OrderedFloat(*f)),
+            Value::Array(values) => IndexKey::Array(values.iter().map(IndexKey::from).collect()),
             Value::Null => IndexKey::Null,
         }
     }
 }
 
+impl IndexKey {
+    /// The value a key was made from
+    pub fn to_value(&self) -> Value {
+        match self {
+            IndexKey::Int(n) => Value::Int(*n),
+            IndexKey::Text(s) => Value::Text(s.clone()),
+            IndexKey::Float(f) => Value::Float(f.0),
+            IndexKey::Array(keys) => Value::Array(keys.iter().map(IndexKey::to_value).collect()),
+            IndexKey::Null => Value::Null,
+        }
+    }
+}
+
 impl Index {
     /// Create a new index on a column
     pub fn new(column_name: String, column_index: usize) -> Self {
         Self {
+            name: format!("idx_{}", column_name),
+            expr: Expr::Column(column_name.clone()),
+            unique: false,
+            column_name,
+            column_index,
+            tree: BTreeMap::new(),
+        }
+    }
+
+    /// Create a new index on an expression such as LOWER(name), where
+    /// `column_index` is the position of the single column it references
+    pub fn with_expr(expr: Expr, column_name: String, column_index: usize) -> Self {
+        Self {
+            name: format!("idx_{}", sanitize_name(&expr.to_string())),
+            expr,
+            unique: false,
             column_name,
             column_index,
             tree: BTreeMap::new(),
         }
     }
 
+    /// Compute the index key for a column value. Expression types are
+    /// checked when the index is created, so evaluation errors map to NULL.
+    pub fn key_value(&self, value: &Value) -> Value {
+        match &self.expr {
+            Expr::Column(_) => value.clone(),
+            expr => eval::evaluate(expr, &|_| Ok(value.clone())).unwrap_or(Value::Null),
+        }
+    }
+
+    /// The key a column value is stored under, made without an intermediate
+    /// copy for a plain column index
+    fn key(&self, value: &Value) -> IndexKey {
+        match &self.expr {
+            Expr::Column(_) => IndexKey::from(value),
+            _ => IndexKey::from(&self.key_value(value)),
+        }
+    }
+
+    /// A key held by more than one row, other than NULL, which a unique
+    /// index must not have
+    pub fn duplicate_key(&self) -> Option<&IndexKey> {
+        self.tree.iter()
+            .find(|(key, rows)| **key != IndexKey::Null && rows.len() > 1)
+            .map(|(key, _)| key)
+    }
+
     /// Build index from existing rows
     pub fn build(&mut self, rows: &[Vec<Value>]) {
         self.tree.clear();
         
         for (row_idx, row) in rows.iter().enumerate() {
             if let Some(value) = row.get(self.column_index) {
-                let key = IndexKey::from(value);
+                let key = self.key(value);
                 self.tree.entry(key)
-                    .or_insert_with(Vec::new)
+                    .or_default()
                     .push(row_idx);
             }
         }
     }
 
-    /// Insert a new row into the index
+    /// Insert a new row into the index, given the value of the indexed column
     pub fn insert(&mut self, row_idx: usize, value: &Value) {
-        let key = IndexKey::from(value);
+        let key = self.key(value);
         self.tree.entry(key)
-            .or_insert_with(Vec::new)
+            .or_default()
             .push(row_idx);
     }
 
+    /// Number of row entries in the index
+    pub fn len(&self) -> usize {
+        self.tree.values().map(|rows| rows.len()).sum()
+    }
+
+    /// Check whether the index has no entries
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    /// Approximate memory held by the index's keys and row lists
+    pub fn memory_size(&self) -> usize {
+        self.tree.iter()
+            .map(|(key, rows)| {
+                let heap = match key {
+                    IndexKey::Text(s) => s.capacity(),
+                    _ => 0,
+                };
+                std::mem::size_of::<IndexKey>() + heap
+                    + std::mem::size_of::<Vec<usize>>() + rows.capacity() * std::mem::size_of::<usize>()
+            })
+            .sum()
+    }
+
+    /// Number of distinct keys in the index
+    pub fn distinct_keys(&self) -> usize {
+        self.tree.len()
+    }
+
     /// Lookup rows by exact value
     pub fn lookup(&self, value: &Value) -> Option<&Vec<usize>> {
         let key = IndexKey::from(value);
         self.tree.get(&key)
     }
 
+    /// Find all rows whose key satisfies `key <operator> value`, in table order
+    pub fn matching_rows(&self, operator: &Operator, value: &Value) -> Vec<usize> {
+        let mut row_indices = match operator {
+            Operator::Is => self.lookup(value).cloned().unwrap_or_default(),
+            Operator::IsNot => self.not_equal(value),
+            // Comparisons with NULL are never true
+            _ if *value == Value::Null => Vec::new(),
+            Operator::Equals => self.lookup(value).cloned().unwrap_or_default(),
+            Operator::NotEquals => self.not_equal(value),
+            Operator::GreaterThan => self.greater_than(value),
+            Operator::LessThan => self.less_than(value),
+            Operator::GreaterOrEqual => self.greater_or_equal(value),
+            Operator::LessOrEqual => self.less_or_equal(value),
+            Operator::Any | Operator::In => match value {
+                Value::Array(elements) => {
+                    let mut keys: Vec<IndexKey> = elements.iter()
+                        .filter(|element| **element != Value::Null)
+                        .map(IndexKey::from)
+                        .collect();
+                    keys.sort();
+                    keys.dedup();
+                    keys.iter().filter_map(|key| self.tree.get(key)).flatten().copied().collect()
+                }
+                _ => Vec::new(),
+            },
+            Operator::Contains => self.tree.iter()
+                .filter(|(key, _)| eval::compare_values(&key.to_value(), operator, value))
+                .flat_map(|(_, rows)| rows.iter().copied())
+                .collect(),
+        };
+
+        // Return rows in table order, as a scan would
+        row_indices.sort_unstable();
+        row_indices
+    }
+
     /// Range query: find all rows with values in [start, end]
     pub fn range_lookup(&self, start: &Value, end: &Value) -> Vec<usize> {
         let start_key = IndexKey::from(start);
@@ -104,30 +258,66 @@ impl Index {
     /// Get all row indices greater than a value
     pub fn greater_than(&self, value: &Value) -> Vec<usize> {
         let key = IndexKey::from(value);
-        
+        self.collect_same_type(&key, (Bound::Excluded(key.clone()), Bound::Unbounded))
+    }
+
+    /// Get all row indices greater than or equal to a value
+    pub fn greater_or_equal(&self, value: &Value) -> Vec<usize> {
+        let key = IndexKey::from(value);
+        self.collect_same_type(&key, (Bound::Included(key.clone()), Bound::Unbounded))
+    }
+
+    /// Get all row indices less than a value
+    pub fn less_than(&self, value: &Value) -> Vec<usize> {
+        let key = IndexKey::from(value);
+        self.collect_same_type(&key, (Bound::Unbounded, Bound::Excluded(key.clone())))
+    }
+
+    /// Get all row indices less than or equal to a value
+    pub fn less_or_equal(&self, value: &Value) -> Vec<usize> {
+        let key = IndexKey::from(value);
+        self.collect_same_type(&key, (Bound::Unbounded, Bound::Included(key.clone())))
+    }
+
+    /// Get all row indices with a value other than `value` and NULL
+    pub fn not_equal(&self, value: &Value) -> Vec<usize> {
+        let key = IndexKey::from(value);
+
         let mut result = Vec::new();
-        
-        for (_, row_indices) in self.tree.range((std::ops::Bound::Excluded(key), std::ops::Bound::Unbounded)) {
+
+        let below = self.tree.range(..key.clone());
+        let above = self.tree.range((Bound::Excluded(key), Bound::Unbounded));
+        for (_, row_indices) in below.chain(above).filter(|(k, _)| **k != IndexKey::Null) {
             result.extend_from_slice(row_indices);
         }
-        
+
         result
     }
 
-    /// Get all row indices less than a value
-    pub fn less_than(&self, value: &Value) -> Vec<usize> {
-        let key = IndexKey::from(value);
-        
+    /// Collect row indices in a key range, skipping keys of a different type
+    /// than `key` since ordering comparisons across types never match
+    fn collect_same_type(&self, key: &IndexKey, range: (Bound<IndexKey>, Bound<IndexKey>)) -> Vec<usize> {
         let mut result = Vec::new();
-        
-        for (_, row_indices) in self.tree.range(..key) {
+
+        for (_, row_indices) in self.tree.range(range)
+            .filter(|(k, _)| mem::discriminant(*k) == mem::discriminant(key))
+        {
             result.extend_from_slice(row_indices);
         }
-        
+
         result
     }
 }
 
+/// Turn an expression like LOWER(name) into an identifier-safe fragment like lower_name
+pub fn sanitize_name(text: &str) -> String {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -164,4 +354,57 @@ mod tests {
         let result = index.range_lookup(&Value::Int(5), &Value::Int(10));
         assert_eq!(result, vec![1, 2]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_expression_index() {
+        let expr = Expr::Function {
+            name: "LOWER".to_string(),
+            args: vec![Expr::Column("name".to_string())],
+        };
+        let mut index = Index::with_expr(expr, "name".to_string(), 1);
+        
+        let rows = vec![
+            vec![Value::Int(1), Value::Text("Alice".to_string())],
+            vec![Value::Int(2), Value::Text("BOB".to_string())],
+            vec![Value::Int(3), Value::Null],
+        ];
+        
+        index.build(&rows);
+        
+        assert_eq!(index.lookup(&Value::Text("bob".to_string())), Some(&vec![1]));
+        assert_eq!(index.lookup(&Value::Text("BOB".to_string())), None);
+        assert_eq!(index.lookup(&Value::Null), Some(&vec![2]));
+    }
+    
+    #[test]
+    fn test_index_comparisons() {
+        let mut index = Index::new("id".to_string(), 0);
+        
+        let rows = vec![
+            vec![Value::Int(1)],
+            vec![Value::Int(5)],
+            vec![Value::Null],
+            vec![Value::Int(10)],
+        ];
+        
+        index.build(&rows);
+        
+        assert_eq!(index.greater_than(&Value::Int(5)), vec![3]);
+        assert_eq!(index.greater_or_equal(&Value::Int(5)), vec![1, 3]);
+        assert_eq!(index.less_than(&Value::Int(5)), vec![0]);
+        assert_eq!(index.less_or_equal(&Value::Int(5)), vec![0, 1]);
+        assert_eq!(index.not_equal(&Value::Int(5)), vec![0, 3]);
+        assert_eq!(index.matching_rows(&Operator::Is, &Value::Null), vec![2]);
+        assert_eq!(index.matching_rows(&Operator::Equals, &Value::Null), Vec::<usize>::new());
+    }
+    #[test]
+    fn test_nan_keys() {
+        let mut index = Index::new("x".to_string(), 0);
+        let rows = vec![vec![Value::Float(f64::NAN)], vec![Value::Float(1.0)], vec![Value::Float(f64::NAN)]];
+        index.build(&rows);
+
+        assert_eq!(index.distinct_keys(), 2);
+        assert_eq!(index.lookup(&Value::Float(f64::NAN)), Some(&vec![0, 2]));
+        assert_eq!(index.greater_than(&Value::Float(1.0)), vec![0, 2]);
+    }
+}
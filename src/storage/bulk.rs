@@ -0,0 +1,137 @@
+// Bulk - loading many rows from Rust code at once
+//
+// `copy_in` takes rows from an iterator in batches. Each batch is validated
+// as a whole, including UNIQUE checks against the table and within the batch,
+// then appended with one pass over each index. The table is written to disk
+// once at the end. A rejected row undoes the whole load.
+
+use super::btree::IndexKey;
+use super::hooks::ChangeKind;
+use super::{check_unique, prepare_row, Database};
+use crate::error::DbError;
+use crate::parser::Value;
+use std::collections::HashSet;
+
+/// Rows validated and appended at a time
+const COPY_BATCH_SIZE: usize = 10_000;
+
+impl Database {
+    /// Append every row from `rows` to a table, e.g. from a parsed file, and
+    /// return how many were loaded. Either all rows are loaded or, if one is
+    /// rejected, none are.
+    pub fn copy_in<I>(&mut self, table_name: &str, rows: I) -> Result<usize, DbError>
+    where
+        I: IntoIterator<Item = Vec<Value>>,
+    {
+        let start = self.get_table(table_name)?.rows.len();
+        let mut rows = rows.into_iter();
+        let expected = rows.size_hint().0;
+        if let Some(table) = self.tables.get_mut(table_name) {
+            table.rows.reserve(expected);
+        }
+        let mut result = Ok(());
+        loop {
+            let batch: Vec<Vec<Value>> = rows.by_ref().take(COPY_BATCH_SIZE).collect();
+            if batch.is_empty() {
+                break;
+            }
+            if let Err(e) = self.append_batch(table_name, batch, start) {
+                result = Err(e);
+                break;
+            }
+        }
+
+        let table = self.tables.get_mut(table_name)
+            .ok_or_else(|| DbError::TableNotFound(table_name.to_string()))?;
+        if let Err(e) = result {
+            table.rows.truncate(start);
+            if let Some(table_indexes) = self.indexes.get_mut(table_name) {
+                for index in table_indexes.values_mut() {
+                    index.build(&table.rows);
+                }
+            }
+            return Err(e);
+        }
+
+        let count = table.rows.len() - start;
+        if count > 0 {
+            let changed = if self.hooks.is_empty() { Vec::new() } else { table.rows[start..].to_vec() };
+            self.save_appended(table_name, count)?;
+            self.notify(table_name, ChangeKind::Insert, changed);
+        }
+        Ok(count)
+    }
+
+    /// Validate a batch and append it to the table and its indexes, without
+    /// saving. `start` is the table's length before the load, for numbering
+    /// rows in errors.
+    fn append_batch(&mut self, table_name: &str, batch: Vec<Vec<Value>>, start: usize) -> Result<(), DbError> {
+        let table = self.tables.get_mut(table_name)
+            .ok_or_else(|| DbError::TableNotFound(table_name.to_string()))?;
+        let first = table.rows.len();
+        let row_number = |i: usize| format!("Row {}", first - start + i + 1);
+
+        let batch = batch.into_iter()
+            .enumerate()
+            .map(|(i, values)| prepare_row(table, values, &self.limits).map_err(|e| e.context(row_number(i))))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if !self.constraints_deferred && let Some(table_indexes) = self.indexes.get(table_name) {
+            for index in table_indexes.values().filter(|i| i.unique) {
+                let mut seen = HashSet::new();
+                for (i, values) in batch.iter().enumerate() {
+                    let value = &values[index.column_index];
+                    check_unique(index, value, &[]).map_err(|e| e.context(row_number(i)))?;
+                    let key = index.key_value(value);
+                    if key != Value::Null && !seen.insert(IndexKey::from(&key)) {
+                        return Err(DbError::ConstraintViolation(format!(
+                            "UNIQUE constraint violated: {} = {} appears more than once",
+                            index.expr, key
+                        )).context(row_number(i)));
+                    }
+                }
+            }
+        }
+
+        table.rows.extend(batch);
+        if let Some(table_indexes) = self.indexes.get_mut(table_name) {
+            for index in table_indexes.values_mut() {
+                for (row_idx, row) in table.rows.iter().enumerate().skip(first) {
+                    index.insert(row_idx, &row[index.column_index]);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{Column, DataType, Expr};
+
+    #[test]
+    fn test_copy_in_is_all_or_nothing() {
+        // Inside an uncommitted transaction, so nothing is written to disk
+        let mut db = Database::new();
+        let mut tx = db.begin();
+        let mut id = Column::new("id".to_string(), DataType::Int);
+        id.primary_key = true;
+        tx.create_table("copy_test".to_string(), vec![id, Column::new("name".to_string(), DataType::Text)]).unwrap();
+
+        let rows = (1..=3).map(|n| vec![Value::Int(n), Value::Text(format!("user{}", n))]);
+        assert_eq!(tx.copy_in("copy_test", rows), Ok(3));
+
+        let duplicate = vec![
+            vec![Value::Int(4), Value::Null],
+            vec![Value::Int(4), Value::Null],
+        ];
+        let err = tx.copy_in("copy_test", duplicate).unwrap_err();
+        assert_eq!(
+            err,
+            DbError::ConstraintViolation("Row 2: UNIQUE constraint violated: id = 4 appears more than once".to_string())
+        );
+        assert_eq!(tx.get_table("copy_test").unwrap().rows.len(), 3);
+        assert_eq!(tx.find_index("copy_test", &Expr::Column("id".to_string())).unwrap().tree.len(), 3);
+    }
+}
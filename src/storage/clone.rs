@@ -0,0 +1,92 @@
+// Clone - new tables made like existing ones
+//
+// `CREATE TABLE new LIKE old` copies a table's columns with their
+// constraints (PRIMARY KEY, UNIQUE, TTL) and its indexes, under names of the
+// new table; a copy of an append-only table is append-only too. `WITH DATA`
+// copies the rows as well, which makes a quick backup before a migration. A
+// copy of a materialized view or an external table is a plain table.
+
+use super::Database;
+use crate::error::DbError;
+use crate::parser::normalize_identifier;
+
+impl Database {
+    /// Create a table like `source`, with its rows too when `with_data` is
+    /// set. Returns how many rows were copied.
+    pub fn create_table_like(&mut self, name: &str, source: &str, with_data: bool) -> Result<usize, DbError> {
+        self.refresh_external_table(source)?;
+        let table = self.get_table(source)?;
+        let columns = table.columns.clone();
+        let append_only = table.append_only;
+        let rows = if with_data { table.rows.clone() } else { Vec::new() };
+        let indexes: Vec<_> = self.indexes.get(source)
+            .map(|indexes| indexes.values().map(|index| (index.expr.clone(), index.unique)).collect())
+            .unwrap_or_default();
+
+        let name = normalize_identifier(name);
+        self.create_table_with(name.clone(), columns, append_only)?;
+        // The constraints' indexes were created with the table
+        for (expr, unique) in indexes {
+            if self.find_index(&name, &expr).is_none() {
+                self.create_index(&name, &expr, None, unique)?;
+            }
+        }
+
+        let count = rows.len();
+        if count > 0 {
+            let table = self.tables.get_mut(&name)
+                .ok_or_else(|| DbError::TableNotFound(name.clone()))?;
+            table.rows = rows;
+            if let Some(indexes) = self.indexes.get_mut(&name) {
+                for index in indexes.values_mut() {
+                    index.build(&table.rows);
+                }
+            }
+            self.save_table(&name)?;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{Column, DataType, Expr, Value};
+
+    #[test]
+    fn test_create_table_like() {
+        let mut db = Database::new();
+        db.set_persistent(false);
+        let mut id = Column::new("id".to_string(), DataType::Int);
+        id.primary_key = true;
+        db.create_table("users".to_string(), vec![id, Column::new("name".to_string(), DataType::Text)]).unwrap();
+        db.create_index("users", &Expr::Column("name".to_string()), Some("by_name".to_string()), false).unwrap();
+        db.insert_row("users", vec![Value::Int(1), Value::Text("ann".to_string())]).unwrap();
+
+        assert_eq!(db.create_table_like("empty_users", "users", false), Ok(0));
+        assert!(db.get_table("empty_users").unwrap().columns[0].primary_key);
+        assert_eq!(db.create_table_like("users_backup", "users", true), Ok(1));
+        let names: Vec<String> = db.list_indexes().into_iter()
+            .filter(|index| index.table == "users_backup")
+            .map(|index| index.name)
+            .collect();
+        assert_eq!(names, ["idx_users_backup_name", "users_backup_pkey"]);
+
+        // The copied primary key index holds the copied rows
+        assert_eq!(
+            db.insert_row("users_backup", vec![Value::Int(1), Value::Null]),
+            Err(DbError::ConstraintViolation("UNIQUE constraint violated: id = 1 already exists".to_string()))
+        );
+        assert!(db.create_table_like("users", "users_backup", false).is_err());
+
+        // A copy of an append-only table keeps its rows from changing
+        let columns = vec![Column::new("event".to_string(), DataType::Text)];
+        db.create_append_only_table("events".to_string(), columns).unwrap();
+        db.insert_row("events", vec![Value::Text("login".to_string())]).unwrap();
+        assert!(db.delete_rows("events", None).is_err());
+        assert_eq!(db.create_table_like("events_copy", "events", true), Ok(1));
+        let event = Expr::Literal(Value::Text("logout".to_string()));
+        assert!(db.update_rows("events_copy", "event", &event, None).is_err());
+        assert!(db.delete_rows("users_backup", None).is_ok());
+    }
+}
@@ -0,0 +1,74 @@
+// Counters - running totals of the work the database does
+//
+// Scans count the table rows they read, index lookups count as hits, and
+// writes count the bytes they put in table files. The totals only grow;
+// a statement's share is the difference between the totals before and after
+// it ran, which is how `executor::execute_with_stats` measures it. They are
+// atomic so the threads of a parallel scan can count too.
+
+use super::Database;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Totals of rows scanned, index hits, and bytes written at some moment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AccessCounts {
+    pub rows_scanned: u64,
+    pub index_hits: u64,
+    pub bytes_written: u64,
+}
+
+impl AccessCounts {
+    /// The work done between an earlier snapshot and this one
+    pub fn since(&self, earlier: &AccessCounts) -> AccessCounts {
+        AccessCounts {
+            rows_scanned: self.rows_scanned.saturating_sub(earlier.rows_scanned),
+            index_hits: self.index_hits.saturating_sub(earlier.index_hits),
+            bytes_written: self.bytes_written.saturating_sub(earlier.bytes_written),
+        }
+    }
+}
+
+/// The database's running totals
+#[derive(Debug, Default)]
+pub struct Counters {
+    rows_scanned: AtomicU64,
+    index_hits: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+impl Counters {
+    /// Count rows read from a table
+    pub fn count_scanned(&self, rows: usize) {
+        self.rows_scanned.fetch_add(rows as u64, Ordering::Relaxed);
+    }
+
+    /// Count a lookup answered by an index
+    pub fn count_index_hit(&self) {
+        self.index_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count bytes written to table files
+    pub fn count_written(&self, bytes: u64) {
+        self.bytes_written.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> AccessCounts {
+        AccessCounts {
+            rows_scanned: self.rows_scanned.load(Ordering::Relaxed),
+            index_hits: self.index_hits.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Database {
+    /// The counters scans, index lookups, and writes add to
+    pub fn counters(&self) -> &Counters {
+        &self.counters
+    }
+
+    /// Totals of the work done since the database was opened
+    pub fn access_counts(&self) -> AccessCounts {
+        self.counters.snapshot()
+    }
+}
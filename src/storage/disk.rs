@@ -1,13 +1,29 @@
 // Disk persistence module
 
 use std::fs::{self, File};
-use std::io::{self, BufRead, BufReader, Write};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
-use crate::parser::{Column, DataType, Value};
+use std::time::Instant;
+use tracing::{debug, warn};
+use crate::parser::{array, Column, DataType, Value};
+use super::external::External;
 use super::Table;
 
 const DATA_DIR: &str = "data";
 const TABLE_EXTENSION: &str = ".tbl";
+const CORRUPT_EXTENSION: &str = ".corrupt";
+const VIEW_EXTENSION: &str = ".view";
+const APPEND_ONLY_EXTENSION: &str = ".append_only";
+const EXTERNAL_EXTENSION: &str = ".external";
+const INDEXES_EXTENSION: &str = ".indexes";
+
+/// A line of a table file that could not be read as a row
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorruptRow {
+    pub line: usize, // 1-based, counting the schema line
+    pub text: String,
+    pub reason: String,
+}
 
 /// Initialize data directory
 pub fn init_data_dir() -> io::Result<()> {
@@ -15,32 +31,128 @@ pub fn init_data_dir() -> io::Result<()> {
     Ok(())
 }
 
-/// Save a table to disk
-pub fn save_table(table: &Table) -> io::Result<()> {
+/// Check that the data directory exists or can be created, and that files
+/// can be written in it
+pub fn check_data_dir() -> io::Result<()> {
     init_data_dir()?;
-    
+    let probe = data_path(".write_test");
+    File::create(&probe)?;
+    fs::remove_file(probe)
+}
+
+/// Save a table to disk, returning the size of its file
+pub fn save_table(table: &Table) -> io::Result<u64> {
+    let start = Instant::now();
+    init_data_dir()?;
+
     let path = get_table_path(&table.name);
     let mut file = File::create(path)?;
 
-    // Write schema: column_name:type,column_name:type,...
+    // Write schema: column_name:type[:constraint][:TTL=seconds],column_name:type,...
     let schema: Vec<String> = table.columns.iter()
-        .map(|col| format!("{}:{}", col.name, datatype_to_string(&col.data_type)))
+        .map(|col| {
+            let mut def = format!("{}:{}", col.name, datatype_to_string(&col.data_type));
+            if col.primary_key {
+                def.push_str(":PRIMARY_KEY");
+            } else if col.unique {
+                def.push_str(":UNIQUE");
+            }
+            if let Some(ttl) = col.ttl {
+                def.push_str(&format!(":TTL={}", ttl));
+            }
+            def
+        })
         .collect();
     writeln!(file, "{}", schema.join(","))?;
 
-    // Write rows: value|value|value
-    for row in &table.rows {
-        let row_str: Vec<String> = row.iter()
-            .map(value_to_string)
-            .collect();
-        writeln!(file, "{}", row_str.join("|"))?;
+    // Write rows: value|value|value. An external table keeps the location
+    // of its rows instead.
+    match &table.external {
+        Some(external) => fs::write(get_external_path(&table.name), &external.location)?,
+        None => {
+            for row in &table.rows {
+                writeln!(file, "{}", format_row(row))?;
+            }
+        }
     }
 
-    Ok(())
+    // A materialized view keeps its query beside its rows
+    if let Some(query) = &table.query {
+        fs::write(get_view_path(&table.name), query)?;
+    }
+    // An empty file beside the rows marks an append-only table
+    if table.append_only {
+        fs::write(get_append_only_path(&table.name), "")?;
+    }
+
+    debug!(table = %table.name, rows = table.rows.len(), elapsed = ?start.elapsed(), "saved table");
+    file.stream_position()
+}
+
+/// Append rows to the end of a table's existing file. A file that does not
+/// end with a complete line, e.g. after a write was cut short, is an error
+/// rather than a place to append; the table should be saved whole instead.
+/// Returns the bytes appended.
+pub fn append_rows(table_name: &str, rows: &[Vec<Value>]) -> io::Result<u64> {
+    let mut file = fs::OpenOptions::new().read(true).append(true).open(get_table_path(table_name))?;
+    let mut last = [0u8];
+    file.seek(SeekFrom::End(-1))?;
+    file.read_exact(&mut last)?;
+    if last[0] != b'\n' {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "file does not end with a complete line"));
+    }
+
+    let mut text = String::new();
+    for row in rows {
+        text.push_str(&format_row(row));
+        text.push('\n');
+    }
+    file.write_all(text.as_bytes())?;
+    Ok(text.len() as u64)
+}
+
+/// Save the CREATE INDEX statements recreating a table's indexes, one per
+/// line, beside its file; without any, the file is removed. Returns the
+/// bytes written.
+pub fn save_indexes(table_name: &str, statements: &[String]) -> io::Result<u64> {
+    let path = get_indexes_path(table_name);
+    if statements.is_empty() {
+        return match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(0),
+        };
+    }
+    let text: String = statements.iter().map(|statement| format!("{}\n", statement)).collect();
+    fs::write(path, &text)?;
+    Ok(text.len() as u64)
+}
+
+/// The CREATE INDEX statements saved beside a table, none if it has no
+/// index file
+pub fn load_indexes(table_name: &str) -> io::Result<Vec<String>> {
+    match fs::read_to_string(get_indexes_path(table_name)) {
+        Ok(text) => Ok(text.lines().filter(|line| !line.is_empty()).map(str::to_string).collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
 }
 
-/// Load a table from disk
+/// Load a table from disk, failing on the first row that cannot be read
 pub fn load_table(table_name: &str) -> io::Result<Table> {
+    let (table, corrupt) = salvage_table(table_name)?;
+    match corrupt.first() {
+        Some(row) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("line {}: {}", row.line, row.reason),
+        )),
+        None => Ok(table),
+    }
+}
+
+/// Load a table from disk, setting aside the rows that cannot be read. Only
+/// an unreadable file or schema line fails the whole table.
+pub fn salvage_table(table_name: &str) -> io::Result<(Table, Vec<CorruptRow>)> {
+    let start = Instant::now();
     let path = get_table_path(table_name);
     let file = File::open(path)?;
     let mut reader = BufReader::new(file);
@@ -54,23 +166,55 @@ pub fn load_table(table_name: &str) -> io::Result<Table> {
 
     // Read data lines
     let mut rows = Vec::new();
-    for line in reader.lines() {
+    let mut corrupt = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
         let line = line?;
         if line.trim().is_empty() {
             continue;
         }
-        let row = parse_row(&line, &columns)?;
-        rows.push(row);
+        match parse_row(&line, &columns) {
+            Ok(row) => rows.push(row),
+            Err(e) => corrupt.push(CorruptRow { line: i + 2, text: line, reason: e.to_string() }),
+        }
     }
 
-    Ok(Table {
+    debug!(table = %table_name, rows = rows.len(), corrupt = corrupt.len(), elapsed = ?start.elapsed(), "loaded table");
+    let query = match fs::read_to_string(get_view_path(table_name)) {
+        Ok(query) => Some(query),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    // An external table's rows are read from its file when it is first used
+    let external = match fs::read_to_string(get_external_path(table_name)) {
+        Ok(location) => Some(External { location, modified: None }),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    let table = Table {
         name: table_name.to_string(),
         columns,
         rows,
-    })
+        query,
+        append_only: get_append_only_path(table_name).exists(),
+        external,
+    };
+    Ok((table, corrupt))
+}
+
+/// Append rows that could not be loaded to the table's `.corrupt` file, each
+/// after a comment giving its line and the reason, and return the file's path
+pub fn quarantine_rows(table_name: &str, rows: &[CorruptRow]) -> io::Result<PathBuf> {
+    let path = Path::new(DATA_DIR).join(format!("{}{}", table_name, CORRUPT_EXTENSION));
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(&path)?;
+    for row in rows {
+        writeln!(file, "# line {}: {}", row.line, row.reason)?;
+        writeln!(file, "{}", row.text)?;
+    }
+    Ok(path)
 }
 
-/// Load all tables from disk
+/// Load all tables from disk. Rows that cannot be read are moved to a
+/// `.corrupt` file next to their table and reported, and the rest are loaded.
 pub fn load_all_tables() -> io::Result<Vec<Table>> {
     init_data_dir()?;
     
@@ -80,12 +224,17 @@ pub fn load_all_tables() -> io::Result<Vec<Table>> {
         let entry = entry?;
         let path = entry.path();
         
-        if path.extension().and_then(|s| s.to_str()) == Some("tbl") {
-            if let Some(table_name) = path.file_stem().and_then(|s| s.to_str()) {
-                match load_table(table_name) {
-                    Ok(table) => tables.push(table),
-                    Err(e) => eprintln!("Failed to load table '{}': {}", table_name, e),
+        if path.extension().and_then(|s| s.to_str()) == Some("tbl")
+            && let Some(table_name) = path.file_stem().and_then(|s| s.to_str())
+        {
+            match salvage_table(table_name) {
+                Ok((table, corrupt)) => {
+                    if !corrupt.is_empty() {
+                        set_aside(&table, &corrupt);
+                    }
+                    tables.push(table);
                 }
+                Err(e) => warn!(table = table_name, error = %e, "failed to load table"),
             }
         }
     }
@@ -93,10 +242,52 @@ pub fn load_all_tables() -> io::Result<Vec<Table>> {
     Ok(tables)
 }
 
+/// Report the rows skipped while loading a table and move them out of its
+/// file. If they cannot be saved elsewhere, the table file is left as it is.
+fn set_aside(table: &Table, corrupt: &[CorruptRow]) {
+    let moved = quarantine_rows(&table.name, corrupt)
+        .and_then(|path| save_table(table).map(|_| path));
+    match moved {
+        Ok(path) => warn!(
+            table = %table.name, rows = corrupt.len(), path = %path.display(),
+            "skipped malformed rows and moved them out of the table file"
+        ),
+        Err(e) => warn!(
+            table = %table.name, rows = corrupt.len(), error = %e,
+            "skipped malformed rows and left them in the table file as they could not be moved"
+        ),
+    }
+    for row in corrupt {
+        warn!(table = %table.name, line = row.line, reason = %row.reason, "malformed row");
+    }
+}
+
 /// Delete a table file from disk
 pub fn delete_table(table_name: &str) -> io::Result<()> {
     let path = get_table_path(table_name);
-    fs::remove_file(path)
+    fs::remove_file(path)?;
+    for path in [
+        get_view_path(table_name),
+        get_append_only_path(table_name),
+        get_external_path(table_name),
+        get_indexes_path(table_name),
+    ] {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Size in bytes of a table's data file
+pub fn table_file_size(table_name: &str) -> io::Result<u64> {
+    Ok(fs::metadata(get_table_path(table_name))?.len())
+}
+
+/// Path of a file kept in the data directory alongside the tables
+pub fn data_path(file_name: &str) -> PathBuf {
+    Path::new(DATA_DIR).join(file_name)
 }
 
 /// Get the file path for a table
@@ -104,13 +295,29 @@ fn get_table_path(table_name: &str) -> PathBuf {
     Path::new(DATA_DIR).join(format!("{}{}", table_name, TABLE_EXTENSION))
 }
 
+fn get_view_path(table_name: &str) -> PathBuf {
+    Path::new(DATA_DIR).join(format!("{}{}", table_name, VIEW_EXTENSION))
+}
+
+fn get_append_only_path(table_name: &str) -> PathBuf {
+    Path::new(DATA_DIR).join(format!("{}{}", table_name, APPEND_ONLY_EXTENSION))
+}
+
+fn get_external_path(table_name: &str) -> PathBuf {
+    Path::new(DATA_DIR).join(format!("{}{}", table_name, EXTERNAL_EXTENSION))
+}
+
+fn get_indexes_path(table_name: &str) -> PathBuf {
+    Path::new(DATA_DIR).join(format!("{}{}", table_name, INDEXES_EXTENSION))
+}
+
 /// Parse schema line into columns
 fn parse_schema(schema_line: &str) -> io::Result<Vec<Column>> {
     let mut columns = Vec::new();
     
     for col_def in schema_line.split(',') {
         let parts: Vec<&str> = col_def.split(':').collect();
-        if parts.len() != 2 {
+        if parts.len() < 2 || parts.len() > 4 {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 format!("Invalid column definition: {}", col_def),
@@ -119,16 +326,41 @@ fn parse_schema(schema_line: &str) -> io::Result<Vec<Column>> {
         
         let name = parts[0].to_string();
         let data_type = string_to_datatype(parts[1])?;
+        let mut column = Column::new(name, data_type);
+
+        for constraint in &parts[2..] {
+            match *constraint {
+                "PRIMARY_KEY" => column.primary_key = true,
+                "UNIQUE" => column.unique = true,
+                other => match other.strip_prefix("TTL=").and_then(|s| s.parse().ok()) {
+                    Some(ttl) => column.ttl = Some(ttl),
+                    None => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("Unknown column constraint: {}", other),
+                        ));
+                    }
+                },
+            }
+        }
         
-        columns.push(Column { name, data_type });
+        columns.push(column);
     }
     
     Ok(columns)
 }
 
+/// Format a row as one line of the table file format
+pub fn format_row(row: &[Value]) -> String {
+    let values: Vec<String> = row.iter()
+        .map(value_to_string)
+        .collect();
+    values.join("|")
+}
+
 /// Parse a data row
-fn parse_row(line: &str, columns: &[Column]) -> io::Result<Vec<Value>> {
-    let parts: Vec<&str> = line.split('|').collect();
+pub fn parse_row(line: &str, columns: &[Column]) -> io::Result<Vec<Value>> {
+    let parts = split_fields(line);
     
     if parts.len() != columns.len() {
         return Err(io::Error::new(
@@ -146,12 +378,33 @@ fn parse_row(line: &str, columns: &[Column]) -> io::Result<Vec<Value>> {
     Ok(row)
 }
 
+/// Split a row line at the `|` separators, leaving escaped `\|` in values
+fn split_fields(line: &str) -> Vec<&str> {
+    let mut fields = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, ch) in line.char_indices() {
+        match ch {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '|' => {
+                fields.push(&line[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    fields.push(&line[start..]);
+    fields
+}
+
 /// Convert DataType to string
-fn datatype_to_string(dt: &DataType) -> &str {
+fn datatype_to_string(dt: &DataType) -> String {
     match dt {
-        DataType::Int => "INT",
-        DataType::Text => "TEXT",
-        DataType::Float => "FLOAT",
+        DataType::Int => "INT".to_string(),
+        DataType::Text => "TEXT".to_string(),
+        DataType::Float => "FLOAT".to_string(),
+        DataType::Array(element) => format!("{}[]", datatype_to_string(element)),
     }
 }
 
@@ -161,6 +414,9 @@ fn string_to_datatype(s: &str) -> io::Result<DataType> {
         "INT" => Ok(DataType::Int),
         "TEXT" => Ok(DataType::Text),
         "FLOAT" => Ok(DataType::Float),
+        _ if s.ends_with("[]") && !s[..s.len() - 2].ends_with("[]") => {
+            Ok(DataType::Array(Box::new(string_to_datatype(&s[..s.len() - 2])?)))
+        }
         _ => Err(io::Error::new(
             io::ErrorKind::InvalidData,
             format!("Unknown data type: {}", s),
@@ -174,6 +430,8 @@ fn value_to_string(value: &Value) -> String {
         Value::Int(n) => n.to_string(),
         Value::Text(s) => escape_string(s),
         Value::Float(f) => f.to_string(),
+        // In the {a,b} form, which cannot be mistaken for NULL
+        Value::Array(values) => escape_string(&array::format_array(values)),
         Value::Null => "NULL".to_string(),
     }
 }
@@ -202,6 +460,9 @@ fn string_to_value(s: &str, data_type: &DataType) -> io::Result<Value> {
                     format!("Invalid float: {}", s),
                 ))
         }
+        DataType::Array(_) => array::parse_array(&unescape_string(s), data_type)
+            .map(Value::Array)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
     }
 }
 
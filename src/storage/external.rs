@@ -0,0 +1,177 @@
+// External - tables read in place from CSV files
+//
+// `CREATE EXTERNAL TABLE logs USING CSV LOCATION 'logs.csv'` makes a CSV file
+// queryable without importing it. Its columns are declared, or inferred from
+// the header line and the values beneath it as `.import` infers them. Only
+// the definition is saved in the data directory; the rows are read from the
+// file when a statement first reads the table, and again whenever the file
+// has changed since. External tables are read-only.
+
+use super::Database;
+use crate::error::DbError;
+use crate::import;
+use crate::parser::{normalize_identifier, Column, Value};
+use std::time::SystemTime;
+
+/// Where an external table's rows come from
+#[derive(Debug, Clone, PartialEq)]
+pub struct External {
+    pub location: String,
+    /// Modification time of the file when its rows were read, None before
+    /// they have been
+    pub modified: Option<SystemTime>,
+}
+
+impl Database {
+    /// Create a table over a CSV file, with the given columns or ones
+    /// inferred from the file. Returns how many rows the file holds.
+    pub fn create_external_table(&mut self, name: &str, columns: Option<Vec<Column>>, location: &str) -> Result<usize, DbError> {
+        let name = normalize_identifier(name);
+        let modified = modified_time(location)?;
+        let (columns, rows) = read_csv(location, columns)?;
+        check_columns(&name, &columns)?;
+
+        self.create_table(name.clone(), columns)?;
+        let table = self.tables.get_mut(&name)
+            .ok_or_else(|| DbError::TableNotFound(name.clone()))?;
+        table.external = Some(External { location: location.to_string(), modified: Some(modified) });
+        let count = rows.len();
+        table.rows = rows;
+        self.save_table(&name)?;
+        Ok(count)
+    }
+
+    /// Read an external table's file again if it has changed since its rows
+    /// were read. Other tables are left as they are.
+    pub fn refresh_external_table(&mut self, name: &str) -> Result<(), DbError> {
+        let Some(table) = self.tables.get(name) else {
+            return Ok(());
+        };
+        let Some(external) = &table.external else {
+            return Ok(());
+        };
+        let modified = modified_time(&external.location)?;
+        if external.modified == Some(modified) {
+            return Ok(());
+        }
+
+        let (_, rows) = read_csv(&external.location, Some(table.columns.clone()))
+            .map_err(|e| e.context(format!("External table '{}'", name)))?;
+        let table = self.tables.get_mut(name)
+            .ok_or_else(|| DbError::TableNotFound(name.to_string()))?;
+        table.rows = rows;
+        if let Some(external) = &mut table.external {
+            external.modified = Some(modified);
+        }
+        if let Some(indexes) = self.indexes.get_mut(name) {
+            for index in indexes.values_mut() {
+                index.build(&table.rows);
+            }
+        }
+        self.touch(name);
+        Ok(())
+    }
+
+    /// The file an external table reads, or None if the table is not external
+    pub fn external_location(&self, name: &str) -> Option<&str> {
+        Some(self.tables.get(name)?.external.as_ref()?.location.as_str())
+    }
+}
+
+/// The columns and rows of a CSV file. Declared columns skip a header line
+/// naming them; otherwise the header names the columns.
+fn read_csv(location: &str, columns: Option<Vec<Column>>) -> Result<(Vec<Column>, Vec<Vec<Value>>), DbError> {
+    let text = std::fs::read_to_string(location)
+        .map_err(|e| DbError::Io(format!("Failed to read '{}': {}", location, e)))?;
+    let mut records = import::parse_delimited(text.trim_start_matches('\u{feff}'), ',')?;
+
+    let columns = match columns {
+        Some(columns) => {
+            let header = records.first().map(|record| {
+                record.iter().map(|f| normalize_identifier(f.as_deref().unwrap_or("").trim())).collect::<Vec<_>>()
+            });
+            if header.is_some_and(|header| header.iter().eq(columns.iter().map(|c| &c.name))) {
+                records.remove(0);
+            }
+            columns
+        }
+        None if records.is_empty() => return Err(DbError::Parse(format!("'{}' has no header line", location))),
+        None => {
+            let header = records.remove(0);
+            let mut columns = import::infer_columns(&header, &records)?;
+            super::normalize_columns(&mut columns);
+            columns
+        }
+    };
+
+    let rows = records.iter()
+        .enumerate()
+        .map(|(n, record)| {
+            import::convert_record(record, &columns).map_err(|e| e.context(format!("Record {}", n + 1)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((columns, rows))
+}
+
+/// Check an external table's columns: they have no constraints, and their
+/// names are distinct and fit in the table file's schema line
+pub fn check_columns(table_name: &str, columns: &[Column]) -> Result<(), DbError> {
+    for (i, column) in columns.iter().enumerate() {
+        if column.primary_key || column.unique || column.ttl.is_some() {
+            return Err(DbError::Plan(format!(
+                "Column '{}' of external table '{}' cannot have constraints",
+                column.name, table_name
+            )));
+        }
+        if column.name.contains([',', ':']) {
+            return Err(DbError::Plan(format!(
+                "External table '{}' cannot have a column named '{}'",
+                table_name, column.name
+            )));
+        }
+        if columns[..i].iter().any(|c| c.name == column.name) {
+            return Err(DbError::Plan(format!(
+                "External table '{}' would have two columns named '{}'",
+                table_name, column.name
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn modified_time(location: &str) -> Result<SystemTime, DbError> {
+    std::fs::metadata(location)
+        .and_then(|metadata| metadata.modified())
+        .map_err(|e| DbError::Io(format!("Failed to read '{}': {}", location, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::DataType;
+
+    #[test]
+    fn test_external_table() {
+        let path = std::env::temp_dir().join(format!("external_{}.csv", std::process::id()));
+        let location = path.to_str().unwrap();
+        std::fs::write(&path, "Level,Code\ninfo,200\nerror,500\n").unwrap();
+
+        let mut db = Database::new();
+        db.set_persistent(false);
+        assert_eq!(db.create_external_table("logs", None, location), Ok(2));
+        let table = db.get_table("logs").unwrap();
+        assert_eq!(table.columns, [Column::new("level".to_string(), DataType::Text), Column::new("code".to_string(), DataType::Int)]);
+        assert_eq!(db.external_location("logs"), Some(location));
+
+        // A changed file is read again
+        std::fs::write(&path, "level,code\ninfo,200\n").unwrap();
+        db.tables.get_mut("logs").unwrap().external.as_mut().unwrap().modified = None;
+        db.refresh_external_table("logs").unwrap();
+        assert_eq!(db.get_table("logs").unwrap().rows, [vec![Value::Text("info".to_string()), Value::Int(200)]]);
+
+        let mut id = Column::new("id".to_string(), DataType::Int);
+        id.primary_key = true;
+        assert!(db.create_external_table("bad", Some(vec![id]), location).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,67 @@
+// Hooks - callbacks fired when table data changes
+//
+// `db.on_change(|event| ...)` registers a callback that runs after every
+// INSERT, UPDATE, or DELETE that changed at least one row and was saved.
+// Inside a transaction, events are held back until the outermost commit and
+// discarded on rollback, so callbacks never see changes that did not happen.
+
+use super::Database;
+use crate::parser::Value;
+
+/// Kind of statement that changed a table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Insert,
+    Update,
+    Delete,
+}
+
+/// Rows changed by one statement: the inserted rows, the updated rows with
+/// their new values, or the deleted rows
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangeEvent {
+    pub table: String,
+    pub kind: ChangeKind,
+    pub rows: Vec<Vec<Value>>,
+}
+
+/// Callback registered with `Database::on_change`
+pub type ChangeHook = Box<dyn FnMut(&ChangeEvent) + Send>;
+
+impl Database {
+    /// Call `hook` after each committed change to any table, e.g. to
+    /// invalidate a cache
+    pub fn on_change(&mut self, hook: impl FnMut(&ChangeEvent) + Send + 'static) {
+        self.hooks.push(Box::new(hook));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_changes_wait_for_commit() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut db = Database::new();
+        let log = Arc::clone(&seen);
+        db.on_change(move |event| log.lock().unwrap().push(event.clone()));
+
+        // Queued by the transaction and dropped with its rollback
+        {
+            let mut tx = db.begin();
+            tx.notify("users", ChangeKind::Insert, vec![vec![Value::Int(1)]]);
+            assert!(seen.lock().unwrap().is_empty());
+        }
+        assert!(seen.lock().unwrap().is_empty());
+        assert!(db.pending_changes.is_empty());
+
+        db.notify("users", ChangeKind::Delete, vec![vec![Value::Int(2)]]);
+        assert_eq!(*seen.lock().unwrap(), vec![ChangeEvent {
+            table: "users".to_string(),
+            kind: ChangeKind::Delete,
+            rows: vec![vec![Value::Int(2)]],
+        }]);
+    }
+}
@@ -0,0 +1,108 @@
+// Limits - maximum sizes for names, values, and tables
+//
+// Checked when tables are created and rows are stored, so a runaway script
+// or a malformed import fails with a clear error instead of writing table
+// files too large to load. `db.set_limits(...)` changes them for a session.
+
+use super::Database;
+use crate::error::DbError;
+use crate::parser::{Column, Value};
+
+/// Maximum sizes enforced by a database
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Longest table, column, or index name, in bytes
+    pub max_identifier_length: usize,
+    /// Largest TEXT value, in bytes
+    pub max_text_length: usize,
+    /// Most columns in a table
+    pub max_columns: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_identifier_length: 63,
+            max_text_length: 16 * 1024 * 1024,
+            max_columns: 1000,
+        }
+    }
+}
+
+impl Limits {
+    /// Check the length of a new table, column, or index name
+    pub fn check_identifier(&self, name: &str, kind: &str) -> Result<(), DbError> {
+        if name.len() > self.max_identifier_length {
+            // A pathological name is shortened in the message
+            let shown = match name.char_indices().nth(32) {
+                Some((end, _)) => format!("{}...", &name[..end]),
+                None => name.to_string(),
+            };
+            return Err(DbError::Plan(format!(
+                "{} name '{}' is {} bytes, over the limit of {}",
+                kind, shown, name.len(), self.max_identifier_length
+            )));
+        }
+        Ok(())
+    }
+
+    /// Check the column count and column names of a new table
+    pub fn check_columns(&self, table_name: &str, columns: &[Column]) -> Result<(), DbError> {
+        if columns.len() > self.max_columns {
+            return Err(DbError::Plan(format!(
+                "Table '{}' has {} columns, over the limit of {}",
+                table_name,
+                columns.len(),
+                self.max_columns
+            )));
+        }
+        columns.iter().try_for_each(|c| self.check_identifier(&c.name, "Column"))
+    }
+
+    /// Check the size of a value to be stored in a column
+    pub fn check_value(&self, value: &Value, column: &Column) -> Result<(), DbError> {
+        match value {
+            Value::Text(s) if s.len() > self.max_text_length => Err(DbError::ConstraintViolation(format!(
+                "TEXT value for column '{}' is {} bytes, over the limit of {}",
+                column.name,
+                s.len(),
+                self.max_text_length
+            ))),
+            Value::Array(values) => values.iter().try_for_each(|value| self.check_value(value, column)),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Database {
+    /// The size limits in effect
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// Change the size limits. Existing tables and rows are not rechecked.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::DataType;
+
+    #[test]
+    fn test_limits() {
+        let limits = Limits { max_identifier_length: 4, max_text_length: 3, max_columns: 1 };
+        let column = Column::new("name".to_string(), DataType::Text);
+
+        assert!(limits.check_identifier("name", "Table").is_ok());
+        assert!(limits.check_identifier("names", "Table").is_err());
+        assert!(limits.check_columns("t", &[column.clone(), column.clone()]).is_err());
+        assert!(limits.check_value(&Value::Text("abc".to_string()), &column).is_ok());
+        assert!(matches!(
+            limits.check_value(&Value::Text("abcd".to_string()), &column),
+            Err(DbError::ConstraintViolation(_))
+        ));
+    }
+}
@@ -1,12 +1,54 @@
 // Storage module - manages tables and data
 
-use crate::parser::{Column, Value, WhereClause, Operator};
-use std::collections::HashMap;
+use crate::error::DbError;
+use crate::eval;
+use crate::parser::{self, normalize_identifier, Column, DataType, Expr, Operator, Statement, Value, WhereClause};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
+pub mod alter;
+pub mod audit;
 pub mod btree;
+pub mod bulk;
+pub mod clone;
+pub mod counters;
 pub mod disk;
+pub mod external;
+pub mod hooks;
+pub mod limits;
+pub mod stats;
+pub mod transaction;
+pub mod ttl;
+pub mod version;
+pub mod view;
+
+use audit::{AuditLog, AUDIT_TABLE};
+use btree::{Index, IndexKey};
+use counters::Counters;
+use hooks::{ChangeEvent, ChangeHook, ChangeKind};
+use limits::Limits;
+use stats::TableStats;
+
+/// Rows that can be appended to a table file before it is compacted, however
+/// small it was when last rewritten
+const COMPACTION_MIN_ROWS: usize = 1000;
+
+/// Approximate heap and inline size of a value
+pub fn value_size(value: &Value) -> usize {
+    let heap = match value {
+        Value::Text(s) => s.capacity(),
+        Value::Array(values) => {
+            let spare = values.capacity() - values.len();
+            values.iter().map(value_size).sum::<usize>() + spare * std::mem::size_of::<Value>()
+        }
+        _ => 0,
+    };
+    std::mem::size_of::<Value>() + heap
+}
 
-use btree::Index;
+/// Approximate size of a row stored as a vector of values
+pub fn row_size(row: &[Value]) -> usize {
+    std::mem::size_of::<Vec<Value>>() + row.iter().map(value_size).sum::<usize>()
+}
 
 /// Represents a table in the database
 #[derive(Debug, Clone)]
@@ -14,6 +56,12 @@ pub struct Table {
     pub name: String,
     pub columns: Vec<Column>,
     pub rows: Vec<Vec<Value>>,
+    /// The query of a materialized view, whose result the rows hold
+    pub query: Option<String>,
+    /// Rows can be inserted but never updated or deleted (`APPEND ONLY`)
+    pub append_only: bool,
+    /// The file an external table's rows are read from
+    pub external: Option<external::External>,
 }
 
 impl Table {
@@ -22,19 +70,82 @@ impl Table {
             name,
             columns,
             rows: Vec::new(),
+            query: None,
+            append_only: false,
+            external: None,
         }
     }
 
+    /// Approximate memory held by the table's rows
+    pub fn memory_size(&self) -> usize {
+        self.rows.iter().map(|row| row_size(row)).sum()
+    }
+
     /// Get column index by name
     pub fn get_column_index(&self, column_name: &str) -> Option<usize> {
         self.columns.iter().position(|c| c.name == column_name)
     }
 }
 
+/// Description of an index, as reported by `Database::list_indexes`
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexInfo {
+    pub name: String,
+    pub table: String,
+    pub columns: Vec<String>,
+    pub unique: bool,
+    pub entries: usize,
+    pub distinct_keys: usize,
+}
+
+/// Structure of a table, as reported by `Database::table_schema`. Each
+/// column carries its type and PRIMARY KEY and UNIQUE constraints.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableInfo {
+    pub name: String,
+    pub columns: Vec<Column>,
+    pub indexes: Vec<IndexInfo>, // Ordered by name, including constraint indexes
+    pub rows: usize,
+    pub query: Option<String>, // The query of a materialized view
+    pub append_only: bool,
+}
+
+/// Row count and memory and disk footprint of a table, as reported by
+/// `Database::table_usage`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableUsage {
+    pub table: String,
+    pub rows: usize,
+    pub memory: usize,       // Approximate bytes held by rows and indexes
+    pub disk: Option<u64>,   // Data file size, None if it has not been written
+    pub indexes: usize,
+}
+
 /// In-memory database
 pub struct Database {
     tables: HashMap<String, Table>,
-    indexes: HashMap<String, HashMap<String, Index>>, // table_name -> column_name -> Index
+    indexes: HashMap<String, HashMap<String, Index>>, // table_name -> indexed expression -> Index
+    stats: HashMap<String, TableStats>, // Collected by ANALYZE
+    schema_version: u64, // Bumped whenever tables, indexes, or statistics change
+    data_version: u64, // Bumped whenever a table's rows change
+    table_versions: HashMap<String, u64>, // The data version of each table's last change
+    transaction_depth: usize, // Open `Transaction` guards
+    constraints_deferred: bool, // UNIQUE checks wait for the outermost commit
+    dirty: BTreeSet<String>, // Tables changed since they were last written
+    appended: BTreeMap<String, usize>, // Rows added to the end of other tables since then
+    appended_on_disk: HashMap<String, usize>, // Rows appended to table files since they were rewritten
+    hooks: Vec<ChangeHook>, // Registered with `on_change`
+    pending_changes: Vec<ChangeEvent>, // Changes made inside a transaction
+    audit: Option<AuditLog>, // Statement log, when enabled
+    limits: Limits,
+    counters: Counters, // Rows scanned, index hits, and bytes written so far
+    persistent: bool, // Whether changes are written to disk
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Database {
@@ -43,152 +154,498 @@ impl Database {
         Self {
             tables: HashMap::new(),
             indexes: HashMap::new(),
+            stats: HashMap::new(),
+            schema_version: 0,
+            data_version: 0,
+            table_versions: HashMap::new(),
+            transaction_depth: 0,
+            constraints_deferred: false,
+            dirty: BTreeSet::new(),
+            appended: BTreeMap::new(),
+            appended_on_disk: HashMap::new(),
+            hooks: Vec::new(),
+            pending_changes: Vec::new(),
+            audit: None,
+            limits: Limits::default(),
+            counters: Counters::default(),
+            persistent: true,
         }
     }
 
     /// Load database from disk
-    pub fn load_from_disk() -> Result<Self, String> {
+    pub fn load_from_disk() -> Result<Self, DbError> {
         let tables_vec = disk::load_all_tables()
-            .map_err(|e| format!("Failed to load tables: {}", e))?;
+            .map_err(|e| DbError::Io(format!("Failed to load tables: {}", e)))?;
 
+        let names: HashSet<String> = tables_vec.iter().map(|t| t.name.clone()).collect();
         let mut tables = HashMap::new();
-        for table in tables_vec {
+        for mut table in tables_vec {
+            // Tables saved before names were case-insensitive are renamed,
+            // unless that would replace another table
+            let old_name = table.name.clone();
+            let name = normalize_identifier(&old_name);
+            let renamed = name != old_name && !names.contains(&name);
+            if renamed || table.columns.iter().any(|c| normalize_identifier(&c.name) != c.name) {
+                if renamed {
+                    table.name = name;
+                }
+                normalize_columns(&mut table.columns);
+                disk::save_table(&table)
+                    .and_then(|_| if renamed { disk::delete_table(&old_name) } else { Ok(()) })
+                    .map_err(|e| DbError::Io(format!("Failed to rename table '{}': {}", old_name, e)))?;
+            }
             tables.insert(table.name.clone(), table);
         }
 
-        Ok(Self {
+        let mut db = Self {
             tables,
-            indexes: HashMap::new(),
-        })
-    }
+            ..Self::new()
+        };
 
-    /// Save database to disk
-    pub fn save_to_disk(&self) -> Result<(), String> {
-        for table in self.tables.values() {
-            disk::save_table(table)
-                .map_err(|e| format!("Failed to save table '{}': {}", table.name, e))?;
+        // Rebuild the indexes backing PRIMARY KEY and UNIQUE constraints,
+        // then those created with CREATE INDEX
+        let table_names = db.list_tables();
+        for table_name in table_names {
+            if let Err(e) = db.create_constraint_indexes(&table_name).and_then(|_| db.load_indexes(&table_name)) {
+                tracing::warn!(table = %table_name, error = %e, "failed to index table");
+            }
         }
-        Ok(())
+
+        Ok(db)
+    }
+
+    /// Save database to disk. Only the tables changed since they were last
+    /// written are saved, as by `flush`.
+    pub fn save_to_disk(&mut self) -> Result<(), DbError> {
+        self.flush().map(|_| ())
     }
 
     /// Create a new table
-    pub fn create_table(&mut self, name: String, columns: Vec<Column>) -> Result<(), String> {
+    pub fn create_table(&mut self, name: String, columns: Vec<Column>) -> Result<(), DbError> {
+        self.create_table_with(name, columns, false)
+    }
+
+    /// Create a table whose rows can be inserted but never updated or
+    /// deleted, as for a ledger or an event log
+    pub fn create_append_only_table(&mut self, name: String, columns: Vec<Column>) -> Result<(), DbError> {
+        self.create_table_with(name, columns, true)
+    }
+
+    fn create_table_with(&mut self, name: String, mut columns: Vec<Column>, append_only: bool) -> Result<(), DbError> {
+        let name = normalize_identifier(&name);
+        normalize_columns(&mut columns);
         if self.tables.contains_key(&name) {
-            return Err(format!("Table '{}' already exists", name));
+            return Err(DbError::TableExists(name));
+        }
+        if name == AUDIT_TABLE {
+            return Err(DbError::Plan(format!("Table name '{}' is reserved for the audit log", name)));
+        }
+
+        self.limits.check_identifier(&name, "Table")?;
+        self.limits.check_columns(&name, &columns)?;
+        if columns.iter().filter(|c| c.primary_key).count() > 1 {
+            return Err(DbError::Plan(format!("Table '{}' has more than one PRIMARY KEY", name)));
         }
+        ttl::check_columns(&name, &columns)?;
+        version::check_columns(&name, &columns)?;
+        if append_only && columns.iter().any(|c| c.ttl.is_some()) {
+            return Err(DbError::Plan(format!("Append-only table '{}' cannot have a TTL column", name)));
+        }
+
+        let mut table = Table::new(name.clone(), columns);
+        table.append_only = append_only;
+        self.tables.insert(name.clone(), table);
 
-        let table = Table::new(name.clone(), columns);
-        
         // Save to disk
-        disk::save_table(&table)
-            .map_err(|e| format!("Failed to save table: {}", e))?;
+        if let Err(e) = self.save_table(&name) {
+            self.tables.remove(&name);
+            self.dirty.remove(&name);
+            return Err(e);
+        }
+        self.schema_version += 1;
+        self.create_constraint_indexes(&name)
+    }
 
-        self.tables.insert(name, table);
+    /// Create the unique indexes backing a table's PRIMARY KEY and UNIQUE columns
+    fn create_constraint_indexes(&mut self, table_name: &str) -> Result<(), DbError> {
+        for (column_name, index_name) in self.constraint_indexes(table_name)? {
+            self.add_index(table_name, &Expr::Column(column_name), Some(index_name), true)?;
+        }
         Ok(())
     }
 
-    /// Create an index on a column
-    pub fn create_index(&mut self, table_name: &str, column_name: &str) -> Result<(), String> {
+    /// The constrained columns of a table with the names of their indexes
+    fn constraint_indexes(&self, table_name: &str) -> Result<Vec<(String, String)>, DbError> {
+        let table = self.tables.get(table_name)
+            .ok_or_else(|| DbError::TableNotFound(table_name.to_string()))?;
+
+        Ok(table.columns.iter()
+            .filter_map(|c| {
+                if c.primary_key {
+                    Some((c.name.clone(), format!("{}_pkey", table_name)))
+                } else if c.unique {
+                    Some((c.name.clone(), format!("{}_{}_key", table_name, c.name)))
+                } else {
+                    None
+                }
+            })
+            .collect())
+    }
+
+    /// Rebuild the indexes saved beside a table by CREATE INDEX
+    fn load_indexes(&mut self, table_name: &str) -> Result<(), DbError> {
+        let statements = disk::load_indexes(table_name)
+            .map_err(|e| DbError::Io(format!("Failed to read indexes: {}", e)))?;
+        for statement in statements {
+            match parser::parse(&statement)? {
+                Statement::CreateIndex { index_name, expr, unique, .. } => {
+                    self.add_index(table_name, &expr, index_name, unique)?;
+                }
+                _ => return Err(DbError::Parse(format!("Not an index definition: {}", statement))),
+            }
+        }
+        Ok(())
+    }
+
+    /// The CREATE INDEX statements recreating a table's indexes, other than
+    /// those backing its constraints
+    fn index_definitions(&self, table_name: &str) -> Vec<String> {
+        let constraints = self.constraint_indexes(table_name).unwrap_or_default();
+        let mut definitions: Vec<(&String, String)> = self.indexes.get(table_name)
+            .into_iter()
+            .flat_map(|indexes| indexes.values())
+            .filter(|index| !constraints.iter().any(|(_, name)| *name == index.name))
+            .map(|index| {
+                let unique = if index.unique { "UNIQUE " } else { "" };
+                (&index.name, format!("CREATE {}INDEX {} ON {} ({})", unique, index.name, table_name, index.expr))
+            })
+            .collect();
+        definitions.sort();
+        definitions.into_iter().map(|(_, statement)| statement).collect()
+    }
+
+    /// Create an index on a column or on an expression over a single column,
+    /// and save its definition with the table. Returns the name of the new
+    /// index.
+    pub fn create_index(
+        &mut self,
+        table_name: &str,
+        expr: &Expr,
+        index_name: Option<String>,
+        unique: bool,
+    ) -> Result<String, DbError> {
+        let index_name = self.add_index(table_name, expr, index_name, unique)?;
+        self.dirty.insert(table_name.to_string());
+        self.flush()?;
+        Ok(index_name)
+    }
+
+    fn add_index(
+        &mut self,
+        table_name: &str,
+        expr: &Expr,
+        index_name: Option<String>,
+        unique: bool,
+    ) -> Result<String, DbError> {
         let table = self.tables.get(table_name)
-            .ok_or_else(|| format!("Table '{}' does not exist", table_name))?;
+            .ok_or_else(|| DbError::TableNotFound(table_name.to_string()))?;
+
+        let index_name = index_name.unwrap_or_else(|| {
+            format!("idx_{}_{}", table_name, btree::sanitize_name(&expr.to_string()))
+        });
+        self.limits.check_identifier(&index_name, "Index")?;
+        if self.indexes.values().flat_map(|t| t.values()).any(|i| i.name == index_name) {
+            return Err(DbError::Plan(format!("Index '{}' already exists", index_name)));
+        }
+        if let Some(existing) = self.indexes.get(table_name).and_then(|t| t.get(&expr.to_string())) {
+            return Err(DbError::Plan(format!(
+                "Index '{}' already covers '{}' on table '{}'",
+                existing.name, expr, table_name
+            )));
+        }
+
+        // Check that the expression is well-typed so evaluation can't fail later
+        eval::infer_type(expr, &table.columns)?;
 
-        let column_index = table.get_column_index(column_name)
-            .ok_or_else(|| format!("Column '{}' does not exist", column_name))?;
+        let referenced = eval::referenced_columns(expr);
+        let column_name = match referenced.as_slice() {
+            [name] => name.to_string(),
+            _ => {
+                return Err(DbError::Plan(format!(
+                    "Index expression '{}' must reference exactly one column",
+                    expr
+                )));
+            }
+        };
+        let column_index = table.get_column_index(&column_name)
+            .ok_or_else(|| DbError::ColumnNotFound(column_name.to_string()))?;
 
         // Create index
-        let mut index = Index::new(column_name.to_string(), column_index);
+        let mut index = match expr {
+            Expr::Column(_) => Index::new(column_name, column_index),
+            _ => Index::with_expr(expr.clone(), column_name, column_index),
+        };
+        index.name = index_name.clone();
+        index.unique = unique;
         index.build(&table.rows);
 
+        if unique && let Some(key) = index.duplicate_key() {
+            return Err(DbError::ConstraintViolation(format!(
+                "Cannot create unique index '{}': duplicate value {}",
+                index_name, key.to_value()
+            )));
+        }
+
         // Store index
         self.indexes
             .entry(table_name.to_string())
-            .or_insert_with(HashMap::new)
-            .insert(column_name.to_string(), index);
+            .or_default()
+            .insert(expr.to_string(), index);
+        self.schema_version += 1;
 
-        Ok(())
+        Ok(index_name)
+    }
+
+    /// Describe all indexes, ordered by table and index name
+    pub fn list_indexes(&self) -> Vec<IndexInfo> {
+        let mut infos: Vec<IndexInfo> = self.indexes.iter()
+            .flat_map(|(table_name, table_indexes)| {
+                table_indexes.values().map(move |index| IndexInfo {
+                    name: index.name.clone(),
+                    table: table_name.clone(),
+                    columns: vec![index.expr.to_string()],
+                    unique: index.unique,
+                    entries: index.len(),
+                    distinct_keys: index.distinct_keys(),
+                })
+            })
+            .collect();
+
+        infos.sort_by(|a, b| (&a.table, &a.name).cmp(&(&b.table, &b.name)));
+        infos
+    }
+
+    /// Describe a table's columns, constraints, indexes, and row count
+    pub fn table_schema(&self, table_name: &str) -> Result<TableInfo, DbError> {
+        let table = self.get_table(table_name)?;
+        Ok(TableInfo {
+            name: table.name.clone(),
+            columns: table.columns.clone(),
+            indexes: self.list_indexes().into_iter().filter(|i| i.table == table.name).collect(),
+            rows: table.rows.len(),
+            query: table.query.clone(),
+            append_only: table.append_only,
+        })
+    }
+
+    /// Row counts and memory and disk use of every table, sorted by name
+    pub fn table_usage(&self) -> Vec<TableUsage> {
+        let mut usage: Vec<TableUsage> = self.tables.values()
+            .map(|table| {
+                let indexes = self.indexes.get(&table.name);
+                TableUsage {
+                    table: table.name.clone(),
+                    rows: table.rows.len(),
+                    memory: table.memory_size()
+                        + indexes.map_or(0, |i| i.values().map(Index::memory_size).sum()),
+                    disk: disk::table_file_size(&table.name).ok(),
+                    indexes: indexes.map_or(0, HashMap::len),
+                }
+            })
+            .collect();
+
+        usage.sort_by(|a, b| a.table.cmp(&b.table));
+        usage
     }
 
     /// Insert a row into a table
-    pub fn insert_row(&mut self, table_name: &str, values: Vec<Value>) -> Result<(), String> {
-        let table = self.tables.get_mut(table_name)
-            .ok_or_else(|| format!("Table '{}' does not exist", table_name))?;
-
-        if values.len() != table.columns.len() {
-            return Err(format!(
-                "Expected {} values, got {}",
-                table.columns.len(),
-                values.len()
-            ));
-        }
-
-        // Validate types
-        for (value, column) in values.iter().zip(table.columns.iter()) {
-            match (value, &column.data_type) {
-                (Value::Int(_), crate::parser::DataType::Int) => {}
-                (Value::Text(_), crate::parser::DataType::Text) => {}
-                (Value::Float(_), crate::parser::DataType::Float) => {}
-                (Value::Null, _) => {}
-                _ => {
-                    return Err(format!(
-                        "Type mismatch for column '{}': expected {:?}, got {:?}",
-                        column.name, column.data_type, value
-                    ));
+    pub fn insert_row(&mut self, table_name: &str, values: Vec<Value>) -> Result<(), DbError> {
+        let changed = if self.hooks.is_empty() { Vec::new() } else { vec![values.clone()] };
+        self.insert_in_memory(table_name, values)?;
+        self.save_appended(table_name, 1)?;
+        self.notify(table_name, ChangeKind::Insert, changed);
+        Ok(())
+    }
+
+    /// Insert many rows, writing the table to disk once. If a row is
+    /// rejected, the rows before it are kept and saved.
+    pub fn insert_rows(&mut self, table_name: &str, rows: Vec<Vec<Value>>) -> Result<usize, DbError> {
+        if let Some(table) = self.tables.get_mut(table_name) {
+            table.rows.reserve(rows.len());
+        }
+        let watched = !self.hooks.is_empty();
+        let mut changed = Vec::new();
+        let mut inserted = 0;
+        let mut result = Ok(());
+        for values in rows {
+            let copy = watched.then(|| values.clone());
+            if let Err(e) = self.insert_in_memory(table_name, values) {
+                result = Err(e.context(format!("Row {}", inserted + 1)));
+                break;
+            }
+            changed.extend(copy);
+            inserted += 1;
+        }
+        if inserted > 0 {
+            self.save_appended(table_name, inserted)?;
+            self.notify(table_name, ChangeKind::Insert, changed);
+        }
+        result.map(|_| inserted)
+    }
+
+    /// Mark a table as changed and, outside a transaction, write it to disk
+    fn save_table(&mut self, table_name: &str) -> Result<(), DbError> {
+        self.touch(table_name);
+        self.appended.remove(table_name);
+        self.dirty.insert(table_name.to_string());
+        self.flush().map(|_| ())
+    }
+
+    /// Note that a table's rows have changed, which makes query results
+    /// cached over them stale
+    fn touch(&mut self, table_name: &str) {
+        self.data_version += 1;
+        self.table_versions.insert(table_name.to_string(), self.data_version);
+    }
+
+    /// Mark rows as added to the end of a table and, outside a transaction,
+    /// append them to its file
+    fn save_appended(&mut self, table_name: &str, count: usize) -> Result<(), DbError> {
+        self.touch(table_name);
+        if !self.dirty.contains(table_name) {
+            *self.appended.entry(table_name.to_string()).or_default() += count;
+        }
+        self.flush().map(|_| ())
+    }
+
+    /// Write the tables changed since they were last written, returning how
+    /// many were. Inside a transaction, or while changes are kept in memory,
+    /// nothing is written. A table that fails to write stays marked, so the
+    /// next flush tries it again.
+    pub fn flush(&mut self) -> Result<usize, DbError> {
+        if self.transaction_depth > 0 || !self.persistent {
+            return Ok(0);
+        }
+        let mut written = 0;
+        while let Some(name) = self.dirty.first().cloned() {
+            // A table dropped since it changed has nothing left to write
+            if let Some(table) = self.tables.get(&name) {
+                let indexes = self.index_definitions(&name);
+                let bytes = disk::save_table(table)
+                    .and_then(|bytes| Ok(bytes + disk::save_indexes(&name, &indexes)?))
+                    .map_err(|e| write_error(&name, e))?;
+                self.counters.count_written(bytes);
+                self.appended_on_disk.remove(&name);
+                written += 1;
+            }
+            self.dirty.remove(&name);
+        }
+        while let Some((name, count)) = self.appended.pop_first() {
+            if let Some(table) = self.tables.get(&name) {
+                match append_to_file(table, count, &mut self.appended_on_disk) {
+                    Ok(bytes) => self.counters.count_written(bytes),
+                    Err(e) => {
+                        self.appended.insert(name.clone(), count);
+                        return Err(write_error(&name, e));
+                    }
                 }
+                written += 1;
             }
         }
+        Ok(written)
+    }
 
-        let row_idx = table.rows.len();
-        table.rows.push(values.clone());
+    /// Names of the tables with changes not yet written to disk, sorted
+    pub fn unsaved_tables(&self) -> Vec<String> {
+        let mut names: BTreeSet<&String> = self.dirty.iter().collect();
+        names.extend(self.appended.keys());
+        names.into_iter().cloned().collect()
+    }
 
-        // Update indexes
-        if let Some(table_indexes) = self.indexes.get_mut(table_name) {
-            for index in table_indexes.values_mut() {
+    /// Whether changes are written to disk
+    pub fn is_persistent(&self) -> bool {
+        self.persistent
+    }
+
+    /// Turn writing changes to disk off, e.g. when the data directory is read
+    /// only. Turning it back on writes the changes made meanwhile at the next
+    /// `flush` or change.
+    pub fn set_persistent(&mut self, persistent: bool) {
+        self.persistent = persistent;
+    }
+
+    /// Report changed rows to the hooks, or queue them until commit
+    fn notify(&mut self, table_name: &str, kind: ChangeKind, rows: Vec<Vec<Value>>) {
+        if self.hooks.is_empty() || rows.is_empty() {
+            return;
+        }
+        let event = ChangeEvent { table: table_name.to_string(), kind, rows };
+        if self.transaction_depth > 0 {
+            self.pending_changes.push(event);
+        } else {
+            self.fire(&event);
+        }
+    }
+
+    /// Run every hook on one event
+    fn fire(&mut self, event: &ChangeEvent) {
+        for hook in &mut self.hooks {
+            hook(event);
+        }
+    }
+
+    /// Validate a row and add it to the table and its indexes, without saving
+    fn insert_in_memory(&mut self, table_name: &str, values: Vec<Value>) -> Result<(), DbError> {
+        let table = self.tables.get_mut(table_name)
+            .ok_or_else(|| DbError::TableNotFound(table_name.to_string()))?;
+
+        let values = prepare_row(table, values, &self.limits)?;
+
+        // Check UNIQUE constraints with index lookups
+        if !self.constraints_deferred && let Some(table_indexes) = self.indexes.get(table_name) {
+            for index in table_indexes.values().filter(|i| i.unique) {
                 if let Some(value) = values.get(index.column_index) {
-                    index.insert(row_idx, value);
+                    check_unique(index, value, &[])?;
                 }
             }
         }
 
-        // Save to disk
-        disk::save_table(table)
-            .map_err(|e| format!("Failed to save table: {}", e))?;
+        // The row is moved into the table and indexed from there
+        let row_idx = table.rows.len();
+        table.rows.push(values);
+        if let Some(table_indexes) = self.indexes.get_mut(table_name) {
+            for index in table_indexes.values_mut() {
+                index.insert(row_idx, &table.rows[row_idx][index.column_index]);
+            }
+        }
 
         Ok(())
     }
 
     /// Delete rows from a table based on filter
-    pub fn delete_rows(&mut self, table_name: &str, filter: Option<&WhereClause>) -> Result<usize, String> {
+    pub fn delete_rows(&mut self, table_name: &str, filter: Option<&WhereClause>) -> Result<usize, DbError> {
+        self.check_changeable(table_name)?;
+        let indices_to_delete = self.matching_rows(table_name, filter)?;
+        self.remove_rows(table_name, indices_to_delete)
+    }
+
+    /// Delete the rows at the given positions, in table order
+    fn remove_rows(&mut self, table_name: &str, indices_to_delete: Vec<usize>) -> Result<usize, DbError> {
         let table = self.tables.get_mut(table_name)
-            .ok_or_else(|| format!("Table '{}' does not exist", table_name))?;
-
-        let indices_to_delete = if let Some(where_clause) = filter {
-            // Get column index
-            let col_idx = table.get_column_index(&where_clause.column)
-                .ok_or_else(|| format!("Column '{}' does not exist", where_clause.column))?;
-
-            // Find matching rows
-            table.rows.iter()
-                .enumerate()
-                .filter(|(_, row)| {
-                    if let Some(value) = row.get(col_idx) {
-                        compare_values(value, &where_clause.operator, &where_clause.value)
-                    } else {
-                        false
-                    }
-                })
-                .map(|(idx, _)| idx)
-                .collect::<Vec<_>>()
-        } else {
-            // Delete all rows
-            (0..table.rows.len()).collect()
-        };
+            .ok_or_else(|| DbError::TableNotFound(table_name.to_string()))?;
 
         let count = indices_to_delete.len();
+        let watched = !self.hooks.is_empty();
+        let mut changed = Vec::new();
 
         // Remove rows in reverse order to maintain indices
         for &idx in indices_to_delete.iter().rev() {
-            table.rows.remove(idx);
+            let row = table.rows.remove(idx);
+            if watched {
+                changed.push(row);
+            }
         }
+        changed.reverse();
 
         // Rebuild all indexes for this table
         if let Some(table_indexes) = self.indexes.get_mut(table_name) {
@@ -198,192 +655,273 @@ impl Database {
         }
 
         // Save to disk
-        disk::save_table(table)
-            .map_err(|e| format!("Failed to save table: {}", e))?;
+        self.save_table(table_name)?;
+        self.notify(table_name, ChangeKind::Delete, changed);
 
         Ok(count)
     }
 
-    /// Update rows in a table
+    /// Check that a table's rows may be updated and deleted, which those of
+    /// an append-only table may not
+    pub fn check_changeable(&self, table_name: &str) -> Result<(), DbError> {
+        match self.tables.get(table_name) {
+            Some(table) if table.append_only => Err(DbError::Plan(format!(
+                "Table '{}' is append-only: its rows cannot be updated or deleted",
+                table_name
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// The rows `delete_rows` would remove, leaving them in place
+    pub fn preview_delete(&self, table_name: &str, filter: Option<&WhereClause>) -> Result<Vec<Vec<Value>>, DbError> {
+        let table = self.get_table(table_name)?;
+        let matching = self.matching_rows(table_name, filter)?;
+        Ok(matching.into_iter().map(|idx| table.rows[idx].clone()).collect())
+    }
+
+    /// The rows `update_rows` would change, as they would be after it. The
+    /// new values are checked as for the update itself, but not stored.
+    pub fn preview_update(
+        &self,
+        table_name: &str,
+        column_name: &str,
+        expr: &Expr,
+        filter: Option<&WhereClause>
+    ) -> Result<Vec<Vec<Value>>, DbError> {
+        let table = self.get_table(table_name)?;
+        let (update_col_idx, matching, new_values) = self.updated_values(table_name, column_name, expr, filter)?;
+        let version = version::version_position(&table.columns);
+        Ok(matching.into_iter()
+            .zip(new_values)
+            .map(|(idx, new_value)| {
+                let mut row = table.rows[idx].clone();
+                row[update_col_idx] = new_value;
+                if let Some(position) = version {
+                    row[position] = version::next_version(&row[position]);
+                }
+                row
+            })
+            .collect())
+    }
+
+    /// Set a column of the matching rows to `expr`, evaluated against each
+    /// row's current values
     pub fn update_rows(
         &mut self,
         table_name: &str,
         column_name: &str,
-        new_value: Value,
+        expr: &Expr,
         filter: Option<&WhereClause>
-    ) -> Result<usize, String> {
-        let table = self.tables.get_mut(table_name)
-            .ok_or_else(|| format!("Table '{}' does not exist", table_name))?;
-
-        // Get the column index to update
-        let update_col_idx = table.get_column_index(column_name)
-            .ok_or_else(|| format!("Column '{}' does not exist", column_name))?;
-
-        // Validate the new value type
-        let expected_type = &table.columns[update_col_idx].data_type;
-        match (&new_value, expected_type) {
-            (Value::Int(_), crate::parser::DataType::Int) => {}
-            (Value::Text(_), crate::parser::DataType::Text) => {}
-            (Value::Float(_), crate::parser::DataType::Float) => {}
-            (Value::Null, _) => {}
-            _ => {
-                return Err(format!(
-                    "Type mismatch for column '{}': expected {:?}, got {:?}",
-                    column_name, expected_type, new_value
-                ));
-            }
+    ) -> Result<usize, DbError> {
+        let (update_col_idx, matching, new_values) = self.updated_values(table_name, column_name, expr, filter)?;
+        if matching.is_empty() {
+            return Ok(0);
         }
 
-        let mut count = 0;
-
-        if let Some(where_clause) = filter {
-            // Get column index for filter
-            let filter_col_idx = table.get_column_index(&where_clause.column)
-                .ok_or_else(|| format!("Column '{}' does not exist", where_clause.column))?;
-
-            // Update matching rows
-            for row in &mut table.rows {
-                if let Some(value) = row.get(filter_col_idx) {
-                    if compare_values(value, &where_clause.operator, &where_clause.value) {
-                        row[update_col_idx] = new_value.clone();
-                        count += 1;
-                    }
-                }
-            }
-        } else {
-            // Update all rows
-            for row in &mut table.rows {
-                row[update_col_idx] = new_value.clone();
-                count += 1;
+        let table = self.tables.get_mut(table_name)
+            .ok_or_else(|| DbError::TableNotFound(table_name.to_string()))?;
+        let version = version::version_position(&table.columns);
+        for (&idx, new_value) in matching.iter().zip(new_values) {
+            let row = &mut table.rows[idx];
+            row[update_col_idx] = new_value;
+            if let Some(position) = version {
+                row[position] = version::next_version(&row[position]);
             }
         }
+        let count = matching.len();
+        let changed: Vec<Vec<Value>> = if self.hooks.is_empty() {
+            Vec::new()
+        } else {
+            matching.iter().map(|&idx| table.rows[idx].clone()).collect()
+        };
 
-        // Rebuild indexes if the updated column is indexed
-        if let Some(table_indexes) = self.indexes.get_mut(table_name) {
-            if table_indexes.contains_key(column_name) {
-                // Rebuild all indexes to be safe
-                for index in table_indexes.values_mut() {
-                    index.build(&table.rows);
-                }
+        // Rebuild indexes if the updated column or the version is indexed
+        if let Some(table_indexes) = self.indexes.get_mut(table_name)
+            && table_indexes.values()
+                .any(|index| index.column_index == update_col_idx || Some(index.column_index) == version)
+        {
+            // Rebuild all indexes to be safe
+            for index in table_indexes.values_mut() {
+                index.build(&table.rows);
             }
         }
 
         // Save to disk
-        disk::save_table(table)
-            .map_err(|e| format!("Failed to save table: {}", e))?;
+        self.save_table(table_name)?;
+        self.notify(table_name, ChangeKind::Update, changed);
 
         Ok(count)
     }
 
-    /// Select all columns from a table
-    pub fn select_all(&self, table_name: &str) -> Result<(Vec<String>, Vec<Vec<Value>>), String> {
+    /// The column an UPDATE sets, the positions of the rows it changes, and
+    /// their new values, computed from the rows' current values and checked
+    /// against the column's type and constraints before anything changes
+    fn updated_values(
+        &self,
+        table_name: &str,
+        column_name: &str,
+        expr: &Expr,
+        filter: Option<&WhereClause>
+    ) -> Result<(usize, Vec<usize>, Vec<Value>), DbError> {
         let table = self.tables.get(table_name)
-            .ok_or_else(|| format!("Table '{}' does not exist", table_name))?;
+            .ok_or_else(|| DbError::TableNotFound(table_name.to_string()))?;
 
-        let column_names: Vec<String> = table.columns.iter()
-            .map(|c| c.name.clone())
-            .collect();
+        // Get the column index to update
+        let update_col_idx = table.get_column_index(column_name)
+            .ok_or_else(|| DbError::ColumnNotFound(column_name.to_string()))?;
+        let column = &table.columns[update_col_idx];
+        self.check_changeable(table_name)?;
+        if column.name == version::VERSION_COLUMN {
+            return Err(DbError::Plan(format!("Column '{}' is maintained automatically", column_name)));
+        }
+        eval::infer_type(expr, &table.columns)?;
+
+        let matching = self.matching_rows(table_name, filter)?;
+
+        // Compute every new value from the row's current values before
+        // modifying anything, so a failure leaves the table unchanged
+        let new_values = matching.iter()
+            .map(|&idx| {
+                let value = eval::evaluate_row(expr, &table.columns, &table.rows[idx])?;
+                eval::check_value_type(&value, column)?;
+                self.limits.check_value(&value, column)?;
+                Ok(eval::coerce_value(value, &column.data_type))
+            })
+            .collect::<Result<Vec<_>, DbError>>()?;
 
-        Ok((column_names, table.rows.clone()))
+        // Check PRIMARY KEY and UNIQUE constraints
+        if column.primary_key && new_values.contains(&Value::Null) {
+            return Err(DbError::ConstraintViolation(format!("PRIMARY KEY column '{}' cannot be NULL", column_name)));
+        }
+        if !self.constraints_deferred && let Some(table_indexes) = self.indexes.get(table_name) {
+            for index in table_indexes.values()
+                .filter(|i| i.unique && i.column_index == update_col_idx)
+            {
+                let mut keys = HashSet::new();
+                for new_value in &new_values {
+                    let key = index.key_value(new_value);
+                    if key != Value::Null && !keys.insert(IndexKey::from(&key)) {
+                        return Err(DbError::ConstraintViolation(format!(
+                            "UNIQUE constraint violated: several rows would have {} = {}",
+                            index.expr, key
+                        )));
+                    }
+                    check_unique(index, new_value, &matching)?;
+                }
+            }
+        }
+        Ok((update_col_idx, matching, new_values))
     }
 
-    /// Select with specific columns and optional filter
-    pub fn select_with_filter(
-        &self,
-        table_name: &str,
-        columns: Vec<String>,
-        filter: Option<&WhereClause>,
-    ) -> Result<(Vec<String>, Vec<Vec<Value>>), String> {
-        let table = self.tables.get(table_name)
-            .ok_or_else(|| format!("Table '{}' does not exist", table_name))?;
-
-        // Validate and get column indices
-        let col_indices: Result<Vec<usize>, String> = if columns.is_empty() {
-            Ok((0..table.columns.len()).collect())
-        } else {
-            columns.iter()
-                .map(|name| {
-                    table.get_column_index(name)
-                        .ok_or_else(|| format!("Column '{}' does not exist", name))
-                })
-                .collect()
+    /// Collect statistics for one table, or all tables when `table_name` is
+    /// None. Returns the number of tables analyzed.
+    pub fn analyze(&mut self, table_name: Option<&str>) -> Result<usize, DbError> {
+        let names = match table_name {
+            Some(name) => vec![self.get_table(name)?.name.clone()],
+            None => self.list_tables(),
         };
-        let col_indices = col_indices?;
 
-        let column_names = if columns.is_empty() {
-            table.columns.iter().map(|c| c.name.clone()).collect()
-        } else {
-            columns
-        };
+        for name in &names {
+            let table_stats = stats::analyze(&self.tables[name]);
+            self.stats.insert(name.clone(), table_stats);
+        }
 
-        // Apply filter
-        let filtered_rows = if let Some(where_clause) = filter {
-            self.filter_rows(table, where_clause)?
-        } else {
-            table.rows.clone()
-        };
+        // Plans chosen without (or with older) statistics may now be stale
+        self.schema_version += 1;
+        Ok(names.len())
+    }
 
-        // Project columns
-        let result_rows: Vec<Vec<Value>> = filtered_rows.iter()
-            .map(|row| {
-                col_indices.iter()
-                    .map(|&i| row.get(i).cloned().unwrap_or(Value::Null))
-                    .collect()
-            })
-            .collect();
+    /// Statistics from the last ANALYZE of a table, if any
+    pub fn table_stats(&self, table_name: &str) -> Option<&TableStats> {
+        self.stats.get(table_name)
+    }
 
-        Ok((column_names, result_rows))
+    /// Version counter that changes whenever tables, indexes, or statistics
+    /// change, so cached plans can detect they are stale
+    pub fn schema_version(&self) -> u64 {
+        self.schema_version
     }
 
-    /// Filter rows based on WHERE clause
-    fn filter_rows(&self, table: &Table, where_clause: &WhereClause) -> Result<Vec<Vec<Value>>, String> {
-        let col_idx = table.get_column_index(&where_clause.column)
-            .ok_or_else(|| format!("Column '{}' does not exist", where_clause.column))?;
+    /// Version of a table's rows, which changes whenever they do (0 for a
+    /// table whose rows have not changed since the database was loaded)
+    pub fn table_version(&self, table_name: &str) -> u64 {
+        self.table_versions.get(table_name).copied().unwrap_or(0)
+    }
 
-        // Try to use index if available
-        if let Some(table_indexes) = self.indexes.get(&table.name) {
-            if let Some(index) = table_indexes.get(&where_clause.column) {
-                return self.filter_with_index(table, index, where_clause);
-            }
+    /// Get a table by name, including the `__audit` table while the audit
+    /// log is enabled
+    pub fn get_table(&self, table_name: &str) -> Result<&Table, DbError> {
+        if table_name == AUDIT_TABLE && let Some(log) = &self.audit {
+            return Ok(log.table());
         }
+        self.tables.get(table_name)
+            .ok_or_else(|| DbError::TableNotFound(table_name.to_string()))
+    }
 
-        // Fallback to table scan
-        Ok(table.rows.iter()
-            .filter(|row| {
-                if let Some(value) = row.get(col_idx) {
-                    compare_values(value, &where_clause.operator, &where_clause.value)
-                } else {
-                    false
-                }
-            })
-            .cloned()
-            .collect())
+    /// Find the index on an expression (or plain column) of a table
+    pub fn find_index(&self, table_name: &str, expr: &Expr) -> Option<&Index> {
+        self.indexes.get(table_name)?.get(&expr.to_string())
     }
 
-    /// Filter using an index
-    fn filter_with_index(
-        &self,
-        table: &Table,
-        index: &Index,
-        where_clause: &WhereClause,
-    ) -> Result<Vec<Vec<Value>>, String> {
-        let row_indices = match &where_clause.operator {
-            Operator::Equals => {
-                index.lookup(&where_clause.value)
-                    .map(|v| v.clone())
-                    .unwrap_or_default()
-            }
-            Operator::GreaterThan => index.greater_than(&where_clause.value),
-            Operator::LessThan => index.less_than(&where_clause.value),
-            _ => {
-                // For other operators, fall back to table scan
-                return self.filter_rows(table, where_clause);
-            }
+    /// Find an index that can answer a predicate comparing an expression with
+    /// a constant. Index keys of different types never match, so e.g.
+    /// `id < 2.5` on an INT column is left to a filter, which compares
+    /// numerically. A variable not yet bound is taken to fit; that is checked
+    /// again once its value is known.
+    pub fn index_for(&self, table_name: &str, predicate: &WhereClause) -> Option<&Index> {
+        let value = match &predicate.right {
+            Expr::Variable { value: None, .. } => None,
+            right => Some(eval::known_value(right)?),
+        };
+        let index = self.find_index(table_name, &predicate.left)?;
+        let table = self.tables.get(table_name)?;
+        let key_type = eval::infer_type(&predicate.left, &table.columns);
+        let mixed = |value: &Value| matches!(
+            (value, &key_type),
+            (Value::Int(_), Ok(Some(DataType::Float))) | (Value::Float(_), Ok(Some(DataType::Int)))
+        );
+        // = ANY looks up each element of a constant array, and @> is left
+        // to a scan
+        let usable = match (&predicate.operator, value) {
+            (Operator::Any | Operator::Contains | Operator::In, None) => false,
+            (_, None) => true,
+            (Operator::Any, Some(Value::Array(elements))) => !elements.iter().any(mixed),
+            (Operator::Any | Operator::Contains | Operator::In, _) => false,
+            (_, Some(value)) => !mixed(value),
         };
+        usable.then_some(index)
+    }
 
-        Ok(row_indices.iter()
-            .filter_map(|&idx| table.rows.get(idx).cloned())
-            .collect())
+    /// Positions of the rows of a table that match a filter (all rows without
+    /// one), in table order. The filter is answered by an index when one fits.
+    fn matching_rows(&self, table_name: &str, filter: Option<&WhereClause>) -> Result<Vec<usize>, DbError> {
+        let table = self.tables.get(table_name)
+            .ok_or_else(|| DbError::TableNotFound(table_name.to_string()))?;
+        let Some(where_clause) = filter else {
+            self.counters.count_scanned(table.rows.len());
+            return Ok((0..table.rows.len()).collect());
+        };
+
+        // Validate the filter expression
+        eval::check_where(where_clause, &table.columns)?;
+
+        if let Some(index) = self.index_for(table_name, where_clause)
+            && let Some(value) = eval::known_value(&where_clause.right)
+        {
+            let matching = index.matching_rows(&where_clause.operator, value);
+            self.counters.count_index_hit();
+            self.counters.count_scanned(matching.len());
+            return Ok(matching);
+        }
+        self.counters.count_scanned(table.rows.len());
+        let mut matching = Vec::new();
+        for (idx, row) in table.rows.iter().enumerate() {
+            if eval::matches(where_clause, &table.columns, row)? {
+                matching.push(idx);
+            }
+        }
+        Ok(matching)
     }
 
     /// List all table names
@@ -392,34 +930,90 @@ impl Database {
     }
 }
 
-/// Compare two values using an operator
-fn compare_values(left: &Value, operator: &Operator, right: &Value) -> bool {
-    match operator {
-        Operator::Equals => left == right,
-        Operator::NotEquals => left != right,
-        Operator::GreaterThan => match (left, right) {
-            (Value::Int(a), Value::Int(b)) => a > b,
-            (Value::Float(a), Value::Float(b)) => a > b,
-            (Value::Text(a), Value::Text(b)) => a > b,
-            _ => false,
-        },
-        Operator::LessThan => match (left, right) {
-            (Value::Int(a), Value::Int(b)) => a < b,
-            (Value::Float(a), Value::Float(b)) => a < b,
-            (Value::Text(a), Value::Text(b)) => a < b,
-            _ => false,
-        },
-        Operator::GreaterOrEqual => match (left, right) {
-            (Value::Int(a), Value::Int(b)) => a >= b,
-            (Value::Float(a), Value::Float(b)) => a >= b,
-            (Value::Text(a), Value::Text(b)) => a >= b,
-            _ => false,
-        },
-        Operator::LessOrEqual => match (left, right) {
-            (Value::Int(a), Value::Int(b)) => a <= b,
-            (Value::Float(a), Value::Float(b)) => a <= b,
-            (Value::Text(a), Value::Text(b)) => a <= b,
-            _ => false,
-        },
-    }
-}
\ No newline at end of file
+/// Write the last `count` rows of a table to the end of its file. Once the
+/// rows appended since the file was last rewritten outnumber the rest, it is
+/// compacted, i.e. rewritten whole, as it is when appending fails. Returns
+/// the bytes written.
+fn append_to_file(table: &Table, count: usize, appended_on_disk: &mut HashMap<String, usize>) -> std::io::Result<u64> {
+    let on_disk = appended_on_disk.get(&table.name).copied().unwrap_or(0) + count;
+    let rewritten_rows = table.rows.len().saturating_sub(on_disk);
+    let appended = (on_disk <= rewritten_rows.max(COMPACTION_MIN_ROWS))
+        .then(|| disk::append_rows(&table.name, &table.rows[table.rows.len() - count..]).ok())
+        .flatten();
+    if let Some(bytes) = appended {
+        appended_on_disk.insert(table.name.clone(), on_disk);
+        return Ok(bytes);
+    }
+    let bytes = disk::save_table(table)?;
+    appended_on_disk.remove(&table.name);
+    Ok(bytes)
+}
+
+/// The error for a table file that could not be written
+fn write_error(table_name: &str, error: std::io::Error) -> DbError {
+    DbError::Io(format!(
+        "Persistence unavailable: could not write table '{}' to '{}': {}",
+        table_name,
+        disk::data_path("").display(),
+        error
+    ))
+}
+
+/// Check a row's length, value types and sizes, and PRIMARY KEY columns, and
+/// convert its values to their columns' types in place. A new row of a
+/// versioned table gets its first version.
+fn prepare_row(table: &Table, mut values: Vec<Value>, limits: &Limits) -> Result<Vec<Value>, DbError> {
+    version::fill_version(&table.columns, &mut values);
+    if values.len() != table.columns.len() {
+        return Err(DbError::Plan(format!(
+            "Expected {} values, got {}",
+            table.columns.len(),
+            values.len()
+        )));
+    }
+
+    for (value, column) in values.iter_mut().zip(&table.columns) {
+        eval::check_value_type(value, column)?;
+        limits.check_value(value, column)?;
+        *value = eval::coerce_value(std::mem::replace(value, Value::Null), &column.data_type);
+    }
+
+    check_not_null(table, &values)?;
+    Ok(values)
+}
+
+/// Fold column names to the case they are looked up in
+fn normalize_columns(columns: &mut [Column]) {
+    for column in columns {
+        column.name = normalize_identifier(&column.name);
+    }
+}
+
+/// Check that PRIMARY KEY columns are not NULL
+fn check_not_null(table: &Table, values: &[Value]) -> Result<(), DbError> {
+    for (value, column) in values.iter().zip(table.columns.iter()) {
+        if column.primary_key && *value == Value::Null {
+            return Err(DbError::ConstraintViolation(format!("PRIMARY KEY column '{}' cannot be NULL", column.name)));
+        }
+    }
+    Ok(())
+}
+
+/// Check that storing `value` in a unique index would not duplicate an
+/// existing entry, other than those of the rows in `ignore` (sorted)
+fn check_unique(index: &Index, value: &Value, ignore: &[usize]) -> Result<(), DbError> {
+    let key = index.key_value(value);
+    if key == Value::Null {
+        return Ok(());
+    }
+
+    if let Some(rows) = index.lookup(&key)
+        && rows.iter().any(|row| ignore.binary_search(row).is_err())
+    {
+        return Err(DbError::ConstraintViolation(format!(
+            "UNIQUE constraint violated: {} = {} already exists",
+            index.expr, key
+        )));
+    }
+    Ok(())
+}
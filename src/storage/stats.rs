@@ -0,0 +1,172 @@
+// Table statistics collected by ANALYZE, used for selectivity estimates
+
+use super::btree::IndexKey;
+use super::Table;
+use crate::parser::{Operator, Value};
+use std::collections::HashMap;
+
+const HISTOGRAM_BUCKETS: usize = 10;
+
+/// Statistics for a single column
+#[derive(Debug, Clone)]
+pub struct ColumnStats {
+    pub distinct: usize,
+    pub nulls: usize,
+    pub min: Option<Value>,
+    pub max: Option<Value>,
+    // Upper bound of each equi-depth bucket, in ascending order
+    pub histogram: Vec<Value>,
+}
+
+/// Statistics for a table, as of the last ANALYZE
+#[derive(Debug, Clone)]
+pub struct TableStats {
+    pub row_count: usize,
+    pub columns: HashMap<String, ColumnStats>,
+}
+
+/// Collect statistics for every column of a table
+pub fn analyze(table: &Table) -> TableStats {
+    let columns = table.columns.iter()
+        .enumerate()
+        .map(|(i, column)| {
+            let values = table.rows.iter().filter_map(|row| row.get(i));
+            (column.name.clone(), analyze_column(values))
+        })
+        .collect();
+
+    TableStats {
+        row_count: table.rows.len(),
+        columns,
+    }
+}
+
+/// Collect statistics for one column's values
+fn analyze_column<'a>(values: impl Iterator<Item = &'a Value>) -> ColumnStats {
+    let mut nulls = 0;
+    let mut keys = Vec::new();
+    for value in values {
+        match value {
+            Value::Null => nulls += 1,
+            value => keys.push(IndexKey::from(value)),
+        }
+    }
+    keys.sort();
+
+    // Keys are sorted, so each new distinct value starts where it differs from
+    // its predecessor
+    let distinct = if keys.is_empty() {
+        0
+    } else {
+        1 + keys.windows(2).filter(|pair| pair[0] != pair[1]).count()
+    };
+    let histogram = if keys.is_empty() {
+        Vec::new()
+    } else {
+        let buckets = HISTOGRAM_BUCKETS.min(keys.len());
+        (1..=buckets)
+            .map(|b| keys[b * keys.len() / buckets - 1].to_value())
+            .collect()
+    };
+
+    ColumnStats {
+        distinct,
+        nulls,
+        min: keys.first().map(IndexKey::to_value),
+        max: keys.last().map(IndexKey::to_value),
+        histogram,
+    }
+}
+
+impl ColumnStats {
+    /// Estimate the fraction of rows for which `column <operator> value` holds
+    pub fn selectivity(&self, operator: &Operator, value: &Value, row_count: usize) -> f64 {
+        if row_count == 0 {
+            return 0.0;
+        }
+        let non_null = (row_count.saturating_sub(self.nulls)) as f64 / row_count as f64;
+
+        let equal = if self.distinct == 0 || self.out_of_range(value) {
+            0.0
+        } else {
+            non_null / self.distinct as f64
+        };
+
+        let below = self.fraction_below(value) * non_null;
+        let selectivity = match operator {
+            Operator::Is => 1.0 - non_null,
+            Operator::IsNot => non_null,
+            _ if *value == Value::Null => 0.0,
+            Operator::Equals => equal,
+            Operator::NotEquals => non_null - equal,
+            Operator::LessThan => below,
+            Operator::LessOrEqual => below + equal,
+            Operator::GreaterThan => non_null - below - equal,
+            Operator::GreaterOrEqual => non_null - below,
+            Operator::Any | Operator::In => match value {
+                Value::Array(elements) => elements.iter()
+                    .filter(|e| **e != Value::Null)
+                    .map(|e| self.selectivity(&Operator::Equals, e, row_count))
+                    .sum(),
+                _ => 0.0,
+            },
+            // Assumed as selective as equality, its narrowest case
+            Operator::Contains => equal,
+        };
+        selectivity.clamp(0.0, 1.0)
+    }
+
+    /// Whether a value lies outside [min, max]
+    fn out_of_range(&self, value: &Value) -> bool {
+        let key = IndexKey::from(value);
+        match (&self.min, &self.max) {
+            (Some(min), Some(max)) => key < IndexKey::from(min) || key > IndexKey::from(max),
+            _ => true,
+        }
+    }
+
+    /// Fraction of non-NULL values strictly below `value`, from the histogram.
+    /// A value inside a bucket counts as halfway through it.
+    fn fraction_below(&self, value: &Value) -> f64 {
+        if self.histogram.is_empty() {
+            return 0.0;
+        }
+        let key = IndexKey::from(value);
+        if self.min.as_ref().is_some_and(|min| key <= IndexKey::from(min)) {
+            return 0.0;
+        }
+
+        let buckets = self.histogram.len() as f64;
+        let full = self.histogram.iter()
+            .take_while(|bound| IndexKey::from(*bound) < key)
+            .count() as f64;
+        if full >= buckets {
+            1.0
+        } else {
+            (full + 0.5) / buckets
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selectivity_estimates() {
+        let values: Vec<Value> = (1..=100).map(Value::Int).collect();
+        let stats = analyze_column(values.iter());
+
+        assert_eq!(stats.distinct, 100);
+        assert_eq!(stats.histogram.len(), HISTOGRAM_BUCKETS);
+
+        let eq = stats.selectivity(&Operator::Equals, &Value::Int(50), 100);
+        assert!((eq - 0.01).abs() < 1e-9);
+        assert_eq!(stats.selectivity(&Operator::Equals, &Value::Int(500), 100), 0.0);
+
+        let lt = stats.selectivity(&Operator::LessThan, &Value::Int(25), 100);
+        assert!(lt > 0.15 && lt < 0.35, "got {}", lt);
+        let gt = stats.selectivity(&Operator::GreaterThan, &Value::Int(90), 100);
+        assert!(gt < 0.15, "got {}", gt);
+    }
+}
@@ -0,0 +1,239 @@
+// Transaction - all-or-nothing groups of changes for library users
+//
+// A `Transaction` snapshots the database when it begins. Changes made through
+// it stay in memory until `commit`, which writes every table they touched;
+// dropping it uncommitted (an early `?` return or a panic unwinding through
+// it) restores the snapshot, so nothing reaches disk and change hooks never
+// hear of it. Transactions nest: an inner commit keeps its changes for the
+// outer transaction to commit or roll back.
+//
+// With constraints deferred (`SET CONSTRAINTS ALL DEFERRED`), UNIQUE is not
+// checked as rows change but when the outermost transaction commits, so rows
+// may pass through duplicate values on the way to a valid state. A
+// violation found then makes the commit fail and roll back.
+
+use super::{Database, Table};
+use super::btree::Index;
+use super::stats::TableStats;
+use crate::error::DbError;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::{Deref, DerefMut};
+
+/// Guard over a database with uncommitted changes; derefs to the database
+pub struct Transaction<'a> {
+    db: &'a mut Database,
+    snapshot: Option<Snapshot>, // None once committed
+}
+
+/// State restored on rollback
+struct Snapshot {
+    tables: HashMap<String, Table>,
+    indexes: HashMap<String, HashMap<String, Index>>,
+    stats: HashMap<String, TableStats>,
+    dirty: BTreeSet<String>,
+    appended: BTreeMap<String, usize>,
+    pending_changes: usize, // Queued change events to keep
+}
+
+impl Database {
+    /// Begin a transaction; it rolls back when dropped without `commit`
+    pub fn begin(&mut self) -> Transaction<'_> {
+        let snapshot = Snapshot {
+            tables: self.tables.clone(),
+            indexes: self.indexes.clone(),
+            stats: self.stats.clone(),
+            dirty: self.dirty.clone(),
+            appended: self.appended.clone(),
+            pending_changes: self.pending_changes.len(),
+        };
+        self.transaction_depth += 1;
+        Transaction { db: self, snapshot: Some(snapshot) }
+    }
+
+    /// Check UNIQUE constraints when the outermost transaction commits rather
+    /// than after each change, until it ends. Turning deferral off checks
+    /// the changes made so far.
+    pub fn set_constraints_deferred(&mut self, deferred: bool) -> Result<(), DbError> {
+        if self.transaction_depth == 0 {
+            return Err(DbError::Plan("SET CONSTRAINTS only applies inside a transaction".to_string()));
+        }
+        if !deferred {
+            self.check_deferred_constraints()?;
+        }
+        self.constraints_deferred = deferred;
+        Ok(())
+    }
+
+    /// Whether UNIQUE checks wait for the transaction to commit
+    pub fn constraints_deferred(&self) -> bool {
+        self.constraints_deferred
+    }
+
+    /// Check the unique indexes of the tables changed since the last write,
+    /// which include every table the transaction changed
+    fn check_deferred_constraints(&self) -> Result<(), DbError> {
+        if !self.constraints_deferred {
+            return Ok(());
+        }
+        for table_name in self.dirty.iter().chain(self.appended.keys()) {
+            let indexes = self.indexes.get(table_name).into_iter().flat_map(|indexes| indexes.values());
+            for index in indexes.filter(|index| index.unique) {
+                if let Some(key) = index.duplicate_key() {
+                    return Err(DbError::ConstraintViolation(format!(
+                        "Deferred UNIQUE constraint violated: {} = {} appears more than once in '{}'",
+                        index.expr, key.to_value(), table_name
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Run `f` in a transaction, e.g. `db.transaction(|tx| { ...; Ok(()) })`.
+    /// Its changes are committed if it returns Ok and rolled back if it
+    /// returns Err or panics.
+    pub fn transaction<T>(&mut self, f: impl FnOnce(&mut Database) -> Result<T, DbError>) -> Result<T, DbError> {
+        let mut tx = self.begin();
+        let value = f(&mut tx)?;
+        tx.commit()?;
+        Ok(value)
+    }
+}
+
+impl Transaction<'_> {
+    /// Keep the changes; the outermost commit checks deferred constraints,
+    /// writes the tables the changes touched, and then runs the change hooks
+    pub fn commit(mut self) -> Result<(), DbError> {
+        if self.db.transaction_depth == 1 {
+            // On a violation, dropping the guard rolls everything back
+            self.db.check_deferred_constraints()?;
+        }
+        self.snapshot = None;
+        self.db.transaction_depth -= 1;
+        if self.db.transaction_depth > 0 {
+            return Ok(());
+        }
+        self.db.constraints_deferred = false;
+        self.db.flush()?;
+        for event in std::mem::take(&mut self.db.pending_changes) {
+            self.db.fire(&event);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if let Some(snapshot) = self.snapshot.take() {
+            self.db.tables = snapshot.tables;
+            self.db.indexes = snapshot.indexes;
+            self.db.stats = snapshot.stats;
+            self.db.dirty = snapshot.dirty;
+            self.db.appended = snapshot.appended;
+            self.db.pending_changes.truncate(snapshot.pending_changes);
+            self.db.transaction_depth -= 1;
+            if self.db.transaction_depth == 0 {
+                self.db.constraints_deferred = false;
+            }
+            // Plans and completions may refer to tables that no longer exist,
+            // and cached results to rows that were rolled back
+            self.db.schema_version += 1;
+            let names: Vec<String> = self.db.tables.keys().cloned().collect();
+            for name in names {
+                self.db.touch(&name);
+            }
+        }
+    }
+}
+
+impl Deref for Transaction<'_> {
+    type Target = Database;
+
+    fn deref(&self) -> &Database {
+        self.db
+    }
+}
+
+impl DerefMut for Transaction<'_> {
+    fn deref_mut(&mut self) -> &mut Database {
+        self.db
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{Column, DataType, Value};
+    use std::panic::{self, AssertUnwindSafe};
+
+    #[test]
+    fn test_rollback_on_error_and_panic() {
+        // Nothing here commits, so nothing is written to disk
+        let mut db = Database::new();
+        let result: Result<(), DbError> = db.transaction(|tx| {
+            tx.create_table("tx_test".to_string(), vec![Column::new("id".to_string(), DataType::Int)])?;
+            tx.insert_row("tx_test", vec![Value::Int(1)])?;
+            assert_eq!(tx.get_table("tx_test")?.rows.len(), 1);
+            Err(DbError::Execution("abort".to_string()))
+        });
+        assert_eq!(result, Err(DbError::Execution("abort".to_string())));
+        assert!(db.get_table("tx_test").is_err());
+
+        let unwound = panic::catch_unwind(AssertUnwindSafe(|| {
+            db.transaction::<()>(|tx| {
+                tx.create_table("tx_test".to_string(), Vec::new())?;
+                panic!("boom");
+            })
+        }));
+        assert!(unwound.is_err());
+        assert!(db.get_table("tx_test").is_err());
+        assert_eq!(db.transaction_depth, 0);
+    }
+    #[test]
+    fn test_unsaved_tables() {
+        // Kept in memory, so changes stay unsaved until persistence returns
+        let mut db = Database::new();
+        db.set_persistent(false);
+        db.transaction(|tx| tx.create_table("tx_kept".to_string(), Vec::new())).unwrap();
+        let _ = db.transaction::<()>(|tx| {
+            tx.create_table("tx_dropped".to_string(), Vec::new())?;
+            Err(DbError::Execution("abort".to_string()))
+        });
+        assert_eq!(db.unsaved_tables(), vec!["tx_kept".to_string()]);
+        assert_eq!(db.flush(), Ok(0));
+    }
+
+    #[test]
+    fn test_deferred_constraints() {
+        let mut db = Database::new();
+        db.set_persistent(false);
+        let mut id = Column::new("id".to_string(), DataType::Int);
+        id.unique = true;
+        db.create_table("tx_deferred".to_string(), vec![id]).unwrap();
+        db.insert_row("tx_deferred", vec![Value::Int(1)]).unwrap();
+        assert!(db.set_constraints_deferred(true).is_err());
+
+        // A duplicate resolved before the commit is accepted
+        db.transaction(|tx| {
+            tx.set_constraints_deferred(true)?;
+            tx.insert_row("tx_deferred", vec![Value::Int(1)])?;
+            tx.delete_rows("tx_deferred", None)?;
+            tx.insert_row("tx_deferred", vec![Value::Int(2)])
+        }).unwrap();
+        assert!(!db.constraints_deferred());
+
+        // One left at the commit rolls the transaction back
+        let result = db.transaction(|tx| {
+            tx.set_constraints_deferred(true)?;
+            tx.insert_row("tx_deferred", vec![Value::Int(3)])?;
+            tx.insert_row("tx_deferred", vec![Value::Int(3)])
+        });
+        assert_eq!(
+            result,
+            Err(DbError::ConstraintViolation(
+                "Deferred UNIQUE constraint violated: id = 3 appears more than once in 'tx_deferred'".to_string()
+            ))
+        );
+        assert_eq!(db.get_table("tx_deferred").unwrap().rows, vec![vec![Value::Int(2)]]);
+    }
+}
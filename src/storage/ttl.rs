@@ -0,0 +1,164 @@
+// TTL - expiring rows by the age of a timestamp column
+//
+// A column declared `WITH TTL <seconds>` makes each row expire that many
+// seconds after the time the column holds: Unix seconds in an INT column, or
+// a UTC timestamp 'YYYY-MM-DD HH:MM:SS' (as NOW() returns) in a TEXT column.
+// Rows whose column is NULL or does not hold a timestamp never expire.
+// Expired rows stay visible until they are deleted, by PURGE or by the
+// servers, which purge every table once a minute.
+
+use super::Database;
+use crate::error::DbError;
+use crate::parser::{Column, DataType, Value};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How often the servers purge expired rows
+pub const PURGE_INTERVAL: Duration = Duration::from_secs(60);
+
+impl Database {
+    /// Delete the expired rows of a table, or of every table with a TTL
+    /// column. Returns how many rows were deleted.
+    pub fn purge_expired(&mut self, table_name: Option<&str>) -> Result<usize, DbError> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64);
+        self.purge_expired_at(table_name, now)
+    }
+
+    /// Delete the rows that have expired as of `now`, in Unix seconds
+    pub fn purge_expired_at(&mut self, table_name: Option<&str>, now: i64) -> Result<usize, DbError> {
+        let names = match table_name {
+            Some(name) => vec![self.get_table(name)?.name.clone()],
+            None => self.list_tables(),
+        };
+
+        let mut purged = 0;
+        for name in names {
+            let table = &self.tables[&name];
+            let Some((position, ttl)) = table.columns.iter()
+                .enumerate()
+                .find_map(|(i, column)| column.ttl.map(|ttl| (i, ttl)))
+            else {
+                continue;
+            };
+            let expired: Vec<usize> = table.rows.iter()
+                .enumerate()
+                .filter(|(_, row)| is_expired(&row[position], ttl, now))
+                .map(|(i, _)| i)
+                .collect();
+            if !expired.is_empty() {
+                purged += self.remove_rows(&name, expired)?;
+            }
+        }
+        Ok(purged)
+    }
+}
+
+/// Purge expired rows every `PURGE_INTERVAL` on a background thread, until
+/// the database is dropped
+pub fn purge_periodically(db: &Arc<Mutex<Database>>) {
+    let db = Arc::downgrade(db);
+    std::thread::spawn(move || loop {
+        std::thread::sleep(PURGE_INTERVAL);
+        let Some(db) = db.upgrade() else {
+            return;
+        };
+        let mut db = db.lock().unwrap_or_else(|e| e.into_inner());
+        match db.purge_expired(None) {
+            Ok(0) => {}
+            Ok(count) => tracing::debug!(count, "purged expired rows"),
+            Err(e) => tracing::warn!(error = %e, "failed to purge expired rows"),
+        }
+    });
+}
+
+/// Check a new table's TTL: at most one column, of a type holding times
+pub fn check_columns(table_name: &str, columns: &[Column]) -> Result<(), DbError> {
+    let mut ttl_columns = columns.iter().filter(|c| c.ttl.is_some());
+    if let Some(column) = ttl_columns.next() {
+        if ttl_columns.next().is_some() {
+            return Err(DbError::Plan(format!("Table '{}' has more than one TTL column", table_name)));
+        }
+        if !is_timestamp_type(&column.data_type) {
+            return Err(DbError::Plan(format!(
+                "TTL column '{}' must be INT (Unix seconds) or TEXT (timestamps), not {}",
+                column.name, column.data_type
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Whether a TTL column may have a type
+pub fn is_timestamp_type(data_type: &DataType) -> bool {
+    matches!(data_type, DataType::Int | DataType::Text)
+}
+
+/// Whether a row whose TTL column holds `value` has expired as of `now`
+fn is_expired(value: &Value, ttl: u64, now: i64) -> bool {
+    let time = match value {
+        Value::Int(seconds) => *seconds,
+        Value::Text(text) => match parse_utc(text) {
+            Some(seconds) => seconds,
+            None => return false,
+        },
+        _ => return false,
+    };
+    time.saturating_add(ttl as i64) <= now
+}
+
+/// Unix seconds of a UTC timestamp `YYYY-MM-DD HH:MM:SS` (or with a `T`
+/// between date and time), the inverse of `audit::format_utc`
+pub fn parse_utc(text: &str) -> Option<i64> {
+    let text = text.trim();
+    let (date, time) = text.split_once([' ', 'T'])?;
+    let mut date = date.splitn(3, '-').map(str::parse::<i64>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+    let mut time = time.trim_end_matches('Z').splitn(3, ':').map(str::parse::<i64>);
+    let (hour, minute, second) = (time.next()?.ok()?, time.next()?.ok()?, time.next()?.ok()?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+
+    // Days since 1970-01-01 from a civil date (Howard Hinnant's algorithm)
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    Some(days * 86_400 + hour * 3600 + minute * 60 + second)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::audit::format_utc;
+
+    #[test]
+    fn test_purge_expired() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(parse_utc(&format_utc(time)), Some(1_700_000_000));
+        assert_eq!(parse_utc("2024-02-29T12:00:00Z"), Some(1_709_208_000));
+        assert_eq!(parse_utc("yesterday"), None);
+
+        let mut db = Database::new();
+        db.set_persistent(false);
+        let mut created = Column::new("created".to_string(), DataType::Text);
+        created.ttl = Some(60);
+        db.create_table("sessions".to_string(), vec![Column::new("id".to_string(), DataType::Int), created]).unwrap();
+        let now = 1_700_000_000;
+        for (id, created) in [(1, now - 61), (2, now - 60), (3, now - 59)] {
+            let created = Value::Text(format_utc(UNIX_EPOCH + Duration::from_secs(created as u64)));
+            db.insert_row("sessions", vec![Value::Int(id), created]).unwrap();
+        }
+        db.insert_row("sessions", vec![Value::Int(4), Value::Null]).unwrap();
+
+        assert_eq!(db.purge_expired_at(None, now), Ok(2));
+        let ids: Vec<Value> = db.get_table("sessions").unwrap().rows.iter().map(|row| row[0].clone()).collect();
+        assert_eq!(ids, [Value::Int(3), Value::Int(4)]);
+
+        let mut second = Column::new("expires".to_string(), DataType::Float);
+        second.ttl = Some(1);
+        assert!(db.create_table("bad".to_string(), vec![second]).is_err());
+    }
+}
@@ -0,0 +1,102 @@
+// Version - a row version column for optimistic concurrency
+//
+// A table with an INT column named `_version` keeps it up to date: a new row
+// starts at version 1 when the INSERT leaves the column out or gives NULL,
+// and every UPDATE adds 1 to the version of each row it changes. A writer
+// that read a row at version n changes it with
+// `UPDATE ... WHERE (id, _version) = (1, n)`; if another writer changed the
+// row in the meantime, the UPDATE matches no row and reports 0 rows updated.
+
+use crate::error::DbError;
+use crate::parser::{Column, DataType, Value};
+
+/// Name of the column holding a row's version
+pub const VERSION_COLUMN: &str = "_version";
+
+/// Position of the version column among a table's columns, if it has one
+pub fn version_position(columns: &[Column]) -> Option<usize> {
+    columns.iter().position(|c| c.name == VERSION_COLUMN)
+}
+
+/// Check a new table's version column: it must be INT
+pub fn check_columns(table_name: &str, columns: &[Column]) -> Result<(), DbError> {
+    match columns.iter().find(|c| c.name == VERSION_COLUMN) {
+        Some(column) if column.data_type != DataType::Int => Err(DbError::Plan(format!(
+            "Version column '{}' of table '{}' must be INT, not {}",
+            VERSION_COLUMN, table_name, column.data_type
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// The columns an INSERT of `count` values fills, in order: all of them, or
+/// all but the version column when the INSERT leaves it out
+pub fn inserted_columns(columns: &[Column], count: usize) -> Vec<&Column> {
+    match version_position(columns) {
+        Some(position) if count + 1 == columns.len() => columns.iter()
+            .enumerate()
+            .filter(|&(i, _)| i != position)
+            .map(|(_, column)| column)
+            .collect(),
+        _ => columns.iter().collect(),
+    }
+}
+
+/// Give a new row its first version, when it has none
+pub fn fill_version(columns: &[Column], values: &mut Vec<Value>) {
+    let Some(position) = version_position(columns) else {
+        return;
+    };
+    if values.len() + 1 == columns.len() {
+        values.insert(position, Value::Int(1));
+    } else if values.get(position) == Some(&Value::Null) {
+        values[position] = Value::Int(1);
+    }
+}
+
+/// The version of a row after an update
+pub fn next_version(version: &Value) -> Value {
+    match version {
+        Value::Int(n) => Value::Int(n.saturating_add(1)),
+        _ => Value::Int(1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{self, Expr, Statement};
+    use crate::storage::Database;
+
+    #[test]
+    fn test_version_column() {
+        let mut db = Database::new();
+        db.set_persistent(false);
+        let columns = vec![
+            Column::new("id".to_string(), DataType::Int),
+            Column::new("balance".to_string(), DataType::Int),
+            Column::new(VERSION_COLUMN.to_string(), DataType::Int),
+        ];
+        db.create_table("accounts".to_string(), columns).unwrap();
+        db.insert_row("accounts", vec![Value::Int(1), Value::Int(100)]).unwrap();
+        db.insert_row("accounts", vec![Value::Int(2), Value::Int(50), Value::Null]).unwrap();
+
+        // Only the row still at the version read is updated
+        let at_version = |version| {
+            let sql = format!("UPDATE accounts SET balance = 90 WHERE id = 1 AND _version = {}", version);
+            let Ok(Statement::Update { where_clause: Some(filter), .. }) = parser::parse(&sql) else {
+                panic!("expected an UPDATE");
+            };
+            filter
+        };
+        let balance = Expr::Literal(Value::Int(90));
+        assert_eq!(db.update_rows("accounts", "balance", &balance, Some(&at_version(1))), Ok(1));
+        assert_eq!(db.update_rows("accounts", "balance", &balance, Some(&at_version(1))), Ok(0));
+        let rows = &db.get_table("accounts").unwrap().rows;
+        assert_eq!(rows[0], [Value::Int(1), Value::Int(90), Value::Int(2)]);
+        assert_eq!(rows[1][2], Value::Int(1));
+
+        let version = Column::new(VERSION_COLUMN.to_string(), DataType::Text);
+        assert!(db.create_table("bad".to_string(), vec![version]).is_err());
+    }
+}
@@ -0,0 +1,145 @@
+// View - materialized views, stored as tables
+//
+// `CREATE MATERIALIZED VIEW v AS SELECT ...` runs the query once and keeps its
+// result as an ordinary table, along with the query's SQL, which is saved
+// beside the table file as `v.view`. Reading the view reads the stored rows;
+// they change only when `REFRESH MATERIALIZED VIEW v` runs the query again and
+// replaces them. Indexes on a view are rebuilt over the new rows, and dropped
+// when their column is gone or has changed type.
+
+use super::{stats, Database, Table};
+use crate::error::DbError;
+use crate::parser::{normalize_identifier, Column, Value};
+
+impl Database {
+    /// Create a materialized view holding the result of `query`, given its
+    /// output columns and rows
+    pub fn create_materialized_view(&mut self, name: &str, columns: Vec<Column>, rows: Vec<Vec<Value>>, query: String) -> Result<(), DbError> {
+        // Constraints of the queried tables do not carry over to the view
+        let columns: Vec<Column> = columns.into_iter().map(|c| Column::new(c.name, c.data_type)).collect();
+        check_columns(name, &columns)?;
+        self.create_table(name.to_string(), columns)?;
+        let table = self.tables.get_mut(name)
+            .ok_or_else(|| DbError::TableNotFound(name.to_string()))?;
+        table.query = Some(query);
+        table.rows = rows;
+        self.save_table(name)
+    }
+
+    /// Replace the rows of a materialized view with a new result of its
+    /// query. Returns how many rows it now holds.
+    pub fn refresh_materialized_view(&mut self, name: &str, columns: Vec<Column>, rows: Vec<Vec<Value>>) -> Result<usize, DbError> {
+        let mut columns: Vec<Column> = columns.into_iter().map(|c| Column::new(c.name, c.data_type)).collect();
+        super::normalize_columns(&mut columns);
+        check_columns(name, &columns)?;
+        let table = self.view(name)?;
+
+        // Rebuild the indexes whose column is still there, checking unique
+        // ones before anything changes
+        let mut indexes = self.indexes.get(name).cloned().unwrap_or_default();
+        let dropped = indexes.len();
+        indexes.retain(|_, index| {
+            let old_type = table.get_column_index(&index.column_name).map(|i| &table.columns[i].data_type);
+            match columns.iter().position(|c| c.name == index.column_name) {
+                Some(position) if old_type == Some(&columns[position].data_type) => {
+                    index.column_index = position;
+                    true
+                }
+                _ => false,
+            }
+        });
+        let dropped = dropped - indexes.len();
+        for index in indexes.values_mut() {
+            index.build(&rows);
+            if index.unique && let Some(key) = index.duplicate_key() {
+                return Err(DbError::ConstraintViolation(format!(
+                    "Cannot refresh materialized view '{}': unique index '{}' would have duplicate value {}",
+                    name, index.name, key.to_value()
+                )));
+            }
+        }
+
+        let count = rows.len();
+        let table = self.tables.get_mut(name)
+            .ok_or_else(|| DbError::TableNotFound(name.to_string()))?;
+        let changed = table.columns != columns;
+        table.columns = columns;
+        table.rows = rows;
+        if self.indexes.contains_key(name) {
+            self.indexes.insert(name.to_string(), indexes);
+        }
+        if self.stats.contains_key(name) {
+            let table_stats = stats::analyze(&self.tables[name]);
+            self.stats.insert(name.to_string(), table_stats);
+        }
+        if changed || dropped > 0 {
+            self.schema_version += 1;
+        }
+        self.save_table(name)?;
+        Ok(count)
+    }
+
+    /// The SQL of a materialized view's query, or None if the table is not
+    /// a materialized view
+    pub fn view_query(&self, name: &str) -> Option<&str> {
+        self.tables.get(name)?.query.as_deref()
+    }
+
+    fn view(&self, name: &str) -> Result<&Table, DbError> {
+        match self.tables.get(name) {
+            Some(table) if table.query.is_some() => Ok(table),
+            Some(_) => Err(DbError::Plan(format!("'{}' is not a materialized view", name))),
+            None => Err(DbError::TableNotFound(name.to_string())),
+        }
+    }
+}
+
+/// Check the columns a view's query produces: their names must be distinct
+/// and fit in the table file's schema line, which `,` and `:` separate
+pub fn check_columns(view_name: &str, columns: &[Column]) -> Result<(), DbError> {
+    for (i, column) in columns.iter().enumerate() {
+        if column.name.contains([',', ':']) {
+            return Err(DbError::Plan(format!(
+                "Materialized view '{}' cannot have a column named '{}'; select a plain column or a simpler expression",
+                view_name, column.name
+            )));
+        }
+        if columns[..i].iter().any(|c| normalize_identifier(&c.name) == normalize_identifier(&column.name)) {
+            return Err(DbError::Plan(format!(
+                "Materialized view '{}' would have two columns named '{}'",
+                view_name, column.name
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{DataType, Expr};
+
+    #[test]
+    fn test_refresh_materialized_view() {
+        let mut db = Database::new();
+        db.set_persistent(false);
+        let columns = vec![Column::new("n".to_string(), DataType::Int)];
+        db.create_materialized_view("v", columns.clone(), vec![vec![Value::Int(1)]], "SELECT n FROM t".to_string()).unwrap();
+        db.create_index("v", &Expr::Column("n".to_string()), None, false).unwrap();
+        assert_eq!(db.view_query("v"), Some("SELECT n FROM t"));
+
+        let rows = vec![vec![Value::Int(2)], vec![Value::Int(3)]];
+        assert_eq!(db.refresh_materialized_view("v", columns, rows), Ok(2));
+        assert_eq!(db.get_table("v").unwrap().rows.len(), 2);
+        assert_eq!(db.list_indexes().len(), 1);
+
+        // An index whose column changes type is dropped
+        let columns = vec![Column::new("n".to_string(), DataType::Text)];
+        assert_eq!(db.refresh_materialized_view("v", columns, vec![]), Ok(0));
+        assert!(db.list_indexes().is_empty());
+
+        db.create_table("t".to_string(), vec![Column::new("n".to_string(), DataType::Int)]).unwrap();
+        db.insert_row("t", vec![Value::Int(1)]).unwrap();
+        assert!(db.refresh_materialized_view("t", vec![], vec![]).is_err());
+    }
+}
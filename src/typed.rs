@@ -0,0 +1,526 @@
+// Typed - serde mapping between result rows and Rust structs
+//
+// Rows deserialize like maps from column name to value, so any type deriving
+// `Deserialize` whose field names match the query's columns can be read
+// straight from a query. Inserting goes the other way: a struct serializes
+// to field names and values, which are matched to the table's columns.
+
+use crate::error::DbError;
+use crate::executor::{Interrupt, Rows};
+use crate::parser::{self, Value};
+use crate::planner::{self, Plan};
+use crate::storage::Database;
+use serde::de::value::{Error, MapDeserializer, SeqDeserializer};
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::ser::{self, Impossible, Serialize};
+
+impl Database {
+    /// Run a query and deserialize each row into `T` by column name, e.g.
+    /// `db.query_as::<User>("SELECT id, name FROM users")`
+    pub fn query_as<T: DeserializeOwned>(&self, sql: &str) -> Result<Vec<T>, DbError> {
+        let Plan::Query(physical) = planner::plan(parser::parse(sql)?, self)? else {
+            return Err(DbError::Plan("query_as needs a query that returns rows".to_string()));
+        };
+        let interrupt = Interrupt::default();
+        Rows::new(&physical, self, &interrupt)?
+            .enumerate()
+            .map(|(n, row)| {
+                let row = row?;
+                let fields = row.columns().to_vec().into_iter()
+                    .zip(row.into_values().into_iter().map(ValueDeserializer));
+                T::deserialize(MapDeserializer::new(fields))
+                    .map_err(|e| DbError::Type(format!("Row {}: {}", n + 1, e)))
+            })
+            .collect()
+    }
+
+    /// Insert a serializable struct (or map) into a table, matching its
+    /// fields to columns by name. Columns without a field are NULL.
+    pub fn insert<T: Serialize>(&mut self, table_name: &str, record: &T) -> Result<(), DbError> {
+        let mut fields = record.serialize(RecordSerializer).map_err(|e| DbError::Type(e.to_string()))?;
+        let table = self.get_table(table_name)?;
+        if let Some((name, _)) = fields.iter().find(|(name, _)| table.get_column_index(name).is_none()) {
+            return Err(DbError::ColumnNotFound(name.clone()));
+        }
+
+        let values = table.columns.iter()
+            .map(|column| {
+                fields.iter()
+                    .position(|(name, _)| *name == column.name)
+                    .map_or(Value::Null, |i| fields.swap_remove(i).1)
+            })
+            .collect();
+        self.insert_row(table_name, values)
+    }
+}
+
+/// Deserializer for a single column value
+struct ValueDeserializer(Value);
+
+impl<'de> IntoDeserializer<'de, Error> for ValueDeserializer {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            Value::Int(n) => visitor.visit_i64(n),
+            Value::Float(f) => visitor.visit_f64(f),
+            Value::Text(s) => visitor.visit_string(s),
+            Value::Array(values) => visitor.visit_seq(SeqDeserializer::new(values.into_iter().map(ValueDeserializer))),
+            Value::Null => visitor.visit_unit(),
+        }
+    }
+
+    /// Booleans are stored as the integers 0 and 1
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            Value::Int(0) => visitor.visit_bool(false),
+            Value::Int(1) => visitor.visit_bool(true),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            Value::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    /// Unit enum variants are stored as their names
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self.0 {
+            Value::Text(s) => visitor.visit_enum(s.into_deserializer()),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes
+        byte_buf unit unit_struct seq tuple tuple_struct map struct identifier
+        ignored_any
+    }
+}
+
+fn unsupported(what: &str) -> Error {
+    ser::Error::custom(format!("{} cannot be stored in a column", what))
+}
+
+/// Serializer for a single column value
+struct ValueSerializer;
+
+impl ser::Serializer for ValueSerializer {
+    type Ok = Value;
+    type Error = Error;
+    type SerializeSeq = ArrayElements;
+    type SerializeTuple = Impossible<Value, Error>;
+    type SerializeTupleStruct = Impossible<Value, Error>;
+    type SerializeTupleVariant = Impossible<Value, Error>;
+    type SerializeMap = Impossible<Value, Error>;
+    type SerializeStruct = Impossible<Value, Error>;
+    type SerializeStructVariant = Impossible<Value, Error>;
+
+    fn serialize_bool(self, v: bool) -> Result<Value, Error> {
+        Ok(Value::Int(v as i64))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Value, Error> {
+        Ok(Value::Int(v.into()))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Value, Error> {
+        Ok(Value::Int(v.into()))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Value, Error> {
+        Ok(Value::Int(v.into()))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Value, Error> {
+        Ok(Value::Int(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Value, Error> {
+        Ok(Value::Int(v.into()))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Value, Error> {
+        Ok(Value::Int(v.into()))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Value, Error> {
+        Ok(Value::Int(v.into()))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Value, Error> {
+        i64::try_from(v)
+            .map(Value::Int)
+            .map_err(|_| ser::Error::custom(format!("{} is too large for an INT column", v)))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Value, Error> {
+        Ok(Value::Float(v.into()))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Value, Error> {
+        Ok(Value::Float(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Value, Error> {
+        Ok(Value::Text(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Value, Error> {
+        Ok(Value::Text(v.to_string()))
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<Value, Error> {
+        Err(unsupported("Bytes"))
+    }
+
+    fn serialize_none(self) -> Result<Value, Error> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<Value, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value, Error> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value, Error> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str) -> Result<Value, Error> {
+        Ok(Value::Text(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(self, _name: &'static str, value: &T) -> Result<Value, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Value, Error> {
+        Err(unsupported("An enum variant with data"))
+    }
+
+    /// Sequences are stored as arrays
+    fn serialize_seq(self, len: Option<usize>) -> Result<ArrayElements, Error> {
+        Ok(ArrayElements(Vec::with_capacity(len.unwrap_or(0))))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Error> {
+        Err(unsupported("A tuple"))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeTupleStruct, Error> {
+        Err(unsupported("A tuple struct"))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        Err(unsupported("An enum variant with data"))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        Err(unsupported("A map"))
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct, Error> {
+        Err(unsupported("A struct"))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Error> {
+        Err(unsupported("An enum variant with data"))
+    }
+}
+
+/// Elements collected from a sequence, for an array column
+struct ArrayElements(Vec<Value>);
+
+impl ser::SerializeSeq for ArrayElements {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        match value.serialize(ValueSerializer)? {
+            Value::Array(_) => Err(unsupported("A nested sequence")),
+            element => {
+                self.0.push(element);
+                Ok(())
+            }
+        }
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(Value::Array(self.0))
+    }
+}
+
+/// Serializer for a whole record: a struct or a map keyed by column name
+struct RecordSerializer;
+
+/// Field names and values collected from a record
+#[derive(Default)]
+struct RecordFields {
+    fields: Vec<(String, Value)>,
+    key: Option<String>, // Map key waiting for its value
+}
+
+fn not_a_record() -> Error {
+    ser::Error::custom("Only structs and maps can be inserted as rows")
+}
+
+impl ser::Serializer for RecordSerializer {
+    type Ok = Vec<(String, Value)>;
+    type Error = Error;
+    type SerializeSeq = Impossible<Self::Ok, Error>;
+    type SerializeTuple = Impossible<Self::Ok, Error>;
+    type SerializeTupleStruct = Impossible<Self::Ok, Error>;
+    type SerializeTupleVariant = Impossible<Self::Ok, Error>;
+    type SerializeMap = RecordFields;
+    type SerializeStruct = RecordFields;
+    type SerializeStructVariant = Impossible<Self::Ok, Error>;
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<RecordFields, Error> {
+        Ok(RecordFields::default())
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<RecordFields, Error> {
+        Ok(RecordFields::default())
+    }
+
+    fn serialize_bool(self, _v: bool) -> Result<Self::Ok, Error> {
+        Err(not_a_record())
+    }
+
+    fn serialize_i8(self, _v: i8) -> Result<Self::Ok, Error> {
+        Err(not_a_record())
+    }
+
+    fn serialize_i16(self, _v: i16) -> Result<Self::Ok, Error> {
+        Err(not_a_record())
+    }
+
+    fn serialize_i32(self, _v: i32) -> Result<Self::Ok, Error> {
+        Err(not_a_record())
+    }
+
+    fn serialize_i64(self, _v: i64) -> Result<Self::Ok, Error> {
+        Err(not_a_record())
+    }
+
+    fn serialize_u8(self, _v: u8) -> Result<Self::Ok, Error> {
+        Err(not_a_record())
+    }
+
+    fn serialize_u16(self, _v: u16) -> Result<Self::Ok, Error> {
+        Err(not_a_record())
+    }
+
+    fn serialize_u32(self, _v: u32) -> Result<Self::Ok, Error> {
+        Err(not_a_record())
+    }
+
+    fn serialize_u64(self, _v: u64) -> Result<Self::Ok, Error> {
+        Err(not_a_record())
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<Self::Ok, Error> {
+        Err(not_a_record())
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<Self::Ok, Error> {
+        Err(not_a_record())
+    }
+
+    fn serialize_char(self, _v: char) -> Result<Self::Ok, Error> {
+        Err(not_a_record())
+    }
+
+    fn serialize_str(self, _v: &str) -> Result<Self::Ok, Error> {
+        Err(not_a_record())
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<Self::Ok, Error> {
+        Err(not_a_record())
+    }
+
+    fn serialize_none(self) -> Result<Self::Ok, Error> {
+        Err(not_a_record())
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<Self::Ok, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Self::Ok, Error> {
+        Err(not_a_record())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, Error> {
+        Err(not_a_record())
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, _variant: &'static str) -> Result<Self::Ok, Error> {
+        Err(not_a_record())
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(self, _name: &'static str, value: &T) -> Result<Self::Ok, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Self::Ok, Error> {
+        Err(not_a_record())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Error> {
+        Err(not_a_record())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Error> {
+        Err(not_a_record())
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeTupleStruct, Error> {
+        Err(not_a_record())
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        Err(not_a_record())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Error> {
+        Err(not_a_record())
+    }
+}
+
+impl ser::SerializeStruct for RecordFields {
+    type Ok = Vec<(String, Value)>;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, key: &'static str, value: &T) -> Result<(), Error> {
+        let value = value.serialize(ValueSerializer)
+            .map_err(|e| <Error as ser::Error>::custom(format!("Field '{}': {}", key, e)))?;
+        self.fields.push((key.to_string(), value));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok, Error> {
+        Ok(self.fields)
+    }
+}
+
+impl ser::SerializeMap for RecordFields {
+    type Ok = Vec<(String, Value)>;
+    type Error = Error;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), Error> {
+        match key.serialize(ValueSerializer)? {
+            Value::Text(name) => {
+                self.key = Some(name);
+                Ok(())
+            }
+            _ => Err(ser::Error::custom("Map keys must be column names")),
+        }
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self.key.take().ok_or_else(|| <Error as ser::Error>::custom("Map value without a key"))?;
+        let value = value.serialize(ValueSerializer)
+            .map_err(|e| <Error as ser::Error>::custom(format!("Field '{}': {}", key, e)))?;
+        self.fields.push((key, value));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok, Error> {
+        Ok(self.fields)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct User {
+        id: i64,
+        name: Option<String>,
+        score: f64,
+    }
+
+    #[test]
+    fn test_struct_round_trip() {
+        let user = User { id: 1, name: None, score: 2.5 };
+        let fields = user.serialize(RecordSerializer).unwrap();
+        assert_eq!(fields, vec![
+            ("id".to_string(), Value::Int(1)),
+            ("name".to_string(), Value::Null),
+            ("score".to_string(), Value::Float(2.5)),
+        ]);
+
+        let deserialize = |fields: Vec<(String, Value)>| {
+            let fields = fields.into_iter().map(|(name, value)| (name, ValueDeserializer(value)));
+            User::deserialize(MapDeserializer::new(fields)).map_err(|e| e.to_string())
+        };
+        assert_eq!(deserialize(fields), Ok(user));
+        assert_eq!(
+            deserialize(vec![("id".to_string(), Value::Text("x".to_string()))]),
+            Err("invalid type: string \"x\", expected i64".to_string())
+        );
+    }
+}
@@ -0,0 +1,366 @@
+// WebSocket - queries and change notifications over a WebSocket
+//
+// A `GET /ws` upgrade request on the HTTP server starts a session. Each text
+// message from the client is one SQL statement, answered with the same JSON
+// as `POST /query`, or `LISTEN <table>` / `UNLISTEN <table>`. While a client
+// listens to a table, every committed INSERT, UPDATE, or DELETE on it is
+// pushed as `{"table": ..., "change": "insert", "rows": [[...], ...]}`, using
+// the database's change hooks.
+
+use crate::executor::{json_string, json_value, ResultCache};
+use crate::http::{error_json, result_json, run_query};
+use crate::parser::normalize_identifier;
+use crate::storage::hooks::{ChangeEvent, ChangeKind};
+use crate::storage::Database;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Mutex;
+use std::thread;
+
+/// Largest message accepted from a client
+const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+/// GUID appended to the client's key in the handshake (RFC 6455)
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+/// A frame queued for a client: opcode and payload
+type Frame = (u8, Vec<u8>);
+
+/// Open sessions and the tables each one listens to
+#[derive(Default)]
+pub struct Listeners {
+    next_id: AtomicU64,
+    sessions: Mutex<HashMap<u64, Listener>>,
+}
+
+struct Listener {
+    tables: HashSet<String>,
+    outbox: Sender<Frame>,
+}
+
+impl Listeners {
+    /// Push a change to every session listening to its table. Registered
+    /// with `Database::on_change` by the server.
+    pub fn notify(&self, event: &ChangeEvent) {
+        let sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        let mut listening = sessions.values().filter(|l| l.tables.contains(&event.table)).peekable();
+        if listening.peek().is_none() {
+            return;
+        }
+        let message = change_json(event).into_bytes();
+        for listener in listening {
+            // A closed session is removed when its reader finishes
+            let _ = listener.outbox.send((OP_TEXT, message.clone()));
+        }
+    }
+
+    fn add(&self, outbox: Sender<Frame>) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let listener = Listener { tables: HashSet::new(), outbox };
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner()).insert(id, listener);
+        id
+    }
+
+    fn remove(&self, id: u64) {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner()).remove(&id);
+    }
+
+    /// Start or stop listening to a table, returning whether that changed anything
+    fn set_listening(&self, id: u64, table: &str, listen: bool) -> bool {
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(listener) = sessions.get_mut(&id) else {
+            return false;
+        };
+        if listen {
+            listener.tables.insert(table.to_string())
+        } else {
+            listener.tables.remove(table)
+        }
+    }
+}
+
+/// Complete the handshake for a client that sent `key` and serve it until it
+/// disconnects. `reader` holds whatever the client sent after the request.
+pub fn session(
+    mut reader: impl Read,
+    mut stream: TcpStream,
+    key: &str,
+    db: &Mutex<Database>,
+    listeners: &Listeners,
+) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(key)
+    )?;
+    stream.flush()?;
+
+    // Replies and notifications both go through the outbox, so a slow client
+    // never holds up the statement that triggered a notification
+    let (outbox, inbox) = mpsc::channel::<Frame>();
+    let writer = thread::spawn(move || {
+        for (opcode, payload) in inbox {
+            if write_frame(&mut stream, opcode, &payload).is_err() {
+                break;
+            }
+        }
+    });
+
+    let id = listeners.add(outbox.clone());
+    let result = read_messages(&mut reader, &outbox, id, db, listeners);
+    listeners.remove(id);
+    drop(outbox);
+    let _ = writer.join();
+    result
+}
+
+/// Answer the client's messages until it closes the connection
+fn read_messages(
+    reader: &mut impl Read,
+    outbox: &Sender<Frame>,
+    id: u64,
+    db: &Mutex<Database>,
+    listeners: &Listeners,
+) -> io::Result<()> {
+    let send = |opcode: u8, payload: Vec<u8>| {
+        let _ = outbox.send((opcode, payload));
+    };
+    let mut message = Vec::new();
+    let mut cache = ResultCache::default();
+    while let Some((fin, opcode, payload)) = read_frame(reader)? {
+        match opcode {
+            OP_TEXT | OP_CONTINUATION => {
+                message.extend(payload);
+                if message.len() > MAX_MESSAGE_SIZE {
+                    send(OP_CLOSE, close_payload(1009, "Message too large"));
+                    break;
+                }
+                if fin {
+                    let reply = match String::from_utf8(std::mem::take(&mut message)) {
+                        Ok(text) => handle_message(&text, id, db, listeners, &mut cache),
+                        Err(_) => error_json("Message is not UTF-8"),
+                    };
+                    send(OP_TEXT, reply.into_bytes());
+                }
+            }
+            OP_PING => send(OP_PONG, payload),
+            OP_PONG => {}
+            OP_CLOSE => {
+                // Echo the client's status code back
+                send(OP_CLOSE, payload.into_iter().take(2).collect());
+                break;
+            }
+            _ => {
+                send(OP_CLOSE, close_payload(1003, "Only text messages are supported"));
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reply to one text message. Queries are answered from the session's
+/// result cache when their tables have not changed.
+fn handle_message(text: &str, id: u64, db: &Mutex<Database>, listeners: &Listeners, cache: &mut ResultCache) -> String {
+    let words: Vec<&str> = text.trim().trim_end_matches(';').split_whitespace().collect();
+    let (listen, table) = match words.as_slice() {
+        [command, table] if command.eq_ignore_ascii_case("LISTEN") => (true, *table),
+        [command, table] if command.eq_ignore_ascii_case("UNLISTEN") => (false, *table),
+        _ => {
+            let mut db = db.lock().unwrap_or_else(|e| e.into_inner());
+            return match run_query(text, &mut db, cache) {
+                Ok(result) => result_json(result),
+                Err(e) => error_json(&e.to_string()),
+            };
+        }
+    };
+
+    let table = &normalize_identifier(table);
+    if listen && let Err(e) = db.lock().unwrap_or_else(|e| e.into_inner()).get_table(table) {
+        return error_json(&e.to_string());
+    }
+    let message = match (listen, listeners.set_listening(id, table, listen)) {
+        (true, _) => format!("Listening for changes to '{}'", table),
+        (false, true) => format!("Stopped listening for changes to '{}'", table),
+        (false, false) => format!("Not listening for changes to '{}'", table),
+    };
+    format!("{{\"message\":{}}}", json_string(&message))
+}
+
+fn change_json(event: &ChangeEvent) -> String {
+    let change = match event.kind {
+        ChangeKind::Insert => "insert",
+        ChangeKind::Update => "update",
+        ChangeKind::Delete => "delete",
+    };
+    let rows: Vec<String> = event.rows.iter()
+        .map(|row| {
+            let values: Vec<String> = row.iter().map(json_value).collect();
+            format!("[{}]", values.join(","))
+        })
+        .collect();
+    format!(
+        "{{\"table\":{},\"change\":\"{}\",\"rows\":[{}]}}",
+        json_string(&event.table), change, rows.join(",")
+    )
+}
+
+/// Read one frame as (FIN bit, opcode, unmasked payload), or `None` once the
+/// client has disconnected
+fn read_frame(reader: &mut impl Read) -> io::Result<Option<(bool, u8, Vec<u8>)>> {
+    let mut head = [0; 2];
+    match reader.read_exact(&mut head) {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    }
+    let fin = head[0] & 0x80 != 0;
+    let opcode = head[0] & 0x0F;
+    let len = match head[1] & 0x7F {
+        126 => {
+            let mut bytes = [0; 2];
+            reader.read_exact(&mut bytes)?;
+            u16::from_be_bytes(bytes) as u64
+        }
+        127 => {
+            let mut bytes = [0; 8];
+            reader.read_exact(&mut bytes)?;
+            u64::from_be_bytes(bytes)
+        }
+        n => n as u64,
+    };
+    if len > MAX_MESSAGE_SIZE as u64 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Frame too large"));
+    }
+
+    let mut mask = [0; 4];
+    if head[1] & 0x80 != 0 {
+        reader.read_exact(&mut mask)?;
+    }
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload)?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    Ok(Some((fin, opcode, payload)))
+}
+
+/// Write an unmasked, unfragmented frame
+fn write_frame(writer: &mut impl Write, opcode: u8, payload: &[u8]) -> io::Result<()> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xFFFF => {
+            frame.push(126);
+            frame.extend((len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend((len as u64).to_be_bytes());
+        }
+    }
+    frame.extend(payload);
+    writer.write_all(&frame)?;
+    writer.flush()
+}
+
+fn close_payload(code: u16, reason: &str) -> Vec<u8> {
+    let mut payload = code.to_be_bytes().to_vec();
+    payload.extend(reason.as_bytes());
+    payload
+}
+
+/// `Sec-WebSocket-Accept` value for a client's `Sec-WebSocket-Key`
+fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{}{}", key.trim(), HANDSHAKE_GUID).as_bytes()))
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend((data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (state, value) in h.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (chunk, word) in digest.chunks_mut(4).zip(h) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| bits | (byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handshake_and_frames() {
+        // The example from RFC 6455, section 1.3
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+
+        // A masked "Hello" from a client, then the server's unmasked reply
+        let frame = [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58];
+        let parsed = read_frame(&mut &frame[..]).unwrap();
+        assert_eq!(parsed, Some((true, OP_TEXT, b"Hello".to_vec())));
+        assert_eq!(read_frame(&mut &[][..]).unwrap(), None);
+
+        let mut written = Vec::new();
+        write_frame(&mut written, OP_TEXT, b"Hello").unwrap();
+        assert_eq!(written, [0x81, 0x05, b'H', b'e', b'l', b'l', b'o']);
+    }
+}